@@ -513,6 +513,9 @@ fn build_recipes_py(
         exclude_newer,
         // TODO: implement build number override!
         None,
+        None,       // codesign identity
+        Vec::new(), // explain
+        false,      // overall download progress
     );
 
     run_async_task(async {
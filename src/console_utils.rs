@@ -13,7 +13,8 @@ use std::{
 use clap_verbosity_flag::{InfoLevel, Verbosity};
 use console::{Style, style};
 use indicatif::{
-    HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressState, ProgressStyle,
+    FormattedDuration, HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressState,
+    ProgressStyle,
 };
 use tracing::{Level, field};
 use tracing_core::{Event, Field, Subscriber, span::Id};
@@ -363,6 +364,22 @@ impl Default for LoggingOutputHandler {
     }
 }
 
+/// Formats the estimated time until a transfer of `len` bytes completes,
+/// based on the average rate since the start. Shows `ETA -` if the total size
+/// is unknown or nothing has been transferred yet.
+fn format_eta(pos: u64, len: Option<u64>, elapsed: Duration) -> String {
+    match len {
+        Some(len) if pos > 0 && !elapsed.is_zero() => {
+            let remaining = len.saturating_sub(pos) as f64 * elapsed.as_secs_f64() / pos as f64;
+            format!(
+                "ETA {}",
+                FormattedDuration(Duration::from_secs(remaining.ceil() as u64))
+            )
+        }
+        _ => "ETA -".to_string(),
+    }
+}
+
 impl LoggingOutputHandler {
     /// Return a string with the current indentation level (bars added to the
     /// front of the string), colored according to each span's color.
@@ -384,10 +401,11 @@ impl LoggingOutputHandler {
     }
 
     /// Returns the style to use for a progressbar that is currently in
-    /// progress.
+    /// progress. The `{stalled}` key is empty unless a reporter fills it in
+    /// (see [`crate::render::reporters::GatewayReporterBuilder::with_stall_timeout`]).
     pub fn default_bytes_style(&self) -> indicatif::ProgressStyle {
         let template_str = self.with_indent_levels(
-            "{spinner:.green} {prefix:20!} [{elapsed_precise}] [{bar:40!.bright.yellow/dim.white}] {bytes:>8} @ {smoothed_bytes_per_sec:8}"
+            "{spinner:.green} {prefix:20!} [{elapsed_precise}] [{bar:40!.bright.yellow/dim.white}] {bytes:>8} @ {smoothed_bytes_per_sec:8} {smoothed_eta} {stalled}"
         );
 
         indicatif::ProgressStyle::default_bar()
@@ -413,6 +431,12 @@ impl LoggingOutputHandler {
                     }
                 },
             )
+            .with_key(
+                "smoothed_eta",
+                |s: &ProgressState, w: &mut dyn std::fmt::Write| {
+                    _ = write!(w, "{}", format_eta(s.pos(), s.len(), s.elapsed()));
+                },
+            )
     }
 
     /// Returns the style to use for a progressbar that is currently in
@@ -726,3 +750,24 @@ pub fn github_integration_enabled() -> bool {
 pub fn github_action_runner() -> bool {
     std::env::var(consts::GITHUB_ACTIONS) == Ok("true".to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_eta() {
+        // half of the bytes in 10 seconds, so 10 more seconds to go
+        assert_eq!(
+            format_eta(500, Some(1000), Duration::from_secs(10)),
+            "ETA 00:00:10"
+        );
+        assert_eq!(
+            format_eta(1000, Some(1000), Duration::from_secs(10)),
+            "ETA 00:00:00"
+        );
+        assert_eq!(format_eta(500, None, Duration::from_secs(10)), "ETA -");
+        assert_eq!(format_eta(0, Some(1000), Duration::from_secs(10)), "ETA -");
+        assert_eq!(format_eta(500, Some(1000), Duration::ZERO), "ETA -");
+    }
+}
@@ -73,7 +73,9 @@ use rattler_index::ensure_channel_initialized_fs;
 use rattler_index::ensure_channel_initialized_s3;
 use rattler_solve::SolveStrategy;
 use rattler_virtual_packages::VirtualPackageOverrides;
-use recipe::parser::{BuildString, Dependency, TestType, find_outputs_from_src};
+use recipe::parser::{
+    BuildString, Dependency, FindOutputsOptions, TestType, find_outputs_from_src_with_options,
+};
 use recipe::variable::Variable;
 use render::resolved_dependencies::RunExportsDownload;
 use selectors::SelectorConfig;
@@ -167,6 +169,9 @@ pub fn get_tool_config(
         .with_error_prefix_in_binary(build_data.error_prefix_in_binary)
         .with_allow_symlinks_on_windows(build_data.allow_symlinks_on_windows)
         .with_allow_absolute_license_paths(build_data.allow_absolute_license_paths)
+        .with_codesign_identity(build_data.codesign_identity.clone())
+        .with_explain_packages(build_data.explain.clone())
+        .with_repodata_overall_progress(build_data.overall_download_progress)
         .with_zstd_repodata_enabled(build_data.common.use_zstd)
         .with_bz2_repodata_enabled(build_data.common.use_bz2)
         .with_sharded_repodata_enabled(build_data.common.use_sharded)
@@ -225,7 +230,13 @@ pub async fn get_build_output(
 
     // First find all outputs from the recipe
     let named_source = Source::from_path(recipe_path).into_diagnostic()?;
-    let outputs = find_outputs_from_src(named_source.clone())?;
+    let outputs = find_outputs_from_src_with_options(
+        named_source.clone(),
+        &FindOutputsOptions {
+            selector_config: Some(selector_config.clone()),
+            ..Default::default()
+        },
+    )?;
 
     // Check if there is a `variants.yaml` or `conda_build_config.yaml` file next to
     // the recipe that we should potentially use.
@@ -330,7 +341,7 @@ pub async fn get_build_output(
 
         let variant_channels = if let Some(channel_sources) = discovered_output
             .used_vars
-            .get(&NormalizedKey("channel_sources".to_string()))
+            .get(&NormalizedKey::from("channel_sources"))
         {
             Some(
                 channel_sources
@@ -828,33 +839,11 @@ pub async fn rebuild(
     let (_temp_dir_guard, package_path) = match rebuild_data.package_file {
         PackageSource::Url(ref url) => {
             // Download the package to a temporary location
-            tracing::info!("Downloading package from {}", url);
-
-            let response = reqwest_client
-                .get_client()
-                .get(url.as_str())
-                .send()
-                .await
-                .into_diagnostic()?;
-
-            if !response.status().is_success() {
-                miette::bail!("Failed to download package: HTTP {}", response.status());
-            }
-
-            // Extract filename from URL or use a default
-            let Some(filename) = url
-                .path_segments()
-                .and_then(|mut segments| segments.next_back())
-                .map(|s| s.to_string())
-            else {
-                miette::bail!("Failed to extract filename from URL: {}", url);
-            };
-
             let temp_dir = tempfile::tempdir().into_diagnostic()?;
-            let package_path = temp_dir.path().join(filename);
-
-            let bytes = response.bytes().await.into_diagnostic()?;
-            fs::write(&package_path, &bytes).into_diagnostic()?;
+            let package_path =
+                rebuild::download_package(reqwest_client.get_client(), url, temp_dir.path())
+                    .await
+                    .into_diagnostic()?;
 
             tracing::info!("Downloaded package to: {:?}", package_path);
 
@@ -879,7 +868,7 @@ pub async fn rebuild(
     // build
     let temp_folder = tempfile::tempdir().into_diagnostic()?;
 
-    rebuild::extract_recipe(&package_path, temp_folder.path()).into_diagnostic()?;
+    rebuild::extract_recipe_verified(&package_path, temp_folder.path(), None).into_diagnostic()?;
 
     let temp_dir = temp_folder.keep();
 
@@ -945,15 +934,16 @@ pub async fn rebuild(
     // Now we can drop the temp directory
     drop(temp_output_dir);
 
-    // Calculate SHA256 of the rebuilt package
-    let rebuilt_sha = rattler_digest::compute_file_digest::<rattler_digest::Sha256>(&rebuilt_path)
+    // Compare the original and the rebuilt package
+    let report = rebuild::check_reproducibility(&package_path, &rebuilt_path, &Default::default())
         .into_diagnostic()?;
+    let rebuilt_sha = report.rebuilt_sha256;
 
     tracing::info!("Rebuilt package SHA256: {:x}", rebuilt_sha);
     tracing::info!("Rebuilt package saved to: \"{:?}\"", rebuilt_path);
 
     // Compare the SHA hashes
-    if original_sha == rebuilt_sha {
+    if report.is_bit_identical() {
         tracing::info!(
             "✅ Rebuild successful! SHA256 hashes match. Packages are bit-for-bit identical!"
         );
@@ -964,6 +954,12 @@ pub async fn rebuild(
         tracing::info!("  Rebuilt SHA256:  {:x}", rebuilt_sha);
         tracing::info!("  Rebuilt package: {}", rebuilt_path.display());
 
+        if report.is_content_identical() {
+            tracing::info!("  The package contents are identical, only the archives differ.");
+        } else {
+            tracing::info!("  Files that differ:\n{}", report.diff);
+        }
+
         // Check if diffoscope is available
         let diffoscope_available = Command::new("diffoscope").arg("--version").output().is_ok();
 
@@ -1108,6 +1104,38 @@ pub fn get_rattler_build_version() -> &'static str {
     env!("CARGO_PKG_VERSION")
 }
 
+/// Resolve the dependencies of the outputs (without downloading missing run
+/// exports), solving up to [`Configuration::max_concurrent_solves`] outputs
+/// concurrently.
+async fn resolve_outputs(
+    outputs: Vec<Output>,
+    tool_config: &Configuration,
+) -> miette::Result<Vec<Output>> {
+    let errors = match render::solver::solve_concurrently(
+        outputs,
+        tool_config.max_concurrent_solves,
+        |output| output.resolve_dependencies(tool_config, RunExportsDownload::SkipDownload),
+    )
+    .await
+    {
+        Ok(outputs) => return Ok(outputs),
+        Err(errors) => errors,
+    };
+
+    let count = errors.len();
+    let mut errors = errors.into_iter();
+    let first = errors.next().expect("at least one error");
+    for error in errors {
+        tracing::error!("{error}");
+    }
+    let result = Err(first).into_diagnostic();
+    if count > 1 {
+        result.wrap_err(format!("failed to solve {count} outputs"))
+    } else {
+        result
+    }
+}
+
 /// Build rattler-build recipes
 pub async fn build_recipes(
     recipe_paths: Vec<std::path::PathBuf>,
@@ -1126,16 +1154,7 @@ pub async fn build_recipes(
         sort_build_outputs_topologically(&mut outputs, build_data.up_to.as_deref())?;
 
         let outputs = if build_data.with_solve {
-            let mut updated_outputs = Vec::new();
-            for output in outputs {
-                updated_outputs.push(
-                    output
-                        .resolve_dependencies(&tool_config, RunExportsDownload::SkipDownload)
-                        .await
-                        .into_diagnostic()?,
-                );
-            }
-            updated_outputs
+            resolve_outputs(outputs, &tool_config).await?
         } else {
             outputs
         };
@@ -1342,16 +1361,7 @@ pub async fn publish_packages(
 
         if publish_data.build.render_only {
             let outputs = if publish_data.build.with_solve {
-                let mut updated_outputs = Vec::new();
-                for output in outputs {
-                    updated_outputs.push(
-                        output
-                            .resolve_dependencies(&tool_config, RunExportsDownload::SkipDownload)
-                            .await
-                            .into_diagnostic()?,
-                    );
-                }
-                updated_outputs
+                resolve_outputs(outputs, &tool_config).await?
             } else {
                 outputs
             };
@@ -1432,6 +1442,9 @@ pub async fn debug_recipe(
         allow_absolute_license_paths: false,
         exclude_newer: None,
         build_num_override: None,
+        codesign_identity: None,
+        explain: Vec::new(),
+        overall_download_progress: false,
     };
 
     let tool_config = get_tool_config(&build_data, log_handler)?;
@@ -1535,3 +1548,76 @@ pub fn show_package_info(args: InspectOpts) -> miette::Result<()> {
 pub async fn extract_package(args: opt::ExtractOpts) -> miette::Result<()> {
     package_info::extract_package(args).await
 }
+
+/// Print the rpaths and the shared library dependencies of a binary
+pub fn list_rpaths(args: ListRpathsOpts) -> miette::Result<()> {
+    let binary = canonicalize(&args.binary)
+        .into_diagnostic()
+        .with_context(|| format!("failed to find binary {}", args.binary.display()))?;
+    let platform = args.platform.unwrap_or_else(Platform::current);
+    let prefix = match args.prefix {
+        Some(prefix) => canonicalize(&prefix)
+            .into_diagnostic()
+            .with_context(|| format!("failed to find prefix {}", prefix.display()))?,
+        None => binary
+            .parent()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| miette::miette!("binary has no parent directory"))?,
+    };
+
+    let link_info = match post_process::relink::link_info(&binary, platform, &prefix) {
+        Err(post_process::relink::RelinkError::UnknownFileFormat) => {
+            return Err(miette::miette!(
+                "{} is not a binary for {} (ELF, Mach-O or PE file)",
+                binary.display(),
+                platform
+            ));
+        }
+        Err(post_process::relink::RelinkError::StripPrefixError(_)) => {
+            return Err(miette::miette!(
+                "{} is not located in the prefix {}",
+                binary.display(),
+                prefix.display()
+            ));
+        }
+        result => result.into_diagnostic()?,
+    };
+
+    println!("{} ({})", console::style(binary.display()).bold(), platform);
+
+    println!("\nrpaths:");
+    if link_info.rpaths.is_empty() {
+        println!("  (none)");
+    }
+    for (rpath, resolved) in &link_info.rpaths {
+        if rpath == resolved {
+            println!("  {}", rpath.display());
+        } else {
+            println!("  {} => {}", rpath.display(), resolved.display());
+        }
+    }
+
+    println!("\nlibraries:");
+    if link_info.libraries.is_empty() {
+        println!("  (none)");
+    }
+    for (library, resolved) in &link_info.libraries {
+        match resolved {
+            Some(resolved) => println!("  {} => {}", library.display(), resolved.display()),
+            None => println!(
+                "  {} => {}",
+                library.display(),
+                console::style("not found").yellow()
+            ),
+        }
+    }
+
+    if !link_info.system_libraries.is_empty() {
+        println!("\nsystem libraries:");
+        for library in &link_info.system_libraries {
+            println!("  {}", library.display());
+        }
+    }
+
+    Ok(())
+}
@@ -1,6 +1,9 @@
 //! Relink shared objects to use an relative path prefix
 
-use goblin::elf::header::{ELFCLASS32, ELFCLASS64, ET_DYN, ET_EXEC, et_to_str, header32, header64};
+use goblin::elf::header::{
+    EI_CLASS, EI_DATA, ELFCLASS32, ELFCLASS64, ELFDATA2LSB, ELFDATA2MSB, ET_DYN, ET_EXEC,
+    SIZEOF_IDENT, et_to_str, header64,
+};
 use goblin::elf::{Dyn, Elf};
 use goblin::strtab::Strtab;
 use itertools::Itertools;
@@ -12,9 +15,10 @@ use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 
-use crate::post_process::relink::{RelinkError, Relinker};
-use crate::recipe::parser::GlobVec;
-use crate::system_tools::{SystemTools, Tool};
+use crate::post_process::relink::{
+    AppliedRelink, RelinkError, RelinkOptions, RelinkTool, Relinker, strip_default_rpaths,
+};
+use crate::system_tools::{SystemTools, Tool, ToolError};
 use crate::unix::permission_guard::{PermissionGuard, READ_WRITE};
 use crate::utils::to_lexical_absolute;
 
@@ -51,21 +55,18 @@ impl Relinker for SharedObject {
             return Ok(false);
         }
 
-        // Determine if it's 32 or 64 bit and parse accordingly
-        let e_type = match header_buf[4] {
-            // EI_CLASS
-            ELFCLASS32 => {
-                let header_bytes: &[u8; header32::SIZEOF_EHDR] = header_buf
-                    [..header32::SIZEOF_EHDR]
-                    .try_into()
-                    .expect("Invalid header size for ELFCLASS32");
-                header32::Header::from_bytes(header_bytes).e_type
-            }
-            ELFCLASS64 => {
-                let header_bytes: &[u8; header64::SIZEOF_EHDR] = &header_buf; // Already the right size
-                header64::Header::from_bytes(header_bytes).e_type
-            }
-            _ => return Ok(false), // Invalid ELF class
+        // The class (32 / 64 bit) and the data encoding (endianness) of the file.
+        // Nothing here depends on the machine (x86_64, aarch64, riscv64, ...).
+        if !matches!(header_buf[EI_CLASS], ELFCLASS32 | ELFCLASS64) {
+            return Ok(false);
+        }
+
+        // `e_type` directly follows `e_ident` for both classes
+        let e_type_bytes = [header_buf[SIZEOF_IDENT], header_buf[SIZEOF_IDENT + 1]];
+        let e_type = match header_buf[EI_DATA] {
+            ELFDATA2LSB => u16::from_le_bytes(e_type_bytes),
+            ELFDATA2MSB => u16::from_be_bytes(e_type_bytes),
+            _ => return Ok(false), // Invalid data encoding
         };
 
         // Only process executables and shared libraries
@@ -84,9 +85,16 @@ impl Relinker for SharedObject {
 
     /// Create a new shared object from a path
     fn new(path: &Path) -> Result<Self, RelinkError> {
-        let file = File::open(path).expect("Failed to open the ELF file");
+        let file = File::open(path)?;
         let mmap = unsafe { memmap2::Mmap::map(&file)? };
-        let elf = Elf::parse(&mmap).expect("Failed to parse the ELF file");
+        let elf = Elf::parse(&mmap).map_err(|e| {
+            tracing::warn!(
+                "Failed to parse ELF file {}, it is not relinked: {}",
+                path.display(),
+                e
+            );
+            RelinkError::UnknownFileFormat
+        })?;
         Ok(Self {
             path: path.to_path_buf(),
             libraries: elf.libraries.iter().map(PathBuf::from).collect(),
@@ -167,31 +175,48 @@ impl Relinker for SharedObject {
         rpath.to_path_buf()
     }
 
-    /// Find all RPATH and RUNPATH entries and replace them with the encoded prefix.
+    fn rpaths(&self) -> Vec<PathBuf> {
+        self.rpaths
+            .iter()
+            .chain(self.runpaths.iter())
+            .flat_map(|r| r.split(':'))
+            .filter(|r| !r.is_empty())
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    /// Computes the new RPATH from the RPATH and RUNPATH entries, rewriting
+    /// entries in the encoded prefix to be relative to `$ORIGIN`.
     ///
-    /// If the rpath is outside of the prefix, it is removed.
-    fn relink(
+    /// If the rpath is outside of the prefix (and not allow-listed), it is removed.
+    fn planned_rpaths(
         &self,
         prefix: &Path,
         encoded_prefix: &Path,
-        custom_rpaths: &[String],
-        rpath_allowlist: &GlobVec,
-        system_tools: &SystemTools,
-    ) -> Result<(), RelinkError> {
+        options: &RelinkOptions,
+    ) -> Result<Vec<PathBuf>, RelinkError> {
         if !self.has_dynamic {
-            tracing::info!("{} is not dynamically linked", self.path.display());
-            return Ok(());
+            return Ok(Vec::new());
         }
+        let rpath_allowlist = &options.rpath_allowlist;
 
-        let mut rpaths = self
-            .rpaths
+        // prepended rpaths come first so that they are searched before the
+        // rpaths of the binary itself
+        let mut rpaths = options
+            .prepend_rpaths
             .iter()
-            .flat_map(|r| r.split(':'))
-            .filter(|r| !r.is_empty())
-            .map(PathBuf::from)
+            .map(|v| encoded_prefix.join(v))
             .collect::<Vec<_>>();
         rpaths.extend(
-            custom_rpaths
+            self.rpaths
+                .iter()
+                .flat_map(|r| r.split(':'))
+                .filter(|r| !r.is_empty())
+                .map(PathBuf::from),
+        );
+        rpaths.extend(
+            options
+                .custom_rpaths
                 .iter()
                 .map(|v| encoded_prefix.join(v))
                 .collect::<Vec<PathBuf>>(),
@@ -258,15 +283,54 @@ impl Relinker for SharedObject {
 
         // keep only first unique item
         final_rpaths = final_rpaths.into_iter().unique().collect();
+        Ok(strip_default_rpaths(
+            final_rpaths,
+            &options.default_rpaths,
+            &self.path,
+        ))
+    }
+
+    /// Find all RPATH and RUNPATH entries and replace them with the encoded prefix.
+    ///
+    /// If the rpath is outside of the prefix, it is removed.
+    fn relink(
+        &self,
+        prefix: &Path,
+        encoded_prefix: &Path,
+        options: &RelinkOptions,
+        system_tools: &SystemTools,
+    ) -> Result<Option<AppliedRelink>, RelinkError> {
+        if !self.has_dynamic {
+            tracing::info!("{} is not dynamically linked", self.path.display());
+            return Ok(None);
+        }
+
+        let final_rpaths = self.planned_rpaths(prefix, encoded_prefix, options)?;
+
+        // RUNPATHs are always converted to RPATHs, so only a binary with the
+        // final RPATH already in place can be skipped
+        if self.runpaths.is_empty() && final_rpaths == self.rpaths() {
+            tracing::debug!(
+                "{} is already relocatable, skipping relinking",
+                self.path.display()
+            );
+            return Ok(None);
+        }
 
         let _permission_guard = PermissionGuard::new(&self.path, READ_WRITE)?;
 
         // run builtin relink. if it fails, try patchelf
-        if builtin_relink(&self.path, &final_rpaths).is_err() {
+        let tool = if builtin_relink(&self.path, &final_rpaths).is_err() {
             call_patchelf(&self.path, &final_rpaths, system_tools)?;
-        }
+            RelinkTool::Patchelf
+        } else {
+            RelinkTool::Builtin
+        };
 
-        Ok(())
+        Ok(Some(AppliedRelink {
+            rpaths: final_rpaths,
+            tool,
+        }))
     }
 }
 
@@ -284,7 +348,10 @@ fn call_patchelf(
     );
     tracing::debug!("New rpath: {:?}", new_rpath);
 
-    let mut cmd = system_tools.call(Tool::Patchelf)?;
+    let (patchelf, version) = system_tools
+        .find_tool_with_version(Tool::Patchelf)
+        .map_err(|e| ToolError::ToolNotFound(Tool::Patchelf, e))?;
+    let mut cmd = std::process::Command::new(patchelf);
 
     // prefer using RPATH over RUNPATH because RPATH takes precedence when
     // searching for shared libraries and cannot be overridden with
@@ -302,7 +369,7 @@ fn call_patchelf(
             "patchelf failed: {}",
             String::from_utf8_lossy(&output.stderr)
         );
-        Err(RelinkError::PatchElfFailed)
+        Err(RelinkError::PatchElfFailed { version })
     } else {
         Ok(())
     }
@@ -451,6 +518,7 @@ fn builtin_relink(elf_path: &Path, new_rpath: &[PathBuf]) -> Result<(), RelinkEr
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::recipe::parser::{GlobOrRegexVec, GlobVec};
     use fs_err as fs;
     use std::path::Path;
     use tempfile::tempdir_in;
@@ -484,6 +552,135 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn relink_riscv64() -> Result<(), RelinkError> {
+        // `zlink-riscv64` is a riscv64 (rv64gc, lp64d) PIE with the same rpaths
+        // as `zlink`, and RISC-V relocations, flags and attributes
+        let prefix = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files");
+        let tmp_dir = tempdir_in(&prefix)?;
+        let binary_path = tmp_dir.path().join("zlink-riscv64");
+        fs::copy(prefix.join("zlink-riscv64"), &binary_path)?;
+
+        let data = fs::read(&binary_path)?;
+        let elf = Elf::parse(&data)?;
+        assert_eq!(elf.header.e_machine, goblin::elf::header::EM_RISCV);
+        // RVC | double-float ABI
+        assert_eq!(elf.header.e_flags, 0x5);
+        let relocations = elf
+            .dynrelas
+            .iter()
+            .chain(elf.pltrelocs.iter())
+            .map(|rela| (rela.r_offset, rela.r_type))
+            .collect::<Vec<_>>();
+        assert_eq!(relocations.len(), 3);
+
+        assert!(SharedObject::test_file(&binary_path)?);
+        let object = SharedObject::new(&binary_path)?;
+
+        let options = RelinkOptions {
+            rpath_allowlist: GlobOrRegexVec::from(GlobVec::from_vec(
+                vec!["/usr/lib/custom**"],
+                None,
+            )),
+            ..Default::default()
+        };
+        let encoded_prefix = Path::new("/rattler-build_zlink/host_env_placehold");
+        let planned = object.planned_rpaths(&prefix, encoded_prefix, &options)?;
+        assert_eq!(
+            planned,
+            vec![
+                PathBuf::from("$ORIGIN/../lib"),
+                PathBuf::from("/usr/lib/custom_lib")
+            ]
+        );
+
+        super::builtin_relink(&binary_path, &planned)?;
+        let object = SharedObject::new(&binary_path)?;
+        assert_eq!(object.rpaths(), planned);
+
+        // only the dynamic section was touched, the relocations are unchanged
+        let data = fs::read(&binary_path)?;
+        let elf = Elf::parse(&data)?;
+        assert_eq!(elf.header.e_machine, goblin::elf::header::EM_RISCV);
+        assert_eq!(
+            elf.dynrelas
+                .iter()
+                .chain(elf.pltrelocs.iter())
+                .map(|rela| (rela.r_offset, rela.r_type))
+                .collect::<Vec<_>>(),
+            relocations
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn relink_skips_relocatable_binary() -> Result<(), RelinkError> {
+        let prefix = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files");
+        let tmp_dir = tempdir_in(&prefix)?;
+        let binary_path = tmp_dir.path().join("zlink");
+        fs::copy(prefix.join("zlink"), &binary_path)?;
+
+        let options = RelinkOptions {
+            rpath_allowlist: GlobOrRegexVec::from(GlobVec::from_vec(
+                vec!["/usr/lib/custom**"],
+                None,
+            )),
+            ..Default::default()
+        };
+        let encoded_prefix = Path::new("/rattler-build_zlink/host_env_placehold");
+        let relink = |path: &Path| {
+            SharedObject::new(path)?.relink(
+                &prefix,
+                encoded_prefix,
+                &options,
+                &SystemTools::default(),
+            )
+        };
+
+        let applied = relink(&binary_path)?.unwrap();
+        assert_eq!(applied.tool, RelinkTool::Builtin);
+        assert_eq!(applied.rpaths, SharedObject::new(&binary_path)?.rpaths());
+        assert!(!logs_contain("already relocatable"));
+        let relinked = fs::read(&binary_path)?;
+
+        // the second time, the rpaths are already correct
+        assert_eq!(relink(&binary_path)?, None);
+        assert!(logs_contain("already relocatable, skipping relinking"));
+        assert_eq!(fs::read(&binary_path)?, relinked);
+
+        Ok(())
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_unparsable_elf() -> Result<(), RelinkError> {
+        let prefix = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files");
+        let tmp_dir = tempdir_in(&prefix)?;
+        let binary_path = tmp_dir.path().join("zlink-riscv64");
+
+        // only keep the ELF header, the program headers are missing now
+        let data = fs::read(prefix.join("zlink-riscv64"))?;
+        fs::write(&binary_path, &data[..header64::SIZEOF_EHDR])?;
+
+        assert!(SharedObject::test_file(&binary_path)?);
+        assert!(matches!(
+            SharedObject::new(&binary_path),
+            Err(RelinkError::UnknownFileFormat)
+        ));
+        assert!(logs_contain("it is not relinked"));
+
+        // big endian headers are detected as well
+        let mut data = data[..header64::SIZEOF_EHDR].to_vec();
+        data[EI_DATA] = ELFDATA2MSB;
+        data[SIZEOF_IDENT..SIZEOF_IDENT + 2].copy_from_slice(&ET_DYN.to_be_bytes());
+        fs::write(&binary_path, &data)?;
+        assert!(SharedObject::test_file(&binary_path)?);
+
+        Ok(())
+    }
+
     #[test]
     fn relink_patchelf() -> Result<(), RelinkError> {
         if which::which("patchelf").is_err() {
@@ -497,7 +694,13 @@ mod test {
         let binary_path = tmp_dir.join("zlink");
         fs::copy(prefix.join("zlink"), &binary_path)?;
 
-        let globvec = GlobVec::from_vec(vec!["/usr/lib/custom**"], None);
+        let options = RelinkOptions {
+            rpath_allowlist: GlobOrRegexVec::from(GlobVec::from_vec(
+                vec!["/usr/lib/custom**"],
+                None,
+            )),
+            ..Default::default()
+        };
 
         // default rpaths of the test binary are:
         // - /rattler-build_zlink/host_env_placehold/lib
@@ -505,13 +708,7 @@ mod test {
         // so we are expecting it to keep the host prefix and discard the build prefix
         let encoded_prefix = Path::new("/rattler-build_zlink/host_env_placehold");
         let object = SharedObject::new(&binary_path)?;
-        object.relink(
-            &prefix,
-            encoded_prefix,
-            &[],
-            &globvec,
-            &SystemTools::default(),
-        )?;
+        object.relink(&prefix, encoded_prefix, &options, &SystemTools::default())?;
         let object = SharedObject::new(&binary_path)?;
         assert!(SharedObject::test_file(&binary_path)?);
         assert_eq!(
@@ -530,6 +727,121 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn planned_rpaths_does_not_modify_binary() -> Result<(), RelinkError> {
+        let prefix = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files");
+        let binary_path = prefix.join("zlink");
+        let original = fs::read(&binary_path)?;
+
+        let options = RelinkOptions {
+            rpath_allowlist: GlobOrRegexVec::from(GlobVec::from_vec(
+                vec!["/usr/lib/custom**"],
+                None,
+            )),
+            ..Default::default()
+        };
+        let encoded_prefix = Path::new("/rattler-build_zlink/host_env_placehold");
+        let object = SharedObject::new(&binary_path)?;
+        let planned = object.planned_rpaths(&prefix, encoded_prefix, &options)?;
+
+        assert_eq!(
+            planned,
+            vec![
+                PathBuf::from("$ORIGIN/lib"),
+                PathBuf::from("/usr/lib/custom_lib")
+            ]
+        );
+        assert_eq!(
+            object.rpaths(),
+            vec![
+                PathBuf::from("/rattler-build_zlink/host_env_placehold/lib"),
+                PathBuf::from("/rattler-build_zlink/build_env/lib"),
+                PathBuf::from("/usr/lib/custom_lib"),
+            ]
+        );
+        assert_eq!(fs::read(&binary_path)?, original);
+
+        Ok(())
+    }
+
+    #[test]
+    fn planned_rpaths_regex_allowlist() -> Result<(), RelinkError> {
+        let prefix = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files");
+        let encoded_prefix = Path::new("/rattler-build_zlink/host_env_placehold");
+        let object = SharedObject::new(&prefix.join("zlink"))?;
+
+        let options = RelinkOptions {
+            rpath_allowlist: serde_yaml::from_str(
+                r#"[{ regex: "^/usr/lib/(custom|system)(_lib)?$" }]"#,
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        let planned = object.planned_rpaths(&prefix, encoded_prefix, &options)?;
+        assert_eq!(
+            planned,
+            vec![
+                PathBuf::from("$ORIGIN/lib"),
+                PathBuf::from("/usr/lib/custom_lib")
+            ]
+        );
+
+        // the anchored regex does not match a longer path
+        let options = RelinkOptions {
+            rpath_allowlist: serde_yaml::from_str(r#"[{ regex: "^/usr/lib/custom$" }]"#).unwrap(),
+            ..Default::default()
+        };
+        let planned = object.planned_rpaths(&prefix, encoded_prefix, &options)?;
+        assert_eq!(planned, vec![PathBuf::from("$ORIGIN/lib")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn planned_rpaths_prepend() -> Result<(), RelinkError> {
+        let prefix = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files");
+        let encoded_prefix = Path::new("/rattler-build_zlink/host_env_placehold");
+        let object = SharedObject::new(&prefix.join("zlink"))?;
+        let options = RelinkOptions {
+            custom_rpaths: vec![String::from("lib/extra")],
+            prepend_rpaths: vec![String::from("lib/first"), String::from("lib/second")],
+            rpath_allowlist: GlobOrRegexVec::from(GlobVec::from_vec(
+                vec!["/usr/lib/custom**"],
+                None,
+            )),
+            ..Default::default()
+        };
+        let planned = object.planned_rpaths(&prefix, encoded_prefix, &options)?;
+        assert_eq!(
+            planned,
+            vec![
+                PathBuf::from("$ORIGIN/lib/first"),
+                PathBuf::from("$ORIGIN/lib/second"),
+                PathBuf::from("$ORIGIN/lib"),
+                PathBuf::from("/usr/lib/custom_lib"),
+                PathBuf::from("$ORIGIN/lib/extra"),
+            ]
+        );
+
+        // a prepended rpath that the binary already has is moved to the front
+        let options = RelinkOptions {
+            custom_rpaths: Vec::new(),
+            prepend_rpaths: vec![String::from("lib/second"), String::from("lib")],
+            ..options
+        };
+        let planned = object.planned_rpaths(&prefix, encoded_prefix, &options)?;
+        assert_eq!(
+            planned,
+            vec![
+                PathBuf::from("$ORIGIN/lib/second"),
+                PathBuf::from("$ORIGIN/lib"),
+                PathBuf::from("/usr/lib/custom_lib"),
+            ]
+        );
+
+        Ok(())
+    }
+
     // rpath: none
     // encoded prefix: "/rattler-build_zlink/host_env_placehold"
     // binary path: test-data/binary_files/tmp/zlink
@@ -551,13 +863,11 @@ mod test {
         let encoded_prefix = Path::new("/rattler-build_zlink/host_env_placehold");
         let object = SharedObject::new(&binary_path)?;
         assert!(SharedObject::test_file(&binary_path)?);
-        object.relink(
-            &prefix,
-            encoded_prefix,
-            &[String::from("lib/")],
-            &GlobVec::default(),
-            &SystemTools::default(),
-        )?;
+        let options = RelinkOptions {
+            custom_rpaths: vec![String::from("lib/")],
+            ..Default::default()
+        };
+        object.relink(&prefix, encoded_prefix, &options, &SystemTools::default())?;
         let object = SharedObject::new(&binary_path)?;
         assert_eq!(
             vec!["$ORIGIN/../lib"],
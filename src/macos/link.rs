@@ -1,20 +1,22 @@
 //! Relink a dylib to use relative paths for rpaths
 use fs_err::File;
-use goblin::mach::Mach;
+use goblin::mach::fat::FAT_MAGIC;
 use goblin::mach::header::{
     Header, MH_BUNDLE, MH_DYLIB, MH_EXECUTE, SIZEOF_HEADER_32, SIZEOF_HEADER_64,
 };
+use goblin::mach::{Mach, MachO};
 use indexmap::IndexSet;
 use memmap2::MmapMut;
 use scroll::Pread;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, hash_map::Entry};
 use std::fmt;
 use std::io::{Read, Seek};
 use std::path::{Path, PathBuf};
 
-use crate::post_process::relink::{RelinkError, Relinker};
-use crate::recipe::parser::GlobVec;
-use crate::system_tools::{SystemTools, Tool};
+use crate::post_process::relink::{
+    AppliedRelink, RelinkError, RelinkOptions, RelinkTool, Relinker, strip_default_rpaths,
+};
+use crate::system_tools::{SystemTools, Tool, ToolError};
 use crate::unix::permission_guard::{PermissionGuard, READ_WRITE};
 use crate::utils::to_lexical_absolute;
 
@@ -29,6 +31,134 @@ pub struct Dylib {
     rpaths: Vec<PathBuf>,
     /// ID of the dylib (encoded)
     id: Option<PathBuf>,
+    /// Path to the main executable that `@executable_path` refers to, if known.
+    /// For executables this is the file itself.
+    executable_path: Option<PathBuf>,
+}
+
+impl Dylib {
+    /// Sets the main executable that `@executable_path` should resolve
+    /// against. Without it, `@executable_path` in a library falls back to
+    /// the directory of the library itself (the same as `@loader_path`),
+    /// which is correct for libraries that live next to the executable
+    /// loading them.
+    pub fn with_executable_path(mut self, executable_path: PathBuf) -> Self {
+        self.executable_path = Some(executable_path);
+        self
+    }
+
+    /// Resolves the `@executable_path` dependencies in `resolved_libraries`
+    /// that were not found relative to the library itself against the
+    /// `executables` of the package. A library does not know which executable
+    /// loads it, so a dependency is resolved with the first executable it is
+    /// found for. Executables resolve `@executable_path` against themselves and
+    /// are left unchanged.
+    fn resolve_with_executables(
+        self,
+        resolved_libraries: &mut HashMap<PathBuf, Option<PathBuf>>,
+        executables: &[PathBuf],
+        prefix: &Path,
+        encoded_prefix: &Path,
+    ) {
+        if self.executable_path.is_some() {
+            return;
+        }
+
+        let is_unresolved = |(lib, resolved): (&PathBuf, &Option<PathBuf>)| {
+            resolved.is_none() && lib.starts_with("@executable_path")
+        };
+        let mut dylib = self;
+        for executable in executables {
+            if !resolved_libraries.iter().any(is_unresolved) {
+                break;
+            }
+
+            dylib = dylib.with_executable_path(executable.clone());
+            for (lib, resolved) in dylib.resolve_libraries(prefix, encoded_prefix) {
+                if let Some(resolved) = resolved
+                    && let Some(entry) = resolved_libraries.get_mut(&lib)
+                    && entry.is_none()
+                {
+                    tracing::debug!(
+                        "resolved {:?} of {:?} with the executable {:?}",
+                        lib,
+                        dylib.path,
+                        executable
+                    );
+                    *entry = Some(resolved);
+                }
+            }
+        }
+    }
+
+    /// Computes the `@rpath` based install names for absolute install names
+    /// that point outside of the prefix, e.g. to the build directory of an
+    /// upstream project. The id of a dylib in `lib_dir` becomes
+    /// `@rpath/<path relative to lib_dir>`, and an absolute (non-system)
+    /// dependency becomes `@rpath/<file name>` if a library with the same name
+    /// exists in `lib_dir`.
+    fn rpath_install_names(&self, lib_dir: &Path) -> (Option<PathBuf>, HashMap<PathBuf, PathBuf>) {
+        let to_rpath = |path: &Path| PathBuf::from(format!("@rpath/{}", path.to_string_lossy()));
+        let is_system = |path: &Path| path.starts_with("/usr/lib") || path.starts_with("/System");
+
+        let change_id = self
+            .id
+            .as_ref()
+            .filter(|id| id.is_absolute())
+            .and_then(|_| self.path.strip_prefix(lib_dir).ok())
+            .map(to_rpath);
+
+        let change_dylib = self
+            .libraries
+            .iter()
+            .filter(|lib| lib.is_absolute() && !is_system(lib))
+            .filter_map(|lib| {
+                let name = lib.file_name()?;
+                lib_dir
+                    .join(name)
+                    .exists()
+                    .then(|| (lib.clone(), to_rpath(Path::new(name))))
+            })
+            .collect();
+
+        (change_id, change_dylib)
+    }
+}
+
+/// Returns the Mach-O executables among `files`, i.e. the binaries that
+/// `@executable_path` in a library of the package can refer to.
+pub fn find_executables<'a>(files: impl IntoIterator<Item = &'a PathBuf>) -> Vec<PathBuf> {
+    let mut executables = files
+        .into_iter()
+        .filter(|path| Dylib::test_file(path).unwrap_or(false))
+        .filter_map(|path| Dylib::new(path).ok()?.executable_path)
+        .collect::<Vec<_>>();
+    executables.sort();
+    executables
+}
+
+/// Resolves the `@executable_path` dependencies of the Mach-O file at `path`
+/// that [`Relinker::resolve_libraries`] could not find against the
+/// `executables` of the package (see [`find_executables`]).
+pub fn resolve_with_executables(
+    path: &Path,
+    resolved_libraries: &mut HashMap<PathBuf, Option<PathBuf>>,
+    executables: &[PathBuf],
+    prefix: &Path,
+    encoded_prefix: &Path,
+) -> Result<(), RelinkError> {
+    let has_executable_path = resolved_libraries
+        .keys()
+        .any(|lib| lib.starts_with("@executable_path"));
+    if has_executable_path && !executables.is_empty() {
+        Dylib::new(path)?.resolve_with_executables(
+            resolved_libraries,
+            executables,
+            prefix,
+            encoded_prefix,
+        );
+    }
+    Ok(())
 }
 
 impl Relinker for Dylib {
@@ -45,6 +175,17 @@ impl Relinker for Dylib {
             Err(e) => return Err(e.into()),
         }
 
+        // Universal binaries are relinked if all of their slices are
+        if u32::from_be_bytes(buf) == FAT_MAGIC {
+            let mmap = unsafe { memmap2::Mmap::map(&file)? };
+            let should_relink = macho_slices(&mmap).is_ok_and(|slices| {
+                slices.iter().all(|(_, slice)| {
+                    matches!(slice.header.filetype, MH_DYLIB | MH_BUNDLE | MH_EXECUTE)
+                })
+            });
+            return Ok(should_relink);
+        }
+
         let ctx_res = goblin::mach::parse_magic_and_ctx(&buf, 0);
         match ctx_res {
             Ok((_, Some(ctx))) => {
@@ -83,22 +224,34 @@ impl Relinker for Dylib {
         }
     }
 
-    /// parse the Mach-O file and extract all relevant information
+    /// parse the Mach-O file and extract all relevant information. For a
+    /// universal binary, the information of all slices is merged.
     fn new(path: &Path) -> Result<Self, RelinkError> {
-        let file = File::open(path).expect("Failed to open the Mach-O binary");
+        let file = File::open(path)?;
         let mmap = unsafe { memmap2::Mmap::map(&file)? };
-        match goblin::mach::Mach::parse(&mmap)? {
-            Mach::Binary(mach) => Ok(Dylib {
-                path: path.to_path_buf(),
-                id: mach.name.map(PathBuf::from),
-                rpaths: mach.rpaths.iter().map(PathBuf::from).collect(),
-                libraries: mach.libs.iter().map(PathBuf::from).collect(),
-            }),
-            _ => {
-                tracing::error!("Not a valid Mach-O binary.");
-                Err(RelinkError::FileTypeNotHandled)
+
+        let mut dylib = Dylib {
+            path: path.to_path_buf(),
+            id: None,
+            rpaths: Vec::new(),
+            libraries: HashSet::new(),
+            executable_path: None,
+        };
+        for (_, mach) in macho_slices(&mmap)? {
+            if dylib.id.is_none() {
+                dylib.id = mach.name.map(PathBuf::from);
+            }
+            for rpath in mach.rpaths.iter().map(PathBuf::from) {
+                if !dylib.rpaths.contains(&rpath) {
+                    dylib.rpaths.push(rpath);
+                }
+            }
+            dylib.libraries.extend(mach.libs.iter().map(PathBuf::from));
+            if mach.header.filetype == MH_EXECUTE {
+                dylib.executable_path = Some(path.to_path_buf());
             }
         }
+        Ok(dylib)
     }
 
     /// Returns the shared libraries contained in the file.
@@ -135,6 +288,12 @@ impl Relinker for Dylib {
                         break;
                     }
                 }
+            } else if lib.starts_with("@loader_path") || lib.starts_with("@executable_path") {
+                let resolved = self.resolve_rpath(lib, prefix, encoded_prefix);
+                if resolved.exists() {
+                    let resolved_library_path = Some(resolved.canonicalize().unwrap_or(resolved));
+                    resolved_libraries.insert(lib.clone(), resolved_library_path);
+                }
             } else if lib.is_absolute() {
                 resolved_libraries.insert(lib.clone(), Some(lib.clone()));
             }
@@ -143,53 +302,58 @@ impl Relinker for Dylib {
     }
 
     /// Resolve the rpath and replace `@loader_path` with the path of the dylib
+    /// and `@executable_path` with the path of the main executable.
+    ///
+    /// If the main executable is not known (e.g. when resolving a library on
+    /// its own), `@executable_path` is resolved relative to the dylib itself.
     fn resolve_rpath(&self, rpath: &Path, prefix: &Path, encoded_prefix: &Path) -> PathBuf {
         // get self path in "encoded prefix"
-        let self_path =
-            encoded_prefix.join(self.path.strip_prefix(prefix).expect("dylib not in prefix"));
+        let to_encoded = |path: &Path| {
+            encoded_prefix.join(path.strip_prefix(prefix).expect("dylib not in prefix"))
+        };
+        let self_path = to_encoded(&self.path);
         if let Ok(rpath_without_loader) = rpath.strip_prefix("@loader_path") {
             if let Some(library_parent) = self_path.parent() {
                 return to_lexical_absolute(rpath_without_loader, library_parent);
             } else {
                 tracing::warn!("shared library {:?} has no parent directory", self.path);
             }
+        } else if let Ok(rpath_without_executable) = rpath.strip_prefix("@executable_path") {
+            let executable_path = match &self.executable_path {
+                Some(executable_path) if executable_path.starts_with(prefix) => {
+                    to_encoded(executable_path)
+                }
+                Some(executable_path) => executable_path.clone(),
+                None => {
+                    tracing::debug!(
+                        "no main executable known for {:?}, resolving @executable_path relative to it",
+                        self.path
+                    );
+                    self_path
+                }
+            };
+            if let Some(executable_parent) = executable_path.parent() {
+                return to_lexical_absolute(rpath_without_executable, executable_parent);
+            } else {
+                tracing::warn!("executable {:?} has no parent directory", executable_path);
+            }
         }
         rpath.to_path_buf()
     }
 
-    /// Modify a dylib to use relative paths for rpaths and dylibs
-    /// This makes the dylib relocatable and allows it to be used in a conda environment.
-    ///
-    /// The main trick is to use `install_name_tool` to change the rpaths and dylibs to use relative paths.
-    ///
-    /// ### What is an RPath?
-    ///
-    /// An RPath is a path that is searched for dylibs when loading a dylib. It is similar to the `LD_LIBRARY_PATH`
-    /// on Linux. The RPath is encoded in the dylib itself.
-    ///
-    /// We change the rpath to use `@loader_path` which is the *path of the dylib* itself.
-    /// When loading a dylib, we use `@rpath` which is the rpath of the executable that loads the dylib. This allows
-    /// us to use the same dylib in different environments/prefixes.
-    ///
-    /// We also change the dylib id to use `@rpath` so that the dylib can be loaded by other dylibs. The dylib id
-    /// is the path that other dylibs use when linking to this dylib.
-    ///
-    /// # Arguments
-    ///
-    /// * `dylib_path` - Path to the dylib to modify
-    /// * `prefix` - The prefix of the file (usually a temporary directory)
-    /// * `encoded_prefix` - The prefix of the file as encoded in the dylib at build time (e.g. the host prefix)
-    fn relink(
+    fn rpaths(&self) -> Vec<PathBuf> {
+        self.rpaths.clone()
+    }
+
+    /// Computes the new rpaths, rewriting rpaths in the encoded prefix to be
+    /// relative to `@loader_path` and adding the custom rpaths.
+    fn planned_rpaths(
         &self,
         prefix: &Path,
         encoded_prefix: &Path,
-        custom_rpaths: &[String],
-        rpath_allowlist: &GlobVec,
-        system_tools: &SystemTools,
-    ) -> Result<(), RelinkError> {
-        let mut changes = DylibChanges::default();
-        let mut modified = false;
-
+        options: &RelinkOptions,
+    ) -> Result<Vec<PathBuf>, RelinkError> {
+        let rpath_allowlist = &options.rpath_allowlist;
         let resolved_rpaths = self
             .rpaths
             .iter()
@@ -197,7 +361,7 @@ impl Relinker for Dylib {
             .collect::<Vec<_>>();
         let mut new_rpaths = self.rpaths.clone();
 
-        for rpath in custom_rpaths.iter().rev() {
+        for rpath in options.custom_rpaths.iter().rev() {
             let rpath = encoded_prefix.join(rpath);
             if !resolved_rpaths.contains(&rpath) {
                 tracing::debug!("Adding rpath: {:?}", rpath);
@@ -205,6 +369,15 @@ impl Relinker for Dylib {
             }
         }
 
+        // prepended rpaths take precedence over all other rpaths, so existing
+        // entries are moved to the front
+        for rpath in options.prepend_rpaths.iter().rev() {
+            let rpath = encoded_prefix.join(rpath);
+            new_rpaths.retain(|r| self.resolve_rpath(r, prefix, encoded_prefix) != rpath);
+            tracing::debug!("Prepending rpath: {:?}", rpath);
+            new_rpaths.insert(0, rpath);
+        }
+
         let mut final_rpaths = Vec::new();
 
         for rpath in &new_rpaths {
@@ -215,11 +388,12 @@ impl Relinker for Dylib {
                 } else if rpath_allowlist.is_match(rpath) {
                     tracing::info!("Rpath in allow list: {}", rpath.display());
                     final_rpaths.push(rpath.clone());
+                } else {
+                    tracing::info!(
+                        "Rpath not in prefix or allow-listed: {} - removing it",
+                        rpath.display()
+                    );
                 }
-                tracing::info!(
-                    "Rpath not in prefix or allow-listed: {} - removing it",
-                    rpath.display()
-                );
             } else if let Ok(rel) = rpath.strip_prefix(encoded_prefix) {
                 let new_rpath = prefix.join(rel);
 
@@ -247,6 +421,47 @@ impl Relinker for Dylib {
             }
         }
 
+        Ok(strip_default_rpaths(
+            final_rpaths,
+            &options.default_rpaths,
+            &self.path,
+        ))
+    }
+
+    /// Modify a dylib to use relative paths for rpaths and dylibs
+    /// This makes the dylib relocatable and allows it to be used in a conda environment.
+    ///
+    /// The main trick is to use `install_name_tool` to change the rpaths and dylibs to use relative paths.
+    ///
+    /// ### What is an RPath?
+    ///
+    /// An RPath is a path that is searched for dylibs when loading a dylib. It is similar to the `LD_LIBRARY_PATH`
+    /// on Linux. The RPath is encoded in the dylib itself.
+    ///
+    /// We change the rpath to use `@loader_path` which is the *path of the dylib* itself.
+    /// When loading a dylib, we use `@rpath` which is the rpath of the executable that loads the dylib. This allows
+    /// us to use the same dylib in different environments/prefixes.
+    ///
+    /// We also change the dylib id to use `@rpath` so that the dylib can be loaded by other dylibs. The dylib id
+    /// is the path that other dylibs use when linking to this dylib.
+    ///
+    /// # Arguments
+    ///
+    /// * `dylib_path` - Path to the dylib to modify
+    /// * `prefix` - The prefix of the file (usually a temporary directory)
+    /// * `encoded_prefix` - The prefix of the file as encoded in the dylib at build time (e.g. the host prefix)
+    fn relink(
+        &self,
+        prefix: &Path,
+        encoded_prefix: &Path,
+        options: &RelinkOptions,
+        system_tools: &SystemTools,
+    ) -> Result<Option<AppliedRelink>, RelinkError> {
+        let mut changes = DylibChanges::default();
+        let mut modified = false;
+
+        let final_rpaths = self.planned_rpaths(prefix, encoded_prefix, options)?;
+
         if final_rpaths != self.rpaths {
             for (old, new) in self.rpaths.iter().zip(final_rpaths.iter()) {
                 changes
@@ -269,7 +484,8 @@ impl Relinker for Dylib {
 
         // find the first rpath that looks like `lib/` and extends the prefix
         // by default, the first element of custom_rpaths is `lib/`
-        let base_rpath = custom_rpaths
+        let base_rpath = options
+            .custom_rpaths
             .iter()
             .find(|r| !r.contains("@") && !r.starts_with('/') && !r.starts_with('.'));
 
@@ -310,22 +526,58 @@ impl Relinker for Dylib {
             }
         }
 
-        if modified {
-            let _permission_guard = PermissionGuard::new(&self.path, READ_WRITE)?;
-            // run builtin relink. If it fails, try install_name_tool
-            if let Err(e) = relink(&self.path, &changes) {
-                assert!(self.path.exists());
-                tracing::debug!("Builtin relink failed {:?}, trying install_name_tool", e);
-                install_name_tool(&self.path, &changes, system_tools)?;
+        if options.rewrite_install_names {
+            let lib_dir = prefix.join(base_rpath.map(String::as_str).unwrap_or("lib"));
+            let (change_id, change_dylib) = self.rpath_install_names(&lib_dir);
+            if changes.change_id.is_none() && change_id.is_some() {
+                changes.change_id = change_id;
+                modified = true;
+            }
+            for (old, new) in change_dylib {
+                if let Entry::Vacant(entry) = changes.change_dylib.entry(old) {
+                    entry.insert(new);
+                    modified = true;
+                }
             }
-            codesign(&self.path, system_tools)?;
         }
 
-        Ok(())
+        if !modified {
+            tracing::debug!(
+                "{} is already relocatable, skipping relinking",
+                self.path.display()
+            );
+            return Ok(None);
+        }
+
+        let _permission_guard = PermissionGuard::new(&self.path, READ_WRITE)?;
+        // run builtin relink. If it fails, try install_name_tool
+        let tool = if let Err(e) = relink(&self.path, &changes) {
+            assert!(self.path.exists());
+            tracing::debug!("Builtin relink failed {:?}, trying install_name_tool", e);
+            install_name_tool(&self.path, &changes, system_tools)?;
+            RelinkTool::InstallNameTool
+        } else {
+            RelinkTool::Builtin
+        };
+        codesign(
+            &self.path,
+            options.codesign_identity.as_deref(),
+            system_tools,
+        )?;
+
+        Ok(Some(AppliedRelink {
+            rpaths: final_rpaths,
+            tool,
+        }))
     }
 }
 
-fn codesign(path: &Path, system_tools: &SystemTools) -> Result<(), RelinkError> {
+/// Signs the binary with the given identity, or ad-hoc (`-s -`) if no identity is given.
+pub(crate) fn codesign(
+    path: &Path,
+    identity: Option<&str>,
+    system_tools: &SystemTools,
+) -> Result<(), RelinkError> {
     let codesign = system_tools.find_tool(Tool::Codesign).map_err(|e| {
         tracing::error!("codesign not found: {}", e);
         RelinkError::CodesignFailed
@@ -333,8 +585,8 @@ fn codesign(path: &Path, system_tools: &SystemTools) -> Result<(), RelinkError>
 
     let is_system_codesign = codesign.starts_with("/usr/bin/");
 
-    let mut cmd = std::process::Command::new(codesign);
-    cmd.args(["-f", "-s", "-"]);
+    let mut cmd = std::process::Command::new(&codesign);
+    cmd.args(["-f", "-s", identity.unwrap_or("-")]);
 
     if is_system_codesign {
         cmd.arg("--preserve-metadata=entitlements,requirements");
@@ -359,6 +611,36 @@ fn codesign(path: &Path, system_tools: &SystemTools) -> Result<(), RelinkError>
         return Err(RelinkError::CodesignFailed);
     }
 
+    verify_codesign(path, &codesign)
+}
+
+/// Verifies the signature of a freshly signed binary with `codesign --verify`,
+/// so that a broken signature fails the build instead of the binary being
+/// killed when it is loaded.
+///
+/// The verification needs the system `codesign` and is skipped when
+/// cross-compiling on a non-macOS host (e.g. with `sigtool`).
+fn verify_codesign(path: &Path, codesign: &Path) -> Result<(), RelinkError> {
+    if !cfg!(target_os = "macos") || !codesign.starts_with("/usr/bin/") {
+        tracing::debug!(
+            "Skipping codesign verification of {} (no system codesign)",
+            path.display()
+        );
+        return Ok(());
+    }
+
+    let output = std::process::Command::new(codesign)
+        .args(["--verify", "--strict"])
+        .arg(path)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(RelinkError::CodesignVerificationFailed {
+            path: path.to_path_buf(),
+            reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
     Ok(())
 }
 
@@ -422,6 +704,20 @@ impl fmt::Display for DylibChanges {
     }
 }
 
+/// Parses the Mach-O slices of a file together with their offset in the file.
+/// A thin binary has a single slice at offset 0, a universal ("fat") binary has
+/// one slice per architecture.
+fn macho_slices(data: &[u8]) -> Result<Vec<(usize, MachO<'_>)>, RelinkError> {
+    match Mach::parse(data)? {
+        Mach::Binary(mach) => Ok(vec![(0, mach)]),
+        Mach::Fat(multi) => multi
+            .arches()?
+            .into_iter()
+            .map(|arch| Ok((arch.offset as usize, MachO::parse(arch.slice(data), 0)?)))
+            .collect(),
+    }
+}
+
 /// The builtin relink function is used instead of calling out to `install_name_tool`.
 /// The function attempts to modify the dylib rpath, dylib id and dylib dependencies
 /// in order to make it more easily relocatable.
@@ -451,17 +747,19 @@ fn relink(dylib_path: &Path, changes: &DylibChanges) -> Result<(), RelinkError>
         .write(true)
         .open(dylib_path)?;
 
+    // SAFETY: the file is a binary in the temporary package directory of this
+    // build, nothing outside of rattler-build modifies it. Every binary is
+    // relinked by exactly one task, and the parsed binaries of the relinker
+    // cache do not keep a mapping of the file, so no other mapping or open
+    // handle writes to it while it is mapped here.
     let data = unsafe { memmap2::Mmap::map(&file) }?;
 
-    let object = match goblin::mach::Mach::parse(&data)? {
-        Mach::Binary(mach) => mach,
-        _ => {
-            tracing::error!("Not a valid Mach-O binary.");
-            return Err(RelinkError::FileTypeNotHandled);
-        }
-    };
+    let slices = macho_slices(&data)?;
 
     // Reopen for the borrow checker
+    // SAFETY: see above. `data` maps the same file and observes the writes
+    // through this mapping, every path is read from it before it is
+    // overwritten.
     let mut data_mut = unsafe { memmap2::MmapMut::map_mut(&file) }?;
 
     let overwrite_path = |data_mut: &mut MmapMut,
@@ -497,44 +795,48 @@ fn relink(dylib_path: &Path, changes: &DylibChanges) -> Result<(), RelinkError>
         .map(|(old, new)| (old.as_ref().unwrap(), new.as_ref().unwrap()))
         .collect::<HashMap<&PathBuf, &PathBuf>>();
 
-    for cmd in object.load_commands.iter() {
-        match cmd.command {
-            goblin::mach::load_command::CommandVariant::Rpath(ref rpath) => {
-                let offset = cmd.offset + rpath.path as usize;
-                let old_path = data.pread::<&str>(offset).unwrap().to_string();
-
-                let path = PathBuf::from(&old_path);
-                if let Some(new_path) = rpath_changes.get(&path) {
-                    overwrite_path(&mut data_mut, offset, new_path, &old_path)?;
-                    modified = true;
+    // The paths are overwritten in place, so the slices of a universal binary
+    // keep their offsets and the fat header stays valid
+    for (slice_offset, object) in &slices {
+        for cmd in object.load_commands.iter() {
+            match cmd.command {
+                goblin::mach::load_command::CommandVariant::Rpath(ref rpath) => {
+                    let offset = slice_offset + cmd.offset + rpath.path as usize;
+                    let old_path = data.pread::<&str>(offset)?.to_string();
+
+                    let path = PathBuf::from(&old_path);
+                    if let Some(new_path) = rpath_changes.get(&path) {
+                        overwrite_path(&mut data_mut, offset, new_path, &old_path)?;
+                        modified = true;
+                    }
                 }
-            }
 
-            // check dylib id
-            goblin::mach::load_command::CommandVariant::IdDylib(ref id) => {
-                let offset = cmd.offset + id.dylib.name as usize;
-                let old_path = data_mut.pread::<&str>(offset)?.to_string();
+                // check dylib id
+                goblin::mach::load_command::CommandVariant::IdDylib(ref id) => {
+                    let offset = slice_offset + cmd.offset + id.dylib.name as usize;
+                    let old_path = data_mut.pread::<&str>(offset)?.to_string();
 
-                if let Some(new_path) = changes.change_id.as_ref() {
-                    overwrite_path(&mut data_mut, offset, new_path, &old_path)?;
-                    modified = true;
+                    if let Some(new_path) = changes.change_id.as_ref() {
+                        overwrite_path(&mut data_mut, offset, new_path, &old_path)?;
+                        modified = true;
+                    }
                 }
-            }
-            goblin::mach::load_command::CommandVariant::LoadWeakDylib(ref id)
-            | goblin::mach::load_command::CommandVariant::LoadUpwardDylib(ref id)
-            | goblin::mach::load_command::CommandVariant::ReexportDylib(ref id)
-            | goblin::mach::load_command::CommandVariant::LazyLoadDylib(ref id)
-            | goblin::mach::load_command::CommandVariant::LoadDylib(ref id) => {
-                let offset = cmd.offset + id.dylib.name as usize;
-                let old_path = data_mut.pread::<&str>(offset)?.to_string();
-
-                let path = PathBuf::from(&old_path);
-                if let Some(new_path) = changes.change_dylib.get(&path) {
-                    overwrite_path(&mut data_mut, offset, new_path, &old_path)?;
-                    modified = true;
+                goblin::mach::load_command::CommandVariant::LoadWeakDylib(ref id)
+                | goblin::mach::load_command::CommandVariant::LoadUpwardDylib(ref id)
+                | goblin::mach::load_command::CommandVariant::ReexportDylib(ref id)
+                | goblin::mach::load_command::CommandVariant::LazyLoadDylib(ref id)
+                | goblin::mach::load_command::CommandVariant::LoadDylib(ref id) => {
+                    let offset = slice_offset + cmd.offset + id.dylib.name as usize;
+                    let old_path = data_mut.pread::<&str>(offset)?.to_string();
+
+                    let path = PathBuf::from(&old_path);
+                    if let Some(new_path) = changes.change_dylib.get(&path) {
+                        overwrite_path(&mut data_mut, offset, new_path, &old_path)?;
+                        modified = true;
+                    }
                 }
+                _ => {}
             }
-            _ => {}
         }
     }
 
@@ -557,7 +859,11 @@ fn install_name_tool(
     );
     tracing::debug!("Relink changes:\n{}", changes);
 
-    let mut cmd = system_tools.call(Tool::InstallNameTool)?;
+    let (install_name_tool, version) =
+        system_tools
+            .find_tool_with_version(Tool::InstallNameTool)
+            .map_err(|e| ToolError::ToolNotFound(Tool::InstallNameTool, e))?;
+    let mut cmd = std::process::Command::new(install_name_tool);
 
     if let Some(id) = &changes.change_id {
         cmd.arg("-id").arg(id);
@@ -603,7 +909,13 @@ fn install_name_tool(
             "install_name_tool failed: {}",
             String::from_utf8_lossy(&output.stderr)
         );
-        return Err(RelinkError::InstallNameToolFailed);
+        return Err(RelinkError::InstallNameToolFailed {
+            version: if version.is_empty() {
+                "unknown".to_string()
+            } else {
+                version
+            },
+        });
     }
 
     Ok(())
@@ -619,12 +931,12 @@ mod tests {
     };
     use tempfile::tempdir_in;
 
-    use super::{RelinkError, install_name_tool};
+    use super::{FAT_MAGIC, RelinkError, find_executables, install_name_tool};
+    use crate::post_process::relink::{RelinkOptions, Relinker};
     use crate::{
         macos::link::{Dylib, DylibChanges},
         system_tools::SystemTools,
     };
-    use crate::{post_process::relink::Relinker, recipe::parser::GlobVec};
 
     const EXPECTED_PATH: &str = "/Users/wolfv/Programs/rattler-build/output/bld/rattler-build_zlink_1705569778/host_env_placehold_placehold_placehold_placehold_placehold_placehold_placehold_placehold_placehold_placehold_placehold_placehold_placehold_placehold_placehold_placehold_placehol/lib";
 
@@ -686,6 +998,68 @@ mod tests {
         Ok(())
     }
 
+    /// Creates a universal binary with an x86_64 and an arm64 slice of `thin`.
+    fn universal_binary(thin: &[u8]) -> Vec<u8> {
+        const ALIGN: u32 = 14;
+        let slice_len = thin.len().next_multiple_of(1 << ALIGN);
+        let mut x86_64 = thin.to_vec();
+        x86_64[4..8].copy_from_slice(&0x0100_0007u32.to_le_bytes());
+
+        let mut fat = Vec::new();
+        fat.extend_from_slice(&FAT_MAGIC.to_be_bytes());
+        fat.extend_from_slice(&2u32.to_be_bytes());
+        for (index, (cputype, cpusubtype)) in [(0x0100_0007u32, 3u32), (0x0100_000c, 0)]
+            .into_iter()
+            .enumerate()
+        {
+            let offset = (1 << ALIGN) + index * slice_len;
+            for value in [cputype, cpusubtype, offset as u32, thin.len() as u32, ALIGN] {
+                fat.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        for slice in [&x86_64[..], thin] {
+            fat.resize(fat.len().next_multiple_of(1 << ALIGN), 0);
+            fat.extend_from_slice(slice);
+        }
+        fat
+    }
+
+    #[test]
+    fn test_relink_universal_binary() -> Result<(), RelinkError> {
+        let prefix = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files");
+        let tmp_dir = tempdir_in(&prefix)?;
+        let binary_path = tmp_dir.path().join("zlink-universal");
+        fs::write(
+            &binary_path,
+            universal_binary(&fs::read(prefix.join("zlink-macos"))?),
+        )?;
+
+        assert!(Dylib::test_file(&binary_path)?);
+        let expected_rpath = PathBuf::from(EXPECTED_PATH);
+        let object = Dylib::new(&binary_path)?;
+        assert_eq!(object.rpaths, vec![expected_rpath.clone()]);
+
+        let changes = DylibChanges {
+            change_rpath: vec![(
+                Some(expected_rpath),
+                Some(PathBuf::from("@loader_path/../lib")),
+            )],
+            change_id: None,
+            change_dylib: HashMap::default(),
+        };
+        super::relink(&binary_path, &changes)?;
+
+        // both slices are relinked
+        let data = fs::read(&binary_path)?;
+        let slices = super::macho_slices(&data)?;
+        assert_eq!(slices.len(), 2);
+        for (_, slice) in slices {
+            assert_eq!(slice.rpaths, vec!["@loader_path/../lib"]);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_relink_install_name_tool() -> Result<(), RelinkError> {
         let prefix = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files");
@@ -830,8 +1204,7 @@ mod tests {
             .relink(
                 tmp_prefix,
                 &encoded_prefix,
-                &[],
-                &GlobVec::default(),
+                &RelinkOptions::default(),
                 &SystemTools::default(),
             )
             .unwrap();
@@ -849,6 +1222,7 @@ mod tests {
             id: None,
             rpaths: vec![PathBuf::from("@loader_path/../lib")],
             libraries: HashSet::new(),
+            executable_path: None,
         };
 
         let prefix = PathBuf::from("/foo/prefix");
@@ -861,4 +1235,161 @@ mod tests {
         );
         assert_eq!(resolved, PathBuf::from("/foo/very_long_encoded_prefix/lib"));
     }
+
+    #[test]
+    fn test_executable_path_resolve() {
+        let prefix = PathBuf::from("/foo/prefix");
+        let encoded_prefix = PathBuf::from("/foo/very_long_encoded_prefix");
+        let rpath = PathBuf::from("@executable_path/../lib");
+
+        // a library loaded by an executable in `bin/`
+        let dylib = Dylib {
+            path: PathBuf::from("/foo/prefix/lib/plugins/libbar.dylib"),
+            id: None,
+            rpaths: vec![],
+            libraries: HashSet::from([PathBuf::from("@executable_path/../lib/libfoo.dylib")]),
+            executable_path: None,
+        };
+
+        // without a known executable we fall back to the library location
+        let resolved = dylib.resolve_rpath(&rpath, &prefix, &encoded_prefix);
+        assert_eq!(
+            resolved,
+            PathBuf::from("/foo/very_long_encoded_prefix/lib/lib")
+        );
+
+        let dylib = dylib.with_executable_path(PathBuf::from("/foo/prefix/bin/main"));
+        let resolved = dylib.resolve_rpath(&rpath, &prefix, &encoded_prefix);
+        assert_eq!(resolved, PathBuf::from("/foo/very_long_encoded_prefix/lib"));
+
+        // the dependency of an executable resolves against the executable itself
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let prefix = tmp_dir.path();
+        fs::create_dir_all(prefix.join("lib")).unwrap();
+        fs::write(prefix.join("lib/libfoo.dylib"), "").unwrap();
+
+        let library = PathBuf::from("@executable_path/../lib/libfoo.dylib");
+        let executable = Dylib {
+            path: prefix.join("bin/main"),
+            id: None,
+            rpaths: vec![],
+            libraries: HashSet::from([library.clone()]),
+            executable_path: Some(prefix.join("bin/main")),
+        };
+        let resolved = executable.resolve_libraries(prefix, prefix);
+        assert_eq!(
+            resolved[&library],
+            Some(prefix.join("lib/libfoo.dylib").canonicalize().unwrap())
+        );
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_planned_rpaths_loader_path() -> Result<(), RelinkError> {
+        let prefix = Path::new("/prefix");
+        let encoded_prefix = Path::new("/encoded/prefix");
+        let dylib = Dylib {
+            path: prefix.join("lib/libfoo.dylib"),
+            id: None,
+            rpaths: vec![
+                PathBuf::from("@loader_path/"),
+                PathBuf::from("@loader_path/../../outside"),
+            ],
+            libraries: HashSet::new(),
+            executable_path: None,
+        };
+
+        let planned = dylib.planned_rpaths(prefix, encoded_prefix, &RelinkOptions::default())?;
+        assert_eq!(planned, vec![PathBuf::from("@loader_path/")]);
+        // only the rpath that is removed is reported
+        assert!(!logs_contain(
+            "Rpath not in prefix or allow-listed: @loader_path/ -"
+        ));
+        assert!(logs_contain(
+            "Rpath not in prefix or allow-listed: @loader_path/../../outside - removing it"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_with_executables() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let prefix = tmp_dir.path();
+        fs::create_dir_all(prefix.join("lib")).unwrap();
+        fs::write(prefix.join("lib/libfoo.dylib"), "").unwrap();
+
+        // a plugin that is loaded by `bin/main`
+        let library = PathBuf::from("@executable_path/../lib/libfoo.dylib");
+        let dylib = || Dylib {
+            path: prefix.join("lib/plugins/libbar.dylib"),
+            id: None,
+            rpaths: vec![],
+            libraries: HashSet::from([library.clone()]),
+            executable_path: None,
+        };
+
+        // relative to the plugin itself the dependency is not found
+        let mut resolved = dylib().resolve_libraries(prefix, prefix);
+        assert_eq!(resolved[&library], None);
+
+        let executables = [prefix.join("share/tool"), prefix.join("bin/main")];
+        dylib().resolve_with_executables(&mut resolved, &executables, prefix, prefix);
+        assert_eq!(
+            resolved[&library],
+            Some(prefix.join("lib/libfoo.dylib").canonicalize().unwrap())
+        );
+
+        // the package executables are found
+        let test_data = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files");
+        let files = [
+            test_data.join("simple.dylib"),
+            test_data.join("simple-macho.o"),
+            test_data.join("zlink-macos"),
+            test_data.join("simple.c"),
+        ];
+        assert_eq!(
+            find_executables(&files),
+            vec![test_data.join("zlink-macos")]
+        );
+    }
+
+    #[test]
+    fn test_rpath_install_names() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let prefix = tmp_dir.path();
+        let lib_dir = prefix.join("lib");
+        fs::create_dir_all(lib_dir.join("sub")).unwrap();
+        fs::write(lib_dir.join("libfoo.dylib"), "").unwrap();
+
+        let dylib = Dylib {
+            path: lib_dir.join("sub/libbar.dylib"),
+            id: Some(PathBuf::from("/build/upstream/lib/libbar.dylib")),
+            rpaths: vec![],
+            libraries: HashSet::from([
+                PathBuf::from("/build/upstream/lib/libfoo.dylib"),
+                PathBuf::from("/build/upstream/lib/libmissing.dylib"),
+                PathBuf::from("/usr/lib/libSystem.B.dylib"),
+                PathBuf::from("@rpath/libbaz.dylib"),
+            ]),
+            executable_path: None,
+        };
+
+        let (change_id, change_dylib) = dylib.rpath_install_names(&lib_dir);
+        assert_eq!(change_id, Some(PathBuf::from("@rpath/sub/libbar.dylib")));
+        assert_eq!(
+            change_dylib,
+            HashMap::from([(
+                PathBuf::from("/build/upstream/lib/libfoo.dylib"),
+                PathBuf::from("@rpath/libfoo.dylib")
+            )])
+        );
+
+        // relative ids are left alone
+        let dylib = Dylib {
+            id: Some(PathBuf::from("@rpath/libbar.dylib")),
+            ..dylib
+        };
+        assert_eq!(dylib.rpath_install_names(&lib_dir).0, None);
+    }
 }
@@ -17,7 +17,7 @@ use miette::IntoDiagnostic;
 use rattler_build::{
     build_recipes, bump_recipe,
     console_utils::init_logging,
-    debug_recipe, extract_package, get_recipe_path,
+    debug_recipe, extract_package, get_recipe_path, list_rpaths,
     opt::{
         App, BuildData, BumpRecipeOpts, DebugData, DebugShellOpts, PackageCommands, PublishData,
         RebuildData, ShellCompletion, SubCommands, TestData,
@@ -438,6 +438,7 @@ async fn async_main() -> miette::Result<()> {
             PackageCommands::Inspect(opts) => show_package_info(opts),
             PackageCommands::Extract(opts) => extract_package(opts).await,
         },
+        Some(SubCommands::ListRpaths(opts)) => list_rpaths(opts),
         Some(SubCommands::BumpRecipe(opts)) => run_bump_recipe(opts).await,
         None => {
             _ = App::command().print_long_help();
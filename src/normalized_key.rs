@@ -1,21 +1,166 @@
 use rattler_conda_types::PackageName;
 use serde::{Deserialize, Serialize};
-use std::hash::Hash;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, btree_map::Entry},
+    hash::Hash,
+};
 
 /// A key in a variant configuration.
+///
+/// Keys are compared on their normalized form, where `-`, `_` and `.` are
+/// treated the same. Comparisons are case-sensitive unless case folding is
+/// enabled explicitly with [`NormalizedKey::with_case_folding`]. Keys should
+/// only be compared with keys that use the same mode.
+///
+/// Keys that must stay verbatim (e.g. environment variables such as
+/// `PKG_CONFIG_PATH`) can be created with [`NormalizedKey::verbatim`]. They are
+/// not normalized at all and only ever equal to other verbatim keys with the
+/// exact same spelling, so they never collide with normalized keys.
+///
+/// Equality, ordering and hashing only look at the normalized form, so
+/// `my-key` and `my_key` are the same key even though they were spelled
+/// differently. Which of the two spellings survives in a set or map is the
+/// one that was inserted first. The original spelling is kept for messages:
+/// the [`Display`](std::fmt::Display) implementation shows it, while
+/// [`NormalizedKey::normalize`] (and serialization) use the normalized form.
 #[derive(Debug, Clone, Deserialize)]
-pub struct NormalizedKey(pub String);
+#[serde(from = "String")]
+pub struct NormalizedKey(pub String, Normalization);
+
+/// How the original spelling of a [`NormalizedKey`] is normalized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Normalization {
+    /// `-`, `_` and `.` are treated the same.
+    #[default]
+    Separators,
+    /// Like `Separators`, but the case is folded as well.
+    CaseInsensitive,
+    /// The key is used as is.
+    Verbatim,
+}
 
 impl NormalizedKey {
+    /// Creates a key that is compared case-insensitively (e.g. `Python` and
+    /// `python` are the same key).
+    pub fn case_insensitive(key: impl Into<String>) -> Self {
+        NormalizedKey(key.into(), Normalization::CaseInsensitive)
+    }
+
+    /// Creates a key that is used exactly as spelled, without any normalization.
+    pub fn verbatim(key: impl Into<String>) -> Self {
+        NormalizedKey(key.into(), Normalization::Verbatim)
+    }
+
+    /// Enables case folding, so that the key is compared case-insensitively.
+    pub fn with_case_folding(self) -> Self {
+        NormalizedKey(self.0, Normalization::CaseInsensitive)
+    }
+
+    /// Returns true if the key is compared case-insensitively.
+    pub fn is_case_insensitive(&self) -> bool {
+        self.1 == Normalization::CaseInsensitive
+    }
+
+    /// Returns true if the key is used verbatim, without normalization.
+    pub fn is_verbatim(&self) -> bool {
+        self.1 == Normalization::Verbatim
+    }
+
+    /// Returns the key as it was originally spelled.
+    pub fn original(&self) -> &str {
+        &self.0
+    }
+
     /// Returns the normalized form of the key.
     pub fn normalize(&self) -> String {
-        self.0
+        if self.is_verbatim() {
+            return self.0.clone();
+        }
+
+        let normalized: String = self
+            .0
             .chars()
             .map(|c| match c {
                 '-' | '_' | '.' => '_',
                 x => x,
             })
-            .collect()
+            .collect();
+        if self.is_case_insensitive() {
+            normalized.to_lowercase()
+        } else {
+            normalized
+        }
+    }
+
+    /// Builds a set of keys from raw strings.
+    ///
+    /// Besides the set, the keys that were dropped because an equal key (after
+    /// normalization) was already part of the set are returned in order, so
+    /// that callers can report them. The first spelling of a key is kept.
+    pub fn set_from<I, S>(keys: I) -> (HashSet<NormalizedKey>, Vec<NormalizedKey>)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<NormalizedKey>,
+    {
+        let mut set = HashSet::new();
+        let mut duplicates = Vec::new();
+        for key in keys {
+            let key = key.into();
+            if !set.contains(&key) {
+                set.insert(key);
+            } else {
+                duplicates.push(key);
+            }
+        }
+        (set, duplicates)
+    }
+
+    /// Builds a map from raw string keys and their values.
+    ///
+    /// Like [`NormalizedKey::set_from`], the first entry of a key is kept and
+    /// the keys of the dropped entries are returned in order.
+    pub fn map_from<I, S, V>(entries: I) -> (BTreeMap<NormalizedKey, V>, Vec<NormalizedKey>)
+    where
+        I: IntoIterator<Item = (S, V)>,
+        S: Into<NormalizedKey>,
+    {
+        let mut map = BTreeMap::new();
+        let mut duplicates = Vec::new();
+        for (key, value) in entries {
+            let key = key.into();
+            match map.entry(key.clone()) {
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+                Entry::Occupied(_) => duplicates.push(key),
+            }
+        }
+        (map, duplicates)
+    }
+
+    /// Returns the canonical key for this key if it is an alias in the given
+    /// table, otherwise the key itself.
+    pub fn resolve_alias(&self, table: &KeyAliases) -> NormalizedKey {
+        table.0.get(self).unwrap_or(self).clone()
+    }
+}
+
+/// A table of aliases for variant keys (e.g. `numpy_version` for `numpy`).
+///
+/// Aliases are opt-in, keys are only treated as the same key when the alias is
+/// explicitly added to the table. Aliases are not resolved transitively.
+#[derive(Debug, Clone, Default)]
+pub struct KeyAliases(HashMap<NormalizedKey, NormalizedKey>);
+
+impl KeyAliases {
+    /// Adds an alias that resolves to the `canonical` key.
+    pub fn with_alias(
+        mut self,
+        alias: impl Into<NormalizedKey>,
+        canonical: impl Into<NormalizedKey>,
+    ) -> Self {
+        self.0.insert(alias.into(), canonical.into());
+        self
     }
 }
 
@@ -28,15 +173,22 @@ impl Serialize for NormalizedKey {
     }
 }
 
+impl std::fmt::Display for NormalizedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl Hash for NormalizedKey {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.is_verbatim().hash(state);
         self.normalize().hash(state)
     }
 }
 
 impl PartialEq for NormalizedKey {
     fn eq(&self, other: &Self) -> bool {
-        self.normalize() == other.normalize()
+        self.is_verbatim() == other.is_verbatim() && self.normalize() == other.normalize()
     }
 }
 
@@ -50,20 +202,22 @@ impl PartialOrd for NormalizedKey {
 
 impl Ord for NormalizedKey {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.normalize().cmp(&other.normalize())
+        self.normalize()
+            .cmp(&other.normalize())
+            .then_with(|| self.is_verbatim().cmp(&other.is_verbatim()))
     }
 }
 
 // For convenience, implement From<String> and From<&str>
 impl From<String> for NormalizedKey {
     fn from(s: String) -> Self {
-        NormalizedKey(s)
+        NormalizedKey(s, Normalization::default())
     }
 }
 
 impl From<&str> for NormalizedKey {
     fn from(s: &str) -> Self {
-        NormalizedKey(s.to_string())
+        NormalizedKey(s.to_string(), Normalization::default())
     }
 }
 
@@ -72,3 +226,135 @@ impl From<&PackageName> for NormalizedKey {
         p.as_normalized().into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_equality() {
+        let a = NormalizedKey::from("foo-bar");
+        let b = NormalizedKey::from("foo_bar");
+        let c = NormalizedKey::from("foo.bar");
+        assert_eq!(a, b);
+        assert_eq!(b, c);
+
+        // comparisons are case-sensitive by default
+        assert_ne!(NormalizedKey::from("Python"), NormalizedKey::from("python"));
+        assert_eq!(NormalizedKey::from("Python").normalize(), "Python");
+    }
+
+    #[test]
+    fn test_equality_case_insensitive() {
+        let a = NormalizedKey::case_insensitive("Foo-Bar");
+        let b = NormalizedKey::case_insensitive("foo_bar");
+        let c = NormalizedKey::from("FOO.BAR").with_case_folding();
+        assert_eq!(a, b);
+        assert_eq!(b, c);
+        assert!(c.is_case_insensitive());
+        assert_eq!(a.normalize(), "foo_bar");
+        // the original spelling is kept
+        assert_eq!(a.0, "Foo-Bar");
+
+        let python = NormalizedKey::case_insensitive("Python");
+        assert_eq!(python, NormalizedKey::case_insensitive("python"));
+        assert_ne!(python, NormalizedKey::case_insensitive("python3"));
+
+        // hashing is consistent with the comparison
+        let keys = HashSet::from([python, NormalizedKey::case_insensitive("PYTHON")]);
+        assert_eq!(keys.len(), 1);
+    }
+
+    #[test]
+    fn test_original_spelling() {
+        let key = NormalizedKey::from("my-key");
+        assert_eq!(key.original(), "my-key");
+        assert_eq!(key.normalize(), "my_key");
+        assert_eq!(
+            format!("unused variant key `{key}`"),
+            "unused variant key `my-key`"
+        );
+
+        // the first inserted spelling is kept
+        let mut keys = HashSet::new();
+        keys.insert(NormalizedKey::from("my-key"));
+        assert!(!keys.insert(NormalizedKey::from("my_key")));
+        assert_eq!(keys.iter().next().unwrap().to_string(), "my-key");
+    }
+
+    #[test]
+    fn test_verbatim() {
+        let key = NormalizedKey::verbatim("PKG_CONFIG.PATH");
+        assert!(key.is_verbatim());
+        assert_eq!(key.normalize(), "PKG_CONFIG.PATH");
+        assert_eq!(key, NormalizedKey::verbatim("PKG_CONFIG.PATH"));
+        assert_ne!(key, NormalizedKey::verbatim("PKG_CONFIG_PATH"));
+        assert_ne!(key, NormalizedKey::verbatim("pkg_config.path"));
+
+        // verbatim keys never collide with normalized keys, even if the
+        // normalized form is spelled the same
+        assert_ne!(key, NormalizedKey::from("PKG_CONFIG.PATH"));
+        assert_ne!(
+            NormalizedKey::verbatim("PKG_CONFIG_PATH"),
+            NormalizedKey::from("PKG-CONFIG-PATH")
+        );
+        let (set, duplicates) = NormalizedKey::set_from([
+            NormalizedKey::verbatim("PKG_CONFIG_PATH"),
+            NormalizedKey::from("PKG_CONFIG_PATH"),
+            NormalizedKey::from("PKG.CONFIG.PATH"),
+        ]);
+        assert_eq!(set.len(), 2);
+        assert_eq!(duplicates.len(), 1);
+        assert!(!duplicates[0].is_verbatim());
+    }
+
+    #[test]
+    fn test_set_from() {
+        let (set, duplicates) = NormalizedKey::set_from(["python", "my-key", "my_key", "numpy"]);
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&NormalizedKey::from("my.key")));
+        assert_eq!(
+            duplicates.iter().map(|k| k.original()).collect::<Vec<_>>(),
+            ["my_key"]
+        );
+
+        let (set, duplicates) = NormalizedKey::set_from(Vec::<String>::new());
+        assert!(set.is_empty() && duplicates.is_empty());
+    }
+
+    #[test]
+    fn test_map_from() {
+        let (map, duplicates) =
+            NormalizedKey::map_from([("my-key", 1), ("python", 2), ("my.key", 3)]);
+        assert_eq!(map.len(), 2);
+        // the first entry is kept
+        assert_eq!(map[&NormalizedKey::from("my_key")], 1);
+        assert_eq!(map.keys().next().unwrap().original(), "my-key");
+        assert_eq!(
+            duplicates.iter().map(|k| k.original()).collect::<Vec<_>>(),
+            ["my.key"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_alias() {
+        let aliases = KeyAliases::default().with_alias("numpy_version", "numpy");
+
+        let numpy = NormalizedKey::from("numpy");
+        let numpy_version = NormalizedKey::from("numpy-version");
+        assert_ne!(numpy, numpy_version);
+        assert_eq!(
+            numpy.resolve_alias(&aliases),
+            numpy_version.resolve_alias(&aliases)
+        );
+        assert_eq!(numpy_version.resolve_alias(&aliases).0, "numpy");
+
+        // keys without an alias resolve to themselves
+        let python = NormalizedKey::from("python");
+        assert_eq!(python.resolve_alias(&aliases), python);
+        assert_eq!(
+            numpy_version.resolve_alias(&KeyAliases::default()),
+            numpy_version
+        );
+    }
+}
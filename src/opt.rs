@@ -8,7 +8,8 @@ use clap_complete::{Generator, shells};
 use clap_complete_nushell::Nushell;
 use clap_verbosity_flag::{InfoLevel, Verbosity};
 use rattler_conda_types::{
-    NamedChannelOrUrl, Platform, compression_level::CompressionLevel, package::ArchiveType,
+    NamedChannelOrUrl, PackageName, Platform, compression_level::CompressionLevel,
+    package::ArchiveType,
 };
 use rattler_config::config::ConfigBase;
 use rattler_config::config::build::PackageFormatAndCompression;
@@ -86,6 +87,9 @@ pub enum SubCommands {
     #[command(subcommand)]
     Package(PackageCommands),
 
+    /// Show the rpaths and shared library dependencies of a binary
+    ListRpaths(ListRpathsOpts),
+
     /// Bump a recipe to a new version
     ///
     /// This command updates the version and SHA256 checksum(s) in a recipe file.
@@ -262,7 +266,9 @@ pub struct CommonOpts {
     #[clap(long, env = "RATTLER_AUTH_FILE", hide = true)]
     pub auth_file: Option<PathBuf>,
 
-    /// Channel priority to use when solving
+    /// Channel priority to use when solving. With `strict` (the default) a
+    /// package is only taken from the first channel that provides it, with
+    /// `disabled` the highest version wins regardless of the channel
     #[arg(long)]
     pub channel_priority: Option<ChannelPriorityWrapper>,
 }
@@ -547,6 +553,21 @@ pub struct BuildOpts {
     /// Override the build number for all outputs (defaults to the build number in the recipe)
     #[arg(long, help_heading = "Modifying result")]
     pub build_num: Option<u64>,
+
+    /// The signing identity to re-sign macOS binaries with after relinking
+    /// (defaults to ad-hoc signing)
+    #[arg(long, help_heading = "Modifying result")]
+    pub codesign_identity: Option<String>,
+
+    /// Explain after each solve which requirements bounded the selected
+    /// version of the given packages
+    #[arg(long, value_delimiter = ',')]
+    pub explain: Vec<PackageName>,
+
+    /// Show a single progress bar for all repodata downloads instead of one
+    /// bar per download
+    #[arg(long)]
+    pub overall_download_progress: bool,
 }
 
 /// Publish options for the `publish` command.
@@ -703,6 +724,9 @@ pub struct BuildData {
     pub allow_absolute_license_paths: bool,
     pub exclude_newer: Option<chrono::DateTime<chrono::Utc>>,
     pub build_num_override: Option<u64>,
+    pub codesign_identity: Option<String>,
+    pub explain: Vec<PackageName>,
+    pub overall_download_progress: bool,
 }
 
 impl BuildData {
@@ -739,6 +763,9 @@ impl BuildData {
         allow_absolute_license_paths: bool,
         exclude_newer: Option<chrono::DateTime<chrono::Utc>>,
         build_num_override: Option<u64>,
+        codesign_identity: Option<String>,
+        explain: Vec<PackageName>,
+        overall_download_progress: bool,
     ) -> Self {
         Self {
             up_to,
@@ -779,6 +806,9 @@ impl BuildData {
             allow_absolute_license_paths,
             exclude_newer,
             build_num_override,
+            codesign_identity,
+            explain,
+            overall_download_progress,
         }
     }
 }
@@ -830,6 +860,9 @@ impl BuildData {
             opts.allow_absolute_license_paths,
             opts.exclude_newer,
             opts.build_num,
+            opts.codesign_identity,
+            opts.explain,
+            opts.overall_download_progress,
         )
     }
 }
@@ -1214,6 +1247,22 @@ pub struct ExtractOpts {
     pub dest: Option<PathBuf>,
 }
 
+/// Options for the `list-rpaths` command.
+#[derive(Parser, Debug, Clone)]
+pub struct ListRpathsOpts {
+    /// Path to the binary (ELF, Mach-O or PE file)
+    pub binary: PathBuf,
+
+    /// The platform of the binary (defaults to the current platform)
+    #[arg(long)]
+    pub platform: Option<Platform>,
+
+    /// The prefix to resolve relative rpaths and libraries in (defaults to the
+    /// directory of the binary)
+    #[arg(long)]
+    pub prefix: Option<PathBuf>,
+}
+
 /// Options for the `bump-recipe` command.
 #[derive(Parser, Debug, Clone)]
 pub struct BumpRecipeOpts {
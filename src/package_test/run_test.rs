@@ -433,6 +433,7 @@ pub async fn run_test(
             config.channel_priority,
             config.solve_strategy,
             config.exclude_newer,
+            None,
         )
         .await
         .map_err(TestError::TestEnvironmentSetup)?;
@@ -623,6 +624,7 @@ impl PythonTest {
             config.channel_priority,
             config.solve_strategy,
             config.exclude_newer,
+            None,
         )
         .await
         .map_err(TestError::TestEnvironmentSetup)?;
@@ -721,6 +723,7 @@ impl PerlTest {
             config.channel_priority,
             config.solve_strategy,
             config.exclude_newer,
+            None,
         )
         .await
         .map_err(TestError::TestEnvironmentSetup)?;
@@ -796,6 +799,7 @@ impl CommandsTest {
                 config.channel_priority,
                 config.solve_strategy,
                 config.exclude_newer,
+                None,
             )
             .await
             .map_err(TestError::TestEnvironmentSetup)?;
@@ -832,6 +836,7 @@ impl CommandsTest {
             config.channel_priority,
             config.solve_strategy,
             config.exclude_newer,
+            None,
         )
         .await
         .map_err(TestError::TestEnvironmentSetup)?;
@@ -913,6 +918,7 @@ impl DownstreamTest {
             config.channel_priority,
             config.solve_strategy,
             config.exclude_newer,
+            None,
         )
         .await;
 
@@ -1004,6 +1010,7 @@ impl RTest {
             config.channel_priority,
             config.solve_strategy,
             config.exclude_newer,
+            None,
         )
         .await
         .map_err(TestError::TestEnvironmentSetup)?;
@@ -1077,6 +1084,7 @@ impl RubyTest {
             config.channel_priority,
             config.solve_strategy,
             config.exclude_newer,
+            None,
         )
         .await
         .map_err(TestError::TestEnvironmentSetup)?;
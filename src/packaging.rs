@@ -694,7 +694,7 @@ pub fn package_conda(
 
     tracing::info!("Copying done!");
 
-    post_process::relink::relink(&tmp, output)?;
+    post_process::relink::relink(&tmp, output, tool_configuration)?;
 
     post_process::menuinst::menuinst(&tmp)?;
 
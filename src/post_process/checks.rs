@@ -6,17 +6,19 @@ use std::{
 };
 
 use crate::{
-    metadata::Output,
-    post_process::{package_nature::PrefixInfo, relink::RelinkError},
+    macos::link::{find_executables, resolve_with_executables},
+    post_process::{package_nature::PackageNature, relink::RelinkerCache},
+    windows::link::WIN_ALLOWLIST,
 };
 use crate::{
-    post_process::{package_nature::PackageNature, relink},
-    windows::link::WIN_ALLOWLIST,
+    metadata::Output,
+    post_process::{package_nature::PrefixInfo, relink::RelinkError},
 };
 
 use crate::render::resolved_dependencies::RunExportDependency;
-use globset::{Glob, GlobSet, GlobSetBuilder};
-use rattler_conda_types::{PackageName, PrefixRecord};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use itertools::Itertools;
+use rattler_conda_types::{PackageName, Platform, PrefixRecord};
 use text_stub_library::TbdVersionedRecord;
 use walkdir::WalkDir;
 
@@ -29,18 +31,63 @@ pub enum LinkingCheckError {
     SharedObject(String),
 
     #[error("Overlinking against: {package} (file: {file:?})")]
-    Overlinking { package: PathBuf, file: PathBuf },
+    Overlinking {
+        package: PathBuf,
+        file: PathBuf,
+        searched: Vec<PathBuf>,
+    },
 
     #[error("Overdepending against: {package}")]
     Overdepending { package: PathBuf },
 
+    #[error("Absolute rpath into the build environment: {rpath:?} (file: {file:?})")]
+    AbsoluteRpath { rpath: PathBuf, file: PathBuf },
+
     #[error("failed to build glob from pattern")]
     GlobError(#[from] globset::Error),
+
+    #[error("{} linking checks failed:\n{}", .0.len(), linking_failures_report(.0))]
+    Multiple(Vec<LinkingCheckError>),
+}
+
+/// Renders the overlinking failures as a table of binary, missing library and
+/// the searched rpaths, followed by the unused run dependencies.
+fn linking_failures_report(failures: &[LinkingCheckError]) -> String {
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(comfy_table::presets::UTF8_FULL_CONDENSED)
+        .apply_modifier(comfy_table::modifiers::UTF8_ROUND_CORNERS)
+        .set_header(["Binary", "Missing library", "Searched paths"]);
+
+    let mut report = Vec::new();
+    for failure in failures {
+        match failure {
+            LinkingCheckError::Overlinking {
+                package,
+                file,
+                searched,
+            } => {
+                table.add_row([
+                    file.display().to_string(),
+                    package.display().to_string(),
+                    searched.iter().map(|path| path.display()).join("\n"),
+                ]);
+            }
+            other => report.push(other.to_string()),
+        }
+    }
+
+    if !table.is_empty() {
+        report.insert(0, table.to_string());
+    }
+    report.join("\n")
 }
 
 #[derive(Debug)]
 struct PackageFile {
     pub file: PathBuf,
+    /// The rpaths of the file, resolved relative to the host prefix.
+    pub searched_paths: Vec<PathBuf>,
     pub linked_dsos: HashMap<PathBuf, PackageName>,
     pub shared_libraries: HashSet<PathBuf>,
 }
@@ -224,7 +271,7 @@ fn add_osx_system_libs(
 
 fn add_windows_system_libs(system_libs: &mut GlobSetBuilder) -> Result<(), globset::Error> {
     for pattern in WIN_ALLOWLIST {
-        system_libs.add(Glob::new(pattern)?);
+        system_libs.add(GlobBuilder::new(pattern).case_insensitive(true).build()?);
     }
     Ok(())
 }
@@ -292,6 +339,7 @@ pub fn perform_linking_checks(
     output: &Output,
     new_files: &HashSet<PathBuf>,
     tmp_prefix: &Path,
+    relinker_cache: &RelinkerCache,
 ) -> Result<(), LinkingCheckError> {
     let dynamic_linking = output.recipe.build().dynamic_linking();
     let system_libs = find_system_libs(output)?;
@@ -306,16 +354,39 @@ pub fn perform_linking_checks(
     let target_platform = output.target_platform();
     let host_prefix = output.prefix();
 
+    // Libraries can refer to the executable that loads them with
+    // `@executable_path`, which is resolved against the executables of the
+    // package.
+    let executables = if output.build_configuration.target_platform.is_osx() {
+        find_executables(new_files)
+    } else {
+        Vec::new()
+    };
+
     // Parallel processing of DSO files
     let package_files: Vec<PackageFile> = new_files
         .par_iter()
         .filter_map(|file| {
             // Parse the DSO to get the list of libraries it links to
-            match relink::get_relinker(output.build_configuration.target_platform, file) {
+            match relinker_cache.get_relinker(output.build_configuration.target_platform, file) {
                 Ok(relinker) => {
                     let mut file_dsos = Vec::new();
 
-                    let resolved_libraries = relinker.resolve_libraries(tmp_prefix, host_prefix);
+                    let mut resolved_libraries =
+                        relinker.resolve_libraries(tmp_prefix, host_prefix);
+                    if let Err(e) = resolve_with_executables(
+                        file,
+                        &mut resolved_libraries,
+                        &executables,
+                        tmp_prefix,
+                        host_prefix,
+                    ) {
+                        tracing::warn!(
+                            "Failed to resolve @executable_path of {}: {}",
+                            file.display(),
+                            e
+                        );
+                    }
                     for (lib, resolved) in &resolved_libraries {
                         // filter out @self on macOS
                         if target_platform.is_osx() && lib.to_str() == Some("self") {
@@ -336,12 +407,25 @@ pub fn perform_linking_checks(
                         }
                     }
 
+                    let searched_paths = relinker
+                        .rpaths()
+                        .iter()
+                        .map(|rpath| {
+                            let resolved = relinker.resolve_rpath(rpath, tmp_prefix, host_prefix);
+                            match resolved.strip_prefix(host_prefix) {
+                                Ok(rel) => Path::new("$PREFIX").join(rel),
+                                Err(_) => resolved,
+                            }
+                        })
+                        .collect();
+
                     Some(PackageFile {
                         file: file
                             .clone()
                             .strip_prefix(tmp_prefix)
                             .unwrap_or(file)
                             .to_path_buf(),
+                        searched_paths,
                         linked_dsos: file_dsos.into_iter().collect(),
                         shared_libraries: resolved_libraries
                             .into_iter()
@@ -359,6 +443,8 @@ pub fn perform_linking_checks(
         .collect();
     tracing::trace!("Package files: {package_files:#?}");
 
+    // collect all failures to report them at once
+    let mut failures = Vec::new();
     let mut linked_packages = Vec::new();
     for package in package_files.iter() {
         let mut link_info = PackageLinkInfo {
@@ -411,18 +497,10 @@ pub fn perform_linking_checks(
                 );
             // Error on overlinking.
             } else if dynamic_linking.error_on_overlinking() {
-                link_info.linked_packages.push(LinkedPackage {
-                    name: lib.to_path_buf(),
-                    link_origin: LinkOrigin::NotFound,
-                });
-                linked_packages.push(link_info);
-                linked_packages.iter().for_each(|linked_package| {
-                    tracing::info!("\n{linked_package}");
-                });
-
-                return Err(LinkingCheckError::Overlinking {
+                failures.push(LinkingCheckError::Overlinking {
                     package: lib.to_path_buf(),
                     file: package.file.clone(),
+                    searched: package.searched_paths.clone(),
                 });
             } else {
                 let warn_str = format!("Overlinking against {lib:?} for {:?}", package.file);
@@ -456,21 +534,153 @@ pub fn perform_linking_checks(
             .any(|libraries| libraries.contains(run_dependency))
         {
             if dynamic_linking.error_on_overdepending() {
-                return Err(LinkingCheckError::Overdepending {
+                failures.push(LinkingCheckError::Overdepending {
                     package: PathBuf::from(run_dependency),
                 });
+            } else {
+                tracing::warn!("Overdepending against {run_dependency}");
+                output.record_warning(&format!("Overdepending against {run_dependency}"));
             }
-            tracing::warn!("Overdepending against {run_dependency}");
-            output.record_warning(&format!("Overdepending against {run_dependency}"));
         }
     }
-    Ok(())
+
+    match failures.len() {
+        0 => Ok(()),
+        1 => Err(failures.remove(0)),
+        _ => Err(LinkingCheckError::Multiple(failures)),
+    }
+}
+
+/// Returns the rpaths of the binaries that (still) point into one of the given
+/// prefixes, together with the binary they belong to.
+fn find_absolute_rpaths(
+    binaries: &HashSet<PathBuf>,
+    target_platform: Platform,
+    prefixes: &[&Path],
+    relinker_cache: &RelinkerCache,
+) -> Vec<(PathBuf, PathBuf)> {
+    let mut absolute_rpaths = binaries
+        .par_iter()
+        .flat_map_iter(|binary| {
+            let rpaths = relinker_cache
+                .get_relinker(target_platform, binary)
+                .map(|relinker| relinker.rpaths())
+                .unwrap_or_default();
+            rpaths
+                .into_iter()
+                .filter(|rpath| prefixes.iter().any(|prefix| rpath.starts_with(prefix)))
+                .map(|rpath| (binary.clone(), rpath))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    absolute_rpaths.sort();
+    absolute_rpaths
+}
+
+/// Verifies that none of the relinked binaries still has an absolute rpath
+/// that points into the build environment, as that breaks relocation.
+/// Depending on the `absolute_rpath_behavior` this is a warning or an error.
+pub fn check_absolute_rpaths(
+    output: &Output,
+    binaries: &HashSet<PathBuf>,
+    tmp_prefix: &Path,
+    relinker_cache: &RelinkerCache,
+) -> Result<(), LinkingCheckError> {
+    let prefixes = [
+        output.build_configuration.directories.build_dir.as_path(),
+        tmp_prefix,
+    ];
+    let dynamic_linking = output.recipe.build().dynamic_linking();
+
+    let mut failures = Vec::new();
+    for (binary, rpath) in find_absolute_rpaths(
+        binaries,
+        output.build_configuration.target_platform,
+        &prefixes,
+        relinker_cache,
+    ) {
+        let file = binary
+            .strip_prefix(tmp_prefix)
+            .unwrap_or(&binary)
+            .to_path_buf();
+        if dynamic_linking.error_on_absolute_rpath() {
+            failures.push(LinkingCheckError::AbsoluteRpath { rpath, file });
+            continue;
+        }
+        let warn_str = format!(
+            "Absolute rpath {rpath:?} into the build environment remains in {file:?} after relinking"
+        );
+        tracing::warn!(warn_str);
+        output.record_warning(&warn_str);
+    }
+
+    match failures.len() {
+        0 => Ok(()),
+        1 => Err(failures.remove(0)),
+        _ => Err(LinkingCheckError::Multiple(failures)),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_multiple_linking_failures() {
+        let overlinking = |file: &str, package: &str| LinkingCheckError::Overlinking {
+            package: PathBuf::from(package),
+            file: PathBuf::from(file),
+            searched: vec![PathBuf::from("$PREFIX/lib"), PathBuf::from("/opt/lib")],
+        };
+        let error = LinkingCheckError::Multiple(vec![
+            overlinking("bin/foo", "libz.so.1"),
+            overlinking("lib/libfoo.so", "libssl.so.3"),
+            LinkingCheckError::Overdepending {
+                package: PathBuf::from("openssl"),
+            },
+        ]);
+        insta::assert_snapshot!(error.to_string());
+    }
+
+    #[test]
+    fn test_find_absolute_rpaths() {
+        let binary = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files/zlink");
+        let binaries = HashSet::from([binary.clone()]);
+        let relinker_cache = RelinkerCache::default();
+
+        // the binary was never relinked, so it still points into its build environment
+        let absolute_rpaths = find_absolute_rpaths(
+            &binaries,
+            Platform::Linux64,
+            &[Path::new("/rattler-build_zlink")],
+            &relinker_cache,
+        );
+        assert_eq!(
+            absolute_rpaths,
+            vec![
+                (
+                    binary.clone(),
+                    PathBuf::from("/rattler-build_zlink/build_env/lib")
+                ),
+                (
+                    binary.clone(),
+                    PathBuf::from("/rattler-build_zlink/host_env_placehold/lib")
+                ),
+            ]
+        );
+
+        // rpaths outside of the build environment are fine
+        assert!(
+            find_absolute_rpaths(
+                &binaries,
+                Platform::Linux64,
+                &[Path::new("/other")],
+                &relinker_cache
+            )
+            .is_empty()
+        );
+    }
+
     #[test]
     fn test_extract_tbd_install_names() {
         let test_sysroot = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/tbd_files");
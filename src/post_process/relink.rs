@@ -1,19 +1,29 @@
 use fs_err as fs;
 
-use crate::packaging::TempFiles;
-
 use crate::linux::link::SharedObject;
 use crate::macos::link::Dylib;
 use crate::metadata::Output;
-use crate::recipe::parser::GlobVec;
-use crate::system_tools::{SystemTools, ToolError};
+use crate::packaging::{TempFiles, content_type as content_type_of};
+use crate::recipe::parser::{GlobOrRegexVec, GlobVec};
+use crate::system_tools::{SystemTools, Tool, ToolError};
+use crate::tool_configuration::Configuration;
+use crate::unix::permission_guard::{PermissionGuard, READ_WRITE};
 use crate::windows::link::Dll;
+use content_inspector::ContentType;
 use rattler_conda_types::{Arch, Platform};
+use rattler_shell::{
+    activation::{ActivationVariables, Activator},
+    shell::ShellEnum,
+};
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use thiserror::Error;
 
-use super::checks::{LinkingCheckError, perform_linking_checks};
+use super::checks::{LinkingCheckError, check_absolute_rpaths, perform_linking_checks};
 
 #[derive(Error, Debug)]
 #[allow(missing_docs)]
@@ -21,12 +31,15 @@ pub enum RelinkError {
     #[error("linking check error: {0}")]
     LinkingCheck(#[from] LinkingCheckError),
 
-    #[error("failed to run install_name_tool")]
-    InstallNameToolFailed,
+    #[error("failed to run install_name_tool (version: {version})")]
+    InstallNameToolFailed { version: String },
 
     #[error("Codesign failed")]
     CodesignFailed,
 
+    #[error("codesign verification failed for {path}: {reason}")]
+    CodesignVerificationFailed { path: PathBuf, reason: String },
+
     #[error(transparent)]
     SystemToolError(#[from] ToolError),
 
@@ -54,8 +67,11 @@ pub enum RelinkError {
     #[error("shared library has no parent directory")]
     NoParentDir,
 
-    #[error("failed to run patchelf")]
-    PatchElfFailed,
+    #[error("failed to run patchelf (version: {version})")]
+    PatchElfFailed { version: String },
+
+    #[error("failed to strip debug symbols")]
+    StripFailed,
 
     #[error("rpath not found in dynamic section")]
     RpathNotFound,
@@ -65,6 +81,12 @@ pub enum RelinkError {
 
     #[error("unknown file format for relinking")]
     UnknownFileFormat,
+
+    #[error("failed to relink {path}: {source}")]
+    RelinkFileFailed {
+        path: PathBuf,
+        source: Box<RelinkError>,
+    },
 }
 
 /// Platform specific relinker.
@@ -93,36 +115,596 @@ pub trait Relinker {
     /// Resolve the rpath with the path of the dylib.
     fn resolve_rpath(&self, rpath: &Path, prefix: &Path, encoded_prefix: &Path) -> PathBuf;
 
-    /// Relinks the file.
+    /// Returns the rpaths (and runpaths) that are currently encoded in the file.
+    fn rpaths(&self) -> Vec<PathBuf>;
+
+    /// Computes the rpaths the file will have after relinking with the given
+    /// options, without modifying it.
+    fn planned_rpaths(
+        &self,
+        prefix: &Path,
+        encoded_prefix: &Path,
+        options: &RelinkOptions,
+    ) -> Result<Vec<PathBuf>, RelinkError>;
+
+    /// Checks, without modifying the file, whether the file can be made
+    /// relocatable: every rpath has to resolve into the prefix, be on the
+    /// rpath allowlist or be one of the default rpaths of the `options`. The
+    /// rpaths that do not are returned, relinking would remove them.
+    fn can_relocate(
+        &self,
+        prefix: &Path,
+        encoded_prefix: &Path,
+        options: &RelinkOptions,
+    ) -> Vec<RelocationIssue> {
+        self.rpaths()
+            .into_iter()
+            .filter(|rpath| {
+                !options.default_rpaths.contains(rpath) && !options.rpath_allowlist.is_match(rpath)
+            })
+            .filter_map(|rpath| {
+                let resolved = self.resolve_rpath(&rpath, prefix, encoded_prefix);
+                let in_prefix =
+                    resolved.starts_with(encoded_prefix) || resolved.starts_with(prefix);
+                (!in_prefix).then_some(RelocationIssue { rpath, resolved })
+            })
+            .collect()
+    }
+
+    /// Relinks the file with the given options.
+    ///
+    /// Returns the rpaths that were written and the tool that modified the
+    /// file, or `None` if the file was left unchanged.
     fn relink(
         &self,
         prefix: &Path,
         encoded_prefix: &Path,
-        custom_rpaths: &[String],
-        rpath_allowlist: &GlobVec,
+        options: &RelinkOptions,
         system_tools: &SystemTools,
-    ) -> Result<(), RelinkError>;
+    ) -> Result<Option<AppliedRelink>, RelinkError>;
+}
+
+/// The options of the recipe (and the tool configuration) that control how
+/// binaries are relinked. Not every option applies to every binary format.
+#[derive(Debug, Clone, Default)]
+pub struct RelinkOptions {
+    /// The rpaths (relative to the prefix) that are added to the existing
+    /// ones.
+    pub custom_rpaths: Vec<String>,
+    /// The rpaths (relative to the prefix) that are placed in front of all
+    /// other rpaths, in the given order.
+    pub prepend_rpaths: Vec<String>,
+    /// The rpaths outside of the prefix that are kept.
+    pub rpath_allowlist: GlobOrRegexVec,
+    /// The default library paths of the system, which are removed from the
+    /// rpaths (see [`strip_default_rpaths`]).
+    pub default_rpaths: Vec<PathBuf>,
+    /// The identity that modified Mach-O files are re-signed with, ad-hoc if
+    /// `None`.
+    pub codesign_identity: Option<String>,
+    /// Whether absolute install names of Mach-O files are rewritten to
+    /// `@rpath` (see `DynamicLinking::rewrite_install_names`).
+    pub rewrite_install_names: bool,
+}
+
+/// The tool that was used to modify a binary while relinking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelinkTool {
+    /// The built-in relinker of rattler-build.
+    Builtin,
+    /// `patchelf` (Linux).
+    Patchelf,
+    /// `install_name_tool` (macOS).
+    InstallNameTool,
+}
+
+/// The rpaths that [`Relinker::relink`] wrote into a binary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedRelink {
+    /// The rpaths of the binary after relinking.
+    pub rpaths: Vec<PathBuf>,
+    /// The tool that modified the binary.
+    pub tool: RelinkTool,
+}
+
+/// The rpath changes `relink` made to a single binary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RelinkChange {
+    /// The path of the binary, relative to the prefix.
+    pub path: PathBuf,
+    /// The rpaths before relinking.
+    pub old_rpaths: Vec<PathBuf>,
+    /// The rpaths after relinking.
+    pub new_rpaths: Vec<PathBuf>,
+    /// The tool that modified the binary.
+    pub tool: RelinkTool,
+}
+
+/// A record of all binaries that were modified by [`relink`], sorted by path.
+/// It can be serialized (e.g. into the `info/` metadata of a package) to audit
+/// the relocation of a package.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RelinkReport {
+    /// The changes per binary.
+    pub changes: Vec<RelinkChange>,
+}
+
+/// An rpath that prevents a binary from being relocated, see
+/// [`Relinker::can_relocate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelocationIssue {
+    /// The rpath as it is encoded in the binary.
+    pub rpath: PathBuf,
+    /// The path the rpath resolves to.
+    pub resolved: PathBuf,
+}
+
+impl std::fmt::Display for RelocationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.rpath == self.resolved {
+            write!(
+                f,
+                "rpath {} points outside of the prefix",
+                self.rpath.display()
+            )
+        } else {
+            write!(
+                f,
+                "rpath {} ({}) points outside of the prefix",
+                self.rpath.display(),
+                self.resolved.display()
+            )
+        }
+    }
+}
+
+/// Removes the rpaths that point to one of the default library search paths
+/// (e.g. `/usr/lib`). These are searched by the loader anyways.
+pub(crate) fn strip_default_rpaths(
+    rpaths: Vec<PathBuf>,
+    default_rpaths: &[PathBuf],
+    path: &Path,
+) -> Vec<PathBuf> {
+    rpaths
+        .into_iter()
+        .filter(|rpath| {
+            let is_default = default_rpaths.contains(rpath);
+            if is_default {
+                tracing::info!(
+                    "Removing rpath {} from {} as it is a default library search path",
+                    rpath.display(),
+                    path.display()
+                );
+            }
+            !is_default
+        })
+        .collect()
+}
+
+/// Returns true if the file is a binary that can be relinked for the given target
+/// platform (an ELF file on Linux, a Mach-O file on macOS or a PE file on Windows).
+///
+/// Returns [`RelinkError::UnknownPlatform`] if relinking is not supported for the
+/// target platform at all (e.g. `emscripten-wasm32`), so that callers can tell an
+/// unsupported platform apart from a file that is not a binary.
+pub fn is_valid_file(platform: Platform, path: &Path) -> Result<bool, RelinkError> {
+    if platform.is_linux() {
+        SharedObject::test_file(path)
+    } else if platform.is_osx() {
+        Dylib::test_file(path)
+    } else if platform.is_windows() {
+        Dll::test_file(path)
+    } else {
+        Err(RelinkError::UnknownPlatform)
+    }
 }
 
 /// Returns the relink helper for the current platform.
-pub fn get_relinker(platform: Platform, path: &Path) -> Result<Box<dyn Relinker>, RelinkError> {
+///
+/// Returns [`RelinkError::UnknownFileFormat`] if the file is not a binary (see
+/// [`is_valid_file`]) and [`RelinkError::UnknownPlatform`] if the platform is not
+/// supported.
+pub fn get_relinker(
+    platform: Platform,
+    path: &Path,
+) -> Result<Box<dyn Relinker + Send + Sync>, RelinkError> {
+    if !is_valid_file(platform, path)? {
+        return Err(RelinkError::UnknownFileFormat);
+    }
+
     if platform.is_linux() {
-        if !SharedObject::test_file(path)? {
-            return Err(RelinkError::UnknownFileFormat);
-        }
         Ok(Box::new(SharedObject::new(path)?))
     } else if platform.is_osx() {
-        if !Dylib::test_file(path)? {
-            return Err(RelinkError::UnknownFileFormat);
-        }
         Ok(Box::new(Dylib::new(path)?))
-    } else if platform.is_windows() {
-        match Dll::try_new(path)? {
-            Some(dll) => Ok(Box::new(dll)),
-            None => Err(RelinkError::UnknownFileFormat),
-        }
     } else {
-        Err(RelinkError::UnknownPlatform)
+        Ok(Box::new(Dll::new(path)?))
+    }
+}
+
+/// A cache of parsed relinkers, so that the same binary is not parsed over and
+/// over again. It is meant to be scoped to a single `relink` call.
+///
+/// Entries are keyed by the path and are only used as long as the modification
+/// time and the size of the file are unchanged. A change that keeps both (e.g.
+/// a same-size rewrite within the timestamp granularity) is not noticed, so
+/// whoever modifies a binary has to [`RelinkerCache::evict`] it. Files with an
+/// unknown format are cached as well.
+#[derive(Default)]
+pub struct RelinkerCache {
+    entries: Mutex<HashMap<PathBuf, RelinkerCacheEntry>>,
+}
+
+type RelinkerCacheEntry = (SystemTime, u64, Option<Arc<dyn Relinker + Send + Sync>>);
+
+impl RelinkerCache {
+    /// Returns the (cached) relink helper for the given file, see [`get_relinker`].
+    pub fn get_relinker(
+        &self,
+        platform: Platform,
+        path: &Path,
+    ) -> Result<Arc<dyn Relinker + Send + Sync>, RelinkError> {
+        let metadata = fs::metadata(path)?;
+        let (modified, len) = (metadata.modified()?, metadata.len());
+
+        if let Some((_, _, relinker)) = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(path)
+            .filter(|(m, l, _)| *m == modified && *l == len)
+        {
+            return relinker.clone().ok_or(RelinkError::UnknownFileFormat);
+        }
+
+        let relinker = match get_relinker(platform, path) {
+            Ok(relinker) => Some(Arc::from(relinker)),
+            Err(RelinkError::UnknownFileFormat) => None,
+            Err(e) => return Err(e),
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), (modified, len, relinker.clone()));
+        relinker.ok_or(RelinkError::UnknownFileFormat)
+    }
+
+    /// Removes the cached relink helper of a file that was modified, so that
+    /// it is parsed again on the next access.
+    pub fn evict(&self, path: &Path) {
+        self.entries.lock().unwrap().remove(path);
+    }
+}
+
+/// The rpaths and shared library dependencies of a single binary.
+#[derive(Debug)]
+pub struct LinkInfo {
+    /// The rpaths (and runpaths) of the binary together with what they resolve to.
+    pub rpaths: Vec<(PathBuf, PathBuf)>,
+    /// The libraries the binary links against and where they were found (if at all).
+    pub libraries: Vec<(PathBuf, Option<PathBuf>)>,
+    /// The libraries that are provided by the system and not looked up.
+    pub system_libraries: Vec<PathBuf>,
+}
+
+/// Reads the rpaths and the linked libraries of a binary (ELF, Mach-O or PE)
+/// without modifying it. Relative entries (`$ORIGIN`, `@loader_path`, ...) are
+/// resolved as if the binary was installed into `prefix`.
+pub fn link_info(path: &Path, platform: Platform, prefix: &Path) -> Result<LinkInfo, RelinkError> {
+    // the relinkers expect the binary to live in the prefix
+    path.strip_prefix(prefix)?;
+
+    let relinker = get_relinker(platform, path)?;
+
+    let rpaths = relinker
+        .rpaths()
+        .into_iter()
+        .map(|rpath| {
+            let resolved = relinker.resolve_rpath(&rpath, prefix, prefix);
+            (rpath, resolved)
+        })
+        .collect();
+
+    // Mach-O files list themselves as `self`
+    let is_self = |library: &Path| platform.is_osx() && library == Path::new("self");
+
+    let mut libraries = relinker
+        .resolve_libraries(prefix, prefix)
+        .into_iter()
+        .filter(|(library, _)| !is_self(library))
+        .collect::<Vec<_>>();
+    libraries.sort();
+
+    // libraries that are not resolved at all (e.g. system DLLs on Windows)
+    let mut system_libraries = relinker
+        .libraries()
+        .into_iter()
+        .filter(|library| !is_self(library) && !libraries.iter().any(|(l, _)| l == library))
+        .collect::<Vec<_>>();
+    system_libraries.sort();
+
+    Ok(LinkInfo {
+        rpaths,
+        libraries,
+        system_libraries,
+    })
+}
+
+/// Finds the `strip` of the cross compilers in the build environment: the
+/// `$STRIP` that is set by activating the build prefix, looked up on the `PATH`
+/// of the activated environment.
+fn find_cross_strip(build_prefix: &Path, build_platform: Platform) -> Option<PathBuf> {
+    let variables = ActivationVariables::from_env().ok()?;
+    let activated = Activator::from_path(build_prefix, ShellEnum::default(), build_platform)
+        .and_then(|activator| activator.run_activation(variables, None));
+    let activated = match activated {
+        Ok(activated) => activated,
+        Err(e) => {
+            tracing::warn!("Failed to activate the build environment to find `$STRIP`: {e}");
+            return None;
+        }
+    };
+
+    let strip = activated.get("STRIP")?;
+    which::which_in(strip, activated.get("PATH"), build_prefix).ok()
+}
+
+/// Strips the debug symbols from an ELF or Mach-O binary with `strip -S`. Mach-O
+/// binaries are re-signed afterwards, as stripping invalidates the signature.
+///
+/// The `strip` of the build machine is used unless another one is given (e.g.
+/// the `strip` of the cross compilers, see [`find_cross_strip`]).
+fn strip_debug_symbols(
+    path: &Path,
+    platform: Platform,
+    strip: Option<&Path>,
+    codesign_identity: Option<&str>,
+    system_tools: &SystemTools,
+) -> Result<(), RelinkError> {
+    let size_before = fs::metadata(path)?.len();
+
+    {
+        let _permission_guard = PermissionGuard::new(path, READ_WRITE)?;
+
+        // `-S` only removes the debug symbols (`--strip-debug` for GNU strip)
+        let mut command = match strip {
+            Some(strip) => std::process::Command::new(strip),
+            None => system_tools.call(Tool::Strip)?,
+        };
+        let output = command.arg("-S").arg(path).output()?;
+        if !output.status.success() {
+            tracing::error!("strip failed: {}", String::from_utf8_lossy(&output.stderr));
+            return Err(RelinkError::StripFailed);
+        }
+    }
+
+    if platform.is_osx() {
+        crate::macos::link::codesign(path, codesign_identity, system_tools)?;
+    }
+
+    let size_after = fs::metadata(path)?.len();
+    tracing::debug!(
+        "Stripped debug symbols from {} ({} bytes saved)",
+        path.display(),
+        size_before.saturating_sub(size_after)
+    );
+    Ok(())
+}
+
+/// Identifies a file on disk independent of the path it is reached by.
+#[cfg(unix)]
+type FileId = (u64, u64);
+#[cfg(not(unix))]
+type FileId = PathBuf;
+
+#[cfg(unix)]
+fn file_id(path: &Path) -> Result<FileId, RelinkError> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(path)?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path) -> Result<FileId, RelinkError> {
+    Ok(dunce::canonicalize(path)?)
+}
+
+/// Collects the files that should be considered for relinking, together with
+/// their content type.
+///
+/// Symlinks are never relinked themselves. Instead, their target is relinked
+/// if it is a regular file inside of the prefix (it might not be part of the
+/// content type map, e.g. when it is only reachable through the symlink).
+///
+/// Every file is returned only once: files are deduplicated by their identity
+/// on disk (device and inode on Unix, the canonical path elsewhere), so a
+/// binary that is reachable through several symlinks or hardlinks is not
+/// relinked multiple times.
+fn relink_candidates(
+    content_type_map: &HashMap<PathBuf, Option<ContentType>>,
+    tmp_prefix: &Path,
+) -> Result<Vec<(PathBuf, Option<ContentType>)>, RelinkError> {
+    // the temporary directory itself might be behind a symlink (e.g. on macOS)
+    let canonical_prefix = dunce::canonicalize(tmp_prefix)?;
+
+    // sort to deterministically relink the same path of a hardlinked file
+    let mut entries = content_type_map.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+    for (path, content_type) in entries {
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.is_dir() {
+            continue;
+        }
+
+        let (path, content_type) = if metadata.is_symlink() {
+            let target = match dunce::canonicalize(path) {
+                Ok(target) if target.is_file() => target,
+                _ => {
+                    tracing::debug!(
+                        "Relink skipping symlink to a directory or a missing file: {}",
+                        path.display()
+                    );
+                    continue;
+                }
+            };
+            let Ok(rel_target) = target.strip_prefix(&canonical_prefix) else {
+                tracing::debug!(
+                    "Relink skipping symlink pointing outside of the prefix: {}",
+                    path.display()
+                );
+                continue;
+            };
+            let target = tmp_prefix.join(rel_target);
+            let content_type = content_type_map
+                .get(&target)
+                .copied()
+                .map_or_else(|| content_type_of(&target), Ok)?;
+            (target, content_type)
+        } else {
+            (path.clone(), *content_type)
+        };
+
+        if !seen.insert(file_id(&path)?) {
+            tracing::debug!(
+                "Relink skipping {} as it was already reached through another path",
+                path.display()
+            );
+            continue;
+        }
+        candidates.push((path, content_type));
+    }
+
+    Ok(candidates)
+}
+
+/// Removes the candidates that match the `relink_exclude` globs. These files
+/// are neither relinked nor checked for overlinking.
+fn exclude_candidates(
+    candidates: Vec<(PathBuf, Option<ContentType>)>,
+    relink_exclude: &GlobVec,
+    tmp_prefix: &Path,
+) -> Vec<(PathBuf, Option<ContentType>)> {
+    candidates
+        .into_iter()
+        .filter(|(path, _)| {
+            let Ok(rel_path) = path.strip_prefix(tmp_prefix) else {
+                return true;
+            };
+            let excluded = relink_exclude.is_match(rel_path);
+            if excluded {
+                tracing::info!(
+                    "Skipping relinking and linking checks for {} (matched by `relink_exclude`)",
+                    rel_path.display()
+                );
+            }
+            !excluded
+        })
+        .collect()
+}
+
+/// Logs the rpath changes that relinking would apply to a binary, together with
+/// the libraries it links against and where they resolve to.
+fn log_relink_plan(
+    relinker: &dyn Relinker,
+    rel_path: &Path,
+    prefix: &Path,
+    encoded_prefix: &Path,
+    options: &RelinkOptions,
+) -> Result<(), RelinkError> {
+    let current_rpaths = relinker.rpaths();
+    let planned_rpaths = relinker.planned_rpaths(prefix, encoded_prefix, options)?;
+
+    let join = |paths: &[PathBuf]| {
+        paths
+            .iter()
+            .map(|p| p.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(":")
+    };
+
+    let mut plan = format!(
+        "[dry-run] {}\n  rpaths: {} -> {}",
+        rel_path.display(),
+        join(&current_rpaths),
+        join(&planned_rpaths),
+    );
+
+    let mut libraries = relinker
+        .resolve_libraries(prefix, encoded_prefix)
+        .into_iter()
+        .collect::<Vec<_>>();
+    libraries.sort();
+    for (library, resolved) in libraries {
+        let resolved = resolved
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| "not found".to_string());
+        plan.push_str(&format!("\n  {} => {}", library.display(), resolved));
+    }
+
+    tracing::info!("{}", plan);
+    Ok(())
+}
+
+/// Formats the rpaths that cannot be relocated (see [`Relinker::can_relocate`])
+/// as a single message, sorted by binary.
+fn relocation_report(mut issues: Vec<(PathBuf, Vec<RelocationIssue>)>) -> String {
+    issues.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut report = String::from(
+        "The following rpaths are not in the prefix or the rpath allowlist and will be removed:",
+    );
+    for (path, issues) in issues {
+        for issue in issues {
+            report.push_str(&format!("\n  {}: {}", path.display(), issue));
+        }
+    }
+    report
+}
+
+/// The magic bytes at the start of a static (`ar`) archive.
+const AR_MAGIC: &[u8] = b"!<arch>\n";
+
+/// The size of the chunks in which static archives are scanned for the prefix.
+const ARCHIVE_SCAN_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Returns true if the file starts with the magic bytes of a static (`ar`)
+/// archive.
+fn is_static_archive(path: &Path) -> Result<bool, RelinkError> {
+    let mut magic = [0u8; AR_MAGIC.len()];
+    match fs::File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == AR_MAGIC),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns true if the file is a static archive (e.g. `libfoo.a`) that contains
+/// the `prefix`. The object files in an archive are not relinked, so a prefix
+/// embedded in them (e.g. in the debug information) leaks into downstream builds.
+fn archive_contains_prefix(path: &Path, prefix: &Path) -> Result<bool, RelinkError> {
+    if !is_static_archive(path)? {
+        return Ok(false);
+    }
+
+    let prefix = prefix.to_string_lossy();
+    let finder = memchr::memmem::Finder::new(prefix.as_bytes());
+    let mut file = fs::File::open(path)?;
+    let mut chunk = vec![0u8; ARCHIVE_SCAN_CHUNK_SIZE];
+    let mut window = Vec::new();
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(false);
+        }
+        window.extend_from_slice(&chunk[..read]);
+        if finder.find(&window).is_some() {
+            return Ok(true);
+        }
+        // keep the end of the window, the prefix may continue in the next chunk
+        let keep = prefix.len().saturating_sub(1).min(window.len());
+        window.drain(..window.len() - keep);
     }
 }
 
@@ -144,7 +726,39 @@ pub fn get_relinker(platform: Platform, path: &Path) -> Result<Box<dyn Relinker>
 ///
 /// On macOS (Mach-O files), we do the same trick and set the rpath to a relative path with the special
 /// `@loader_path` variable. The change for Mach-O files is applied with the `install_name_tool`.
-pub fn relink(temp_files: &TempFiles, output: &Output) -> Result<(), RelinkError> {
+///
+/// On Windows (PE files) there is no rpath, so DLLs are not modified. Instead, we validate that
+/// all imported DLLs can be found with the DLL search order of the environment.
+///
+/// Symlinked binaries are relinked through their target, and every file is relinked only
+/// once, even if it is reachable through several symlinks or hardlinks.
+///
+/// Modified Mach-O files are re-signed afterwards, with the configured
+/// `codesign_identity` or ad-hoc if none is set. On macOS hosts, the new
+/// signature is checked with `codesign --verify`.
+///
+/// With `strip_debug` enabled, the debug symbols of relinked ELF and Mach-O files are stripped
+/// afterwards (except for the binaries matched by `keep_debug`).
+///
+/// Binaries matched by the `relink_exclude` globs are skipped entirely, including the
+/// linking checks.
+///
+/// Before relinking, the rpaths that would be removed because they point outside of the
+/// prefix (see [`Relinker::can_relocate`]) are reported in a single warning.
+///
+/// Static archives are not relinked, but a warning lists the archives that still
+/// contain the encoded prefix.
+///
+/// With `relink_dry_run`, the binaries are not modified. Instead, the current and the
+/// planned rpaths as well as the resolved libraries of every binary are logged.
+///
+/// Returns a [`RelinkReport`] of the rpath changes, which is empty for a dry run.
+pub fn relink(
+    temp_files: &TempFiles,
+    output: &Output,
+    tool_configuration: &Configuration,
+) -> Result<RelinkReport, RelinkError> {
+    let dry_run = tool_configuration.relink_dry_run;
     let dynamic_linking = output.recipe.build().dynamic_linking();
     let target_platform = output.build_configuration.target_platform;
     let relocation_config = dynamic_linking.binary_relocation();
@@ -154,68 +768,616 @@ pub fn relink(temp_files: &TempFiles, output: &Output) -> Result<(), RelinkError
         || target_platform.arch() == Some(Arch::Wasm32)
         || relocation_config.is_none()
     {
-        return Ok(());
+        return Ok(RelinkReport::default());
     }
 
-    let rpaths = dynamic_linking.rpaths();
-    let rpath_allowlist = dynamic_linking.rpath_allowlist();
+    let options = RelinkOptions {
+        custom_rpaths: dynamic_linking.rpaths(),
+        prepend_rpaths: dynamic_linking.prepend_rpaths().to_vec(),
+        rpath_allowlist: dynamic_linking.rpath_allowlist().clone(),
+        default_rpaths: dynamic_linking.default_rpaths(target_platform),
+        codesign_identity: tool_configuration.codesign_identity.clone(),
+        rewrite_install_names: dynamic_linking.rewrite_install_names(),
+    };
+    // DLLs keep their debug information in separate PDB files
+    let mut strip_debug = dynamic_linking.strip_debug() && !target_platform.is_windows();
+
+    // the `strip` of the build machine might not understand the binaries of the
+    // target platform, so cross-compilations need the `strip` of the cross compilers
+    let cross_strip = if strip_debug && output.build_configuration.cross_compilation() {
+        let strip = find_cross_strip(
+            output.build_prefix(),
+            output.build_configuration.build_platform.platform,
+        );
+        if strip.is_none() {
+            tracing::warn!(
+                "Not stripping debug symbols: no `$STRIP` for {target_platform} found in the build environment"
+            );
+            strip_debug = false;
+        }
+        strip
+    } else {
+        None
+    };
 
     let tmp_prefix = temp_files.temp_dir.path();
     let encoded_prefix = &temp_files.encoded_prefix;
 
-    let mut binaries = HashSet::new();
     // allow to use tools from build prefix such as patchelf, install_name_tool, ...
     let system_tools = output.system_tools.with_build_prefix(output.build_prefix());
 
+    // Every binary is relinked independently, so we can process them in parallel.
+    // The first error (annotated with the offending path) aborts the relinking.
     use rayon::prelude::*;
-    let results: Vec<Result<Option<PathBuf>, RelinkError>> = temp_files
-        .content_type_map()
+    // binaries are parsed again by the linking checks, so share the parsed files
+    let relinker_cache = RelinkerCache::default();
+    let candidates = exclude_candidates(
+        relink_candidates(temp_files.content_type_map(), tmp_prefix)?,
+        dynamic_linking.relink_exclude(),
+        tmp_prefix,
+    );
+
+    // Report all rpaths that cannot be relocated at once instead of one
+    // message per binary while relinking
+    let issues = candidates
         .par_iter()
-        .map(|(p, content_type)| {
-            let metadata = fs::symlink_metadata(p)?;
-            if metadata.is_symlink() || metadata.is_dir() {
-                tracing::debug!("Relink skipping symlink or directory: {}", p.display());
-                return Ok(None);
+        .filter(|(_, content_type)| content_type == &Some(content_inspector::ContentType::BINARY))
+        .filter_map(|(p, _)| {
+            let rel_path = p.strip_prefix(tmp_prefix).ok()?;
+            if !relocation_config.is_match(rel_path) {
+                return None;
             }
+            let relinker = relinker_cache.get_relinker(target_platform, p).ok()?;
+            let issues = relinker.can_relocate(tmp_prefix, encoded_prefix, &options);
+            (!issues.is_empty()).then(|| (rel_path.to_path_buf(), issues))
+        })
+        .collect::<Vec<_>>();
+    if !issues.is_empty() {
+        let warn_str = relocation_report(issues);
+        tracing::warn!("{warn_str}");
+        output.record_warning(&warn_str);
+    }
 
-            if content_type != &Some(content_inspector::ContentType::BINARY) {
-                return Ok(None);
-            }
+    let log_handler = &tool_configuration.fancy_log_handler;
+    let binary_count = candidates
+        .iter()
+        .filter(|(_, content_type)| content_type == &Some(content_inspector::ContentType::BINARY))
+        .count();
+    let progress_bar = log_handler.add_progress_bar(
+        indicatif::ProgressBar::new(binary_count as u64)
+            .with_prefix("Relinking")
+            .with_style(log_handler.default_progress_style()),
+    );
 
-            let rel_path = p.strip_prefix(tmp_prefix)?;
-            if !relocation_config.is_match(rel_path) {
-                return Ok(None);
-            }
+    // static archives that still contain the encoded prefix
+    let archives_with_prefix = Mutex::new(Vec::new());
+    let changes = Mutex::new(Vec::new());
+    let relinked: Result<Vec<Option<PathBuf>>, _> = candidates
+        .par_iter()
+        .map(|(p, content_type)| {
+            let relink_file = || -> Result<Option<PathBuf>, RelinkError> {
+                if content_type != &Some(content_inspector::ContentType::BINARY) {
+                    return Ok(None);
+                }
+
+                let rel_path = p.strip_prefix(tmp_prefix)?;
+                if !relocation_config.is_match(rel_path) {
+                    return Ok(None);
+                }
+
+                // object files in static archives are not relinked, but they
+                // are checked for the prefix
+                if is_static_archive(p)? {
+                    if archive_contains_prefix(p, encoded_prefix)? {
+                        archives_with_prefix
+                            .lock()
+                            .unwrap()
+                            .push(rel_path.to_path_buf());
+                    }
+                    return Ok(None);
+                }
 
-            match get_relinker(target_platform, p) {
-                Ok(relinker) => {
-                    if !target_platform.is_windows() {
-                        relinker.relink(
-                            tmp_prefix,
-                            encoded_prefix,
-                            &rpaths,
-                            rpath_allowlist,
-                            &system_tools,
-                        )?;
+                match relinker_cache.get_relinker(target_platform, p) {
+                    Ok(relinker) => {
+                        if dry_run {
+                            log_relink_plan(
+                                relinker.as_ref(),
+                                rel_path,
+                                tmp_prefix,
+                                encoded_prefix,
+                                &options,
+                            )?;
+                        } else {
+                            let applied = relinker.relink(
+                                tmp_prefix,
+                                encoded_prefix,
+                                &options,
+                                &system_tools,
+                            )?;
+                            relinker_cache.evict(p);
+                            if let Some(applied) = applied {
+                                // the relinker still holds the state before relinking
+                                changes.lock().unwrap().push(RelinkChange {
+                                    path: rel_path.to_path_buf(),
+                                    old_rpaths: relinker.rpaths(),
+                                    new_rpaths: applied.rpaths,
+                                    tool: applied.tool,
+                                });
+                            }
+
+                            if strip_debug && !dynamic_linking.keep_debug().is_match(rel_path) {
+                                strip_debug_symbols(
+                                    p,
+                                    target_platform,
+                                    cross_strip.as_deref(),
+                                    options.codesign_identity.as_deref(),
+                                    &system_tools,
+                                )?;
+                                relinker_cache.evict(p);
+                            }
+                        }
+                        Ok(Some(p.clone()))
                     }
-                    Ok(Some(p.clone()))
+                    Err(RelinkError::UnknownFileFormat) => Ok(None),
+                    Err(e) => Err(e),
                 }
-                Err(RelinkError::UnknownFileFormat) => Ok(None),
-                Err(e) => Err(e),
+            };
+
+            let result = relink_file().map_err(|e| RelinkError::RelinkFileFailed {
+                path: p.clone(),
+                source: Box::new(e),
+            });
+            if content_type == &Some(content_inspector::ContentType::BINARY) {
+                progress_bar.inc(1);
             }
+            result
         })
         .collect();
 
-    for result in results {
-        match result {
-            Ok(Some(path)) => {
-                binaries.insert(path);
-            }
-            Ok(None) => {}
-            Err(e) => return Err(e),
+    let relinked = match relinked {
+        Ok(relinked) => {
+            progress_bar.set_style(log_handler.finished_progress_style());
+            progress_bar.finish_with_message(format!(
+                "Relinked {} binaries",
+                relinked.iter().flatten().count()
+            ));
+            relinked
         }
+        Err(e) => {
+            progress_bar.set_style(log_handler.errored_progress_style());
+            progress_bar.abandon_with_message("Relinking failed");
+            return Err(e);
+        }
+    };
+
+    let mut archives_with_prefix = archives_with_prefix.into_inner().unwrap();
+    if !archives_with_prefix.is_empty() {
+        archives_with_prefix.sort();
+        let warn_str = format!(
+            "The following static archives contain the host prefix ({}), which is not replaced \
+             and can leak into downstream builds:\n  {}",
+            encoded_prefix.display(),
+            archives_with_prefix
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join("\n  ")
+        );
+        tracing::warn!("{warn_str}");
+        output.record_warning(&warn_str);
     }
-    perform_linking_checks(output, &binaries, tmp_prefix)?;
 
-    Ok(())
+    let binaries: HashSet<PathBuf> = relinked.into_iter().flatten().collect();
+    if !dry_run {
+        check_absolute_rpaths(output, &binaries, tmp_prefix, &relinker_cache)?;
+    }
+    perform_linking_checks(output, &binaries, tmp_prefix, &relinker_cache)?;
+
+    let mut changes = changes.into_inner().unwrap();
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(RelinkReport { changes })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use rattler_conda_types::Platform;
+
+    use super::{
+        AR_MAGIC, ARCHIVE_SCAN_CHUNK_SIZE, RelinkError, RelinkOptions, RelinkerCache,
+        RelocationIssue, archive_contains_prefix, exclude_candidates, find_cross_strip,
+        get_relinker, is_static_archive, is_valid_file, link_info, relink_candidates,
+        relocation_report, strip_debug_symbols, strip_default_rpaths,
+    };
+    use crate::recipe::parser::{DynamicLinking, GlobOrRegexVec, GlobVec};
+
+    #[test]
+    #[cfg(unix)]
+    fn test_relink_candidates() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let prefix = tmp_dir.path().join("prefix");
+        let outside = tmp_dir.path().join("outside");
+        fs_err::create_dir_all(prefix.join("lib")).unwrap();
+        fs_err::create_dir_all(&outside).unwrap();
+
+        let binary = Path::new("test-data/binary_files/zlink");
+        fs_err::copy(binary, prefix.join("lib/libfoo.so.1.2")).unwrap();
+        fs_err::copy(binary, outside.join("libbar.so")).unwrap();
+        fs_err::copy(binary, prefix.join("lib/libbaz.so")).unwrap();
+        fs_err::hard_link(prefix.join("lib/libbaz.so"), prefix.join("lib/libbaz2.so")).unwrap();
+
+        let link = |target: &Path, name: &str| {
+            fs_err::os::unix::fs::symlink(target, prefix.join("lib").join(name)).unwrap()
+        };
+        link(Path::new("libfoo.so.1.2"), "libfoo.so.1");
+        link(Path::new("libfoo.so.1.2"), "libfoo.so");
+        link(&outside.join("libbar.so"), "libbar.so");
+        link(Path::new("missing.so"), "libmissing.so");
+
+        // the actual library is not part of the map, only reachable via symlinks
+        let content_type_map = [
+            "lib/libfoo.so.1",
+            "lib/libfoo.so",
+            "lib/libbar.so",
+            "lib/libmissing.so",
+            "lib/libbaz.so",
+            "lib/libbaz2.so",
+        ]
+        .iter()
+        .map(|p| {
+            let path = prefix.join(p);
+            let content_type = super::content_type_of(&path).unwrap();
+            (path, content_type)
+        })
+        .collect();
+
+        let candidates = relink_candidates(&content_type_map, &prefix).unwrap();
+        let paths = candidates
+            .iter()
+            .map(|(p, _)| p.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                prefix.join("lib/libbaz.so"),
+                prefix.join("lib/libfoo.so.1.2")
+            ]
+        );
+        assert!(
+            candidates
+                .iter()
+                .all(|(_, ct)| ct == &Some(content_inspector::ContentType::BINARY))
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    #[tracing_test::traced_test]
+    fn test_strip_debug_symbols() {
+        if which::which("strip").is_err() || which::which("cc").is_err() {
+            return;
+        }
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let library = tmp_dir.path().join("libsimple.so");
+        let status = std::process::Command::new("cc")
+            .args(["-g", "-shared", "-fPIC", "-o"])
+            .arg(&library)
+            .arg("test-data/binary_files/simple.c")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let has_debug_sections = |path: &Path| {
+            let data = fs_err::read(path).unwrap();
+            let elf = goblin::elf::Elf::parse(&data).unwrap();
+            elf.section_headers.iter().any(|header| {
+                elf.shdr_strtab
+                    .get_at(header.sh_name)
+                    .is_some_and(|name| name.starts_with(".debug"))
+            })
+        };
+        assert!(has_debug_sections(&library));
+
+        let system_tools = crate::system_tools::SystemTools::default();
+        strip_debug_symbols(&library, Platform::Linux64, None, None, &system_tools).unwrap();
+        assert!(logs_contain("Stripped debug symbols from"));
+        assert!(!has_debug_sections(&library));
+
+        // the stripped binary is still a valid ELF file with the same rpaths
+        let binary = tmp_dir.path().join("zlink");
+        fs_err::copy("test-data/binary_files/zlink", &binary).unwrap();
+        strip_debug_symbols(&binary, Platform::Linux64, None, None, &system_tools).unwrap();
+        let info = link_info(&binary, Platform::Linux64, tmp_dir.path()).unwrap();
+        assert_eq!(info.rpaths.len(), 3);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_find_cross_strip() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let build_prefix = tempfile::tempdir().unwrap();
+        let bin = build_prefix.path().join("bin");
+        let activate_d = build_prefix.path().join("etc/conda/activate.d");
+        fs_err::create_dir_all(&bin).unwrap();
+        fs_err::create_dir_all(&activate_d).unwrap();
+
+        let strip = bin.join("aarch64-conda-linux-gnu-strip");
+        fs_err::write(&strip, "#!/bin/sh\n").unwrap();
+        fs_err::set_permissions(&strip, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        // without an activation script there is no cross `strip`
+        assert_eq!(
+            find_cross_strip(build_prefix.path(), Platform::Linux64),
+            None
+        );
+
+        fs_err::write(
+            activate_d.join("binutils.sh"),
+            "export STRIP=aarch64-conda-linux-gnu-strip\n",
+        )
+        .unwrap();
+        assert_eq!(
+            find_cross_strip(build_prefix.path(), Platform::Linux64),
+            Some(strip)
+        );
+    }
+
+    #[test]
+    fn test_relinker_cache() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let binary = tmp_dir.path().join("zlink");
+        let text_file = tmp_dir.path().join("simple.c");
+        fs_err::copy("test-data/binary_files/zlink", &binary).unwrap();
+        fs_err::copy("test-data/binary_files/simple.c", &text_file).unwrap();
+
+        let cache = RelinkerCache::default();
+        let first = cache.get_relinker(Platform::Linux64, &binary).unwrap();
+        let second = cache.get_relinker(Platform::Linux64, &binary).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // files with an unknown format are cached as well
+        for _ in 0..2 {
+            assert!(matches!(
+                cache.get_relinker(Platform::Linux64, &text_file),
+                Err(RelinkError::UnknownFileFormat)
+            ));
+        }
+
+        // a modified file is parsed again
+        let mut content = fs_err::read(&binary).unwrap();
+        content.extend_from_slice(&[0; 16]);
+        fs_err::write(&binary, content).unwrap();
+        let third = cache.get_relinker(Platform::Linux64, &binary).unwrap();
+        assert!(!Arc::ptr_eq(&first, &third));
+        assert_eq!(first.rpaths(), third.rpaths());
+
+        // a change that keeps the size and the modification time is only
+        // noticed after evicting the file
+        let modified = fs_err::metadata(&binary).unwrap().modified().unwrap();
+        let content = fs_err::read(&binary).unwrap();
+        fs_err::write(&binary, &content).unwrap();
+        fs_err::File::options()
+            .write(true)
+            .open(&binary)
+            .unwrap()
+            .file()
+            .set_modified(modified)
+            .unwrap();
+        let fourth = cache.get_relinker(Platform::Linux64, &binary).unwrap();
+        assert!(Arc::ptr_eq(&third, &fourth));
+        cache.evict(&binary);
+        let fifth = cache.get_relinker(Platform::Linux64, &binary).unwrap();
+        assert!(!Arc::ptr_eq(&third, &fifth));
+    }
+
+    #[test]
+    fn test_strip_default_rpaths() {
+        let rpaths = vec![
+            PathBuf::from("$ORIGIN/../lib"),
+            PathBuf::from("/usr/lib"),
+            PathBuf::from("/usr/lib/"),
+            PathBuf::from("/opt/lib"),
+        ];
+        let default_rpaths = [PathBuf::from("/usr/lib"), PathBuf::from("/lib")];
+
+        let stripped = strip_default_rpaths(rpaths, &default_rpaths, Path::new("lib/libfoo.so"));
+        assert_eq!(
+            stripped,
+            vec![PathBuf::from("$ORIGIN/../lib"), PathBuf::from("/opt/lib")]
+        );
+    }
+
+    #[test]
+    fn test_default_rpaths() {
+        let dynamic_linking = DynamicLinking::default();
+        assert!(dynamic_linking.default_rpaths(Platform::Linux64).is_empty());
+
+        let dynamic_linking: DynamicLinking =
+            serde_yaml::from_str("strip_default_rpaths: true").unwrap();
+        assert!(
+            dynamic_linking
+                .default_rpaths(Platform::Linux64)
+                .contains(&PathBuf::from("/usr/lib"))
+        );
+        assert!(dynamic_linking.default_rpaths(Platform::Win64).is_empty());
+
+        let dynamic_linking: DynamicLinking =
+            serde_yaml::from_str("strip_default_rpaths: true\ndefault_rpaths: [/opt/lib]").unwrap();
+        assert_eq!(
+            dynamic_linking.default_rpaths(Platform::OsxArm64),
+            vec![PathBuf::from("/opt/lib")]
+        );
+    }
+
+    #[test]
+    fn test_exclude_candidates() {
+        let prefix = Path::new("/prefix");
+        let candidates = ["lib/libfoo.so", "vendor/bin/tool", "vendor/lib/libtool.so"]
+            .map(|path| (prefix.join(path), None))
+            .to_vec();
+        let exclude = GlobVec::from_vec(vec!["vendor/**"], None);
+
+        let remaining = exclude_candidates(candidates.clone(), &exclude, prefix);
+        assert_eq!(remaining, vec![(prefix.join("lib/libfoo.so"), None)]);
+
+        let remaining = exclude_candidates(candidates.clone(), &GlobVec::default(), prefix);
+        assert_eq!(remaining, candidates);
+    }
+
+    #[test]
+    fn test_can_relocate() {
+        let prefix = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files");
+        let encoded_prefix = Path::new("/rattler-build_zlink/host_env_placehold");
+        let relinker = get_relinker(Platform::Linux64, &prefix.join("zlink")).unwrap();
+
+        let options = RelinkOptions {
+            rpath_allowlist: GlobOrRegexVec::from(GlobVec::from_vec(
+                vec!["/usr/lib/custom**"],
+                None,
+            )),
+            ..Default::default()
+        };
+        let issues = relinker.can_relocate(&prefix, encoded_prefix, &options);
+        assert_eq!(
+            issues,
+            vec![RelocationIssue {
+                rpath: PathBuf::from("/rattler-build_zlink/build_env/lib"),
+                resolved: PathBuf::from("/rattler-build_zlink/build_env/lib"),
+            }]
+        );
+
+        let issues = relinker.can_relocate(&prefix, encoded_prefix, &RelinkOptions::default());
+        assert_eq!(
+            relocation_report(vec![(PathBuf::from("lib/zlink"), issues)]),
+            "The following rpaths are not in the prefix or the rpath allowlist and will be removed:\n  \
+             lib/zlink: rpath /rattler-build_zlink/build_env/lib points outside of the prefix\n  \
+             lib/zlink: rpath /usr/lib/custom_lib points outside of the prefix"
+        );
+    }
+
+    #[test]
+    fn test_link_info() {
+        let prefix = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/binary_files");
+
+        let info = link_info(&prefix.join("zlink"), Platform::Linux64, &prefix).unwrap();
+        assert_eq!(
+            info.rpaths
+                .iter()
+                .map(|(rpath, _)| rpath.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("/rattler-build_zlink/host_env_placehold/lib"),
+                PathBuf::from("/rattler-build_zlink/build_env/lib"),
+                PathBuf::from("/usr/lib/custom_lib"),
+            ]
+        );
+        assert_eq!(
+            info.libraries
+                .iter()
+                .map(|(library, _)| library.clone())
+                .collect::<Vec<_>>(),
+            vec![PathBuf::from("libc.so.6"), PathBuf::from("libz.so.1")]
+        );
+
+        let info = link_info(&prefix.join("zlink-macos"), Platform::OsxArm64, &prefix).unwrap();
+        assert!(
+            info.libraries
+                .iter()
+                .any(|(library, _)| library == Path::new("@rpath/libz.1.dylib"))
+        );
+        assert!(info.system_libraries.is_empty());
+
+        let dll = prefix.join("windows/zstd/Library/bin/zstd.dll");
+        let info = link_info(&dll, Platform::Win64, &prefix).unwrap();
+        assert!(info.rpaths.is_empty());
+        // the C runtime counts as part of the system
+        assert!(info.libraries.is_empty());
+        assert_eq!(
+            info.system_libraries,
+            [
+                "KERNEL32.dll",
+                "VCRUNTIME140.dll",
+                "api-ms-win-crt-heap-l1-1-0.dll",
+                "api-ms-win-crt-runtime-l1-1-0.dll",
+                "api-ms-win-crt-stdio-l1-1-0.dll",
+                "api-ms-win-crt-time-l1-1-0.dll",
+                "api-ms-win-crt-utility-l1-1-0.dll",
+            ]
+            .map(PathBuf::from)
+        );
+
+        // binaries outside of the prefix are rejected
+        assert!(
+            link_info(
+                &prefix.join("zlink"),
+                Platform::Linux64,
+                Path::new("/other")
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_archive_contains_prefix() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let prefix = Path::new("/very/long/host_prefix_placehold_placehold");
+        let write = |name: &str, content: &[u8]| {
+            let path = tmp_dir.path().join(name);
+            fs_err::write(&path, content).unwrap();
+            path
+        };
+
+        let mut archive =
+            b"!<arch>\nfoo.o/          0           0     0     644     64        `\n".to_vec();
+        archive.extend_from_slice(b"\0\0/very/long/host_prefix_placehold_placehold/include\0");
+        assert!(archive_contains_prefix(&write("libfoo.a", &archive), prefix).unwrap());
+
+        let clean =
+            b"!<arch>\nfoo.o/          0           0     0     644     4         `\n\0\0\0\0";
+        assert!(!archive_contains_prefix(&write("libclean.a", clean), prefix).unwrap());
+
+        // only archives are reported
+        let other = b"\0\0/very/long/host_prefix_placehold_placehold/include\0";
+        assert!(!archive_contains_prefix(&write("data.bin", other), prefix).unwrap());
+        assert!(!archive_contains_prefix(&write("empty", b""), prefix).unwrap());
+
+        // the prefix is found when it spans two chunks
+        let mut large = AR_MAGIC.to_vec();
+        large.resize(ARCHIVE_SCAN_CHUNK_SIZE - 10, 0);
+        large.extend_from_slice(prefix.to_string_lossy().as_bytes());
+        large.resize(2 * ARCHIVE_SCAN_CHUNK_SIZE, 0);
+        let large = write("liblarge.a", &large);
+        assert!(is_static_archive(&large).unwrap());
+        assert!(archive_contains_prefix(&large, prefix).unwrap());
+        assert!(!is_static_archive(&write("short", b"!<ar")).unwrap());
+    }
+
+    #[test]
+    fn test_is_valid_file() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let text_file = tmp_dir.path().join("README.md");
+        fs_err::write(&text_file, "# foo").unwrap();
+        let binary = Path::new("test-data/binary_files/zlink");
+
+        assert!(is_valid_file(Platform::Linux64, binary).unwrap());
+        assert!(!is_valid_file(Platform::Linux64, &text_file).unwrap());
+        assert!(!is_valid_file(Platform::OsxArm64, &text_file).unwrap());
+        assert!(!is_valid_file(Platform::Win64, &text_file).unwrap());
+
+        // an unsupported platform is an error, independent of the file
+        for path in [binary, text_file.as_path()] {
+            assert!(matches!(
+                is_valid_file(Platform::EmscriptenWasm32, path),
+                Err(RelinkError::UnknownPlatform)
+            ));
+        }
+        assert!(matches!(
+            get_relinker(Platform::Linux64, &text_file),
+            Err(RelinkError::UnknownFileFormat)
+        ));
+    }
 }
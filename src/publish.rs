@@ -114,6 +114,7 @@ pub(crate) async fn fetch_highest_build_numbers(
                 .with_multi_progress(tool_config.fancy_log_handler.multi_progress().clone())
                 .with_progress_template(tool_config.fancy_log_handler.default_bytes_style())
                 .with_finish_template(tool_config.fancy_log_handler.finished_progress_style())
+                .with_progress_output(tool_config.progress_output)
                 .finish(),
         )
         .recursive(false)
@@ -200,10 +201,21 @@ pub(crate) fn apply_build_number_override(
             .as_resolved()
             .expect("Build string should be resolved at this point");
 
+        // The suffix (e.g. the architecture) comes after the build number
+        let suffix = output
+            .recipe
+            .build
+            .string_suffix()
+            .map(|suffix| format!("_{suffix}"))
+            .unwrap_or_default();
+        let current_build_string = current_build_string
+            .strip_suffix(&suffix)
+            .unwrap_or(current_build_string);
+
         // Split on last '_' to separate hash from build number
         if let Some(last_underscore) = current_build_string.rfind('_') {
             let hash_part = &current_build_string[..last_underscore];
-            let new_build_string = format!("{}_{}", hash_part, new_build_number);
+            let new_build_string = format!("{}_{}{}", hash_part, new_build_number, suffix);
             output.recipe.build.string = BuildString::Resolved(new_build_string);
         }
     }
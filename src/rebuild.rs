@@ -1,80 +1,2131 @@
 //! The rebuild module contains rebuild helper functions.
 
 use fs_err as fs;
-use rattler_conda_types::package::ArchiveType;
-use std::path::{Path, PathBuf};
+use futures::StreamExt;
+use rattler_conda_types::{compression_level::CompressionLevel, package::ArchiveType};
+use rattler_digest::{Sha256, Sha256Hash, digest::Digest};
+use reqwest_middleware::ClientWithMiddleware;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use url::Url;
+
+use crate::{NormalizedKey, metadata::Output, recipe::parser::GlobVec, recipe::variable::Variable};
+
+/// Receives a notification for every entry that is extracted from a package
+/// archive, e.g. to show the progress of a large extraction. Closures taking
+/// the path in the archive and the size of the entry are reporters as well.
+pub trait ExtractReporter {
+    /// Called after the entry at `path` (relative to the root of the package)
+    /// with a size of `bytes` was extracted.
+    fn on_entry_extracted(&self, path: &Path, bytes: u64);
+}
+
+impl<F: Fn(&Path, u64)> ExtractReporter for F {
+    fn on_entry_extracted(&self, path: &Path, bytes: u64) {
+        self(path, bytes)
+    }
+}
 
 /// Extracts a folder from a tar.bz2 archive.
 fn folder_from_tar_bz2(
     archive_path: &Path,
     find_path: &Path,
     dest_folder: &Path,
+    reporter: Option<&dyn ExtractReporter>,
 ) -> Result<(), std::io::Error> {
     let reader = fs::File::open(archive_path)?;
     let mut archive = rattler_package_streaming::read::stream_tar_bz2(reader);
+    folder_from_archive(&mut archive, find_path, dest_folder, reporter)
+}
+
+/// Extracts all entries below `find_path` from a tar archive.
+///
+/// Symlinks are recreated as symlinks with their original (usually relative)
+/// target, even if the target is extracted later or not at all. Hard links are
+/// resolved against `dest_folder` instead of the current directory.
+fn folder_from_archive(
+    archive: &mut tar::Archive<impl Read>,
+    find_path: &Path,
+    dest_folder: &Path,
+    reporter: Option<&dyn ExtractReporter>,
+) -> Result<(), std::io::Error> {
     archive.set_preserve_permissions(true);
 
     for entry in archive.entries()? {
         let mut entry = entry?;
-        let path = entry.path()?;
+        let path = entry.path()?.into_owned();
         if let Ok(stripped_path) = path.strip_prefix(find_path) {
-            let dest_file = dest_folder.join(stripped_path);
-            if let Some(parent_folder) = dest_file.parent()
-                && !parent_folder.exists()
-            {
-                fs::create_dir_all(parent_folder)?;
+            // the entry of `find_path` itself
+            if stripped_path.as_os_str().is_empty() {
+                fs::create_dir_all(dest_folder)?;
+                continue;
+            }
+            let Some(dest_file) = prepare_dest_file(dest_folder, stripped_path)? else {
+                tracing::warn!(
+                    "Skipping {} because it is outside of the package",
+                    path.display()
+                );
+                continue;
+            };
+            if entry.header().entry_type().is_hard_link() {
+                let target = hard_link_target(&entry, find_path, dest_folder)?;
+                hard_link_entry(&target, dest_folder, &dest_file)?;
+            } else {
+                entry.unpack(dest_file)?;
+            }
+            if let Some(reporter) = reporter {
+                reporter.on_entry_extracted(&path, entry.size());
             }
-            entry.unpack(dest_file)?;
         }
     }
     Ok(())
 }
 
-/// Extracts a folder from a conda archive.
-fn folder_from_conda(
-    archive_path: &Path,
+/// Returns true if the path only consists of normal components, i.e. it is
+/// relative and does not contain `..`.
+fn is_contained_path(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Returns the path in `dest_folder` that an entry at `path` is extracted to
+/// and creates its parent folder. Like [`tar::Entry::unpack_in`], paths that
+/// are absolute or contain `..` are skipped (`None`), and paths that would end
+/// up outside of `dest_folder` through a symlinked folder are rejected.
+fn prepare_dest_file(dest_folder: &Path, path: &Path) -> Result<Option<PathBuf>, std::io::Error> {
+    if !is_contained_path(path) {
+        return Ok(None);
+    }
+
+    let dest_file = dest_folder.join(path);
+    if let Some(parent_folder) = dest_file.parent() {
+        // Check the deepest folder that already exists before creating the
+        // missing ones, so that no folders are created through a symlink that
+        // points outside of `dest_folder`.
+        fs::create_dir_all(dest_folder)?;
+        let existing_folder = parent_folder
+            .ancestors()
+            .find(|folder| fs::symlink_metadata(folder).is_ok())
+            .unwrap_or(dest_folder);
+        if !dunce::canonicalize(existing_folder)?.starts_with(dunce::canonicalize(dest_folder)?) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is outside of {}", path.display(), dest_folder.display()),
+            ));
+        }
+        fs::create_dir_all(parent_folder)?;
+    }
+    Ok(Some(dest_file))
+}
+
+/// Returns the path in `dest_folder` that a hard link entry points to. The
+/// link name is relative to the root of the archive, so `find_path` is
+/// stripped from it. Link names that are absolute, contain `..` or point
+/// outside of `find_path` are rejected.
+fn hard_link_target(
+    entry: &tar::Entry<'_, impl Read>,
     find_path: &Path,
     dest_folder: &Path,
+) -> Result<PathBuf, std::io::Error> {
+    let link_name = entry.link_name()?.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "hard link entry without a link name",
+        )
+    })?;
+    match link_name.strip_prefix(find_path) {
+        Ok(target) if is_contained_path(&link_name) && is_contained_path(target) => {
+            Ok(dest_folder.join(target))
+        }
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "hard link target {} is outside of the extracted folder",
+                link_name.display()
+            ),
+        )),
+    }
+}
+
+/// Recreates a hard link to `target` at `dest_file`. The target is copied if
+/// it cannot be hard linked. A target that resolves (e.g. through a symlink)
+/// to a path outside of `dest_folder` is rejected.
+fn hard_link_entry(
+    target: &Path,
+    dest_folder: &Path,
+    dest_file: &Path,
 ) -> Result<(), std::io::Error> {
-    let reader = fs::File::open(archive_path)?;
+    let target = dunce::canonicalize(target)?;
+    if !target.starts_with(dunce::canonicalize(dest_folder)?) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "hard link target {} is outside of {}",
+                target.display(),
+                dest_folder.display()
+            ),
+        ));
+    }
 
-    let mut archive = if find_path.starts_with("info") {
-        rattler_package_streaming::seek::stream_conda_info(reader)
-            .expect("Could not open conda file")
-    } else {
-        todo!("Not implemented yet");
-    };
+    if fs::symlink_metadata(dest_file).is_ok() {
+        fs::remove_file(dest_file)?;
+    }
+    if fs::hard_link(&target, dest_file).is_err() {
+        fs::copy(&target, dest_file)?;
+    }
+    Ok(())
+}
 
+/// Extracts all file entries that match `filter` from a tar archive, keeping
+/// their path relative to the root of the package. Returns the extracted paths.
+fn matching_from_archive(
+    archive: &mut tar::Archive<impl Read>,
+    filter: &GlobVec,
+    dest_folder: &Path,
+) -> Result<Vec<PathBuf>, std::io::Error> {
     archive.set_preserve_permissions(true);
 
+    let mut extracted = Vec::new();
     for entry in archive.entries()? {
         let mut entry = entry?;
-        let path = entry.path()?;
-        if let Ok(stripped_path) = path.strip_prefix(find_path) {
-            let dest_file = dest_folder.join(stripped_path);
-            if let Some(parent_folder) = dest_file.parent()
-                && !parent_folder.exists()
-            {
-                fs::create_dir_all(parent_folder)?;
+        let path = entry.path()?.into_owned();
+        if entry.header().entry_type().is_dir() || !filter.is_match(&path) {
+            continue;
+        }
+        if entry.header().entry_type().is_hard_link() {
+            let Some(dest_file) = prepare_dest_file(dest_folder, &path)? else {
+                tracing::warn!(
+                    "Skipping {} because it is outside of the package",
+                    path.display()
+                );
+                continue;
+            };
+            let target = hard_link_target(&entry, Path::new(""), dest_folder)?;
+            // the target of the link might not match the filter
+            if fs::symlink_metadata(&target).is_err() {
+                tracing::warn!(
+                    "Skipping {} because it is a hard link to {}, which was not extracted",
+                    path.display(),
+                    target.display()
+                );
+                continue;
+            }
+            hard_link_entry(&target, dest_folder, &dest_file)?;
+        } else {
+            fs::create_dir_all(dest_folder)?;
+            if !entry.unpack_in(dest_folder)? {
+                tracing::warn!(
+                    "Skipping {} because it is outside of the package",
+                    path.display()
+                );
+                continue;
             }
-            entry.unpack(dest_file)?;
         }
+        extracted.push(path);
+    }
+    Ok(extracted)
+}
+
+/// Extracts the files that match `filter` from a tar.bz2 archive.
+fn matching_from_tar_bz2(
+    archive_path: &Path,
+    filter: &GlobVec,
+    dest_folder: &Path,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let reader = fs::File::open(archive_path)?;
+    let mut archive = rattler_package_streaming::read::stream_tar_bz2(reader);
+    matching_from_archive(&mut archive, filter, dest_folder)
+}
+
+/// Extracts the files that match `filter` from both layers of a conda archive.
+fn matching_from_conda(
+    archive_path: &Path,
+    filter: &GlobVec,
+    dest_folder: &Path,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let reader = fs::File::open(archive_path)?;
+    let mut archive = rattler_package_streaming::seek::stream_conda_info(reader)
+        .map_err(std::io::Error::other)?;
+    let mut extracted = matching_from_archive(&mut archive, filter, dest_folder)?;
+
+    let reader = fs::File::open(archive_path)?;
+    let mut archive = rattler_package_streaming::seek::stream_conda_content(reader)
+        .map_err(std::io::Error::other)?;
+    extracted.extend(matching_from_archive(&mut archive, filter, dest_folder)?);
+    Ok(extracted)
+}
+
+/// Extracts a folder from a conda archive.
+fn folder_from_conda(
+    archive_path: &Path,
+    find_path: &Path,
+    dest_folder: &Path,
+    reporter: Option<&dyn ExtractReporter>,
+) -> Result<(), std::io::Error> {
+    // The `info` folder and the package contents are stored in separate
+    // layers of the conda archive. An empty path extracts both.
+    let is_empty = find_path.as_os_str().is_empty();
+    if is_empty || find_path.starts_with("info") {
+        let reader = fs::File::open(archive_path)?;
+        let mut archive = rattler_package_streaming::seek::stream_conda_info(reader)
+            .map_err(std::io::Error::other)?;
+        folder_from_archive(&mut archive, find_path, dest_folder, reporter)?;
+    }
+    if is_empty || !find_path.starts_with("info") {
+        let reader = fs::File::open(archive_path)?;
+        let mut archive = rattler_package_streaming::seek::stream_conda_content(reader)
+            .map_err(std::io::Error::other)?;
+        folder_from_archive(&mut archive, find_path, dest_folder, reporter)?;
     }
     Ok(())
 }
 
 /// Extracts a recipe from a package archive to a destination folder.
 pub fn extract_recipe(package: &Path, dest_folder: &Path) -> Result<(), std::io::Error> {
-    let archive_type = ArchiveType::try_from(package).ok_or_else(|| {
+    extract_folder(package, Path::new("info/recipe"), dest_folder)
+}
+
+/// An error that is returned if a package archive is damaged.
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveIntegrityError {
+    /// The package could not be opened.
+    #[error("failed to read package: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The package is truncated or corrupt.
+    #[error("package {path} is corrupt: {source}")]
+    Corrupt {
+        /// The path of the package.
+        path: PathBuf,
+        /// The error that occurred while reading the package.
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The sha256 of the package does not match the expected hash.
+    #[error("sha256 mismatch for {path}: expected {expected:x}, got {actual:x}")]
+    Sha256Mismatch {
+        /// The path of the package.
+        path: PathBuf,
+        /// The expected hash.
+        expected: Sha256Hash,
+        /// The hash of the package.
+        actual: Sha256Hash,
+    },
+}
+
+/// Reads all entries of a tar archive to the end.
+fn read_all_entries(archive: &mut tar::Archive<impl Read>) -> Result<(), std::io::Error> {
+    for entry in archive.entries()? {
+        std::io::copy(&mut entry?, &mut std::io::sink())?;
+    }
+    Ok(())
+}
+
+/// Verifies that a package archive is intact by decompressing all of its
+/// entries (both the `info` and the content layer of a `.conda` archive), and,
+/// if `expected_sha256` is given, that the archive has the expected hash.
+pub fn verify_archive(
+    package: &Path,
+    expected_sha256: Option<&Sha256Hash>,
+) -> Result<(), ArchiveIntegrityError> {
+    if let Some(expected) = expected_sha256 {
+        let actual = rattler_digest::compute_file_digest::<Sha256>(package)?;
+        if &actual != expected {
+            return Err(ArchiveIntegrityError::Sha256Mismatch {
+                path: package.to_path_buf(),
+                expected: *expected,
+                actual,
+            });
+        }
+    }
+
+    let read = || -> Result<(), std::io::Error> {
+        match archive_type_of(package)? {
+            ArchiveType::TarBz2 => {
+                let reader = fs::File::open(package)?;
+                read_all_entries(&mut rattler_package_streaming::read::stream_tar_bz2(reader))
+            }
+            ArchiveType::Conda => {
+                let reader = fs::File::open(package)?;
+                let mut info = rattler_package_streaming::seek::stream_conda_info(reader)
+                    .map_err(std::io::Error::other)?;
+                read_all_entries(&mut info)?;
+                let reader = fs::File::open(package)?;
+                let mut content = rattler_package_streaming::seek::stream_conda_content(reader)
+                    .map_err(std::io::Error::other)?;
+                read_all_entries(&mut content)
+            }
+        }
+    };
+    read().map_err(|source| ArchiveIntegrityError::Corrupt {
+        path: package.to_path_buf(),
+        source,
+    })
+}
+
+/// Like [`extract_recipe`], but verifies the archive with [`verify_archive`]
+/// first, so that a damaged package does not leave a partially extracted
+/// recipe in `dest_folder`.
+pub fn extract_recipe_verified(
+    package: &Path,
+    dest_folder: &Path,
+    expected_sha256: Option<&Sha256Hash>,
+) -> Result<(), ArchiveIntegrityError> {
+    verify_archive(package, expected_sha256)?;
+    Ok(extract_recipe(package, dest_folder)?)
+}
+
+/// An error that can occur when downloading a package.
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadPackageError {
+    /// The URL does not point to a `.conda` or `.tar.bz2` file.
+    #[error("{0} does not point to a `.conda` or `.tar.bz2` package")]
+    UnsupportedUrl(Url),
+
+    /// The package could not be downloaded.
+    #[error("failed to download {url}: {source}")]
+    Network {
+        /// The URL of the package.
+        url: Url,
+        /// The network error.
+        #[source]
+        source: reqwest_middleware::Error,
+    },
+
+    /// The downloaded package could not be written to disk.
+    #[error("failed to write the downloaded package: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The downloaded file is not a valid package archive.
+    #[error(transparent)]
+    Archive(#[from] ArchiveIntegrityError),
+}
+
+/// Downloads a package archive from `url` into `dest_dir` and returns the path
+/// of the downloaded file. A package that was already downloaded to `dest_dir`
+/// (e.g. a cache directory) is not downloaded again.
+pub async fn download_package(
+    client: &ClientWithMiddleware,
+    url: &Url,
+    dest_dir: &Path,
+) -> Result<PathBuf, DownloadPackageError> {
+    let file_name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| ArchiveType::try_from(Path::new(name)).is_some())
+        .ok_or_else(|| DownloadPackageError::UnsupportedUrl(url.clone()))?;
+
+    // packages with the same file name can come from different channels
+    let url_hash = format!("{:x}", Sha256::digest(url.as_str()));
+    let package = dest_dir.join(&url_hash[..16]).join(file_name);
+    if package.is_file() {
+        tracing::info!("Using cached package {}", package.display());
+        return Ok(package);
+    }
+
+    tracing::info!("Downloading package from {}", url);
+    let network_error = |source| DownloadPackageError::Network {
+        url: url.clone(),
+        source,
+    };
+    let response = client
+        .get(url.clone())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status().map_err(Into::into))
+        .map_err(network_error)?;
+
+    // write to a temporary file first, so that an interrupted download is not
+    // picked up as a cached package
+    let parent = package
+        .parent()
+        .expect("the package has a parent directory");
+    fs::create_dir_all(parent)?;
+    let mut temp_file = tempfile::NamedTempFile::new_in(parent)?;
+
+    // stream the body to disk instead of buffering (large) packages in memory
+    let mut body = response.bytes_stream();
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| network_error(e.into()))?;
+        hasher.update(&chunk);
+        temp_file.write_all(&chunk)?;
+    }
+    temp_file.persist(&package).map_err(|e| e.error)?;
+    tracing::info!(
+        "Downloaded {} (sha256: {:x})",
+        package.display(),
+        hasher.finalize()
+    );
+
+    Ok(package)
+}
+
+/// Downloads a package from `url` (see [`download_package`]) and extracts its
+/// recipe to a destination folder. The package is kept in `cache_dir` if one
+/// is given, otherwise it is downloaded to a temporary directory.
+pub async fn extract_recipe_from_url(
+    client: &ClientWithMiddleware,
+    url: &Url,
+    dest_folder: &Path,
+    cache_dir: Option<&Path>,
+) -> Result<(), DownloadPackageError> {
+    let temp_dir;
+    let download_dir = match cache_dir {
+        Some(cache_dir) => cache_dir,
+        None => {
+            temp_dir = tempfile::tempdir()?;
+            temp_dir.path()
+        }
+    };
+
+    let package = download_package(client, url, download_dir).await?;
+    extract_recipe_verified(&package, dest_folder, None)?;
+    Ok(())
+}
+
+/// Extracts a folder (e.g. `info/recipe` or `lib`) from a package archive to
+/// a destination folder.
+pub fn extract_folder(
+    package: &Path,
+    find_path: &Path,
+    dest_folder: &Path,
+) -> Result<(), std::io::Error> {
+    extract_folder_with_reporter(package, find_path, dest_folder, None)
+}
+
+/// Like [`extract_folder`], but notifies the `reporter` about every extracted
+/// entry.
+pub fn extract_folder_with_reporter(
+    package: &Path,
+    find_path: &Path,
+    dest_folder: &Path,
+    reporter: Option<&dyn ExtractReporter>,
+) -> Result<(), std::io::Error> {
+    match archive_type_of(package)? {
+        ArchiveType::TarBz2 => folder_from_tar_bz2(package, find_path, dest_folder, reporter)?,
+        ArchiveType::Conda => folder_from_conda(package, find_path, dest_folder, reporter)?,
+    };
+    Ok(())
+}
+
+/// Extracts the full contents of a package archive (including the `info`
+/// folder) to a destination folder. File permissions and symlinks are
+/// preserved.
+pub fn extract_package(package: &Path, dest_folder: &Path) -> Result<(), std::io::Error> {
+    extract_folder(package, Path::new(""), dest_folder)
+}
+
+/// Like [`extract_package`], but notifies the `reporter` about every extracted
+/// entry, e.g. to drive a progress bar.
+pub fn extract_package_with_reporter(
+    package: &Path,
+    dest_folder: &Path,
+    reporter: &dyn ExtractReporter,
+) -> Result<(), std::io::Error> {
+    extract_folder_with_reporter(package, Path::new(""), dest_folder, Some(reporter))
+}
+
+/// Extracts the files of a package archive that match `filter` (e.g.
+/// `**/*.so`) to a destination folder, keeping their layout relative to the
+/// root of the package. File permissions and symlinks are preserved. Returns
+/// the paths of the extracted files, relative to `dest_folder`.
+pub fn extract_matching(
+    package: &Path,
+    filter: &GlobVec,
+    dest_folder: &Path,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    match archive_type_of(package)? {
+        ArchiveType::TarBz2 => matching_from_tar_bz2(package, filter, dest_folder),
+        ArchiveType::Conda => matching_from_conda(package, filter, dest_folder),
+    }
+}
+
+/// Packs a folder (e.g. one that was extracted with [`extract_package`] and
+/// modified afterwards) into a package archive of the given type. All files
+/// and symlinks below the folder are added with their permissions, the
+/// folder has to contain the `info` folder of the package.
+///
+/// The name of a `.conda` archive is derived from the file name of `package`.
+pub fn repack_package(
+    folder: &Path,
+    package: &Path,
+    archive_type: ArchiveType,
+) -> Result<(), std::io::Error> {
+    if !folder.join("info").is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} does not contain an `info` folder", folder.display()),
+        ));
+    }
+
+    let mut paths = Vec::new();
+    for entry in walkdir::WalkDir::new(folder).min_depth(1) {
+        let entry = entry?;
+        // directories are created implicitly when the package is installed
+        if !entry.file_type().is_dir() {
+            paths.push(entry.into_path());
+        }
+    }
+    paths.sort();
+
+    let file = fs::File::create(package)?;
+    match archive_type {
+        ArchiveType::TarBz2 => rattler_package_streaming::write::write_tar_bz2_package(
+            file,
+            folder,
+            &paths,
+            CompressionLevel::Default,
+            None,
+            None,
+        ),
+        ArchiveType::Conda => {
+            let file_name = package
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default();
+            let out_name =
+                ArchiveType::split_str(&file_name).map_or(file_name.as_ref(), |(name, _)| name);
+            rattler_package_streaming::write::write_conda_package(
+                file,
+                folder,
+                &paths,
+                CompressionLevel::Default,
+                None,
+                out_name,
+                None,
+                None,
+            )
+        }
+    }
+}
+
+/// An error that can occur when reading the rendered recipe from a package.
+#[derive(Debug, thiserror::Error)]
+pub enum RenderedRecipeError {
+    /// The package could not be read.
+    #[error("failed to read package: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The package does not contain a rendered recipe.
+    #[error(
+        "package does not contain `info/recipe/rendered_recipe.yaml`, it was probably built with an older version of rattler-build"
+    )]
+    MissingRenderedRecipe,
+
+    /// A file in the recipe folder could not be parsed.
+    #[error("failed to parse `{0}`: {1}")]
+    Parse(&'static str, #[source] serde_yaml::Error),
+}
+
+/// The rendered recipe and variant that a package was built with.
+#[derive(Debug, Clone)]
+pub struct RenderedRecipe {
+    /// The rendered recipe (`info/recipe/rendered_recipe.yaml`).
+    pub output: Output,
+    /// The variant (`info/recipe/variant_config.yaml`). Falls back to the
+    /// variant of the rendered recipe if the file is missing.
+    pub variant: BTreeMap<NormalizedKey, Variable>,
+}
+
+/// Reads the given files from a tar archive into memory.
+fn read_archive_files(
+    archive: &mut tar::Archive<impl Read>,
+    paths: &[&'static str],
+    files: &mut BTreeMap<&'static str, String>,
+) -> Result<(), std::io::Error> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        if let Some(path) = paths.iter().find(|p| entry_path == Path::new(p)) {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            files.insert(*path, contents);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the rendered recipe and variant from a package archive without
+/// extracting it to disk.
+pub fn read_rendered_recipe(package: &Path) -> Result<RenderedRecipe, RenderedRecipeError> {
+    const RENDERED_RECIPE: &str = "info/recipe/rendered_recipe.yaml";
+    const VARIANT_CONFIG: &str = "info/recipe/variant_config.yaml";
+
+    let mut files = BTreeMap::new();
+    match archive_type_of(package)? {
+        ArchiveType::TarBz2 => {
+            let reader = fs::File::open(package)?;
+            let mut archive = rattler_package_streaming::read::stream_tar_bz2(reader);
+            read_archive_files(&mut archive, &[RENDERED_RECIPE, VARIANT_CONFIG], &mut files)?;
+        }
+        ArchiveType::Conda => {
+            let mut info =
+                rattler_package_streaming::seek::stream_conda_info(fs::File::open(package)?)
+                    .map_err(std::io::Error::other)?;
+            read_archive_files(&mut info, &[RENDERED_RECIPE, VARIANT_CONFIG], &mut files)?;
+        }
+    }
+
+    let rendered_recipe = files
+        .remove(RENDERED_RECIPE)
+        .ok_or(RenderedRecipeError::MissingRenderedRecipe)?;
+    let output: Output = serde_yaml::from_str(&rendered_recipe)
+        .map_err(|e| RenderedRecipeError::Parse(RENDERED_RECIPE, e))?;
+    let variant = match files.remove(VARIANT_CONFIG) {
+        Some(variant_config) => serde_yaml::from_str(&variant_config)
+            .map_err(|e| RenderedRecipeError::Parse(VARIANT_CONFIG, e))?,
+        None => output.build_configuration.variant.clone(),
+    };
+
+    Ok(RenderedRecipe { output, variant })
+}
+
+/// An entry of a tar archive that was looked up by its path.
+enum ArchiveFile {
+    /// The contents of a regular file.
+    Contents(Vec<u8>),
+    /// A symlink or hard link to the file at the given path in the archive.
+    Link(PathBuf),
+}
+
+/// Resolves the target of a link entry at `path` to a path relative to the
+/// root of the archive. Symlinks are relative to the folder of the entry, hard
+/// links to the root of the archive. Targets outside of the archive are
+/// rejected.
+fn link_target_in_archive(
+    path: &Path,
+    link_name: &Path,
+    is_symlink: bool,
+) -> Result<PathBuf, std::io::Error> {
+    let outside = || {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{} links to {}, which is outside of the package",
+                path.display(),
+                link_name.display()
+            ),
+        )
+    };
+
+    let base = match path.parent() {
+        Some(parent) if is_symlink => parent,
+        _ => Path::new(""),
+    };
+    let mut target = PathBuf::new();
+    for component in base.join(link_name).components() {
+        match component {
+            Component::Normal(name) => target.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !target.pop() {
+                    return Err(outside());
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return Err(outside()),
+        }
+    }
+    Ok(target)
+}
+
+/// Looks up the entry at `path` in a tar archive.
+fn read_archive_file(
+    archive: &mut tar::Archive<impl Read>,
+    path: &Path,
+) -> Result<Option<ArchiveFile>, std::io::Error> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()? != path {
+            continue;
+        }
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let link_name = entry.link_name()?.unwrap_or_default();
+            let target = link_target_in_archive(path, &link_name, entry_type.is_symlink())?;
+            return Ok(Some(ArchiveFile::Link(target)));
+        }
+
+        // the size in the header is not trusted for the allocation
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        return Ok(Some(ArchiveFile::Contents(contents)));
+    }
+    Ok(None)
+}
+
+/// Looks up the entry at `path` in the layer of the package that contains it.
+fn read_package_entry(package: &Path, path: &Path) -> Result<Option<ArchiveFile>, std::io::Error> {
+    match archive_type_of(package)? {
+        ArchiveType::TarBz2 => {
+            let reader = fs::File::open(package)?;
+            let mut archive = rattler_package_streaming::read::stream_tar_bz2(reader);
+            read_archive_file(&mut archive, path)
+        }
+        ArchiveType::Conda => {
+            let reader = fs::File::open(package)?;
+            if path.starts_with("info") {
+                let mut archive = rattler_package_streaming::seek::stream_conda_info(reader)
+                    .map_err(std::io::Error::other)?;
+                read_archive_file(&mut archive, path)
+            } else {
+                let mut archive = rattler_package_streaming::seek::stream_conda_content(reader)
+                    .map_err(std::io::Error::other)?;
+                read_archive_file(&mut archive, path)
+            }
+        }
+    }
+}
+
+/// Reads a single file (e.g. `info/recipe/recipe.yaml`) from a package archive
+/// into memory, without extracting anything to disk. Only the layer of a
+/// `.conda` archive that contains the file is decompressed. Symlinks and hard
+/// links are followed, as long as they point to a file inside of the package.
+/// Returns `None` if the package does not contain the file (or the target of
+/// the link).
+pub fn read_file_from_package(
+    package: &Path,
+    path: &Path,
+) -> Result<Option<Vec<u8>>, std::io::Error> {
+    // the same limit as the `ELOOP` limit of Linux
+    const MAX_LINKS: usize = 40;
+
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_LINKS {
+        match read_package_entry(package, &path)? {
+            None => return Ok(None),
+            Some(ArchiveFile::Contents(contents)) => return Ok(Some(contents)),
+            Some(ArchiveFile::Link(target)) => path = target,
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("too many levels of links while reading {}", path.display()),
+    ))
+}
+
+/// The content hash of a single file in a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileContentHash {
+    /// The sha256 hash of the file contents (or of the link target for symlinks).
+    pub sha256: Sha256Hash,
+    /// The uncompressed size of the file in bytes.
+    pub size: u64,
+}
+
+/// The content hash of a package.
+///
+/// The hash is computed over the files inside the archive and is independent
+/// of the archive type, the compression and the timestamps of the entries.
+#[derive(Debug, Clone, Default)]
+pub struct PackageContentHash {
+    /// The hashes of all files in the package, keyed by their path in the package.
+    pub files: BTreeMap<PathBuf, FileContentHash>,
+    /// The files that matched the ignore globs and were excluded from the hash.
+    pub ignored: BTreeSet<PathBuf>,
+}
+
+impl PackageContentHash {
+    /// Returns a single hash over all (non-ignored) files of the package.
+    pub fn digest(&self) -> Sha256Hash {
+        let mut hasher = Sha256::default();
+        for (path, file) in &self.files {
+            hasher.update(path.to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hasher.update(file.sha256);
+        }
+        hasher.finalize()
+    }
+}
+
+/// A file that is present in both packages but has different contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedFile {
+    /// The path of the file in the package.
+    pub path: PathBuf,
+    /// The size of the file in the first package.
+    pub old_size: u64,
+    /// The size of the file in the second package.
+    pub new_size: u64,
+}
+
+impl ChangedFile {
+    /// Returns the difference in size (in bytes) between the two files.
+    pub fn size_difference(&self) -> i64 {
+        self.new_size as i64 - self.old_size as i64
+    }
+}
+
+/// The difference between the contents of two packages.
+#[derive(Debug, Clone, Default)]
+pub struct PackageDiff {
+    /// Files that only exist in the first package.
+    pub removed: Vec<PathBuf>,
+    /// Files that only exist in the second package.
+    pub added: Vec<PathBuf>,
+    /// Files that exist in both packages but differ in content.
+    pub changed: Vec<ChangedFile>,
+    /// Files that were ignored in either package.
+    pub ignored: BTreeSet<PathBuf>,
+}
+
+impl PackageDiff {
+    /// Returns true if both packages have the same contents (modulo the
+    /// ignored files).
+    pub fn is_identical(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl std::fmt::Display for PackageDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for path in &self.removed {
+            writeln!(f, "  - {} (removed)", path.display())?;
+        }
+        for path in &self.added {
+            writeln!(f, "  + {} (added)", path.display())?;
+        }
+        for file in &self.changed {
+            writeln!(
+                f,
+                "  ~ {} ({:+} bytes)",
+                file.path.display(),
+                file.size_difference()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of comparing a rebuilt package with the original package.
+#[derive(Debug, Clone)]
+pub struct ReproducibilityReport {
+    /// The sha256 hash of the original package archive.
+    pub original_sha256: Sha256Hash,
+    /// The sha256 hash of the rebuilt package archive.
+    pub rebuilt_sha256: Sha256Hash,
+    /// The file by file difference between the two packages.
+    pub diff: PackageDiff,
+}
+
+impl ReproducibilityReport {
+    /// Returns true if both archives are bit-for-bit identical.
+    pub fn is_bit_identical(&self) -> bool {
+        self.original_sha256 == self.rebuilt_sha256
+    }
+
+    /// Returns true if both packages contain the same files (modulo the
+    /// ignored files), even if the archives differ (e.g. in timestamps or
+    /// compression).
+    pub fn is_content_identical(&self) -> bool {
+        self.diff.is_identical()
+    }
+}
+
+/// Compares a rebuilt package with the original package, both by the hash of
+/// the archives and file by file. Files matching the `ignore` globs are
+/// excluded from the file comparison.
+pub fn check_reproducibility(
+    original: &Path,
+    rebuilt: &Path,
+    ignore: &GlobVec,
+) -> Result<ReproducibilityReport, std::io::Error> {
+    Ok(ReproducibilityReport {
+        original_sha256: rattler_digest::compute_file_digest::<Sha256>(original)?,
+        rebuilt_sha256: rattler_digest::compute_file_digest::<Sha256>(rebuilt)?,
+        diff: diff_packages(original, rebuilt, ignore)?,
+    })
+}
+
+fn archive_type_of(package: &Path) -> Result<ArchiveType, std::io::Error> {
+    ArchiveType::try_from(package).ok_or_else(|| {
         std::io::Error::new(
             std::io::ErrorKind::NotFound,
             "package does not point to valid archive",
         )
-    })?;
-    let path = PathBuf::from("info/recipe");
-    match archive_type {
-        ArchiveType::TarBz2 => folder_from_tar_bz2(package, &path, dest_folder)?,
-        ArchiveType::Conda => folder_from_conda(package, &path, dest_folder)?,
+    })
+}
+
+fn hash_archive_entries(
+    archive: &mut tar::Archive<impl Read>,
+    ignore: &GlobVec,
+    result: &mut PackageContentHash,
+) -> Result<(), std::io::Error> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_dir() {
+            continue;
+        }
+
+        if ignore.is_match(&path) {
+            result.ignored.insert(path);
+            continue;
+        }
+
+        let mut hasher = Sha256::default();
+        let size = if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let target = entry.link_name()?.unwrap_or_default();
+            hasher.update(target.to_string_lossy().as_bytes());
+            0
+        } else {
+            std::io::copy(&mut entry, &mut hasher)?
+        };
+
+        result.files.insert(
+            path,
+            FileContentHash {
+                sha256: hasher.finalize(),
+                size,
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Computes the content hash of a package archive. Files matching the
+/// `ignore` globs are excluded from the hash and reported in
+/// [`PackageContentHash::ignored`].
+pub fn package_content_hash(
+    package: &Path,
+    ignore: &GlobVec,
+) -> Result<PackageContentHash, std::io::Error> {
+    let mut result = PackageContentHash::default();
+    match archive_type_of(package)? {
+        ArchiveType::TarBz2 => {
+            let reader = fs::File::open(package)?;
+            let mut archive = rattler_package_streaming::read::stream_tar_bz2(reader);
+            hash_archive_entries(&mut archive, ignore, &mut result)?;
+        }
+        ArchiveType::Conda => {
+            let mut info =
+                rattler_package_streaming::seek::stream_conda_info(fs::File::open(package)?)
+                    .map_err(std::io::Error::other)?;
+            hash_archive_entries(&mut info, ignore, &mut result)?;
+
+            let mut content =
+                rattler_package_streaming::seek::stream_conda_content(fs::File::open(package)?)
+                    .map_err(std::io::Error::other)?;
+            hash_archive_entries(&mut content, ignore, &mut result)?;
+        }
+    }
+    Ok(result)
+}
+
+/// Compares the contents of two packages file by file. Files matching the
+/// `ignore` globs (e.g. files with embedded timestamps) are excluded from
+/// the comparison and reported in [`PackageDiff::ignored`].
+pub fn diff_packages(
+    old: &Path,
+    new: &Path,
+    ignore: &GlobVec,
+) -> Result<PackageDiff, std::io::Error> {
+    let old = package_content_hash(old, ignore)?;
+    let new = package_content_hash(new, ignore)?;
+
+    let mut diff = PackageDiff {
+        ignored: old.ignored.union(&new.ignored).cloned().collect(),
+        ..Default::default()
     };
+
+    for (path, old_file) in &old.files {
+        match new.files.get(path) {
+            None => diff.removed.push(path.clone()),
+            Some(new_file) if new_file != old_file => diff.changed.push(ChangedFile {
+                path: path.clone(),
+                old_size: old_file.size,
+                new_size: new_file.size,
+            }),
+            Some(_) => {}
+        }
+    }
+
+    diff.added = new
+        .files
+        .keys()
+        .filter(|path| !old.files.contains_key(*path))
+        .cloned()
+        .collect();
+
+    Ok(diff)
+}
+
+fn apply_changed_entries(
+    archive: &mut tar::Archive<impl Read>,
+    prefix: &Path,
+    applied: &mut Vec<PathBuf>,
+) -> Result<(), std::io::Error> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_dir() || path.starts_with("info") {
+            continue;
+        }
+
+        let Some(dest_file) = prepare_dest_file(prefix, &path)? else {
+            tracing::warn!(
+                "Skipping {} because it is outside of the prefix",
+                path.display()
+            );
+            continue;
+        };
+
+        if entry_type.is_hard_link() {
+            let target = hard_link_target(&entry, Path::new(""), prefix)?;
+            let is_unchanged = fs::symlink_metadata(&dest_file).is_ok_and(|m| m.is_file())
+                && rattler_digest::compute_file_digest::<Sha256>(&dest_file)?
+                    == rattler_digest::compute_file_digest::<Sha256>(&target)?;
+            if is_unchanged {
+                continue;
+            }
+            hard_link_entry(&target, prefix, &dest_file)?;
+            applied.push(path);
+            continue;
+        }
+
+        if entry_type.is_symlink() {
+            let target = entry.link_name()?.unwrap_or_default();
+            if fs::read_link(&dest_file).is_ok_and(|existing| existing == target) {
+                continue;
+            }
+            if fs::symlink_metadata(&dest_file).is_ok() {
+                fs::remove_file(&dest_file)?;
+            }
+            entry.unpack(&dest_file)?;
+            applied.push(path);
+            continue;
+        }
+
+        // Write the entry to a temporary file next to the destination so that
+        // we can compare the hashes before replacing the existing file.
+        let mut temp_file = tempfile::NamedTempFile::new_in(dest_file.parent().unwrap_or(prefix))?;
+        std::io::copy(&mut entry, &mut temp_file)?;
+        let new_hash = rattler_digest::compute_file_digest::<Sha256>(temp_file.path())?;
+
+        let is_unchanged = fs::symlink_metadata(&dest_file).is_ok_and(|m| m.is_file())
+            && rattler_digest::compute_file_digest::<Sha256>(&dest_file)? == new_hash;
+        if is_unchanged {
+            continue;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = entry.header().mode()?;
+            temp_file
+                .as_file()
+                .set_permissions(std::fs::Permissions::from_mode(mode))?;
+        }
+
+        if fs::symlink_metadata(&dest_file).is_ok_and(|m| m.is_symlink()) {
+            fs::remove_file(&dest_file)?;
+        }
+        temp_file.persist(&dest_file).map_err(|e| e.error)?;
+        applied.push(path);
+    }
     Ok(())
 }
+
+/// Extracts only the files of a package whose contents differ from the files
+/// that are currently in the prefix (by hash). Files missing from the prefix
+/// are extracted as well. The `info` folder and entries that would end up
+/// outside of the prefix are skipped, and hard links are recreated in the
+/// prefix.
+///
+/// Returns the paths (relative to the prefix) of the files that were written.
+pub fn extract_changed_files(
+    package: &Path,
+    prefix: &Path,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut applied = Vec::new();
+    match archive_type_of(package)? {
+        ArchiveType::TarBz2 => {
+            let reader = fs::File::open(package)?;
+            let mut archive = rattler_package_streaming::read::stream_tar_bz2(reader);
+            apply_changed_entries(&mut archive, prefix, &mut applied)?;
+        }
+        ArchiveType::Conda => {
+            let mut content =
+                rattler_package_streaming::seek::stream_conda_content(fs::File::open(package)?)
+                    .map_err(std::io::Error::other)?;
+            apply_changed_entries(&mut content, prefix, &mut applied)?;
+        }
+    }
+    Ok(applied)
+}
+
+/// A file in a package together with its uncompressed size.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PackageEntrySize {
+    /// The uncompressed size of the file in bytes.
+    pub size: u64,
+    /// The path of the file in the package.
+    pub path: PathBuf,
+}
+
+fn collect_largest_entries(
+    archive: &mut tar::Archive<impl Read>,
+    n: usize,
+    heap: &mut BinaryHeap<Reverse<PackageEntrySize>>,
+) -> Result<(), std::io::Error> {
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        heap.push(Reverse(PackageEntrySize {
+            size: entry.header().size()?,
+            path: entry.path()?.into_owned(),
+        }));
+        if heap.len() > n {
+            heap.pop();
+        }
+    }
+    Ok(())
+}
+
+/// Returns the `n` largest files in a package by uncompressed size, largest
+/// first. The archive is streamed, no files are extracted to disk.
+pub fn largest_files(package: &Path, n: usize) -> Result<Vec<PackageEntrySize>, std::io::Error> {
+    let mut heap = BinaryHeap::with_capacity(n + 1);
+    match archive_type_of(package)? {
+        ArchiveType::TarBz2 => {
+            let reader = fs::File::open(package)?;
+            let mut archive = rattler_package_streaming::read::stream_tar_bz2(reader);
+            collect_largest_entries(&mut archive, n, &mut heap)?;
+        }
+        ArchiveType::Conda => {
+            let mut info =
+                rattler_package_streaming::seek::stream_conda_info(fs::File::open(package)?)
+                    .map_err(std::io::Error::other)?;
+            collect_largest_entries(&mut info, n, &mut heap)?;
+
+            let mut content =
+                rattler_package_streaming::seek::stream_conda_content(fs::File::open(package)?)
+                    .map_err(std::io::Error::other)?;
+            collect_largest_entries(&mut content, n, &mut heap)?;
+        }
+    }
+
+    // `into_sorted_vec` sorts ascending by `Reverse`, i.e. largest first
+    Ok(heap
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(entry)| entry)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rattler_package_streaming::write::{write_conda_package, write_tar_bz2_package};
+
+    /// Writes a package with the given files (relative path, contents) to
+    /// `dest`.
+    fn write_package(dest: &Path, files: &[(&str, &[u8])]) {
+        let source = tempfile::tempdir().unwrap();
+        let mut paths = Vec::new();
+        for (path, contents) in files {
+            let full_path = source.path().join(path);
+            fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            fs::write(&full_path, contents).unwrap();
+            paths.push(full_path);
+        }
+        write_package_from_paths(dest, source.path(), &paths);
+    }
+
+    /// Writes a package with the given (absolute) paths below `source` to
+    /// `dest`.
+    fn write_package_from_paths(dest: &Path, source: &Path, paths: &[PathBuf]) {
+        let file = fs::File::create(dest).unwrap();
+        match ArchiveType::try_from(dest).unwrap() {
+            ArchiveType::TarBz2 => {
+                write_tar_bz2_package(file, source, paths, CompressionLevel::Default, None, None)
+                    .unwrap()
+            }
+            ArchiveType::Conda => write_conda_package(
+                file,
+                source,
+                paths,
+                CompressionLevel::Default,
+                None,
+                "test-1.0-0",
+                None,
+                None,
+            )
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_diff_packages_ignores_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let old = tmp.path().join("old/test-1.0-0.conda");
+        let new = tmp.path().join("new/test-1.0-0.conda");
+        fs::create_dir_all(old.parent().unwrap()).unwrap();
+        fs::create_dir_all(new.parent().unwrap()).unwrap();
+
+        write_package(
+            &old,
+            &[
+                ("info/index.json", b"{}"),
+                ("lib/foo.py", b"print('foo')"),
+                ("lib/__pycache__/foo.pyc", b"timestamp-1"),
+            ],
+        );
+        write_package(
+            &new,
+            &[
+                ("info/index.json", b"{}"),
+                ("lib/foo.py", b"print('foo')"),
+                ("lib/__pycache__/foo.pyc", b"timestamp-22"),
+            ],
+        );
+
+        let diff = diff_packages(&old, &new, &GlobVec::default()).unwrap();
+        assert!(!diff.is_identical());
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].size_difference(), 1);
+
+        let ignore = GlobVec::from_vec(vec!["**/*.pyc"], None);
+        let diff = diff_packages(&old, &new, &ignore).unwrap();
+        assert!(diff.is_identical());
+        assert_eq!(
+            diff.ignored.into_iter().collect::<Vec<_>>(),
+            vec![PathBuf::from("lib/__pycache__/foo.pyc")]
+        );
+
+        assert_eq!(
+            package_content_hash(&old, &ignore).unwrap().digest(),
+            package_content_hash(&new, &ignore).unwrap().digest()
+        );
+    }
+
+    #[test]
+    fn test_largest_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let files: &[(&str, &[u8])] = &[
+            ("info/index.json", b"{}"),
+            ("lib/small.so", &[0; 10]),
+            ("lib/large.so", &[0; 1000]),
+            ("share/medium.txt", &[0; 100]),
+        ];
+
+        for name in ["test-1.0-0.tar.bz2", "test-1.0-0.conda"] {
+            let package = tmp.path().join(name);
+            write_package(&package, files);
+
+            let largest = largest_files(&package, 2).unwrap();
+            assert_eq!(
+                largest,
+                vec![
+                    PackageEntrySize {
+                        size: 1000,
+                        path: PathBuf::from("lib/large.so"),
+                    },
+                    PackageEntrySize {
+                        size: 100,
+                        path: PathBuf::from("share/medium.txt"),
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_folder_from_conda_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let package = tmp.path().join("test-1.0-0.conda");
+        write_package(
+            &package,
+            &[
+                ("info/index.json", b"{}"),
+                ("info/recipe/recipe.yaml", b"package: {}"),
+                ("lib/libfoo.so", b"foo"),
+                ("lib/pkgconfig/foo.pc", b"prefix=/opt"),
+                ("bin/foo", b"#!/bin/sh"),
+            ],
+        );
+
+        let dest = tmp.path().join("lib");
+        extract_folder(&package, Path::new("lib"), &dest).unwrap();
+        assert_eq!(fs::read(dest.join("libfoo.so")).unwrap(), b"foo");
+        assert_eq!(
+            fs::read(dest.join("pkgconfig/foo.pc")).unwrap(),
+            b"prefix=/opt"
+        );
+        assert!(!dest.join("foo").exists());
+        assert!(!dest.join("recipe.yaml").exists());
+
+        // the info layer is still used for paths below `info`
+        let dest = tmp.path().join("recipe");
+        extract_recipe(&package, &dest).unwrap();
+        assert_eq!(fs::read(dest.join("recipe.yaml")).unwrap(), b"package: {}");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_extract_package() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source");
+        fs::create_dir_all(source.join("info")).unwrap();
+        fs::create_dir_all(source.join("lib")).unwrap();
+        fs::create_dir_all(source.join("bin")).unwrap();
+        fs::write(source.join("info/index.json"), "{}").unwrap();
+        fs::write(source.join("lib/libfoo.so.1"), "foo").unwrap();
+        fs::write(source.join("bin/foo"), "#!/bin/sh").unwrap();
+        fs::set_permissions(
+            source.join("bin/foo"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        fs_err::os::unix::fs::symlink("libfoo.so.1", source.join("lib/libfoo.so")).unwrap();
+
+        let paths = [
+            "info/index.json",
+            "lib/libfoo.so.1",
+            "lib/libfoo.so",
+            "bin/foo",
+        ]
+        .iter()
+        .map(|p| source.join(p))
+        .collect::<Vec<_>>();
+
+        for name in ["test-1.0-0.tar.bz2", "test-1.0-0.conda"] {
+            let package = tmp.path().join(name);
+            write_package_from_paths(&package, &source, &paths);
+
+            let dest = tmp.path().join(format!("{name}-extracted"));
+            extract_package(&package, &dest).unwrap();
+
+            assert_eq!(fs::read(dest.join("info/index.json")).unwrap(), b"{}");
+            assert_eq!(fs::read(dest.join("lib/libfoo.so.1")).unwrap(), b"foo");
+
+            let symlink = dest.join("lib/libfoo.so");
+            assert!(fs::symlink_metadata(&symlink).unwrap().is_symlink());
+            assert_eq!(
+                fs::read_link(&symlink).unwrap(),
+                PathBuf::from("libfoo.so.1")
+            );
+
+            let mode = fs::metadata(dest.join("bin/foo"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+    }
+
+    /// Serves the given responses (status line, body) to the next connections
+    /// on a local port and returns the base URL of the server.
+    fn serve(responses: Vec<(&'static str, Vec<u8>)>) -> Url {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_extract_recipe_from_url() {
+        let tmp = tempfile::tempdir().unwrap();
+        let package = tmp.path().join("test-1.0-0.conda");
+        write_package(
+            &package,
+            &[
+                ("info/index.json", b"{}"),
+                ("info/recipe/recipe.yaml", b"package: {}"),
+            ],
+        );
+        let client =
+            ClientWithMiddleware::from(reqwest::Client::builder().no_proxy().build().unwrap());
+
+        // the second request is answered from the cache
+        let url = serve(vec![("200 OK", fs::read(&package).unwrap())]);
+        let cache_dir = tmp.path().join("cache");
+        for dest in ["recipe-1", "recipe-2"] {
+            let dest = tmp.path().join(dest);
+            extract_recipe_from_url(
+                &client,
+                &url.join("conda-forge/noarch/test-1.0-0.conda").unwrap(),
+                &dest,
+                Some(&cache_dir),
+            )
+            .await
+            .unwrap();
+            assert_eq!(fs::read(dest.join("recipe.yaml")).unwrap(), b"package: {}");
+        }
+
+        let url = serve(vec![
+            ("404 Not Found", Vec::new()),
+            ("200 OK", b"not a package".to_vec()),
+        ]);
+        let package_url = url.join("missing-1.0-0.conda").unwrap();
+        let dest = tmp.path().join("missing");
+        let error = extract_recipe_from_url(&client, &package_url, &dest, None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, DownloadPackageError::Network { .. }),
+            "{error:?}"
+        );
+        let error = extract_recipe_from_url(&client, &package_url, &dest, None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, DownloadPackageError::Archive(_)),
+            "{error:?}"
+        );
+
+        let error = download_package(&client, &url.join("index.html").unwrap(), tmp.path())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, DownloadPackageError::UnsupportedUrl(_)));
+    }
+
+    #[test]
+    fn test_read_file_from_package() {
+        let tmp = tempfile::tempdir().unwrap();
+        let files: &[(&str, &[u8])] = &[
+            ("info/index.json", b"{}"),
+            ("info/recipe/meta.yaml", b"package:\n  name: test"),
+            ("lib/libfoo.so", &[1, 2, 3]),
+        ];
+
+        for name in ["test-1.0-0.tar.bz2", "test-1.0-0.conda"] {
+            let package = tmp.path().join(name);
+            write_package(&package, files);
+
+            let read = |path: &str| read_file_from_package(&package, Path::new(path)).unwrap();
+            assert_eq!(
+                read("info/recipe/meta.yaml").as_deref(),
+                Some(&b"package:\n  name: test"[..])
+            );
+            assert_eq!(read("lib/libfoo.so").as_deref(), Some(&[1, 2, 3][..]));
+            assert_eq!(read("info/recipe/recipe.yaml"), None);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_read_file_from_package_links() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source");
+        fs::create_dir_all(source.join("lib")).unwrap();
+        fs::create_dir_all(source.join("info")).unwrap();
+        fs::write(source.join("info/index.json"), "{}").unwrap();
+        fs::write(source.join("lib/libfoo.so.1.0"), "foo").unwrap();
+        fs_err::os::unix::fs::symlink("libfoo.so.1.0", source.join("lib/libfoo.so.1")).unwrap();
+        fs_err::os::unix::fs::symlink("../lib/libfoo.so.1", source.join("lib/libfoo.so")).unwrap();
+        fs_err::os::unix::fs::symlink("missing", source.join("lib/dangling")).unwrap();
+        fs_err::os::unix::fs::symlink("loop", source.join("lib/loop")).unwrap();
+
+        let paths = [
+            "info/index.json",
+            "lib/libfoo.so.1.0",
+            "lib/libfoo.so.1",
+            "lib/libfoo.so",
+            "lib/dangling",
+            "lib/loop",
+        ]
+        .iter()
+        .map(|p| source.join(p))
+        .collect::<Vec<_>>();
+
+        for name in ["test-1.0-0.tar.bz2", "test-1.0-0.conda"] {
+            let package = tmp.path().join(name);
+            write_package_from_paths(&package, &source, &paths);
+
+            let read = |path: &str| read_file_from_package(&package, Path::new(path));
+            assert_eq!(read("lib/libfoo.so").unwrap().as_deref(), Some(&b"foo"[..]));
+            assert_eq!(read("lib/dangling").unwrap(), None);
+            assert!(read("lib/loop").is_err());
+        }
+
+        // links may not point outside of the package
+        assert!(
+            link_target_in_archive(Path::new("lib/foo"), Path::new("../../etc/passwd"), true)
+                .is_err()
+        );
+        assert!(
+            link_target_in_archive(Path::new("lib/foo"), Path::new("/etc/passwd"), true).is_err()
+        );
+        assert_eq!(
+            link_target_in_archive(Path::new("lib/foo"), Path::new("lib/bar"), false).unwrap(),
+            Path::new("lib/bar")
+        );
+    }
+
+    #[test]
+    fn test_extract_recipe_verified() {
+        let tmp = tempfile::tempdir().unwrap();
+        let files: &[(&str, &[u8])] = &[
+            ("info/index.json", b"{}"),
+            ("info/recipe/recipe.yaml", b"package: {}"),
+            ("lib/libfoo.so", &[1; 4096]),
+        ];
+
+        for name in ["test-1.0-0.tar.bz2", "test-1.0-0.conda"] {
+            let package = tmp.path().join(name);
+            write_package(&package, files);
+            let sha256 = rattler_digest::compute_file_digest::<Sha256>(&package).unwrap();
+
+            let dest = tmp.path().join(format!("{name}-recipe"));
+            extract_recipe_verified(&package, &dest, Some(&sha256)).unwrap();
+            assert_eq!(fs::read(dest.join("recipe.yaml")).unwrap(), b"package: {}");
+
+            let wrong_sha256 = Sha256::digest(b"other");
+            let error = verify_archive(&package, Some(&wrong_sha256)).unwrap_err();
+            assert!(matches!(
+                error,
+                ArchiveIntegrityError::Sha256Mismatch { actual, .. } if actual == sha256
+            ));
+
+            // a truncated package is detected before anything is extracted
+            let data = fs::read(&package).unwrap();
+            let truncated = tmp.path().join("truncated").join(name);
+            fs::create_dir_all(truncated.parent().unwrap()).unwrap();
+            fs::write(&truncated, &data[..data.len() * 3 / 4]).unwrap();
+            let dest = tmp.path().join(format!("{name}-truncated"));
+            let error = extract_recipe_verified(&truncated, &dest, None).unwrap_err();
+            assert!(
+                matches!(error, ArchiveIntegrityError::Corrupt { .. }),
+                "{error:?}"
+            );
+            assert!(!dest.exists());
+        }
+    }
+
+    #[test]
+    fn test_extract_package_with_reporter() {
+        let tmp = tempfile::tempdir().unwrap();
+        let files: &[(&str, &[u8])] = &[
+            ("info/index.json", b"{}"),
+            ("lib/libfoo.so", &[0; 100]),
+            ("share/doc/README.md", b"readme"),
+        ];
+
+        for name in ["test-1.0-0.tar.bz2", "test-1.0-0.conda"] {
+            let package = tmp.path().join(name);
+            write_package(&package, files);
+
+            let reported = std::sync::Mutex::new(Vec::new());
+            let reporter = |path: &Path, bytes: u64| {
+                reported.lock().unwrap().push((path.to_path_buf(), bytes));
+            };
+            let dest = tmp.path().join(format!("{name}-extracted"));
+            extract_package_with_reporter(&package, &dest, &reporter).unwrap();
+
+            let mut reported = reported.into_inner().unwrap();
+            reported.sort();
+            assert_eq!(
+                reported,
+                vec![
+                    (PathBuf::from("info/index.json"), 2),
+                    (PathBuf::from("lib/libfoo.so"), 100),
+                    (PathBuf::from("share/doc/README.md"), 6),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_extract_matching() {
+        let tmp = tempfile::tempdir().unwrap();
+        let files: &[(&str, &[u8])] = &[
+            ("info/index.json", b"{}"),
+            ("lib/libfoo.so", b"foo"),
+            ("lib/python3.12/site-packages/bar/_bar.so", b"bar"),
+            ("lib/libfoo.a", b"static"),
+            ("share/doc/README.md", b"readme"),
+        ];
+        let filter = GlobVec::from_vec(vec!["**/*.so", "info/*.json"], None);
+
+        for name in ["test-1.0-0.tar.bz2", "test-1.0-0.conda"] {
+            let package = tmp.path().join(name);
+            write_package(&package, files);
+
+            let dest = tmp.path().join(format!("{name}-extracted"));
+            let mut extracted = extract_matching(&package, &filter, &dest).unwrap();
+            extracted.sort();
+            assert_eq!(
+                extracted,
+                vec![
+                    PathBuf::from("info/index.json"),
+                    PathBuf::from("lib/libfoo.so"),
+                    PathBuf::from("lib/python3.12/site-packages/bar/_bar.so"),
+                ]
+            );
+            assert_eq!(
+                fs::read(dest.join("lib/python3.12/site-packages/bar/_bar.so")).unwrap(),
+                b"bar"
+            );
+            assert!(!dest.join("lib/libfoo.a").exists());
+            assert!(!dest.join("share").exists());
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_repack_package() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source");
+        fs::create_dir_all(source.join("info/recipe")).unwrap();
+        fs::create_dir_all(source.join("bin")).unwrap();
+        fs::write(source.join("info/index.json"), "{}").unwrap();
+        fs::write(source.join("info/recipe/recipe.yaml"), "build: {}").unwrap();
+        fs::write(source.join("bin/foo"), "#!/bin/sh").unwrap();
+        fs::set_permissions(
+            source.join("bin/foo"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        fs_err::os::unix::fs::symlink("foo", source.join("bin/bar")).unwrap();
+        let paths = [
+            "info/index.json",
+            "info/recipe/recipe.yaml",
+            "bin/foo",
+            "bin/bar",
+        ]
+        .iter()
+        .map(|p| source.join(p))
+        .collect::<Vec<_>>();
+
+        for (name, archive_type) in [
+            ("test-1.0-0.tar.bz2", ArchiveType::TarBz2),
+            ("test-1.0-0.conda", ArchiveType::Conda),
+        ] {
+            let package = tmp.path().join(name);
+            write_package_from_paths(&package, &source, &paths);
+
+            // extract, patch the recipe and repack
+            let extracted = tmp.path().join(format!("{name}-extracted"));
+            extract_package(&package, &extracted).unwrap();
+            fs::write(
+                extracted.join("info/recipe/recipe.yaml"),
+                "build: {number: 1}",
+            )
+            .unwrap();
+            let repacked = tmp.path().join("repacked").join(name);
+            fs::create_dir_all(repacked.parent().unwrap()).unwrap();
+            repack_package(&extracted, &repacked, archive_type).unwrap();
+
+            let dest = tmp.path().join(format!("{name}-repacked"));
+            extract_package(&repacked, &dest).unwrap();
+            assert_eq!(
+                fs::read(dest.join("info/recipe/recipe.yaml")).unwrap(),
+                b"build: {number: 1}"
+            );
+            assert_eq!(fs::read(dest.join("bin/foo")).unwrap(), b"#!/bin/sh");
+            assert_eq!(
+                fs::read_link(dest.join("bin/bar")).unwrap(),
+                PathBuf::from("foo")
+            );
+            let mode = fs::metadata(dest.join("bin/foo"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+
+            // only the recipe changed
+            let diff = diff_packages(&package, &repacked, &GlobVec::default()).unwrap();
+            assert_eq!(
+                diff.changed
+                    .iter()
+                    .map(|c| c.path.as_path())
+                    .collect::<Vec<_>>(),
+                vec![Path::new("info/recipe/recipe.yaml")]
+            );
+        }
+
+        let error = repack_package(
+            &source.join("bin"),
+            &tmp.path().join("x.conda"),
+            ArchiveType::Conda,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    /// An entry of a tar archive: path, type, link name and contents.
+    type TarEntry<'a> = (&'a str, tar::EntryType, Option<&'a str>, &'a [u8]);
+
+    /// Builds an uncompressed tar archive with the given entries. The link
+    /// names are not validated, so that malicious archives can be created.
+    fn tar_archive(entries: &[TarEntry<'_>]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, entry_type, link, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(*entry_type);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            if let Some(link) = link {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_hard_links_outside_of_dest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let secret = tmp.path().join("secret");
+        fs::write(&secret, "secret").unwrap();
+        let dest = tmp.path().join("dest");
+
+        for link_name in [secret.to_str().unwrap(), "../secret", "lib/../../secret"] {
+            let data = tar_archive(&[
+                ("lib/libfoo.so", tar::EntryType::Regular, None, b"foo"),
+                ("lib/evil", tar::EntryType::Link, Some(link_name), b""),
+            ]);
+
+            let mut archive = tar::Archive::new(data.as_slice());
+            let error = folder_from_archive(&mut archive, Path::new(""), &dest, None).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData, "{link_name}");
+
+            let mut archive = tar::Archive::new(data.as_slice());
+            let filter = GlobVec::from_vec(vec!["lib/**"], None);
+            let error = matching_from_archive(&mut archive, &filter, &dest).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData, "{link_name}");
+
+            assert!(!dest.join("lib/evil").exists());
+        }
+    }
+
+    #[test]
+    fn test_entries_outside_of_dest() {
+        // `tar::Builder` refuses to write these paths, so set the name directly
+        let mut builder = tar::Builder::new(Vec::new());
+        for path in ["lib/libfoo.so", "../evil", "lib/../../evil"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(3);
+            header.set_mode(0o644);
+            header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_cksum();
+            builder.append(&header, &b"foo"[..]).unwrap();
+        }
+        let data = builder.into_inner().unwrap();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dest = tmp.path().join("a/dest");
+
+        let mut archive = tar::Archive::new(data.as_slice());
+        folder_from_archive(&mut archive, Path::new(""), &dest, None).unwrap();
+        assert!(dest.join("lib/libfoo.so").is_file());
+
+        let mut archive = tar::Archive::new(data.as_slice());
+        let filter = GlobVec::from_vec(vec!["**"], None);
+        let extracted = matching_from_archive(&mut archive, &filter, &dest).unwrap();
+        assert_eq!(extracted, vec![PathBuf::from("lib/libfoo.so")]);
+
+        assert!(!tmp.path().join("a/evil").exists());
+        assert!(!tmp.path().join("evil").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_entries_through_symlinked_folder() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        let dest = tmp.path().join("dest");
+
+        let data = tar_archive(&[
+            (
+                "lib",
+                tar::EntryType::Symlink,
+                Some(outside.to_str().unwrap()),
+                b"",
+            ),
+            ("lib/sub/evil", tar::EntryType::Regular, None, b"evil"),
+        ]);
+
+        let mut archive = tar::Archive::new(data.as_slice());
+        let error = folder_from_archive(&mut archive, Path::new(""), &dest, None).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        // no folders were created outside of `dest` before the check failed
+        assert!(!outside.join("sub").exists());
+    }
+
+    #[test]
+    fn test_matching_skips_hard_links_to_filtered_files() {
+        let data = tar_archive(&[
+            ("lib/libfoo.so.1", tar::EntryType::Regular, None, b"foo"),
+            (
+                "lib/libfoo.so",
+                tar::EntryType::Link,
+                Some("lib/libfoo.so.1"),
+                b"",
+            ),
+            ("lib/libbar.so", tar::EntryType::Regular, None, b"bar"),
+        ]);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let mut archive = tar::Archive::new(data.as_slice());
+        let filter = GlobVec::from_vec(vec!["**/*.so"], None);
+        let extracted = matching_from_archive(&mut archive, &filter, tmp.path()).unwrap();
+        assert_eq!(extracted, vec![PathBuf::from("lib/libbar.so")]);
+        assert!(!tmp.path().join("lib/libfoo.so").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_folder_from_archive_links() {
+        // an archive with a symlink before its target, a dangling symlink and
+        // a hard link
+        let data = tar_archive(&[
+            (
+                "lib/libfoo.so",
+                tar::EntryType::Symlink,
+                Some("libfoo.so.1"),
+                b"",
+            ),
+            (
+                "lib/libbar.so",
+                tar::EntryType::Symlink,
+                Some("libbar.so.1"),
+                b"",
+            ),
+            ("lib/libfoo.so.1", tar::EntryType::Regular, None, b"foo"),
+            (
+                "lib/libfoo.so.1.0",
+                tar::EntryType::Link,
+                Some("lib/libfoo.so.1"),
+                b"",
+            ),
+        ]);
+
+        let tmp = tempfile::tempdir().unwrap();
+        for (find_path, dest) in [("", "all"), ("lib", "lib")] {
+            let dest = tmp.path().join(dest);
+            let prefix = if find_path.is_empty() { "lib/" } else { "" };
+            // extracting twice overwrites the existing links
+            for _ in 0..2 {
+                let mut archive = tar::Archive::new(data.as_slice());
+                folder_from_archive(&mut archive, Path::new(find_path), &dest, None).unwrap();
+            }
+
+            let symlink = dest.join(format!("{prefix}libfoo.so"));
+            assert!(fs::symlink_metadata(&symlink).unwrap().is_symlink());
+            assert_eq!(
+                fs::read_link(&symlink).unwrap(),
+                PathBuf::from("libfoo.so.1")
+            );
+            assert_eq!(fs::read(&symlink).unwrap(), b"foo");
+
+            let dangling = dest.join(format!("{prefix}libbar.so"));
+            assert_eq!(
+                fs::read_link(&dangling).unwrap(),
+                PathBuf::from("libbar.so.1")
+            );
+            assert!(!dangling.exists());
+
+            let hard_link = dest.join(format!("{prefix}libfoo.so.1.0"));
+            assert!(!fs::symlink_metadata(&hard_link).unwrap().is_symlink());
+            assert_eq!(fs::read(&hard_link).unwrap(), b"foo");
+        }
+    }
+
+    #[test]
+    fn test_extract_changed_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let package = tmp.path().join("test-1.0-0.conda");
+        write_package(
+            &package,
+            &[
+                ("info/index.json", b"{}"),
+                ("lib/a.txt", b"a"),
+                ("lib/b.txt", b"b-new"),
+                ("share/c.txt", b"c"),
+            ],
+        );
+
+        let prefix = tmp.path().join("prefix");
+        fs::create_dir_all(prefix.join("lib")).unwrap();
+        fs::create_dir_all(prefix.join("share")).unwrap();
+        fs::write(prefix.join("lib/a.txt"), "a").unwrap();
+        fs::write(prefix.join("lib/b.txt"), "b-old").unwrap();
+        fs::write(prefix.join("share/c.txt"), "c").unwrap();
+        let modified = |path: &str| fs::metadata(prefix.join(path)).unwrap().modified().unwrap();
+        let (a_modified, c_modified) = (modified("lib/a.txt"), modified("share/c.txt"));
+
+        let applied = extract_changed_files(&package, &prefix).unwrap();
+        assert_eq!(applied, vec![PathBuf::from("lib/b.txt")]);
+        assert_eq!(fs::read(prefix.join("lib/b.txt")).unwrap(), b"b-new");
+        assert_eq!(modified("lib/a.txt"), a_modified);
+        assert_eq!(modified("share/c.txt"), c_modified);
+        assert!(!prefix.join("info").exists());
+
+        // applying the package again is a no-op
+        assert!(extract_changed_files(&package, &prefix).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_extract_changed_files_links_and_outside_entries() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, entry_type, link, data) in [
+            (
+                "lib/libfoo.so.1",
+                tar::EntryType::Regular,
+                None,
+                &b"foo"[..],
+            ),
+            ("../evil", tar::EntryType::Regular, None, &b"evil"[..]),
+            (
+                "lib/libfoo.so",
+                tar::EntryType::Link,
+                Some("lib/libfoo.so.1"),
+                &b""[..],
+            ),
+        ] {
+            // `tar::Builder` refuses to write `..`, so set the name directly
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+            if let Some(link) = link {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        let data = builder.into_inner().unwrap();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let prefix = tmp.path().join("prefix");
+        let mut applied = Vec::new();
+        let mut archive = tar::Archive::new(data.as_slice());
+        apply_changed_entries(&mut archive, &prefix, &mut applied).unwrap();
+
+        assert_eq!(
+            applied,
+            vec![
+                PathBuf::from("lib/libfoo.so.1"),
+                PathBuf::from("lib/libfoo.so")
+            ]
+        );
+        assert!(!tmp.path().join("evil").exists());
+        assert_eq!(fs::read(prefix.join("lib/libfoo.so")).unwrap(), b"foo");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let metadata = fs::metadata(prefix.join("lib/libfoo.so")).unwrap();
+            assert_eq!(metadata.nlink(), 2);
+        }
+
+        // applying the archive again is a no-op
+        let mut applied = Vec::new();
+        let mut archive = tar::Archive::new(data.as_slice());
+        apply_changed_entries(&mut archive, &prefix, &mut applied).unwrap();
+        assert!(applied.is_empty());
+    }
+
+    #[test]
+    fn test_read_rendered_recipe() {
+        let rendered_recipe = fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test-data/rendered_recipes/curl_recipe.yaml"),
+        )
+        .unwrap();
+
+        let tmp = tempfile::tempdir().unwrap();
+        for name in ["curl-8.0.1-h0.tar.bz2", "curl-8.0.1-h0.conda"] {
+            let package = tmp.path().join(name);
+            write_package(
+                &package,
+                &[
+                    ("info/index.json", b"{}"),
+                    ("info/recipe/rendered_recipe.yaml", &rendered_recipe),
+                    (
+                        "info/recipe/variant_config.yaml",
+                        b"target_platform: osx-arm64\nzlib: '1.2'\n",
+                    ),
+                ],
+            );
+
+            let recipe = read_rendered_recipe(&package).unwrap();
+            assert_eq!(recipe.output.name().as_normalized(), "curl");
+            assert_eq!(recipe.variant.len(), 2);
+            assert_eq!(
+                recipe.variant[&NormalizedKey::from("zlib")],
+                Variable::from_string("1.2")
+            );
+        }
+
+        // packages built before the rendered recipe was embedded
+        let package = tmp.path().join("old-1.0-0.tar.bz2");
+        write_package(
+            &package,
+            &[("info/index.json", b"{}"), ("info/recipe/meta.yaml", b"")],
+        );
+        assert!(matches!(
+            read_rendered_recipe(&package),
+            Err(RenderedRecipeError::MissingRenderedRecipe)
+        ));
+    }
+
+    #[test]
+    fn test_check_reproducibility() {
+        let tmp = tempfile::tempdir().unwrap();
+        let original = tmp.path().join("original/test-1.0-0.tar.bz2");
+        let rebuilt = tmp.path().join("rebuilt/test-1.0-0.tar.bz2");
+        fs::create_dir_all(original.parent().unwrap()).unwrap();
+        fs::create_dir_all(rebuilt.parent().unwrap()).unwrap();
+
+        let files: &[(&str, &[u8])] = &[("info/index.json", b"{}"), ("lib/foo.so", b"foo")];
+        write_package(&original, files);
+        fs::copy(&original, &rebuilt).unwrap();
+
+        let report = check_reproducibility(&original, &rebuilt, &GlobVec::default()).unwrap();
+        assert!(report.is_bit_identical());
+        assert!(report.is_content_identical());
+
+        write_package(
+            &rebuilt,
+            &[
+                ("info/index.json", b"{}"),
+                ("lib/foo.so", b"foobar"),
+                ("lib/bar.so", b"bar"),
+            ],
+        );
+        let report = check_reproducibility(&original, &rebuilt, &GlobVec::default()).unwrap();
+        assert!(!report.is_bit_identical());
+        assert!(!report.is_content_identical());
+        assert_eq!(
+            report.diff.to_string(),
+            "  + lib/bar.so (added)\n  ~ lib/foo.so (+3 bytes)\n"
+        );
+    }
+}
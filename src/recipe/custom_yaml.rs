@@ -23,7 +23,10 @@ use crate::{
     source_code::SourceCode,
 };
 
+mod anchors;
+mod comments;
 mod rendered;
+pub use comments::{Comment, RecipeComments};
 pub use rendered::{RenderedMappingNode, RenderedNode, RenderedScalarNode, RenderedSequenceNode};
 
 use super::Render;
@@ -75,8 +78,15 @@ pub fn parse_yaml<S: SourceCode>(
     let options = LoaderOptions::default()
         .error_on_duplicate_keys(true)
         .prevent_coercion(true);
-    let yaml = parse_yaml_with_options(init_span_index, src.clone(), options)
-        .map_err(|err| crate::recipe::error::load_error_handler(src, err))?;
+    // the default loader rejects anchors, so we only fall back to the
+    // anchor-aware loader for recipes that use them
+    let yaml = match parse_yaml_with_options(init_span_index, src.clone(), options) {
+        Err(marked_yaml::LoadError::UnexpectedAnchor(_)) => {
+            anchors::parse_yaml_with_anchors(init_span_index, src.as_ref())
+        }
+        yaml => yaml,
+    }
+    .map_err(|err| crate::recipe::error::load_error_handler(src, err))?;
 
     Ok(yaml)
 }
@@ -0,0 +1,171 @@
+//! A YAML loader that resolves anchors and aliases.
+//!
+//! [`marked_yaml`] rejects anchors, so recipes that use them are loaded with
+//! this loader instead. It produces the same [`marked_yaml::Node`] tree, where
+//! every alias is replaced by a copy of the anchored node. The copies keep the
+//! spans of the anchored node so that errors point to its definition.
+
+use std::collections::HashMap;
+
+use marked_yaml::{
+    LoadError, Marker, Node, Span,
+    loader::DuplicateKeyInner,
+    types::{MarkedMappingNode, MarkedScalarNode, MarkedSequenceNode},
+};
+use yaml_rust2::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::{Marker as YamlMarker, TScalarStyle},
+};
+
+/// A mapping or sequence that is currently being loaded.
+enum Frame {
+    Mapping {
+        anchor: usize,
+        node: MarkedMappingNode,
+        key: Option<MarkedScalarNode>,
+    },
+    Sequence {
+        anchor: usize,
+        node: MarkedSequenceNode,
+    },
+}
+
+struct AnchorLoader {
+    source: usize,
+    stack: Vec<Frame>,
+    anchors: HashMap<usize, Node>,
+    document: Option<Node>,
+    error: Option<LoadError>,
+}
+
+impl AnchorLoader {
+    fn marker(&self, mark: YamlMarker) -> Marker {
+        Marker::new(self.source, mark.index(), mark.line(), mark.col() + 1)
+    }
+
+    /// Adds a loaded node to the mapping or sequence it belongs to.
+    fn push_node(&mut self, node: Node, anchor: usize, mark: Marker) -> Result<(), LoadError> {
+        if anchor != 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+
+        match self.stack.last_mut() {
+            None => {
+                if node.as_mapping().is_none() {
+                    return Err(LoadError::TopLevelMustBeMapping(mark));
+                }
+                self.document = Some(node);
+            }
+            Some(Frame::Sequence { node: list, .. }) => list.push(node),
+            Some(Frame::Mapping { node: map, key, .. }) => match key.take() {
+                None => {
+                    let Node::Scalar(scalar) = node else {
+                        return Err(LoadError::MappingKeyMustBeScalar(mark));
+                    };
+                    *key = Some(scalar);
+                }
+                Some(key) => {
+                    if let Some(prev_key) = map.keys().find(|prev_key| **prev_key == key) {
+                        return Err(LoadError::DuplicateKey(Box::new(DuplicateKeyInner {
+                            prev_key: prev_key.clone(),
+                            key,
+                        })));
+                    }
+                    map.insert(key, node);
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: Event, mark: Marker) -> Result<(), LoadError> {
+        match event {
+            Event::MappingStart(_, Some(_))
+            | Event::SequenceStart(_, Some(_))
+            | Event::Scalar(_, _, _, Some(_)) => return Err(LoadError::UnexpectedTag(mark)),
+            Event::MappingStart(anchor, None) => self.stack.push(Frame::Mapping {
+                anchor,
+                node: MarkedMappingNode::new_empty(Span::new_start(mark)),
+                key: None,
+            }),
+            Event::SequenceStart(anchor, None) => self.stack.push(Frame::Sequence {
+                anchor,
+                node: MarkedSequenceNode::new_empty(Span::new_start(mark)),
+            }),
+            Event::MappingEnd | Event::SequenceEnd => {
+                let (node, anchor) = match self.stack.pop().expect("unbalanced YAML events") {
+                    Frame::Mapping {
+                        anchor, mut node, ..
+                    } => {
+                        node.span_mut().set_end(Some(mark));
+                        (Node::from(node), anchor)
+                    }
+                    Frame::Sequence { anchor, mut node } => {
+                        node.span_mut().set_end(Some(mark));
+                        (Node::from(node), anchor)
+                    }
+                };
+                self.push_node(node, anchor, mark)?;
+            }
+            Event::Scalar(value, style, anchor, None) => {
+                let mut node = MarkedScalarNode::new(Span::new_start(mark), value);
+                node.set_coerce(matches!(style, TScalarStyle::Plain));
+                self.push_node(Node::from(node), anchor, mark)?;
+            }
+            Event::Alias(anchor) => {
+                let node = self
+                    .anchors
+                    .get(&anchor)
+                    .cloned()
+                    .ok_or(LoadError::UnexpectedAnchor(mark))?;
+                self.push_node(node, 0, mark)?;
+            }
+            Event::StreamEnd => {
+                if self.document.is_none() {
+                    self.document = Some(Node::from(MarkedMappingNode::new_empty(
+                        Span::new_with_marks(mark, mark),
+                    )));
+                }
+            }
+            Event::StreamStart | Event::DocumentStart | Event::DocumentEnd | Event::Nothing => {}
+        }
+        Ok(())
+    }
+}
+
+impl MarkedEventReceiver for AnchorLoader {
+    fn on_event(&mut self, event: Event, mark: YamlMarker) {
+        if self.error.is_some() {
+            return;
+        }
+        let mark = self.marker(mark);
+        if let Err(err) = self.handle_event(event, mark) {
+            self.error = Some(err);
+        }
+    }
+}
+
+/// Parse YAML with a top-level mapping, resolving anchors and aliases. Scalars
+/// are marked so that only plain scalars may be coerced, and duplicate keys are
+/// rejected (like the default recipe parser).
+pub(crate) fn parse_yaml_with_anchors(source: usize, yaml: &str) -> Result<Node, LoadError> {
+    let mut loader = AnchorLoader {
+        source,
+        stack: Vec::new(),
+        anchors: HashMap::new(),
+        document: None,
+        error: None,
+    };
+    let mut parser = Parser::new(yaml.chars());
+    parser.load(&mut loader, false).map_err(|err| {
+        let mark = loader.marker(*err.marker());
+        LoadError::ScanError(mark, err)
+    })?;
+
+    match (loader.error, loader.document) {
+        (Some(err), _) => Err(err),
+        (None, Some(document)) => Ok(document),
+        (None, None) => unreachable!("the YAML stream always ends"),
+    }
+}
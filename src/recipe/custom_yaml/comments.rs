@@ -0,0 +1,242 @@
+//! Collects the comments of a recipe.
+//!
+//! [`marked_yaml`] drops comments while parsing, so they are collected with a
+//! separate (best-effort) scan of the source. The comments are keyed by line
+//! so that tools like linters can quote the comments next to a node, see
+//! [`RecipeComments::near`].
+
+use std::collections::BTreeMap;
+
+use marked_yaml::Span;
+
+/// A comment in the recipe source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    /// The line of the comment, 1-indexed like the spans of the nodes.
+    pub line: usize,
+    /// The column of the `#`, 1-indexed like the spans of the nodes.
+    pub column: usize,
+    /// The text of the comment without the leading `#` and surrounding
+    /// whitespace.
+    pub text: String,
+    /// Whether the comment follows a value on the same line, otherwise the
+    /// comment is on a line of its own.
+    pub trailing: bool,
+}
+
+/// All comments of a recipe, keyed by line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecipeComments {
+    comments: BTreeMap<usize, Comment>,
+}
+
+impl RecipeComments {
+    /// Collects the comments of the given YAML source.
+    ///
+    /// Comments in quoted strings and block scalars (`|` and `>`) are
+    /// skipped. Quoted strings that span multiple lines are not recognized.
+    pub fn from_source(src: &str) -> Self {
+        let mut comments = BTreeMap::new();
+        // The indentation of the line that started a block scalar, more
+        // indented lines belong to the scalar.
+        let mut block_scalar_indent: Option<usize> = None;
+
+        for (idx, line) in src.lines().enumerate() {
+            let indent = line.len() - line.trim_start().len();
+            if let Some(block_indent) = block_scalar_indent {
+                if line.trim().is_empty() || indent > block_indent {
+                    continue;
+                }
+                block_scalar_indent = None;
+            }
+
+            let Some(column) = comment_start(line) else {
+                if starts_block_scalar(line) {
+                    block_scalar_indent = Some(indent);
+                }
+                continue;
+            };
+
+            let content = &line[..column];
+            if starts_block_scalar(content) {
+                block_scalar_indent = Some(indent);
+            }
+            comments.insert(
+                idx + 1,
+                Comment {
+                    line: idx + 1,
+                    column: column + 1,
+                    text: line[column + 1..].trim().to_string(),
+                    trailing: !content.trim().is_empty(),
+                },
+            );
+        }
+
+        Self { comments }
+    }
+
+    /// Returns the comment on the given (1-indexed) line.
+    pub fn get(&self, line: usize) -> Option<&Comment> {
+        self.comments.get(&line)
+    }
+
+    /// Returns all comments in the order of the source.
+    pub fn iter(&self) -> impl Iterator<Item = &Comment> {
+        self.comments.values()
+    }
+
+    /// Returns the number of comments.
+    pub fn len(&self) -> usize {
+        self.comments.len()
+    }
+
+    /// Returns true if the recipe has no comments.
+    pub fn is_empty(&self) -> bool {
+        self.comments.is_empty()
+    }
+
+    /// Returns the comments that belong to the node at `span`: the block of
+    /// comment lines directly above the node, followed by the trailing
+    /// comment on the first line of the node.
+    pub fn near(&self, span: &Span) -> Vec<&Comment> {
+        let Some(line) = span.start().map(|start| start.line()) else {
+            return Vec::new();
+        };
+
+        let mut comments = (1..line)
+            .rev()
+            .map_while(|line| self.get(line).filter(|comment| !comment.trailing))
+            .collect::<Vec<_>>();
+        comments.reverse();
+        comments.extend(self.get(line).filter(|comment| comment.trailing));
+        comments
+    }
+}
+
+/// Returns the byte offset of the `#` that starts a comment in the line.
+fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut previous = None;
+    for (idx, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '#' if previous.is_none_or(char::is_whitespace) => return Some(idx),
+                // Quotes only start a string at the beginning of a value
+                '"' | '\''
+                    if previous.is_none_or(|p: char| {
+                        p.is_whitespace() || matches!(p, ':' | '-' | '[' | '{' | ',')
+                    }) =>
+                {
+                    quote = Some(c)
+                }
+                _ => {}
+            },
+        }
+        previous = Some(c);
+    }
+    None
+}
+
+/// Whether the (comment free) line ends with a block scalar indicator, e.g.
+/// `script: |` or `description: >-`.
+fn starts_block_scalar(line: &str) -> bool {
+    let Some(indicator) = line.trim_end().rsplit([' ', '\t']).next() else {
+        return false;
+    };
+    let mut chars = indicator.chars();
+    matches!(chars.next(), Some('|' | '>'))
+        && chars.all(|c| matches!(c, '+' | '-') || c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::custom_yaml::parse_yaml;
+
+    const RECIPE: &str = r#"# The recipe for foo
+context:
+  version: "1.0" # keep in sync with upstream
+
+package:
+  name: foo
+  # the version is set in the context
+  # and used for the source url
+  version: ${{ version }}
+
+about:
+  summary: "A #1 package" # the best
+  description: |
+    # not a comment
+    foo is great
+  license: MIT
+"#;
+
+    #[test]
+    fn test_collect_comments() {
+        let comments = RecipeComments::from_source(RECIPE);
+        let comments = comments
+            .iter()
+            .map(|c| (c.line, c.column, c.text.as_str(), c.trailing))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            comments,
+            [
+                (1, 1, "The recipe for foo", false),
+                (3, 18, "keep in sync with upstream", true),
+                (7, 3, "the version is set in the context", false),
+                (8, 3, "and used for the source url", false),
+                (12, 27, "the best", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comments_near_node() {
+        let comments = RecipeComments::from_source(RECIPE);
+        let root = parse_yaml(0, RECIPE).unwrap();
+        let node = |section: &str, key: &str| {
+            root.as_mapping()
+                .unwrap()
+                .get_mapping(section)
+                .unwrap()
+                .get_node(key)
+                .unwrap()
+                .span()
+                .to_owned()
+        };
+        let texts = |span: &Span| {
+            comments
+                .near(span)
+                .into_iter()
+                .map(|c| c.text.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            texts(&node("package", "version")),
+            [
+                "the version is set in the context",
+                "and used for the source url"
+            ]
+        );
+        assert_eq!(
+            texts(&node("context", "version")),
+            ["keep in sync with upstream"]
+        );
+        assert!(texts(&node("about", "license")).is_empty());
+    }
+
+    #[test]
+    fn test_starts_block_scalar() {
+        assert!(starts_block_scalar("script: |"));
+        assert!(starts_block_scalar("  description: >-"));
+        assert!(starts_block_scalar("- |2"));
+        assert!(!starts_block_scalar("name: foo|bar"));
+        assert!(!starts_block_scalar("name: foo"));
+    }
+}
@@ -3,6 +3,7 @@
 use crate::source_code::SourceCode;
 use miette::{Diagnostic, SourceOffset, SourceSpan};
 use minijinja::value::ValueKind;
+use serde::Serialize;
 use std::fmt::Debug;
 use std::{borrow::Cow, convert::Infallible, fmt, str::ParseBoolError};
 use thiserror::Error;
@@ -70,6 +71,77 @@ impl<S: SourceCode> ParsingError<S> {
     pub fn kind(&self) -> &ErrorKind {
         &self.kind
     }
+
+    /// Convert the error into a [`JsonDiagnostic`], e.g. to show it in an
+    /// editor instead of rendering it with [`miette`].
+    pub fn to_json_diagnostic(&self) -> JsonDiagnostic {
+        let src = self.src.as_ref();
+        JsonDiagnostic {
+            message: self.kind.to_string(),
+            code: self.kind.code().map(|code| code.to_string()),
+            label: self.label.as_ref().map(|label| label.to_string()),
+            help: self.help.as_ref().map(|help| help.to_string()),
+            start: DiagnosticPosition::from_offset(src, self.span.offset()),
+            end: DiagnosticPosition::from_offset(src, self.span.offset() + self.span.len()),
+        }
+    }
+}
+
+/// Serializes the errors as a JSON array of [`JsonDiagnostic`]s.
+pub fn errors_to_json<'a, S: SourceCode + 'a>(
+    errors: impl IntoIterator<Item = &'a ParsingError<S>>,
+) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(
+        &errors
+            .into_iter()
+            .map(ParsingError::to_json_diagnostic)
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// A machine readable version of a [`ParsingError`], e.g. for language
+/// servers or web editors.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JsonDiagnostic {
+    /// The error message.
+    pub message: String,
+    /// The diagnostic code of the error kind (e.g. `error::missing_field`).
+    pub code: Option<String>,
+    /// The label of the span.
+    pub label: Option<String>,
+    /// Suggestion for fixing the error.
+    pub help: Option<String>,
+    /// The start of the span.
+    pub start: DiagnosticPosition,
+    /// The (exclusive) end of the span.
+    pub end: DiagnosticPosition,
+}
+
+/// A position in the recipe source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DiagnosticPosition {
+    /// The byte offset in the source.
+    pub offset: usize,
+    /// The line, 1-indexed.
+    pub line: usize,
+    /// The column in characters, 1-indexed.
+    pub column: usize,
+}
+
+impl DiagnosticPosition {
+    fn from_offset(src: &str, offset: usize) -> Self {
+        let mut offset = offset.min(src.len());
+        while !src.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let before = &src[..offset];
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        Self {
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
 }
 
 /// Type that represents the kind of error that can happen in the first stage of the recipe parser.
@@ -184,6 +256,14 @@ pub enum ErrorKind {
     /// Error when parsing a field that is experimental only
     #[diagnostic(code(error::experimental))]
     ExperimentalOnly(String),
+
+    /// A warning that was turned into an error because warnings are denied.
+    #[diagnostic(code(error::denied_warning))]
+    DeniedWarning(String),
+
+    /// Error when the recipe uses a schema version that is not supported.
+    #[diagnostic(code(error::unsupported_schema_version))]
+    UnsupportedSchemaVersion(u64),
 }
 
 /// Partial error type, almost the same as the [`ParsingError`] but without the source string.
@@ -305,6 +385,11 @@ impl fmt::Display for ErrorKind {
             ErrorKind::Other => write!(f, "an unspecified error occurred."),
             ErrorKind::ExperimentalOnly(s) => write!(f, "experimental only: `{}`.", s),
             ErrorKind::InvalidContextVariableName => write!(f, "invalid context variable name."),
+            ErrorKind::DeniedWarning(s) => write!(f, "{s}"),
+            ErrorKind::UnsupportedSchemaVersion(version) => write!(
+                f,
+                "schema_version {version} is not supported by this rattler-build."
+            ),
         }
     }
 }
@@ -540,7 +625,24 @@ pub(crate) fn jinja_error_to_label(err: &minijinja::Error) -> String {
 #[cfg(test)]
 mod tests {
 
-    use crate::{assert_miette_snapshot, recipe::Recipe, variant_config::ParseErrors};
+    use super::*;
+    use crate::{
+        assert_miette_snapshot,
+        recipe::{Recipe, parser::find_outputs_from_src},
+        variant_config::ParseErrors,
+    };
+
+    #[test]
+    fn test_errors_to_json() {
+        let src = "package:\n  name: test\n  version: 0.1.0\n  homepage: https://example.com\n";
+        let err = find_outputs_from_src(src)
+            .and_then(|outputs| {
+                Recipe::from_node(&outputs[0], Default::default())
+                    .map_err(|errs| ParsingError::from_partial_vec(src, errs).remove(0))
+            })
+            .unwrap_err();
+        insta::assert_snapshot!(errors_to_json([&err]).unwrap());
+    }
 
     #[test]
     fn miette_output() {
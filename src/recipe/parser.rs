@@ -37,8 +37,11 @@ pub use self::{
     about::About,
     build::{Build, BuildString, DynamicLinking, PrefixDetection, Python},
     cache::Cache,
-    glob_vec::{GlobCheckerVec, GlobVec, GlobWithSource},
-    output::find_outputs_from_src,
+    glob_vec::{GlobCheckerVec, GlobOrRegexVec, GlobVec, GlobWithSource},
+    output::{
+        FindOutputsOptions, MergeStrategy, ParseWarning, find_outputs_and_comments_from_src,
+        find_outputs_from_root_node, find_outputs_from_src, find_outputs_from_src_with_options,
+    },
     package::{OutputPackage, Package},
     regex::SerializableRegex,
     requirements::{
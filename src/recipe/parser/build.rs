@@ -1,10 +1,11 @@
 use std::borrow::Cow;
+use std::path::PathBuf;
 use std::str::FromStr;
 
-use rattler_conda_types::{NoArchType, package::EntryPoint};
+use rattler_conda_types::{NoArchType, Platform, package::EntryPoint};
 use serde::{Deserialize, Serialize};
 
-use super::glob_vec::{AllOrGlobVec, GlobVec};
+use super::glob_vec::{AllOrGlobVec, GlobOrRegexVec, GlobVec};
 use super::{Dependency, FlattenErrors, SerializableRegex};
 use crate::recipe::custom_yaml::RenderedSequenceNode;
 use crate::recipe::parser::script::Script;
@@ -77,6 +78,10 @@ pub struct Build {
     /// It's possible to override this by setting it manually, but not recommended.
     #[serde(default, skip_serializing_if = "BuildString::is_derived")]
     pub string: BuildString,
+    /// Appended to the build string with an underscore, e.g. `arm64` for the
+    /// per-architecture outputs of an output with an `architectures` list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub string_suffix: Option<String>,
     /// List of conditions under which to skip the build of the package.
     #[serde(default, skip)]
     pub skip: Skip,
@@ -227,6 +232,11 @@ impl Build {
         &self.string
     }
 
+    /// Get the suffix that is appended to the build string.
+    pub fn string_suffix(&self) -> Option<&str> {
+        self.string_suffix.as_deref()
+    }
+
     /// Get the skip conditions.
     pub fn skip(&self) -> bool {
         self.skip.eval()
@@ -302,6 +312,7 @@ impl TryConvertNode<Build> for RenderedMappingNode {
             self.iter(),
             number,
             string,
+            string_suffix,
             skip,
             script,
             noarch,
@@ -326,6 +337,10 @@ pub struct DynamicLinking {
     /// List of rpaths to use (linux only).
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub(super) rpaths: Vec<String>,
+    /// List of rpaths (relative to the prefix) that are searched before any
+    /// other rpath of the binary (linux and macOS only).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) prepend_rpaths: Vec<String>,
     /// Whether to relocate binaries or not.
     #[serde(default, skip_serializing_if = "AllOrGlobVec::is_all")]
     pub(super) binary_relocation: AllOrGlobVec,
@@ -333,14 +348,39 @@ pub struct DynamicLinking {
     #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
     pub(super) missing_dso_allowlist: GlobVec,
     /// Allow runpath / rpath to point to these locations outside of the environment.
-    #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
-    pub(super) rpath_allowlist: GlobVec,
+    #[serde(default, skip_serializing_if = "GlobOrRegexVec::is_empty")]
+    pub(super) rpath_allowlist: GlobOrRegexVec,
     /// What to do when detecting overdepending.
     #[serde(default, skip_serializing_if = "LinkingCheckBehavior::is_default")]
     pub(super) overdepending_behavior: LinkingCheckBehavior,
     /// What to do when detecting overlinking.
     #[serde(default, skip_serializing_if = "LinkingCheckBehavior::is_default")]
     pub(super) overlinking_behavior: LinkingCheckBehavior,
+    /// What to do when a binary still has an absolute rpath into the build
+    /// environment after relinking.
+    #[serde(default, skip_serializing_if = "LinkingCheckBehavior::is_default")]
+    pub(super) absolute_rpath_behavior: LinkingCheckBehavior,
+    /// Remove rpaths that point to the default library search paths of the
+    /// platform, even if they are allow-listed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(super) strip_default_rpaths: bool,
+    /// The default library search paths (overrides the platform defaults).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) default_rpaths: Vec<String>,
+    /// Strip the debug symbols from binaries after relinking (linux and macOS only).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(super) strip_debug: bool,
+    /// Binaries that keep their debug symbols when `strip_debug` is enabled.
+    #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
+    pub(super) keep_debug: GlobVec,
+    /// Binaries that are neither relinked nor checked for overlinking (e.g.
+    /// prebuilt binaries that ship their own dependencies).
+    #[serde(default, skip_serializing_if = "GlobVec::is_empty")]
+    pub(super) relink_exclude: GlobVec,
+    /// Rewrite absolute install names of dylibs (and the load commands that
+    /// refer to them) to `@rpath` (macOS only).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(super) rewrite_install_names: bool,
 }
 
 impl DynamicLinking {
@@ -358,6 +398,11 @@ impl DynamicLinking {
         }
     }
 
+    /// Get the rpaths that are searched before all other rpaths, in order.
+    pub fn prepend_rpaths(&self) -> &[String] {
+        &self.prepend_rpaths
+    }
+
     /// Get the binary relocation settings.
     pub fn binary_relocation(&self) -> &AllOrGlobVec {
         &self.binary_relocation
@@ -369,7 +414,7 @@ impl DynamicLinking {
     }
 
     /// Get the rpath allow list.
-    pub fn rpath_allowlist(&self) -> &GlobVec {
+    pub fn rpath_allowlist(&self) -> &GlobOrRegexVec {
         &self.rpath_allowlist
     }
 
@@ -382,6 +427,53 @@ impl DynamicLinking {
     pub fn error_on_overlinking(&self) -> bool {
         self.overlinking_behavior == LinkingCheckBehavior::Error
     }
+
+    /// Get the behavior for absolute rpaths that remain after relinking.
+    pub fn error_on_absolute_rpath(&self) -> bool {
+        self.absolute_rpath_behavior == LinkingCheckBehavior::Error
+    }
+
+    /// Whether to strip the debug symbols from binaries after relinking.
+    pub fn strip_debug(&self) -> bool {
+        self.strip_debug
+    }
+
+    /// Get the binaries that keep their debug symbols.
+    pub fn keep_debug(&self) -> &GlobVec {
+        &self.keep_debug
+    }
+
+    /// Get the binaries that are excluded from relinking and the linking checks.
+    pub fn relink_exclude(&self) -> &GlobVec {
+        &self.relink_exclude
+    }
+
+    /// Whether to rewrite absolute install names of dylibs to `@rpath`.
+    pub fn rewrite_install_names(&self) -> bool {
+        self.rewrite_install_names
+    }
+
+    /// Get the default library search paths whose rpaths should be removed.
+    /// This is empty unless `strip_default_rpaths` is enabled. If no
+    /// `default_rpaths` are configured, the defaults of the platform are used.
+    pub fn default_rpaths(&self, platform: Platform) -> Vec<PathBuf> {
+        if !self.strip_default_rpaths {
+            return Vec::new();
+        }
+
+        if !self.default_rpaths.is_empty() {
+            return self.default_rpaths.iter().map(PathBuf::from).collect();
+        }
+
+        let defaults: &[&str] = if platform.is_linux() {
+            &["/lib", "/lib64", "/usr/lib", "/usr/lib64"]
+        } else if platform.is_osx() {
+            &["/usr/lib", "/usr/local/lib"]
+        } else {
+            &[]
+        };
+        defaults.iter().map(PathBuf::from).collect()
+    }
 }
 
 /// What to do during linking checks.
@@ -439,11 +531,19 @@ impl TryConvertNode<DynamicLinking> for RenderedMappingNode {
             dynamic_linking,
             self.iter(),
             rpaths,
+            prepend_rpaths,
             binary_relocation,
             missing_dso_allowlist,
             rpath_allowlist,
             overdepending_behavior,
-            overlinking_behavior
+            overlinking_behavior,
+            absolute_rpath_behavior,
+            strip_default_rpaths,
+            default_rpaths,
+            strip_debug,
+            keep_debug,
+            relink_exclude,
+            rewrite_install_names
         );
 
         Ok(dynamic_linking)
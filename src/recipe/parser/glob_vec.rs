@@ -14,6 +14,8 @@ use crate::recipe::custom_yaml::{
 };
 use crate::recipe::error::{ErrorKind, PartialParsingError};
 
+use super::SerializableRegex;
+
 /// A glob with the source string
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct GlobWithSource {
@@ -640,6 +642,177 @@ impl TryConvertNode<AllOrGlobVec> for RenderedScalarNode {
     }
 }
 
+/// A list of globs that may also contain regular expressions. Regular
+/// expressions are written as `regex: <expr>` entries and are useful for paths
+/// that are awkward to express as globs (alternations, optional components).
+#[derive(Debug, Default, Clone)]
+pub struct GlobOrRegexVec {
+    globs: GlobVec,
+    regexes: Vec<SerializableRegex>,
+}
+
+impl PartialEq for GlobOrRegexVec {
+    fn eq(&self, other: &Self) -> bool {
+        self.globs == other.globs
+            && self.regexes.len() == other.regexes.len()
+            && self
+                .regexes
+                .iter()
+                .zip(other.regexes.iter())
+                .all(|(a, b)| a.as_str() == b.as_str())
+    }
+}
+
+impl Eq for GlobOrRegexVec {}
+
+impl From<GlobVec> for GlobOrRegexVec {
+    fn from(globs: GlobVec) -> Self {
+        Self {
+            globs,
+            regexes: Vec::new(),
+        }
+    }
+}
+
+impl GlobOrRegexVec {
+    /// Returns true if there are neither globs nor regexes
+    pub fn is_empty(&self) -> bool {
+        self.globs.is_empty() && self.regexes.is_empty()
+    }
+
+    /// Returns the globs
+    pub fn globs(&self) -> &GlobVec {
+        &self.globs
+    }
+
+    /// Returns the regexes
+    pub fn regexes(&self) -> &[SerializableRegex] {
+        &self.regexes
+    }
+
+    /// Returns true if the path matches the globs or any of the regexes.
+    /// Regexes are not anchored implicitly, use `^` and `$` to match the whole path.
+    pub fn is_match(&self, path: &Path) -> bool {
+        if self.globs.is_match(path) {
+            return true;
+        }
+        let path = path.to_string_lossy();
+        self.regexes.iter().any(|regex| regex.is_match(&path))
+    }
+}
+
+impl Serialize for GlobOrRegexVec {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.regexes.is_empty() {
+            return self.globs.serialize(serializer);
+        }
+
+        #[derive(Serialize)]
+        struct RegexEntry<'a> {
+            regex: &'a str,
+        }
+
+        // regexes can only be combined with a plain list of globs
+        let mut seq =
+            serializer.serialize_seq(Some(self.globs.include.len() + self.regexes.len()))?;
+        for glob in self.globs.include.iter() {
+            seq.serialize_element(glob.source())?;
+        }
+        for regex in &self.regexes {
+            seq.serialize_element(&RegexEntry {
+                regex: regex.as_str(),
+            })?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for GlobOrRegexVec {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Entry {
+            Glob(String),
+            Regex { regex: SerializableRegex },
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum GlobOrRegexVecInput {
+            Globs(GlobVec),
+            Entries(Vec<Entry>),
+        }
+
+        match GlobOrRegexVecInput::deserialize(deserializer)? {
+            GlobOrRegexVecInput::Globs(globs) => Ok(globs.into()),
+            GlobOrRegexVecInput::Entries(entries) => {
+                let mut globs = Vec::new();
+                let mut regexes = Vec::new();
+                for entry in entries {
+                    match entry {
+                        Entry::Glob(glob) => globs.push(glob),
+                        Entry::Regex { regex } => regexes.push(regex),
+                    }
+                }
+                let globs = GlobVec::new(globs.into(), InnerGlobVec::default())
+                    .map_err(|e| serde::de::Error::custom(e.to_string()))?;
+                Ok(Self { globs, regexes })
+            }
+        }
+    }
+}
+
+impl TryConvertNode<GlobOrRegexVec> for RenderedNode {
+    fn try_convert(&self, name: &str) -> Result<GlobOrRegexVec, Vec<PartialParsingError>> {
+        match self {
+            RenderedNode::Sequence(sequence) => sequence.try_convert(name),
+            _ => {
+                let globs: GlobVec = self.try_convert(name)?;
+                Ok(globs.into())
+            }
+        }
+    }
+}
+
+impl TryConvertNode<GlobOrRegexVec> for RenderedSequenceNode {
+    fn try_convert(&self, name: &str) -> Result<GlobOrRegexVec, Vec<PartialParsingError>> {
+        let mut globs = Vec::new();
+        let mut regexes = Vec::new();
+
+        for item in self.iter() {
+            if let Some(mapping) = item.as_mapping() {
+                for (key, value) in mapping.iter() {
+                    match key.as_str() {
+                        "regex" => regexes.push(value.try_convert(name)?),
+                        invalid => {
+                            return Err(vec![_partialerror!(
+                                *key.span(),
+                                ErrorKind::InvalidField(invalid.to_string().into()),
+                                help = format!(
+                                    "entries of {} are either globs or `regex: <expr>` mappings",
+                                    name
+                                )
+                            )]);
+                        }
+                    }
+                }
+            } else {
+                let str: String = item.try_convert(name)?;
+                globs.push(to_glob(&str).map_err(|err| {
+                    vec![_partialerror!(*item.span(), ErrorKind::GlobParsing(err),)]
+                })?);
+            }
+        }
+
+        let globs = GlobVec::new(globs.into(), InnerGlobVec::default())
+            .map_err(|err| vec![_partialerror!(*self.span(), ErrorKind::GlobParsing(err),)])?;
+        Ok(GlobOrRegexVec { globs, regexes })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{assert_miette_snapshot, recipe::ParsingError};
@@ -692,6 +865,49 @@ mod tests {
         assert_eq!(parsed_again.exclude_globset.len(), 2);
     }
 
+    #[test]
+    fn test_parsing_glob_or_regex_vec() {
+        let yaml = r#"allowlist:
+        - /usr/lib/custom**
+        - regex: ^/opt/vendor(-[0-9.]+)?/lib$
+        "#;
+
+        let yaml_root = RenderedNode::parse_yaml(0, yaml)
+            .map_err(|err| vec![err])
+            .unwrap();
+        let node = yaml_root.as_mapping().unwrap().get("allowlist").unwrap();
+        let allowlist: GlobOrRegexVec = node.try_convert("allowlist").unwrap();
+        assert_eq!(allowlist.globs().include_globs().len(), 1);
+        assert_eq!(allowlist.regexes().len(), 1);
+
+        assert!(allowlist.is_match(Path::new("/usr/lib/custom_lib")));
+        assert!(allowlist.is_match(Path::new("/opt/vendor/lib")));
+        assert!(allowlist.is_match(Path::new("/opt/vendor-1.2/lib")));
+        assert!(!allowlist.is_match(Path::new("/opt/vendor-1.2/lib64")));
+        assert!(!allowlist.is_match(Path::new("/usr/lib64")));
+
+        let as_yaml = serde_yaml::to_string(&allowlist).unwrap();
+        let parsed_again: GlobOrRegexVec = serde_yaml::from_str(&as_yaml).unwrap();
+        assert_eq!(parsed_again, allowlist);
+
+        // plain globs are still supported, also in the include / exclude form
+        let parsed: GlobOrRegexVec =
+            serde_yaml::from_str("include: [\"/usr/lib/**\"]\nexclude: [\"/usr/lib/foo\"]")
+                .unwrap();
+        assert!(parsed.is_match(Path::new("/usr/lib/bar")));
+        assert!(!parsed.is_match(Path::new("/usr/lib/foo")));
+
+        let yaml = r#"allowlist:
+        - regx: ^/opt/lib$
+        "#;
+        let yaml_root = RenderedNode::parse_yaml(0, yaml)
+            .map_err(|err| vec![err])
+            .unwrap();
+        let node = yaml_root.as_mapping().unwrap().get("allowlist").unwrap();
+        let result: Result<GlobOrRegexVec, _> = node.try_convert("allowlist");
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_glob_match_folder() {
         let globvec = GlobVec::from_vec(vec!["foo/"], None);
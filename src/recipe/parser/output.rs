@@ -4,16 +4,22 @@
 //! each mapping can have its own `package`, `source`, `build`, `requirements`,
 //! `test`, and `about` fields.
 
-use marked_yaml::types::MarkedMappingNode;
+use std::{borrow::Cow, collections::HashSet, sync::LazyLock};
+
+use itertools::Itertools;
+use marked_yaml::types::{MarkedMappingNode, MarkedScalarNode, MarkedSequenceNode};
+use regex::Regex;
 
 use crate::{
     _partialerror,
     recipe::{
-        ParsingError,
-        custom_yaml::{Node, parse_yaml},
+        Jinja, ParsingError,
+        custom_yaml::{Node, RecipeComments, parse_yaml},
         error::{ErrorKind, PartialParsingError},
     },
+    selectors::SelectorConfig,
     source_code::SourceCode,
+    used_variables::variables_from_expression,
 };
 
 static DEEP_MERGE_KEYS: [&str; 4] = ["package", "about", "extra", "build"];
@@ -29,13 +35,86 @@ static ALLOWED_KEYS_MULTI_OUTPUTS: [&str; 9] = [
     "schema_version",
 ];
 
+/// The recipe schema version that is used if none is specified.
+pub(crate) const CURRENT_SCHEMA_VERSION: u64 = 1;
+
+/// A warning emitted while finding the outputs of a recipe.
+#[derive(Debug, Clone)]
+pub struct ParseWarning {
+    /// The span in the recipe the warning refers to.
+    pub span: marked_yaml::Span,
+    /// The warning message.
+    pub message: Cow<'static, str>,
+}
+
+impl ParseWarning {
+    fn new(span: marked_yaml::Span, message: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+
+    fn into_error(self) -> PartialParsingError {
+        _partialerror!(
+            self.span,
+            ErrorKind::DeniedWarning(self.message.into_owned()),
+            help = "warnings are treated as errors because `deny_warnings` is enabled"
+        )
+    }
+}
+
+/// How the values under the deep-merge keys (`package`, `about`, `extra` and
+/// `build`) of the root node are merged into the outputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Only fill in the keys that are absent in the output. Keys of the output
+    /// replace the top-level keys.
+    #[default]
+    Replace,
+    /// Like [`MergeStrategy::Replace`], but if both the top-level and the
+    /// output value are lists, they are concatenated (top-level first).
+    Append,
+}
+
+/// Options for [`find_outputs_from_src_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct FindOutputsOptions {
+    /// Turn every warning emitted while parsing into a hard error.
+    pub deny_warnings: bool,
+    /// The selector config used to evaluate if-selectors in the `outputs`
+    /// list. Uses the default selector config if not set.
+    pub selector_config: Option<SelectorConfig>,
+    /// How the top-level keys are merged into the outputs.
+    pub merge_strategy: MergeStrategy,
+}
+
+/// Logs the collected warnings, or turns the first one into an error if
+/// warnings are denied.
+#[allow(clippy::result_large_err)]
+fn emit_warnings(
+    warnings: Vec<ParseWarning>,
+    options: &FindOutputsOptions,
+) -> Result<(), PartialParsingError> {
+    for warning in warnings {
+        if options.deny_warnings {
+            return Err(warning.into_error());
+        }
+        tracing::warn!("{}", warning.message);
+    }
+    Ok(())
+}
+
 // Check if the `cache` top-level key is present. If it does not contain a
 // source, but there is a top-level `source` key, then we should warn the user
 // because this key was moved to the `cache`
 #[allow(clippy::result_large_err)]
-fn check_src_cache(root: &MarkedMappingNode) -> Result<(), PartialParsingError> {
+fn check_src_cache(
+    root: &MarkedMappingNode,
+    warnings: &mut Vec<ParseWarning>,
+) -> Result<(), PartialParsingError> {
     if let Some(cache) = root.get("cache") {
-        let has_top_level_source = root.contains_key("source");
+        let top_level_source = root.keys().find(|k| k.as_str() == "source");
         let cache_map = cache.as_mapping().ok_or_else(|| {
             _partialerror!(
                 *cache.span(),
@@ -44,20 +123,488 @@ fn check_src_cache(root: &MarkedMappingNode) -> Result<(), PartialParsingError>
             )
         })?;
 
-        if !cache_map.contains_key("source") && has_top_level_source {
-            tracing::warn!(
-                "The cache has its own `source` key now. You probably want to move the top-level `source` key into the `cache` key."
-            );
+        if let Some(source_key) = top_level_source
+            && !cache_map.contains_key("source")
+        {
+            warnings.push(ParseWarning::new(
+                *source_key.span(),
+                "The cache has its own `source` key now. You probably want to move the top-level `source` key into the `cache` key.",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Check that the `schema_version` (if given) is an integer that is supported
+// by this version of rattler-build. A missing `schema_version` defaults to the
+// current version.
+#[allow(clippy::result_large_err)]
+fn check_schema_version(root: &MarkedMappingNode) -> Result<u64, PartialParsingError> {
+    let Some(node) = root.get("schema_version") else {
+        return Ok(CURRENT_SCHEMA_VERSION);
+    };
+
+    let version = node
+        .as_scalar()
+        .and_then(|version| version.as_str().parse::<u64>().ok())
+        .ok_or_else(|| {
+            _partialerror!(
+                *node.span(),
+                ErrorKind::InvalidValue(("schema_version".to_string(), "not an integer".into())),
+                help = "`schema_version` must be an integer, e.g. `schema_version: 1`"
+            )
+        })?;
+
+    if version != CURRENT_SCHEMA_VERSION {
+        return Err(_partialerror!(
+            *node.span(),
+            ErrorKind::UnsupportedSchemaVersion(version),
+            help = format!(
+                "rattler-build {} only supports schema_version {CURRENT_SCHEMA_VERSION}",
+                env!("CARGO_PKG_VERSION")
+            )
+        ));
+    }
+
+    Ok(version)
+}
+
+/// Returns the (raw) package name of an output node, if any.
+fn output_name(output: &marked_yaml::Node) -> Option<&str> {
+    output
+        .as_mapping()
+        .and_then(|output| output.get("package"))
+        .and_then(|package| package.as_mapping())
+        .and_then(|package| package.get("name"))
+        .and_then(|name| name.as_scalar())
+        .map(|name| name.as_str())
+}
+
+// Check if a top-level `source` is present while some outputs define their own
+// `source`. The top-level source is inherited by all other outputs, which
+// can lead to surprising (double) downloads.
+fn check_output_sources(
+    root: &MarkedMappingNode,
+    outputs: &[marked_yaml::Node],
+    warnings: &mut Vec<ParseWarning>,
+) {
+    let Some(source_key) = root.keys().find(|k| k.as_str() == "source") else {
+        return;
+    };
+
+    let (overriding, inheriting): (Vec<_>, Vec<_>) = outputs.iter().partition(|output| {
+        output
+            .as_mapping()
+            .is_some_and(|output| output.contains_key("source"))
+    });
+    if overriding.is_empty() {
+        return;
+    }
+
+    let names = |outputs: Vec<&marked_yaml::Node>| {
+        if outputs.is_empty() {
+            return "none".to_string();
+        }
+        outputs
+            .into_iter()
+            .map(|output| output_name(output).unwrap_or("<unnamed>"))
+            .join(", ")
+    };
+    warnings.push(ParseWarning::new(
+        *source_key.span(),
+        format!(
+            "Some outputs define their own `source`, the top-level `source` is inherited by the other outputs. Overriding: {}. Inheriting: {}.",
+            names(overriding),
+            names(inheriting)
+        ),
+    ));
+}
+
+/// Expands an entry of the `outputs` list into the outputs it selects. An
+/// if-selector is evaluated and the chosen branch, `then` or the optional
+/// `else` (a single output or a sequence of outputs), is expanded
+/// recursively. The selected outputs are merged with the top-level keys like
+/// any other output. The nodes are cloned so that the spans point to the
+/// original recipe source.
+///
+/// A condition that uses a variant key (e.g. `cuda == "true"`) can only be
+/// evaluated per variant. The outputs of both branches are kept and the
+/// condition is added to their `build.skip`, so that every variant only builds
+/// the outputs of the branch it selects.
+#[allow(clippy::result_large_err)]
+fn expand_output_selectors(
+    node: &marked_yaml::Node,
+    jinja: &Jinja,
+    root_skip: Option<&marked_yaml::Node>,
+    expanded: &mut Vec<marked_yaml::Node>,
+) -> Result<(), PartialParsingError> {
+    let Some(map) = node.as_mapping().filter(|map| map.contains_key("if")) else {
+        expanded.push(node.clone());
+        return Ok(());
+    };
+
+    let Some(cond) = map.get("if").and_then(|cond| cond.as_scalar()) else {
+        return Err(_partialerror!(
+            *map.get("if").map_or(map.span(), |cond| cond.span()),
+            ErrorKind::IfSelectorConditionNotScalar,
+            label = "if-selector condition must be a scalar"
+        ));
+    };
+
+    let Some(then) = map.get("then") else {
+        return Err(_partialerror!(
+            *map.span(),
+            ErrorKind::IfSelectorMissingThen,
+            label = "if-selector is missing `then` logic"
+        ));
+    };
+
+    let rendering_error = |err: minijinja::Error| {
+        let label = err.to_string();
+        _partialerror!(
+            *cond.span(),
+            ErrorKind::JinjaRendering(Box::new(err)),
+            label = label,
+            help = "error evaluating if-selector condition"
+        )
+    };
+
+    let uses_variant_keys = variables_from_expression(cond.as_str())
+        .map_err(rendering_error)?
+        .iter()
+        .any(|var| !jinja.context().contains_key(var));
+    if uses_variant_keys {
+        let branches = [
+            (Some(then), format!("not ({})", cond.as_str())),
+            (map.get("else"), cond.as_str().to_string()),
+        ];
+        for (branch, skip) in branches {
+            let Some(branch) = branch else {
+                continue;
+            };
+            let mut outputs = Vec::new();
+            expand_output_branch(branch, jinja, root_skip, &mut outputs)?;
+            for mut output in outputs {
+                add_skip_condition(
+                    &mut output,
+                    MarkedScalarNode::new(*cond.span(), skip.as_str()),
+                    root_skip,
+                );
+                expanded.push(output);
+            }
         }
+        return Ok(());
+    }
+
+    let value = jinja.eval(cond.as_str()).map_err(rendering_error)?;
+    let branch = if value.is_true() {
+        Some(then)
+    } else {
+        map.get("else")
+    };
+    if let Some(branch) = branch {
+        expand_output_branch(branch, jinja, root_skip, expanded)?;
     }
 
     Ok(())
 }
 
+/// Expands the branch of an if-selector in the `outputs` list, which is
+/// either a single output or a sequence of outputs.
+#[allow(clippy::result_large_err)]
+fn expand_output_branch(
+    branch: &marked_yaml::Node,
+    jinja: &Jinja,
+    root_skip: Option<&marked_yaml::Node>,
+    expanded: &mut Vec<marked_yaml::Node>,
+) -> Result<(), PartialParsingError> {
+    match branch {
+        marked_yaml::Node::Sequence(outputs) => outputs
+            .iter()
+            .try_for_each(|output| expand_output_selectors(output, jinja, root_skip, expanded)),
+        output => expand_output_selectors(output, jinja, root_skip, expanded),
+    }
+}
+
+/// Adds a condition to the `build.skip` of an output. If the output has no
+/// `build.skip` of its own, it starts from the top-level `build.skip`, which
+/// would otherwise be replaced by the added condition when merging.
+fn add_skip_condition(
+    output: &mut marked_yaml::Node,
+    condition: MarkedScalarNode,
+    root_skip: Option<&marked_yaml::Node>,
+) {
+    let span = *condition.span();
+    // outputs that are not mappings are reported when merging
+    let Some(output) = output.as_mapping_mut() else {
+        return;
+    };
+    let build = output
+        .entry("build".into())
+        .or_insert_with(|| MarkedMappingNode::new_empty(span).into());
+    let Some(build) = build.as_mapping_mut() else {
+        return;
+    };
+    let skip = build.entry("skip".into()).or_insert_with(|| {
+        root_skip
+            .cloned()
+            .unwrap_or_else(|| MarkedSequenceNode::new_empty(span).into())
+    });
+    match skip {
+        marked_yaml::Node::Sequence(conditions) => conditions.push(condition.into()),
+        marked_yaml::Node::Scalar(existing) => {
+            let mut conditions = MarkedSequenceNode::new_empty(*existing.span());
+            conditions.push(existing.clone().into());
+            conditions.push(condition.into());
+            *skip = conditions.into();
+        }
+        // an invalid `build.skip` is reported when parsing the output
+        marked_yaml::Node::Mapping(_) => {}
+    }
+}
+
+/// Splits every output with an `architectures` list (e.g. `[arm64, x86_64]`
+/// for a universal2 macOS build) into one output per architecture. Each
+/// output gets the architecture as the `arch` context variable and as
+/// `build.string_suffix`, so that it is appended to the (default or user
+/// specified) build string. The split outputs are merged with the top-level
+/// keys like any other output.
+#[allow(clippy::result_large_err)]
+fn split_output_architectures(
+    outputs: Vec<marked_yaml::Node>,
+) -> Result<Vec<marked_yaml::Node>, PartialParsingError> {
+    let mut split = Vec::with_capacity(outputs.len());
+    for output in outputs {
+        let Some(architectures) = output
+            .as_mapping()
+            .and_then(|output| output.get("architectures"))
+        else {
+            split.push(output);
+            continue;
+        };
+        let architectures = architectures.as_sequence().ok_or_else(|| {
+            _partialerror!(
+                *architectures.span(),
+                ErrorKind::ExpectedSequence,
+                help = "`architectures` must be a list, e.g. `[arm64, x86_64]`"
+            )
+        })?;
+
+        for arch in architectures.iter() {
+            let arch = arch.as_scalar().ok_or_else(|| {
+                _partialerror!(
+                    *arch.span(),
+                    ErrorKind::ExpectedScalar,
+                    help = "every architecture must be a string"
+                )
+            })?;
+
+            let mut output = output.clone();
+            let output_map = output
+                .as_mapping_mut()
+                .expect("we checked that the output is a mapping");
+            output_map.remove("architectures");
+
+            let context = output_map
+                .entry("context".into())
+                .or_insert_with(|| MarkedMappingNode::new_empty(*arch.span()).into());
+            if let Some(context) = context.as_mapping_mut() {
+                context.insert("arch".into(), arch.clone().into());
+            }
+
+            let build = output_map
+                .entry("build".into())
+                .or_insert_with(|| MarkedMappingNode::new_empty(*arch.span()).into());
+            if let Some(build) = build.as_mapping_mut() {
+                build.insert("string_suffix".into(), arch.clone().into());
+            }
+
+            split.push(output);
+        }
+    }
+    Ok(split)
+}
+
+/// Collects the literal output names used in `pin_subpackage(...)` expressions
+/// in the given node, together with the span of the scalar they appear in.
+/// Only the selected branch of an if-selector is searched. If the condition
+/// cannot be evaluated yet (e.g. it uses a variant variable), the selector is
+/// skipped and left to the renderer.
+fn collect_pin_subpackages(
+    node: &marked_yaml::Node,
+    jinja: &Jinja,
+    pins: &mut Vec<(String, marked_yaml::Span)>,
+) {
+    static PIN_SUBPACKAGE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r#"pin_subpackage\(\s*["']([^"']+)["']"#).unwrap());
+
+    match node {
+        marked_yaml::Node::Scalar(scalar) => {
+            for captures in PIN_SUBPACKAGE.captures_iter(scalar.as_str()) {
+                pins.push((captures[1].to_string(), *scalar.span()));
+            }
+        }
+        marked_yaml::Node::Mapping(map) if map.contains_key("if") => {
+            let Some(Ok(value)) = map
+                .get("if")
+                .and_then(|cond| cond.as_scalar())
+                .map(|cond| jinja.eval(cond.as_str()))
+            else {
+                return;
+            };
+            let branch = if value.is_true() {
+                map.get("then")
+            } else {
+                map.get("else")
+            };
+            if let Some(branch) = branch {
+                collect_pin_subpackages(branch, jinja, pins);
+            }
+        }
+        marked_yaml::Node::Mapping(map) => {
+            for value in map.values() {
+                collect_pin_subpackages(value, jinja, pins);
+            }
+        }
+        marked_yaml::Node::Sequence(seq) => {
+            for value in seq.iter() {
+                collect_pin_subpackages(value, jinja, pins);
+            }
+        }
+    }
+}
+
+/// Verifies that every `pin_subpackage(...)` in the requirements of the
+/// outputs refers to one of the declared outputs. The check is skipped if any
+/// output name is templated, as the names are only known after rendering.
+///
+/// The outputs are not reordered here: the build order (and the detection of
+/// cycles between outputs) is derived from the rendered dependencies, see
+/// [`crate::sort_build_outputs_topologically`].
+#[allow(clippy::result_large_err)]
+fn check_pin_subpackage_targets(
+    outputs: &[marked_yaml::Node],
+    jinja: &Jinja,
+) -> Result<(), PartialParsingError> {
+    let mut names = Vec::with_capacity(outputs.len());
+    for output in outputs {
+        let Some(name) = output_name(output) else {
+            continue;
+        };
+        if name.contains("${{") {
+            return Ok(());
+        }
+        names.push(name);
+    }
+
+    for output in outputs {
+        let Some(requirements) = output
+            .as_mapping()
+            .and_then(|output| output.get("requirements"))
+        else {
+            continue;
+        };
+
+        let mut pins = Vec::new();
+        collect_pin_subpackages(requirements, jinja, &mut pins);
+        for (pin, span) in pins {
+            if !names.iter().any(|name| name.eq_ignore_ascii_case(&pin)) {
+                return Err(_partialerror!(
+                    span,
+                    ErrorKind::InvalidValue(("pin_subpackage".to_string(), pin.clone().into())),
+                    help = format!(
+                        "`{pin}` is not an output of this recipe. Available outputs are: {}",
+                        names.join(", ")
+                    )
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Verifies that the requirements of the top-level `cache` do not
+/// `pin_subpackage(...)` one of the outputs. The cache is built before any of
+/// the outputs, so the pinned output can never exist at that point.
+#[allow(clippy::result_large_err)]
+fn check_cache_pin_subpackages(
+    root: &MarkedMappingNode,
+    outputs: &[marked_yaml::Node],
+    jinja: &Jinja,
+) -> Result<(), PartialParsingError> {
+    let Some(requirements) = root
+        .get("cache")
+        .and_then(|cache| cache.as_mapping())
+        .and_then(|cache| cache.get("requirements"))
+    else {
+        return Ok(());
+    };
+
+    let mut pins = Vec::new();
+    collect_pin_subpackages(requirements, jinja, &mut pins);
+    for (pin, span) in pins {
+        if outputs
+            .iter()
+            .filter_map(output_name)
+            .any(|name| name.eq_ignore_ascii_case(&pin))
+        {
+            return Err(_partialerror!(
+                span,
+                ErrorKind::InvalidValue(("pin_subpackage".to_string(), pin.clone().into())),
+                help = format!(
+                    "the cache is built before the outputs, so it cannot depend on the output `{pin}`"
+                )
+            ));
+        }
+    }
+    Ok(())
+}
+
 /// Retrieve all outputs from the recipe source (YAML)
 #[allow(clippy::result_large_err)]
 pub fn find_outputs_from_src<S: SourceCode>(src: S) -> Result<Vec<Node>, ParsingError<S>> {
+    find_outputs_from_src_with_options(src, &FindOutputsOptions::default())
+}
+
+/// Retrieve all outputs from the recipe source (YAML) using the given
+/// options.
+#[allow(clippy::result_large_err)]
+pub fn find_outputs_from_src_with_options<S: SourceCode>(
+    src: S,
+    options: &FindOutputsOptions,
+) -> Result<Vec<Node>, ParsingError<S>> {
     let root_node = parse_yaml(0, src.clone())?;
+    find_outputs_from_root_node(src, root_node, options)
+}
+
+/// Retrieve all outputs from the recipe source (YAML) together with the
+/// comments of the recipe, e.g. to quote the comments next to a node in
+/// diagnostics (see [`RecipeComments::near`]). The recipe is only parsed once.
+#[allow(clippy::result_large_err)]
+pub fn find_outputs_and_comments_from_src<S: SourceCode>(
+    src: S,
+    options: &FindOutputsOptions,
+) -> Result<(Vec<Node>, RecipeComments), ParsingError<S>> {
+    let comments = RecipeComments::from_source(src.as_ref());
+    let root_node = parse_yaml(0, src.clone())?;
+    let outputs = find_outputs_from_root_node(src, root_node, options)?;
+    Ok((outputs, comments))
+}
+
+/// Retrieve all outputs from an already parsed recipe. The `root_node` must
+/// have been parsed from `src` (e.g. with
+/// [`crate::recipe::custom_yaml::parse_yaml`]), the source is used to render
+/// errors. This avoids parsing the recipe twice if the caller already needs
+/// the parsed YAML.
+#[allow(clippy::result_large_err)]
+pub fn find_outputs_from_root_node<S: SourceCode>(
+    src: S,
+    root_node: marked_yaml::Node,
+    options: &FindOutputsOptions,
+) -> Result<Vec<Node>, ParsingError<S>> {
+    let mut warnings = Vec::new();
     let root_map = root_node.as_mapping().ok_or_else(|| {
         ParsingError::from_partial(
             src.clone(),
@@ -69,7 +616,11 @@ pub fn find_outputs_from_src<S: SourceCode>(src: S) -> Result<Vec<Node>, Parsing
         )
     })?;
 
-    if let Err(err) = check_src_cache(root_map) {
+    if let Err(err) = check_src_cache(root_map, &mut warnings) {
+        return Err(ParsingError::from_partial(src, err));
+    };
+
+    if let Err(err) = check_schema_version(root_map) {
         return Err(ParsingError::from_partial(src, err));
     };
 
@@ -119,30 +670,47 @@ pub fn find_outputs_from_src<S: SourceCode>(src: S) -> Result<Vec<Node>, Parsing
     }
 
     let Some(outputs) = root_map.get("outputs") else {
+        emit_warnings(warnings, options)
+            .map_err(|err| ParsingError::from_partial(src.clone(), err))?;
         let recipe =
             Node::try_from(root_node).map_err(|err| ParsingError::from_partial(src, err))?;
         return Ok(vec![recipe]);
     };
 
     let mut recipe_version: Option<marked_yaml::Node> = None;
+    let mut recipe_context: Option<MarkedMappingNode> = None;
     // If `recipe` exists in root we will use the version as default for all outputs
+    // and the context to seed the context of all outputs.
     // We otherwise ignore the `recipe.name` value.
     if let Some(recipe_mapping) = root_map
         .get("recipe")
         .and_then(|recipe| recipe.as_mapping())
     {
-        // make sure that mapping only contains name and version
+        // make sure that mapping only contains name, version and context
         for (k, v) in recipe_mapping.iter() {
             match k.as_str() {
                 "name" => {}
                 "version" => recipe_version = Some(v.clone()),
+                "context" => {
+                    let Some(context) = v.as_mapping() else {
+                        return Err(ParsingError::from_partial(
+                            src,
+                            _partialerror!(
+                                *v.span(),
+                                ErrorKind::ExpectedMapping,
+                                help = "`recipe.context` must always be a mapping"
+                            ),
+                        ));
+                    };
+                    recipe_context = Some(context.clone());
+                }
                 _ => {
                     return Err(ParsingError::from_partial(
                         src,
                         _partialerror!(
                             *k.span(),
                             ErrorKind::InvalidField(k.as_str().to_string().into()),
-                            help = "recipe can only contain `name` and `version` fields"
+                            help = "recipe can only contain `name`, `version` and `context` fields"
                         ),
                     ));
                 }
@@ -150,23 +718,43 @@ pub fn find_outputs_from_src<S: SourceCode>(src: S) -> Result<Vec<Node>, Parsing
         }
     }
 
-    let Some(outputs) = outputs.as_sequence() else {
-        return Err(ParsingError::from_partial(
-            src,
-            _partialerror!(
-                *outputs.span(),
-                ErrorKind::ExpectedSequence,
-                help = "`outputs` must always be a sequence"
-            ),
-        ));
+    // the schema says that `outputs` can be either an output, a if-selector or a
+    // sequence of outputs and if-selectors. The if-selectors are evaluated
+    // here so that every selected output goes through the same merge logic.
+    let jinja = Jinja::new(options.selector_config.clone().unwrap_or_default());
+    let root_skip = root_map
+        .get("build")
+        .and_then(|build| build.as_mapping())
+        .and_then(|build| build.get("skip"));
+    let mut selected_outputs = Vec::new();
+    let expanded = match outputs {
+        marked_yaml::Node::Sequence(_) | marked_yaml::Node::Mapping(_) => {
+            expand_output_branch(outputs, &jinja, root_skip, &mut selected_outputs)
+        }
+        marked_yaml::Node::Scalar(_) => Err(_partialerror!(
+            *outputs.span(),
+            ErrorKind::ExpectedSequence,
+            help = "`outputs` must always be a sequence"
+        )),
+    };
+    let selected_outputs = match expanded
+        .and_then(|_| split_output_architectures(selected_outputs))
+        .and_then(|outputs| {
+            check_cache_pin_subpackages(root_map, &outputs, &jinja)?;
+            check_pin_subpackage_targets(&outputs, &jinja)?;
+            Ok(outputs)
+        }) {
+        Ok(outputs) => outputs,
+        Err(err) => return Err(ParsingError::from_partial(src, err)),
     };
 
-    let mut res = Vec::with_capacity(outputs.len());
+    check_output_sources(root_map, &selected_outputs, &mut warnings);
 
-    // the schema says that `outputs` can be either an output, a if-selector or a
-    // sequence of outputs and if-selectors. We need to handle all of these
-    // cases but for now, lets handle only sequence of outputs
-    for output in outputs.iter() {
+    let mut res = Vec::with_capacity(selected_outputs.len());
+    // the top-level keys that were merged into at least one output
+    let mut used_root_keys = HashSet::<String>::new();
+
+    for output in selected_outputs.iter() {
         // 1. clone the root node
         // 2. remove the `outputs` key
         // 3. substitute repeated value (make sure to preserve the spans)
@@ -193,6 +781,7 @@ pub fn find_outputs_from_src<S: SourceCode>(src: S) -> Result<Vec<Node>, Parsing
         for (key, value) in root.iter() {
             if !output_map.contains_key(key) {
                 output_map.insert(key.clone(), value.clone());
+                used_root_keys.insert(key.as_str().to_string());
             } else {
                 // deep merge
                 if DEEP_MERGE_KEYS.contains(&key.as_str()) {
@@ -222,9 +811,21 @@ pub fn find_outputs_from_src<S: SourceCode>(src: S) -> Result<Vec<Node>, Parsing
                         ));
                     };
 
-                    for (key, value) in root_value_map.iter() {
-                        if !output_value_map.contains_key(key) {
-                            output_value_map.insert(key.clone(), value.clone());
+                    for (sub_key, value) in root_value_map.iter() {
+                        match output_value_map.get_mut(sub_key) {
+                            None => {
+                                output_value_map.insert(sub_key.clone(), value.clone());
+                                used_root_keys.insert(key.as_str().to_string());
+                            }
+                            Some(output_value) => {
+                                if options.merge_strategy == MergeStrategy::Append
+                                    && let Some(root_list) = value.as_sequence()
+                                    && let Some(output_list) = output_value.as_sequence_mut()
+                                {
+                                    output_list.splice(0..0, root_list.iter().cloned());
+                                    used_root_keys.insert(key.as_str().to_string());
+                                }
+                            }
                         }
                     }
                 }
@@ -250,6 +851,26 @@ pub fn find_outputs_from_src<S: SourceCode>(src: S) -> Result<Vec<Node>, Parsing
             }
         }
 
+        // seed the context of the output with the shared context, the keys of
+        // the output context take precedence (and come after the shared keys)
+        if let Some(recipe_context) = recipe_context.as_ref() {
+            let mut context = recipe_context.clone();
+            match output_map.get_mut("context") {
+                Some(output_context) => {
+                    if let Some(output_context_map) = output_context.as_mapping() {
+                        for (key, value) in output_context_map.iter() {
+                            context.remove(key);
+                            context.insert(key.clone(), value.clone());
+                        }
+                        *output_context = context.into();
+                    }
+                }
+                None => {
+                    output_map.insert("context".into(), context.into());
+                }
+            }
+        }
+
         output_map.remove("recipe");
 
         let recipe = match Node::try_from(output_node) {
@@ -258,6 +879,24 @@ pub fn find_outputs_from_src<S: SourceCode>(src: S) -> Result<Vec<Node>, Parsing
         };
         res.push(recipe);
     }
+
+    // This is only a hint for the recipe author, so it is never turned into an
+    // error (even if warnings are denied). Without any selected outputs, no
+    // top-level key is used, so there is nothing to hint at.
+    if !res.is_empty() {
+        for key in root_map.keys() {
+            if !matches!(key.as_str(), "outputs" | "recipe")
+                && !used_root_keys.contains(key.as_str())
+            {
+                tracing::warn!(
+                    "The top-level `{}` is not used by any of the selected outputs.",
+                    key.as_str()
+                );
+            }
+        }
+    }
+
+    emit_warnings(warnings, options).map_err(|err| ParsingError::from_partial(src, err))?;
     Ok(res)
 }
 
@@ -266,10 +905,18 @@ mod tests {
     use super::*;
     use crate::{
         assert_miette_snapshot,
-        recipe::{Recipe, jinja::SelectorConfig},
+        recipe::{
+            Recipe,
+            custom_yaml::{HasSpan, SequenceNodeInternal},
+            jinja::SelectorConfig,
+            parser::Dependency,
+            variable::Variable,
+        },
     };
     use fs_err as fs;
     use insta::assert_debug_snapshot;
+    use rattler_conda_types::Platform;
+    use std::collections::BTreeMap;
 
     #[test]
     fn recipe_schema_error() {
@@ -307,4 +954,648 @@ mod tests {
         let src = fs::read_to_string(yaml_file).unwrap();
         assert_debug_snapshot!(find_outputs_from_src(src.as_str()).unwrap());
     }
+
+    #[test]
+    fn test_deny_warnings() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+source:
+  url: https://example.com/test.tar.gz
+cache:
+  build:
+    script: echo hello
+outputs:
+  - package:
+      name: test-a
+"#;
+        // by default, the cache-source issue is only a warning
+        let outputs = find_outputs_from_src(src).unwrap();
+        assert_eq!(outputs.len(), 1);
+
+        let options = FindOutputsOptions {
+            deny_warnings: true,
+            ..Default::default()
+        };
+        let err = find_outputs_from_src_with_options(src, &options).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::DeniedWarning(_)));
+    }
+
+    #[test]
+    fn test_outputs_if_selectors() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+build:
+  number: 1
+outputs:
+  - package:
+      name: test-a
+  - if: unix
+    then:
+      - package:
+          name: test-unix
+      - if: linux
+        then:
+          package:
+            name: test-linux
+    else:
+      package:
+        name: test-win
+"#;
+        let names = |platform: Platform| {
+            let options = FindOutputsOptions {
+                selector_config: Some(SelectorConfig {
+                    target_platform: platform,
+                    host_platform: platform,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            find_outputs_from_src_with_options(src, &options)
+                .unwrap()
+                .iter()
+                .map(|node| {
+                    let package = node.as_mapping().unwrap().get("package").unwrap();
+                    let name = package.as_mapping().unwrap().get("name").unwrap();
+                    name.as_scalar().unwrap().as_str().to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(Platform::Linux64),
+            ["test-a", "test-unix", "test-linux"]
+        );
+        assert_eq!(names(Platform::OsxArm64), ["test-a", "test-unix"]);
+        assert_eq!(names(Platform::Win64), ["test-a", "test-win"]);
+
+        // the root keys are merged into the selected outputs as well
+        let outputs = find_outputs_from_src(src).unwrap();
+        let build = outputs[1].as_mapping().unwrap().get("build").unwrap();
+        assert!(build.as_mapping().unwrap().get("number").is_some());
+    }
+
+    #[test]
+    fn test_outputs_if_else_merge() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+build:
+  number: 1
+outputs:
+  - if: unix
+    then:
+      package:
+        name: test-unix
+      build:
+        script: build.sh
+    else:
+      package:
+        name: test-win
+      build:
+        script: build.bat
+"#;
+        let output = |platform: Platform| {
+            let options = FindOutputsOptions {
+                selector_config: Some(SelectorConfig {
+                    target_platform: platform,
+                    host_platform: platform,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            let outputs = find_outputs_from_src_with_options(src, &options).unwrap();
+            assert_eq!(outputs.len(), 1);
+            outputs.into_iter().next().unwrap()
+        };
+
+        for (platform, name, script, line) in [
+            (Platform::Linux64, "test-unix", "build.sh", 11),
+            (Platform::Win64, "test-win", "build.bat", 16),
+        ] {
+            let output = output(platform);
+            let output = output.as_mapping().unwrap();
+            let name_node = output.get("package").unwrap().as_mapping().unwrap()["name"]
+                .as_scalar()
+                .unwrap();
+            assert_eq!(name_node.as_str(), name);
+            // the span points into the selected branch
+            assert_eq!(name_node.span().start().unwrap().line(), line);
+
+            // both branches are deep merged with the top-level `build`
+            let build = output.get("build").unwrap().as_mapping().unwrap();
+            assert_eq!(build["script"].as_scalar().unwrap().as_str(), script);
+            assert_eq!(build["number"].as_scalar().unwrap().as_str(), "1");
+        }
+    }
+
+    #[test]
+    fn test_outputs_variant_if_selectors() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+build:
+  skip: [win]
+outputs:
+  - if: cuda == "true"
+    then:
+      package:
+        name: test-cuda
+    else:
+      package:
+        name: test-cpu
+      build:
+        skip: osx
+"#;
+        let skips = |outputs: &[marked_yaml::Node]| {
+            outputs
+                .iter()
+                .map(|node| {
+                    let output = node.as_mapping().unwrap();
+                    let package = output.get("package").unwrap().as_mapping().unwrap();
+                    let build = output.get("build").unwrap().as_mapping().unwrap();
+                    let skip = build
+                        .get("skip")
+                        .unwrap()
+                        .as_sequence()
+                        .unwrap()
+                        .iter()
+                        .map(|cond| cond.as_scalar().unwrap().as_str().to_string())
+                        .collect::<Vec<_>>();
+                    (
+                        package["name"].as_scalar().unwrap().as_str().to_string(),
+                        skip,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // without a variant both branches are kept and skipped per variant
+        let outputs = find_outputs_from_src(src).unwrap();
+        assert_eq!(
+            skips(&outputs),
+            [
+                (
+                    "test-cuda".to_string(),
+                    vec!["win".to_string(), "not (cuda == \"true\")".to_string()]
+                ),
+                (
+                    "test-cpu".to_string(),
+                    vec!["osx".to_string(), "cuda == \"true\"".to_string()]
+                ),
+            ]
+        );
+
+        // with the variant key in the context the branch is selected directly
+        let options = FindOutputsOptions {
+            selector_config: Some(SelectorConfig {
+                variant: BTreeMap::from([("cuda".into(), Variable::from("true"))]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let outputs = find_outputs_from_src_with_options(src, &options).unwrap();
+        assert_eq!(
+            skips(&outputs),
+            [("test-cuda".to_string(), vec!["win".to_string()])]
+        );
+    }
+
+    #[test]
+    fn test_split_output_architectures() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+build:
+  number: 2
+outputs:
+  - package:
+      name: libfoo
+    architectures: [arm64, x86_64]
+  - package:
+      name: foo
+    build:
+      string: custom
+    architectures: [arm64]
+"#;
+        let outputs = find_outputs_from_src(src).unwrap();
+        let summary = outputs
+            .iter()
+            .map(|output| {
+                let output = output.as_mapping().unwrap();
+                let scalar = |section: &str, key: &str| {
+                    output[section].as_mapping().unwrap()[key]
+                        .as_scalar()
+                        .unwrap()
+                        .as_str()
+                        .to_string()
+                };
+                assert!(output.get("architectures").is_none());
+                (
+                    scalar("package", "name"),
+                    scalar("context", "arch"),
+                    scalar("build", "string_suffix"),
+                    scalar("build", "number"),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let expected =
+            [("libfoo", "arm64"), ("libfoo", "x86_64"), ("foo", "arm64")].map(|(name, arch)| {
+                (
+                    name.to_string(),
+                    arch.to_string(),
+                    arch.to_string(),
+                    "2".to_string(),
+                )
+            });
+        assert_eq!(summary, expected);
+    }
+
+    #[test]
+    fn test_pin_subpackage_unknown_output() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+outputs:
+  - package:
+      name: output-a
+  - package:
+      name: output-b
+    requirements:
+      run:
+        - ${{ pin_subpackage('output-a', exact=True) }}
+        - ${{ pin_subpackage("otuput-a") }}
+"#;
+        let err = find_outputs_from_src(src).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidValue(_)));
+        assert_miette_snapshot!(err);
+
+        let src = src.replace("otuput-a", "output-a");
+        assert_eq!(find_outputs_from_src(src.as_str()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_pin_subpackage_in_unselected_branch() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+cache:
+  requirements:
+    host:
+      - if: win
+        then: ${{ pin_subpackage('foo-win') }}
+outputs:
+  - package:
+      name: foo
+    requirements:
+      run:
+        - if: win
+          then: ${{ pin_subpackage('foo-win') }}
+          else: ${{ pin_subpackage('foo-unix') }}
+  - package:
+      name: foo-unix
+  - if: win
+    then:
+      package:
+        name: foo-win
+"#;
+        let options = |platform: Platform| FindOutputsOptions {
+            selector_config: Some(SelectorConfig {
+                target_platform: platform,
+                host_platform: platform,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // `foo-win` is not an output on linux, but it is only pinned on windows
+        let outputs = find_outputs_from_src_with_options(src, &options(Platform::Linux64)).unwrap();
+        assert_eq!(outputs.len(), 2);
+
+        // on windows the pin in the cache refers to an output
+        let err = find_outputs_from_src_with_options(src, &options(Platform::Win64)).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidValue(_)));
+    }
+
+    #[test]
+    fn test_find_outputs_from_root_node() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+outputs:
+  - package:
+      name: output-a
+  - package:
+      name: output-b
+"#;
+        let root_node = parse_yaml(0, src).unwrap();
+        let outputs =
+            find_outputs_from_root_node(src, root_node, &FindOutputsOptions::default()).unwrap();
+        assert_eq!(
+            format!("{outputs:?}"),
+            format!("{:?}", find_outputs_from_src(src).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_find_outputs_and_comments_from_src() {
+        let src = r#"
+# shared by all outputs
+recipe:
+  name: test
+  version: 0.1.0
+outputs:
+  - package:
+      name: output-a # the library
+"#;
+        let (outputs, comments) =
+            find_outputs_and_comments_from_src(src, &FindOutputsOptions::default()).unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(
+            comments.iter().map(|c| c.text.as_str()).collect::<Vec<_>>(),
+            ["shared by all outputs", "the library"]
+        );
+    }
+
+    #[test]
+    fn test_cache_pin_subpackage_output() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+cache:
+  requirements:
+    host:
+      - zlib
+      - ${{ pin_subpackage('libfoo') }}
+outputs:
+  - package:
+      name: libfoo
+  - package:
+      name: foo
+    requirements:
+      run:
+        - ${{ pin_subpackage('libfoo') }}
+"#;
+        let err = find_outputs_from_src(src).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidValue(_)));
+        assert_miette_snapshot!(err);
+
+        let src = src.replace("      - ${{ pin_subpackage('libfoo') }}\n", "");
+        assert_eq!(find_outputs_from_src(src.as_str()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_merge_strategy_append() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+build:
+  number: 1
+  script:
+    - echo top-level
+outputs:
+  - package:
+      name: test-a
+    build:
+      script:
+        - echo output
+  - package:
+      name: test-b
+"#;
+        let script = |strategy: MergeStrategy| {
+            let options = FindOutputsOptions {
+                merge_strategy: strategy,
+                ..Default::default()
+            };
+            let outputs = find_outputs_from_src_with_options(src, &options).unwrap();
+            outputs
+                .iter()
+                .map(|output| {
+                    let build = output.as_mapping().unwrap().get("build").unwrap();
+                    let script = build.as_mapping().unwrap().get("script").unwrap();
+                    script
+                        .as_sequence()
+                        .unwrap()
+                        .iter()
+                        .map(|line| match line {
+                            SequenceNodeInternal::Simple(line) => {
+                                line.as_scalar().unwrap().as_str().to_string()
+                            }
+                            SequenceNodeInternal::Conditional(_) => unreachable!(),
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // by default the output script replaces the top-level script
+        assert_eq!(
+            script(MergeStrategy::Replace),
+            [vec!["echo output"], vec!["echo top-level"]]
+        );
+        assert_eq!(
+            script(MergeStrategy::Append),
+            [
+                vec!["echo top-level", "echo output"],
+                vec!["echo top-level"]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_output_source_override_warning() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+source:
+  url: https://example.com/test.tar.gz
+outputs:
+  - package:
+      name: test-a
+  - package:
+      name: test-b
+    source:
+      path: ./other
+"#;
+        let options = FindOutputsOptions {
+            deny_warnings: true,
+            ..Default::default()
+        };
+        let err = find_outputs_from_src_with_options(src, &options).unwrap_err();
+        let ErrorKind::DeniedWarning(message) = err.kind() else {
+            panic!("expected a denied warning, got {:?}", err.kind());
+        };
+        assert!(message.contains("Overriding: test-b. Inheriting: test-a."));
+
+        // no warning if all outputs inherit the top-level source
+        let src = src.replace("    source:\n      path: ./other\n", "");
+        assert!(find_outputs_from_src_with_options(src.as_str(), &options).is_ok());
+    }
+
+    #[test]
+    fn test_outputs_with_anchors() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+outputs:
+  - package:
+      name: test-a
+    requirements: &common_requirements
+      host:
+        - python
+      run:
+        - python
+        - numpy
+  - package:
+      name: test-b
+    requirements: *common_requirements
+"#;
+        let outputs = find_outputs_from_src(src).unwrap();
+        let requirements = outputs
+            .iter()
+            .map(|output| output.as_mapping().unwrap().get("requirements").unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(requirements[0], requirements[1]);
+        // the alias points to the span of the anchored node
+        assert_eq!(requirements[0].span(), requirements[1].span());
+
+        let recipes = outputs
+            .iter()
+            .map(|output| Recipe::from_node(output, SelectorConfig::default()).unwrap())
+            .collect::<Vec<_>>();
+        for recipe in &recipes {
+            let run = recipe
+                .requirements()
+                .run()
+                .iter()
+                .map(|dep| match dep {
+                    Dependency::Spec(spec) => spec.to_string(),
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(run, ["python", "numpy"]);
+        }
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let src = |schema_version: &str| {
+            format!(
+                "{schema_version}\nrecipe:\n  name: test\n  version: 0.1.0\noutputs:\n  - package:\n      name: test-a\n"
+            )
+        };
+
+        // missing defaults to the current version
+        assert!(find_outputs_from_src(src("")).is_ok());
+        assert!(find_outputs_from_src(src("schema_version: 1")).is_ok());
+
+        let err = find_outputs_from_src(src("schema_version: 3")).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::UnsupportedSchemaVersion(3)));
+        assert_eq!(
+            err.kind().to_string(),
+            "schema_version 3 is not supported by this rattler-build."
+        );
+
+        let err = find_outputs_from_src(src("schema_version: one")).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidValue(_)));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_unused_root_keys() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+about:
+  summary: Top-level summary
+build:
+  number: 1
+outputs:
+  - package:
+      name: test-a
+    about:
+      summary: Summary of test-a
+  - package:
+      name: test-b
+    about:
+      summary: Summary of test-b
+"#;
+        // warnings about unused keys never turn into errors
+        let options = FindOutputsOptions {
+            deny_warnings: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            find_outputs_from_src_with_options(src, &options)
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(logs_contain(
+            "The top-level `about` is not used by any of the selected outputs"
+        ));
+        assert!(!logs_contain("The top-level `build` is not used"));
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_unused_root_keys_without_outputs() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+about:
+  summary: Top-level summary
+outputs:
+  - if: false
+    then:
+      package:
+        name: test-a
+"#;
+        assert!(find_outputs_from_src(src).unwrap().is_empty());
+        assert!(!logs_contain("is not used by any of the selected outputs"));
+    }
+
+    #[test]
+    fn test_recipe_context() {
+        let src = r#"
+recipe:
+  name: test
+  version: 0.1.0
+  context:
+    prefix: test
+    suffix: shared
+outputs:
+  - package:
+      name: ${{ prefix }}-${{ suffix }}
+  - context:
+      suffix: own
+    package:
+      name: ${{ prefix }}-${{ suffix }}
+"#;
+        let names = find_outputs_from_src(src)
+            .unwrap()
+            .iter()
+            .map(|output| {
+                let recipe = Recipe::from_node(output, SelectorConfig::default()).unwrap();
+                recipe.package().name().as_normalized().to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["test-shared", "test-own"]);
+    }
 }
@@ -1,23 +1,102 @@
 use std::{
+    io::Write,
+    str::FromStr,
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use indicatif::{MultiProgress, ProgressBar, ProgressFinish, ProgressStyle};
-use rattler::install::Placement;
+use indicatif::{
+    HumanBytes, MultiProgress, ProgressBar, ProgressFinish, ProgressState, ProgressStyle,
+    style::ProgressTracker,
+};
+use rattler::install::{Placement, Transaction};
+use rattler_conda_types::{Platform, PrefixRecord, RepoDataRecord};
 use rattler_repodata_gateway::{DownloadReporter, JLAPReporter, Reporter};
+use serde::Serialize;
 use url::Url;
 
+use super::solver::short_channel_name;
+use crate::tool_configuration::ProgressOutput;
+
+/// A machine readable progress event. Events are written to stderr as
+/// newline-delimited JSON when [`ProgressOutput::Json`] is selected.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum ProgressEvent<'a> {
+    DownloadStart {
+        url: &'a str,
+    },
+    DownloadProgress {
+        url: &'a str,
+        bytes: u64,
+        total: Option<u64>,
+    },
+    DownloadComplete {
+        url: &'a str,
+        bytes: u64,
+    },
+    InstallStart {
+        operations: usize,
+    },
+    PackageDownloadStart {
+        package: &'a str,
+    },
+    PackageDownloadProgress {
+        package: &'a str,
+        bytes: u64,
+        total: Option<u64>,
+    },
+    PackageDownloadComplete {
+        package: &'a str,
+    },
+    LinkStart {
+        package: &'a str,
+    },
+    LinkComplete {
+        package: &'a str,
+    },
+    InstallComplete,
+}
+
+impl ProgressEvent<'_> {
+    fn to_json_line(&self) -> String {
+        serde_json::to_string(self).expect("progress events are always serializable")
+    }
+
+    fn emit(&self) {
+        let mut stderr = std::io::stderr().lock();
+        _ = writeln!(stderr, "{}", self.to_json_line());
+    }
+}
+
+/// A progress bar of a single download.
+struct DownloadBar {
+    progress_bar: ProgressBar,
+    /// Whether the download is still waiting to start (no bytes received yet).
+    waiting: bool,
+    /// The channel the download belongs to, see [`summary_channel`].
+    channel: String,
+    /// Whether the download was already included in a summary line.
+    summarized: bool,
+}
+
 /// Reporter used for tracking download progress via `MultiProgress`.
 pub struct GatewayReporter {
-    progress_bars: Arc<Mutex<Vec<ProgressBar>>>,
+    progress_bars: Arc<Mutex<Vec<DownloadBar>>>,
     multi_progress: MultiProgress,
     progress_template: Option<ProgressStyle>,
     finish_template: Option<ProgressStyle>,
+    waiting_template: Option<ProgressStyle>,
     prefix: String,
     finish_message: Option<String>,
     steady_tick: Option<Duration>,
+    stall_timeout: Option<Duration>,
     placement: Placement,
+    download_summary: bool,
+    overall_progress: bool,
+    overall_bar: Mutex<Option<ProgressBar>>,
+    json_events: bool,
+    clear_on_drop: bool,
 }
 
 /// Builder for [`GatewayReporter`].
@@ -26,10 +105,75 @@ pub struct GatewayReporterBuilder {
     multi_progress: Option<MultiProgress>,
     progress_template: Option<ProgressStyle>,
     finish_template: Option<ProgressStyle>,
+    waiting_template: Option<ProgressStyle>,
     prefix: Option<String>,
     finish_message: Option<String>,
     steady_tick: Option<Duration>,
+    stall_timeout: Option<Duration>,
     placement: Option<Placement>,
+    download_summary: bool,
+    overall_progress: bool,
+    progress_output: ProgressOutput,
+    clear_on_drop: bool,
+}
+
+/// Returns a short name for a repodata url, e.g. `conda-forge/linux-64/repodata.json`.
+fn short_url(url: &Url) -> String {
+    url.path_segments()
+        .map(|segments| {
+            let segments = segments.filter(|s| !s.is_empty()).collect::<Vec<_>>();
+            segments[segments.len().saturating_sub(3)..].join("/")
+        })
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// Returns the short channel name and the subdir of a repodata url, e.g.
+/// `conda-forge` and `linux-64`. The subdir is the last path segment that is a
+/// known platform, the channel is everything before it.
+fn channel_and_subdir(url: &Url) -> Option<(String, String)> {
+    let segments = url
+        .path_segments()?
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    let subdir_index = segments
+        .iter()
+        .rposition(|s| Platform::from_str(s).is_ok())?;
+    let channel = format!(
+        "{}/{}",
+        url.origin().ascii_serialization(),
+        segments[..subdir_index].join("/")
+    );
+    Some((
+        short_channel_name(&channel),
+        segments[subdir_index].to_string(),
+    ))
+}
+
+/// Returns the channel and subdir of a repodata url as a progress bar prefix,
+/// e.g. `conda-forge/linux-64`.
+fn channel_prefix(url: &Url) -> Option<String> {
+    let (channel, subdir) = channel_and_subdir(url)?;
+    Some(format!("{channel}/{subdir}"))
+}
+
+/// Returns the channel that the downloads of a url are summarized under, e.g.
+/// `conda-forge`. Urls without a channel are summarized on their own.
+fn summary_channel(url: &Url) -> String {
+    channel_and_subdir(url).map_or_else(|| short_url(url), |(channel, _)| channel)
+}
+
+/// Returns the prefix of the progress bar of a single download: the configured
+/// prefix followed by the channel and subdir of the url, e.g. `Downloading
+/// conda-forge/linux-64`. Urls without a channel only get the configured prefix.
+fn download_bar_prefix(prefix: &str, url: &Url) -> String {
+    match channel_prefix(url) {
+        Some(channel) if prefix.is_empty() || prefix.ends_with(char::is_whitespace) => {
+            format!("{prefix}{channel}")
+        }
+        Some(channel) => format!("{prefix} {channel}"),
+        None => prefix.to_string(),
+    }
 }
 
 impl GatewayReporter {
@@ -46,31 +190,177 @@ impl GatewayReporter {
             Placement::End => self.multi_progress.add(progress_bar),
         }
     }
+
+    /// Returns the style for a running download. If a stall timeout is
+    /// configured, the `{stalled}` key of the template shows a message when no
+    /// bytes arrived for that long.
+    fn progress_style(&self) -> Option<ProgressStyle> {
+        let template = self.progress_template.clone()?;
+        Some(match self.stall_timeout {
+            Some(timeout) => template.with_key("stalled", StallTracker::new(timeout)),
+            None => template,
+        })
+    }
+
+    /// Enables the steady tick, unless the bars are not drawn anyway.
+    fn enable_steady_tick(&self, progress_bar: &ProgressBar) {
+        if let Some(duration) = self.steady_tick
+            && !self.multi_progress.is_hidden()
+        {
+            progress_bar.enable_steady_tick(duration);
+        }
+    }
+
+    /// Creates the overall progress bar if it does not exist yet or if the
+    /// previous batch of downloads has already finished.
+    fn start_overall_bar(&self) {
+        let mut overall_bar = self.overall_bar.lock().unwrap();
+        if overall_bar.as_ref().is_some_and(|bar| !bar.is_finished()) {
+            return;
+        }
+
+        let progress_bar = ProgressBar::new(0)
+            .with_finish(ProgressFinish::AndLeave)
+            .with_prefix(self.prefix.clone());
+        if let Some(style) = self.progress_style() {
+            progress_bar.set_style(style);
+        }
+        self.enable_steady_tick(&progress_bar);
+        *overall_bar = Some(self.place_progress_bar(progress_bar));
+    }
+
+    /// Updates the overall progress bar from the state of all downloads.
+    fn update_overall_bar(&self, download_bars: &[DownloadBar]) {
+        let overall_bar = self.overall_bar.lock().unwrap();
+        let Some(overall_bar) = overall_bar.as_ref() else {
+            return;
+        };
+
+        let remaining = download_bars
+            .iter()
+            .filter(|bar| !bar.progress_bar.is_finished())
+            .count();
+        let total = download_bars
+            .iter()
+            .map(|bar| bar.progress_bar.length())
+            .sum::<Option<u64>>();
+        match total {
+            Some(total) => overall_bar.set_length(total),
+            None => overall_bar.unset_length(),
+        }
+        overall_bar.set_position(
+            download_bars
+                .iter()
+                .map(|bar| bar.progress_bar.position())
+                .sum(),
+        );
+
+        if remaining == 0 {
+            if let Some(template) = &self.finish_template {
+                overall_bar.set_style(template.clone());
+            }
+            match &self.finish_message {
+                Some(message) => overall_bar.finish_with_message(message.clone()),
+                None => overall_bar.finish(),
+            }
+        } else {
+            overall_bar.set_message(format!(
+                "{remaining} of {} downloads remaining",
+                download_bars.len()
+            ));
+        }
+    }
+}
+
+/// Logs a single summary line for the channel of the download at `index` once
+/// all of its downloads have finished.
+fn log_channel_summary(download_bars: &mut [DownloadBar], index: usize) {
+    let channel = download_bars[index].channel.clone();
+    let mut pending = download_bars
+        .iter_mut()
+        .filter(|bar| bar.channel == channel && !bar.summarized)
+        .collect::<Vec<_>>();
+    if pending.iter().any(|bar| !bar.progress_bar.is_finished()) {
+        return;
+    }
+
+    let bytes = pending
+        .iter()
+        .map(|bar| bar.progress_bar.position())
+        .sum::<u64>();
+    for bar in pending.iter_mut() {
+        bar.summarized = true;
+    }
+    tracing::info!(
+        "Downloaded repodata from {channel}: {} {}, {}",
+        pending.len(),
+        if pending.len() == 1 { "file" } else { "files" },
+        HumanBytes(bytes)
+    );
 }
 
 impl DownloadReporter for GatewayReporter {
-    fn on_download_start(&self, _url: &Url) -> usize {
+    fn on_download_start(&self, url: &Url) -> usize {
+        if self.json_events {
+            ProgressEvent::DownloadStart { url: url.as_str() }.emit();
+        } else if self.overall_progress {
+            self.start_overall_bar();
+        }
+
+        // In overall progress or JSON mode the individual downloads are
+        // tracked with hidden bars so that they are not drawn.
+        if self.overall_progress || self.json_events {
+            let mut progress_bars = self.progress_bars.lock().unwrap();
+            progress_bars.push(DownloadBar {
+                progress_bar: ProgressBar::hidden(),
+                waiting: false,
+                channel: summary_channel(url),
+                summarized: false,
+            });
+            self.update_overall_bar(&progress_bars);
+            return progress_bars.len() - 1;
+        }
+
         let progress_bar = ProgressBar::new(1)
             .with_finish(ProgressFinish::AndLeave)
-            .with_prefix(self.prefix.clone());
+            .with_prefix(download_bar_prefix(&self.prefix, url));
 
-        if let Some(template) = &self.progress_template {
+        // Downloads may be queued (e.g. when the number of concurrent requests
+        // per host is limited), show those as waiting until the first bytes
+        // arrive.
+        let waiting = self.waiting_template.is_some();
+        if let Some(template) = &self.waiting_template {
             progress_bar.set_style(template.clone());
+            progress_bar.set_message("waiting");
+        } else if let Some(style) = self.progress_style() {
+            progress_bar.set_style(style);
         }
 
-        if let Some(duration) = self.steady_tick {
-            progress_bar.enable_steady_tick(duration);
-        }
+        self.enable_steady_tick(&progress_bar);
 
         let progress_bar = self.place_progress_bar(progress_bar);
 
         let mut progress_bars = self.progress_bars.lock().unwrap();
-        progress_bars.push(progress_bar);
+        progress_bars.push(DownloadBar {
+            progress_bar,
+            waiting,
+            channel: summary_channel(url),
+            summarized: false,
+        });
         progress_bars.len() - 1
     }
 
-    fn on_download_complete(&self, _url: &Url, index: usize) {
-        if let Some(progress_bar) = self.progress_bars.lock().unwrap().get(index) {
+    fn on_download_complete(&self, url: &Url, index: usize) {
+        let mut progress_bars = self.progress_bars.lock().unwrap();
+        if let Some(DownloadBar { progress_bar, .. }) = progress_bars.get(index) {
+            if self.json_events {
+                ProgressEvent::DownloadComplete {
+                    url: url.as_str(),
+                    bytes: progress_bar.position(),
+                }
+                .emit();
+            }
+
             if let Some(template) = &self.finish_template {
                 progress_bar.set_style(template.clone());
             }
@@ -80,13 +370,65 @@ impl DownloadReporter for GatewayReporter {
             } else {
                 progress_bar.finish();
             }
+            self.update_overall_bar(&progress_bars);
+            if self.download_summary {
+                log_channel_summary(&mut progress_bars, index);
+            }
+        } else {
+            tracing::debug!("download of {url} completed with unknown progress bar index {index}");
         }
     }
 
-    fn on_download_progress(&self, _url: &Url, index: usize, bytes: usize, total: Option<usize>) {
-        if let Some(progress_bar) = self.progress_bars.lock().unwrap().get(index) {
-            progress_bar.set_length(total.unwrap_or(bytes) as u64);
+    fn on_download_progress(&self, url: &Url, index: usize, bytes: usize, total: Option<usize>) {
+        let mut progress_bars = self.progress_bars.lock().unwrap();
+        if let Some(DownloadBar {
+            progress_bar,
+            waiting,
+        }) = progress_bars.get_mut(index)
+        {
+            if *waiting {
+                *waiting = false;
+                progress_bar.set_message("");
+                if let Some(style) = self.progress_style() {
+                    progress_bar.set_style(style);
+                }
+            }
+            // Without a known total the bar stays empty and the ETA is shown
+            // as unknown, the byte count and rate are still updated.
+            match total {
+                Some(total) => progress_bar.set_length(total as u64),
+                None => progress_bar.unset_length(),
+            }
             progress_bar.set_position(bytes as u64);
+            self.update_overall_bar(&progress_bars);
+            if self.json_events {
+                ProgressEvent::DownloadProgress {
+                    url: url.as_str(),
+                    bytes: bytes as u64,
+                    total: total.map(|total| total as u64),
+                }
+                .emit();
+            }
+        } else {
+            tracing::debug!("progress reported for {url} with unknown progress bar index {index}");
+        }
+    }
+}
+
+impl Drop for GatewayReporter {
+    fn drop(&mut self) {
+        if !self.clear_on_drop {
+            return;
+        }
+
+        let overall_bar = self.overall_bar.lock().unwrap().take();
+        let download_bars = std::mem::take(&mut *self.progress_bars.lock().unwrap());
+        for progress_bar in overall_bar
+            .into_iter()
+            .chain(download_bars.into_iter().map(|bar| bar.progress_bar))
+        {
+            progress_bar.finish_and_clear();
+            self.multi_progress.remove(&progress_bar);
         }
     }
 }
@@ -123,7 +465,18 @@ impl GatewayReporterBuilder {
         self
     }
 
-    /// Configure the prefix shown for the progress bar.
+    /// Configure the style for downloads that are queued but did not receive
+    /// any data yet. If not set, downloads are shown with the progress
+    /// template right away.
+    #[must_use]
+    pub fn with_waiting_template(mut self, template: ProgressStyle) -> Self {
+        self.waiting_template = Some(template);
+        self
+    }
+
+    /// Configure the prefix shown for the progress bars. The bars of single
+    /// downloads append the channel and subdir to it, e.g. `Downloading
+    /// conda-forge/linux-64`.
     #[must_use]
     pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
         self.prefix = Some(prefix.into());
@@ -137,13 +490,24 @@ impl GatewayReporterBuilder {
         self
     }
 
-    /// Enable steady ticking for the progress bar.
+    /// Enable steady ticking for the progress bar. This keeps the spinner
+    /// moving while a download does not make progress. Ticking is disabled if
+    /// the multi progress is hidden.
     #[must_use]
     pub fn with_steady_tick(mut self, duration: Duration) -> Self {
         self.steady_tick = Some(duration);
         self
     }
 
+    /// Show a "stalled" message in the `{stalled}` key of the progress
+    /// template if no bytes arrived for the given duration. This requires a
+    /// steady tick, otherwise the bar is not redrawn while the download stalls.
+    #[must_use]
+    pub fn with_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
     /// Configure where to place the progress bar alongside other bars.
     #[must_use]
     pub fn with_placement(mut self, placement: Placement) -> Self {
@@ -151,6 +515,43 @@ impl GatewayReporterBuilder {
         self
     }
 
+    /// Log a summary line for every channel once all of its downloads have
+    /// completed. The progress bars are usually cleared once all downloads are
+    /// done, the summary lines keep a record of what was downloaded (e.g. in CI
+    /// logs).
+    #[must_use]
+    pub fn with_download_summary(mut self, download_summary: bool) -> Self {
+        self.download_summary = download_summary;
+        self
+    }
+
+    /// Show a single bar with the total number of bytes downloaded and the
+    /// number of remaining downloads instead of one bar per download. This
+    /// keeps the output to a single line, e.g. in CI logs.
+    #[must_use]
+    pub fn with_overall_progress(mut self, overall_progress: bool) -> Self {
+        self.overall_progress = overall_progress;
+        self
+    }
+
+    /// Configure how progress is reported. With [`ProgressOutput::Json`] no
+    /// bars are drawn and every update is written to stderr as a JSON event.
+    #[must_use]
+    pub fn with_progress_output(mut self, progress_output: ProgressOutput) -> Self {
+        self.progress_output = progress_output;
+        self
+    }
+
+    /// Remove the progress bars of this reporter from the multi progress when
+    /// the reporter is dropped. Unlike clearing the whole multi progress this
+    /// leaves the bars of other reporters (e.g. of concurrent solves or of
+    /// package installations) untouched.
+    #[must_use]
+    pub fn with_clear_on_drop(mut self, clear_on_drop: bool) -> Self {
+        self.clear_on_drop = clear_on_drop;
+        self
+    }
+
     /// Finalize the builder.
     pub fn finish(self) -> GatewayReporter {
         GatewayReporter {
@@ -158,12 +559,577 @@ impl GatewayReporterBuilder {
             multi_progress: self.multi_progress.expect("multi progress is required"),
             progress_template: self.progress_template,
             finish_template: self.finish_template,
+            waiting_template: self.waiting_template,
             prefix: self
                 .prefix
                 .unwrap_or_else(|| "Downloading repodata".to_string()),
             finish_message: Some(self.finish_message.unwrap_or_else(|| "Done".to_string())),
             steady_tick: self.steady_tick,
+            stall_timeout: self.stall_timeout,
             placement: self.placement.unwrap_or_default(),
+            download_summary: self.download_summary,
+            overall_progress: self.overall_progress,
+            overall_bar: Mutex::new(None),
+            json_events: self.progress_output == ProgressOutput::Json,
+            clear_on_drop: self.clear_on_drop,
+        }
+    }
+}
+
+/// Progress tracker for the `{stalled}` template key. It remembers when the
+/// position of the bar changed last and shows a message once that is longer
+/// ago than the timeout.
+#[derive(Clone)]
+struct StallTracker {
+    timeout: Duration,
+    last_change: Option<(u64, Instant)>,
+    stalled: bool,
+}
+
+impl StallTracker {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            last_change: None,
+            stalled: false,
+        }
+    }
+
+    fn update(&mut self, pos: u64, now: Instant) {
+        match self.last_change {
+            Some((last_pos, since)) if last_pos == pos => {
+                self.stalled = now.saturating_duration_since(since) >= self.timeout;
+            }
+            _ => {
+                self.last_change = Some((pos, now));
+                self.stalled = false;
+            }
+        }
+    }
+}
+
+impl ProgressTracker for StallTracker {
+    fn clone_box(&self) -> Box<dyn ProgressTracker> {
+        Box::new(self.clone())
+    }
+
+    fn tick(&mut self, state: &ProgressState, now: Instant) {
+        self.update(state.pos(), now);
+    }
+
+    fn reset(&mut self, state: &ProgressState, now: Instant) {
+        self.last_change = Some((state.pos(), now));
+        self.stalled = false;
+    }
+
+    fn write(&self, _state: &ProgressState, w: &mut dyn std::fmt::Write) {
+        if self.stalled {
+            _ = write!(
+                w,
+                "stalled, no data for {}",
+                indicatif::HumanDuration(self.timeout)
+            );
+        }
+    }
+}
+
+/// Install reporter that writes newline-delimited JSON events to stderr
+/// instead of drawing progress bars.
+#[derive(Default)]
+pub struct JsonInstallReporter {
+    /// Package names of the cache entries, by cache entry index.
+    cache_entries: Mutex<Vec<String>>,
+    /// Package names of the downloads, by download index.
+    downloads: Mutex<Vec<String>>,
+    /// Package names of the link operations, by link index.
+    links: Mutex<Vec<String>>,
+}
+
+/// Pushes `package` to `names` and returns its index.
+fn push_name(names: &Mutex<Vec<String>>, package: String) -> usize {
+    let mut names = names.lock().unwrap();
+    names.push(package);
+    names.len() - 1
+}
+
+/// Returns the package name at `index`, or an empty string if unknown.
+fn name_at(names: &Mutex<Vec<String>>, index: usize) -> String {
+    names
+        .lock()
+        .unwrap()
+        .get(index)
+        .cloned()
+        .unwrap_or_default()
+}
+
+impl rattler::install::Reporter for JsonInstallReporter {
+    fn on_transaction_start(&self, transaction: &Transaction<PrefixRecord, RepoDataRecord>) {
+        ProgressEvent::InstallStart {
+            operations: transaction.operations.len(),
+        }
+        .emit();
+    }
+
+    fn on_transaction_operation_start(&self, _operation: usize) {}
+
+    fn on_populate_cache_start(&self, _operation: usize, record: &RepoDataRecord) -> usize {
+        push_name(
+            &self.cache_entries,
+            record.package_record.name.as_normalized().to_string(),
+        )
+    }
+
+    fn on_validate_start(&self, cache_entry: usize) -> usize {
+        cache_entry
+    }
+
+    fn on_validate_complete(&self, _validate_idx: usize) {}
+
+    fn on_download_start(&self, cache_entry: usize) -> usize {
+        let package = name_at(&self.cache_entries, cache_entry);
+        ProgressEvent::PackageDownloadStart { package: &package }.emit();
+        push_name(&self.downloads, package)
+    }
+
+    fn on_download_progress(&self, download_idx: usize, progress: u64, total: Option<u64>) {
+        let package = name_at(&self.downloads, download_idx);
+        ProgressEvent::PackageDownloadProgress {
+            package: &package,
+            bytes: progress,
+            total,
+        }
+        .emit();
+    }
+
+    fn on_download_completed(&self, download_idx: usize) {
+        let package = name_at(&self.downloads, download_idx);
+        ProgressEvent::PackageDownloadComplete { package: &package }.emit();
+    }
+
+    fn on_populate_cache_complete(&self, _cache_entry: usize) {}
+
+    fn on_unlink_start(&self, operation: usize, _record: &PrefixRecord) -> usize {
+        operation
+    }
+
+    fn on_unlink_complete(&self, _index: usize) {}
+
+    fn on_link_start(&self, _operation: usize, record: &RepoDataRecord) -> usize {
+        let package = record.package_record.name.as_normalized().to_string();
+        ProgressEvent::LinkStart { package: &package }.emit();
+        push_name(&self.links, package)
+    }
+
+    fn on_link_complete(&self, index: usize) {
+        let package = name_at(&self.links, index);
+        ProgressEvent::LinkComplete { package: &package }.emit();
+    }
+
+    fn on_transaction_operation_complete(&self, _operation: usize) {}
+
+    fn on_transaction_complete(&self) {
+        ProgressEvent::InstallComplete.emit();
+    }
+
+    fn on_post_link_start(&self, _package_name: &str, _script_path: &str) -> usize {
+        0
+    }
+
+    fn on_post_link_complete(&self, _index: usize, _success: bool) {}
+
+    fn on_pre_unlink_start(&self, _package_name: &str, _script_path: &str) -> usize {
+        0
+    }
+
+    fn on_pre_unlink_complete(&self, _index: usize, _success: bool) {}
+}
+
+/// The stage of linking a package, passed to a [`LinkCallback`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkStage {
+    /// The package is about to be linked into the prefix.
+    Started,
+    /// The package was linked into the prefix.
+    Completed,
+}
+
+/// Callback that is invoked when a package starts and finishes linking into
+/// an environment, e.g. to collect install timings per package.
+pub type LinkCallback = Arc<dyn Fn(&RepoDataRecord, LinkStage) + Send + Sync>;
+
+/// Install reporter that forwards all events to another reporter and
+/// additionally calls a [`LinkCallback`] for every linked package.
+pub struct LinkCallbackReporter<R> {
+    inner: R,
+    callback: LinkCallback,
+    /// The index of the inner reporter and the record, by link index.
+    links: Mutex<Vec<(usize, RepoDataRecord)>>,
+}
+
+impl<R: rattler::install::Reporter> LinkCallbackReporter<R> {
+    /// Wraps `inner`, calling `callback` on each link start and completion.
+    pub fn new(inner: R, callback: LinkCallback) -> Self {
+        Self {
+            inner,
+            callback,
+            links: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<R: rattler::install::Reporter> rattler::install::Reporter for LinkCallbackReporter<R> {
+    fn on_transaction_start(&self, transaction: &Transaction<PrefixRecord, RepoDataRecord>) {
+        self.inner.on_transaction_start(transaction)
+    }
+
+    fn on_transaction_operation_start(&self, operation: usize) {
+        self.inner.on_transaction_operation_start(operation)
+    }
+
+    fn on_populate_cache_start(&self, operation: usize, record: &RepoDataRecord) -> usize {
+        self.inner.on_populate_cache_start(operation, record)
+    }
+
+    fn on_validate_start(&self, cache_entry: usize) -> usize {
+        self.inner.on_validate_start(cache_entry)
+    }
+
+    fn on_validate_complete(&self, validate_idx: usize) {
+        self.inner.on_validate_complete(validate_idx)
+    }
+
+    fn on_download_start(&self, cache_entry: usize) -> usize {
+        self.inner.on_download_start(cache_entry)
+    }
+
+    fn on_download_progress(&self, download_idx: usize, progress: u64, total: Option<u64>) {
+        self.inner
+            .on_download_progress(download_idx, progress, total)
+    }
+
+    fn on_download_completed(&self, download_idx: usize) {
+        self.inner.on_download_completed(download_idx)
+    }
+
+    fn on_populate_cache_complete(&self, cache_entry: usize) {
+        self.inner.on_populate_cache_complete(cache_entry)
+    }
+
+    fn on_unlink_start(&self, operation: usize, record: &PrefixRecord) -> usize {
+        self.inner.on_unlink_start(operation, record)
+    }
+
+    fn on_unlink_complete(&self, index: usize) {
+        self.inner.on_unlink_complete(index)
+    }
+
+    fn on_link_start(&self, operation: usize, record: &RepoDataRecord) -> usize {
+        (self.callback)(record, LinkStage::Started);
+        let inner_index = self.inner.on_link_start(operation, record);
+        let mut links = self.links.lock().unwrap();
+        links.push((inner_index, record.clone()));
+        links.len() - 1
+    }
+
+    fn on_link_complete(&self, index: usize) {
+        let link = self.links.lock().unwrap().get(index).cloned();
+        if let Some((inner_index, record)) = link {
+            self.inner.on_link_complete(inner_index);
+            (self.callback)(&record, LinkStage::Completed);
         }
     }
+
+    fn on_transaction_operation_complete(&self, operation: usize) {
+        self.inner.on_transaction_operation_complete(operation)
+    }
+
+    fn on_transaction_complete(&self) {
+        self.inner.on_transaction_complete()
+    }
+
+    fn on_post_link_start(&self, package_name: &str, script_path: &str) -> usize {
+        self.inner.on_post_link_start(package_name, script_path)
+    }
+
+    fn on_post_link_complete(&self, index: usize, success: bool) {
+        self.inner.on_post_link_complete(index, success)
+    }
+
+    fn on_pre_unlink_start(&self, package_name: &str, script_path: &str) -> usize {
+        self.inner.on_pre_unlink_start(package_name, script_path)
+    }
+
+    fn on_pre_unlink_complete(&self, index: usize, success: bool) {
+        self.inner.on_pre_unlink_complete(index, success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_url() {
+        let url =
+            Url::parse("https://conda.anaconda.org/conda-forge/linux-64/repodata.json").unwrap();
+        assert_eq!(short_url(&url), "conda-forge/linux-64/repodata.json");
+
+        let url = Url::parse("file:///repodata.json").unwrap();
+        assert_eq!(short_url(&url), "repodata.json");
+    }
+
+    #[test]
+    fn test_channel_prefix() {
+        let prefix = |url: &str| channel_prefix(&Url::parse(url).unwrap());
+        assert_eq!(
+            prefix("https://conda.anaconda.org/conda-forge/linux-64/repodata.json").as_deref(),
+            Some("conda-forge/linux-64")
+        );
+        assert_eq!(
+            prefix("https://prefix.dev/conda-forge/noarch/shards/abc.msgpack.zst").as_deref(),
+            Some("conda-forge/noarch")
+        );
+        assert_eq!(
+            prefix("file:///home/user/channel/osx-arm64/repodata.json").as_deref(),
+            Some("channel/osx-arm64")
+        );
+        assert_eq!(prefix("file:///repodata.json"), None);
+    }
+
+    #[test]
+    fn test_download_bar_prefix() {
+        let url =
+            Url::parse("https://conda.anaconda.org/conda-forge/linux-64/repodata.json").unwrap();
+        assert_eq!(
+            download_bar_prefix("Downloading", &url),
+            "Downloading conda-forge/linux-64"
+        );
+        // indentation prefixes are kept as they are
+        assert_eq!(download_bar_prefix(" │ ", &url), " │ conda-forge/linux-64");
+        assert_eq!(download_bar_prefix("", &url), "conda-forge/linux-64");
+
+        let url = Url::parse("file:///repodata.json").unwrap();
+        assert_eq!(download_bar_prefix("Downloading", &url), "Downloading");
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_unknown_index() {
+        let reporter = GatewayReporter::builder()
+            .with_multi_progress(MultiProgress::with_draw_target(
+                indicatif::ProgressDrawTarget::hidden(),
+            ))
+            .finish();
+        let url =
+            Url::parse("https://conda.anaconda.org/conda-forge/linux-64/repodata.json").unwrap();
+
+        // indices that were never handed out must not panic
+        reporter.on_download_progress(&url, 3, 10, Some(100));
+        reporter.on_download_complete(&url, 3);
+        assert!(logs_contain("unknown progress bar index 3"));
+
+        let index = reporter.on_download_start(&url);
+        reporter.on_download_progress(&url, index, 10, Some(100));
+        reporter.on_download_progress(&url, index + 1, 10, Some(100));
+        assert_eq!(
+            reporter.progress_bars.lock().unwrap()[index]
+                .progress_bar
+                .position(),
+            10
+        );
+        reporter.on_download_complete(&url, index);
+    }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_download_summary() {
+        let reporter = GatewayReporter::builder()
+            .with_multi_progress(MultiProgress::with_draw_target(
+                indicatif::ProgressDrawTarget::hidden(),
+            ))
+            .with_download_summary(true)
+            .finish();
+        let url = |url: &str| Url::parse(url).unwrap();
+        let linux = url("https://conda.anaconda.org/conda-forge/linux-64/repodata.json");
+        let noarch = url("https://conda.anaconda.org/conda-forge/noarch/repodata.json");
+        let bioconda = url("https://conda.anaconda.org/bioconda/noarch/repodata.json");
+
+        let first = reporter.on_download_start(&linux);
+        let second = reporter.on_download_start(&noarch);
+        let third = reporter.on_download_start(&bioconda);
+        reporter.on_download_progress(&linux, first, 10, Some(10));
+        reporter.on_download_progress(&noarch, second, 20, Some(20));
+        reporter.on_download_progress(&bioconda, third, 5, Some(5));
+
+        // the summary of a channel waits for all of its downloads
+        reporter.on_download_complete(&linux, first);
+        assert!(!logs_contain("Downloaded repodata from conda-forge"));
+        reporter.on_download_complete(&bioconda, third);
+        assert!(logs_contain(
+            "Downloaded repodata from bioconda: 1 file, 5 B"
+        ));
+        reporter.on_download_complete(&noarch, second);
+        assert!(logs_contain(
+            "Downloaded repodata from conda-forge: 2 files, 30 B"
+        ));
+
+        // a later download of the same channel gets its own summary
+        let fourth = reporter.on_download_start(&linux);
+        reporter.on_download_progress(&linux, fourth, 7, Some(7));
+        reporter.on_download_complete(&linux, fourth);
+        assert!(logs_contain(
+            "Downloaded repodata from conda-forge: 1 file, 7 B"
+        ));
+    }
+
+    #[test]
+    fn test_overall_progress() {
+        let reporter = GatewayReporter::builder()
+            .with_multi_progress(MultiProgress::with_draw_target(
+                indicatif::ProgressDrawTarget::hidden(),
+            ))
+            .with_overall_progress(true)
+            .finish();
+        let url =
+            Url::parse("https://conda.anaconda.org/conda-forge/linux-64/repodata.json").unwrap();
+        let overall = || reporter.overall_bar.lock().unwrap().clone().unwrap();
+
+        let first = reporter.on_download_start(&url);
+        let second = reporter.on_download_start(&url);
+        reporter.on_download_progress(&url, first, 10, Some(100));
+        reporter.on_download_progress(&url, second, 20, Some(50));
+        assert_eq!(overall().position(), 30);
+        assert_eq!(overall().length(), Some(150));
+        assert_eq!(overall().message(), "2 of 2 downloads remaining");
+
+        // an unknown total makes the overall total unknown
+        reporter.on_download_progress(&url, second, 30, None);
+        assert_eq!(overall().position(), 40);
+        assert_eq!(overall().length(), None);
+
+        reporter.on_download_complete(&url, second);
+        assert_eq!(overall().message(), "1 of 2 downloads remaining");
+        assert!(!overall().is_finished());
+
+        reporter.on_download_complete(&url, first);
+        assert!(overall().is_finished());
+    }
+
+    #[test]
+    fn test_progress_event_json() {
+        let url = "https://conda.anaconda.org/conda-forge/noarch/repodata.json";
+        assert_eq!(
+            ProgressEvent::DownloadProgress {
+                url,
+                bytes: 10,
+                total: None,
+            }
+            .to_json_line(),
+            r#"{"event":"download_progress","url":"https://conda.anaconda.org/conda-forge/noarch/repodata.json","bytes":10,"total":null}"#
+        );
+        assert_eq!(
+            ProgressEvent::LinkComplete { package: "zlib" }.to_json_line(),
+            r#"{"event":"link_complete","package":"zlib"}"#
+        );
+        assert_eq!(
+            ProgressEvent::InstallComplete.to_json_line(),
+            r#"{"event":"install_complete"}"#
+        );
+    }
+
+    #[test]
+    fn test_stall_tracker() {
+        let start = Instant::now();
+        let mut tracker = StallTracker::new(Duration::from_secs(10));
+
+        tracker.update(0, start);
+        tracker.update(0, start + Duration::from_secs(5));
+        assert!(!tracker.stalled);
+        tracker.update(0, start + Duration::from_secs(10));
+        assert!(tracker.stalled);
+
+        // new bytes reset the timer
+        tracker.update(100, start + Duration::from_secs(11));
+        assert!(!tracker.stalled);
+        tracker.update(100, start + Duration::from_secs(20));
+        assert!(!tracker.stalled);
+        tracker.update(100, start + Duration::from_secs(21));
+        assert!(tracker.stalled);
+    }
+
+    #[test]
+    fn test_link_callback_reporter() {
+        use rattler::install::Reporter as _;
+        use rattler_conda_types::{PackageName, PackageRecord};
+
+        let record = |name: &str| RepoDataRecord {
+            package_record: PackageRecord::new(
+                PackageName::from_str(name).unwrap(),
+                "1.0".parse::<rattler_conda_types::Version>().unwrap(),
+                "0".to_string(),
+            ),
+            file_name: format!("{name}-1.0-0.conda"),
+            url: Url::parse(&format!("https://example.com/linux-64/{name}-1.0-0.conda")).unwrap(),
+            channel: None,
+        };
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let reporter = LinkCallbackReporter::new(JsonInstallReporter::default(), {
+            let events = events.clone();
+            Arc::new(move |record: &RepoDataRecord, stage| {
+                events.lock().unwrap().push((
+                    record.package_record.name.as_normalized().to_string(),
+                    stage,
+                ))
+            })
+        });
+
+        let zlib = reporter.on_link_start(0, &record("zlib"));
+        let python = reporter.on_link_start(1, &record("python"));
+        reporter.on_link_complete(python);
+        reporter.on_link_complete(zlib);
+        // unknown indices are ignored
+        reporter.on_link_complete(5);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                ("zlib".to_string(), LinkStage::Started),
+                ("python".to_string(), LinkStage::Started),
+                ("python".to_string(), LinkStage::Completed),
+                ("zlib".to_string(), LinkStage::Completed),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clear_on_drop() {
+        let multi_progress =
+            MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden());
+        let url =
+            Url::parse("https://conda.anaconda.org/conda-forge/linux-64/repodata.json").unwrap();
+
+        let reporter = GatewayReporter::builder()
+            .with_multi_progress(multi_progress.clone())
+            .with_clear_on_drop(true)
+            .finish();
+        let index = reporter.on_download_start(&url);
+        reporter.on_download_progress(&url, index, 10, Some(100));
+        let progress_bar = reporter.progress_bars.lock().unwrap()[index]
+            .progress_bar
+            .clone();
+        drop(reporter);
+        // the running download was finished and cleared
+        assert!(progress_bar.is_finished());
+
+        let reporter = GatewayReporter::builder()
+            .with_multi_progress(multi_progress)
+            .finish();
+        let index = reporter.on_download_start(&url);
+        let progress_bar = reporter.progress_bars.lock().unwrap()[index]
+            .progress_bar
+            .clone();
+        drop(reporter);
+        assert!(!progress_bar.is_finished());
+    }
 }
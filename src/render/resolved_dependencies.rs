@@ -24,7 +24,7 @@ use crate::{
     recipe::parser::{Dependency, Requirements},
     render::{
         pin::PinArgs,
-        solver::{install_packages, solve_environment},
+        solver::{SolveError, install_packages, solve_environment},
     },
     tool_configuration::{self, Configuration},
 };
@@ -539,6 +539,17 @@ pub enum ResolveError {
     RefreshChannelError(std::io::Error),
 }
 
+impl ResolveError {
+    /// Returns the typed solver error if resolving failed while solving an
+    /// environment.
+    pub fn solve_error(&self) -> Option<&SolveError> {
+        match self {
+            ResolveError::DependencyResolutionError(err) => err.downcast_ref::<SolveError>(),
+            _ => None,
+        }
+    }
+}
+
 /// Controls whether to download missing run exports during dependency resolution
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RunExportsDownload {
@@ -715,7 +726,9 @@ pub async fn install_environments(
             .unwrap_or(&EMPTY_RECORDS),
         output.build_configuration.build_platform.platform,
         &output.build_configuration.directories.build_prefix,
+        false,
         tool_configuration,
+        None,
     )
     .await?;
 
@@ -728,7 +741,9 @@ pub async fn install_environments(
             .unwrap_or(&EMPTY_RECORDS),
         output.build_configuration.host_platform.platform,
         &output.build_configuration.directories.host_prefix,
+        true,
         tool_configuration,
+        None,
     )
     .await?;
 
@@ -827,6 +842,8 @@ pub(crate) async fn resolve_dependencies(
             output.build_configuration.channel_priority,
             output.build_configuration.solve_strategy,
             output.build_configuration.exclude_newer,
+            &tool_configuration.channel_preferences,
+            &tool_configuration.solve_pins,
         )
         .await
         .map_err(ResolveError::from)?;
@@ -932,6 +949,8 @@ pub(crate) async fn resolve_dependencies(
             output.build_configuration.channel_priority,
             output.build_configuration.solve_strategy,
             output.build_configuration.exclude_newer,
+            &tool_configuration.channel_preferences,
+            &tool_configuration.solve_pins,
         )
         .await
         .map_err(ResolveError::from)?;
@@ -1,19 +1,121 @@
-use std::{future::IntoFuture, path::Path};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    future::IntoFuture,
+    path::Path,
+    time::Duration,
+};
 
-use crate::{metadata::PlatformWithVirtualPackages, packaging::Files, tool_configuration};
+use crate::{
+    metadata::PlatformWithVirtualPackages,
+    packaging::Files,
+    tool_configuration::{self, LinkMethod, ProgressOutput},
+};
 use anyhow::Context;
 use comfy_table::Table;
 use console::style;
-use futures::FutureExt;
+use futures::{FutureExt, StreamExt, TryStreamExt};
 use indicatif::HumanBytes;
 use itertools::Itertools;
-use rattler::install::{DefaultProgressFormatter, IndicatifReporter, Installer};
-use rattler_conda_types::{Channel, ChannelUrl, MatchSpec, Platform, PrefixRecord, RepoDataRecord};
+use rattler::{
+    install::{DefaultProgressFormatter, IndicatifReporter, Installer},
+    package_cache::CacheKey,
+};
+use rattler_cache::validation::{ValidationMode, validate_package_directory};
+use rattler_conda_types::{
+    Channel, ChannelConfig, ChannelUrl, MatchSpec, Matches, PackageName, ParseStrictness, Platform,
+    PrefixRecord, RepoDataRecord,
+};
+use rattler_digest::{Sha256, Sha256Hash, digest::Digest};
+use rattler_repodata_gateway::GatewayError;
 use rattler_solve::{ChannelPriority, SolveStrategy, SolverImpl, SolverTask, resolvo::Solver};
+use thiserror::Error;
+use url::Url;
 
-use super::reporters::GatewayReporter;
+use super::reporters::{GatewayReporter, JsonInstallReporter, LinkCallback, LinkCallbackReporter};
 
-fn print_as_table(packages: &[RepoDataRecord]) {
+/// Errors that can occur while solving an environment.
+///
+/// [`solve_environment`] returns an [`anyhow::Error`] for convenience, but the
+/// underlying error is always a [`SolveError`] which can be retrieved with
+/// [`anyhow::Error::downcast_ref`].
+#[derive(Debug, Error)]
+pub enum SolveError {
+    /// There is no set of packages that satisfies the requested specs.
+    #[error("Cannot solve the request because of: {}", .reasons.join(", "))]
+    Unsatisfiable {
+        /// The specs that were requested.
+        specs: Vec<MatchSpec>,
+        /// The reasons reported by the solver why the specs conflict.
+        reasons: Vec<String>,
+    },
+
+    /// The repodata could not be fetched because of a network problem.
+    #[error("failed to fetch repodata: {0}")]
+    Network(#[source] GatewayError),
+
+    /// An IO error occurred while loading the repodata.
+    #[error("{0}")]
+    Io(String, #[source] std::io::Error),
+
+    /// Any other error reported by the repodata gateway.
+    #[error(transparent)]
+    Gateway(GatewayError),
+
+    /// A pinned package does not satisfy a requirement of the environment.
+    #[error("the pinned package `{pin}` conflicts with the requirement `{spec}`")]
+    PinConflict {
+        /// The pin from the tool configuration.
+        pin: Box<MatchSpec>,
+        /// The requirement that cannot be satisfied with the pin.
+        spec: Box<MatchSpec>,
+    },
+
+    /// Packages were resolved from channels outside of the expected channels.
+    #[error("packages were resolved from unexpected channels: {}", .0.join(", "))]
+    UnexpectedChannels(Vec<String>),
+
+    /// Any other error reported by the solver.
+    #[error(transparent)]
+    Solver(rattler_solve::SolveError),
+}
+
+impl SolveError {
+    fn from_solver(err: rattler_solve::SolveError, specs: &[MatchSpec]) -> Self {
+        match err {
+            rattler_solve::SolveError::Unsolvable(reasons) => SolveError::Unsatisfiable {
+                specs: specs.to_vec(),
+                reasons,
+            },
+            err => SolveError::Solver(err),
+        }
+    }
+}
+
+impl From<GatewayError> for SolveError {
+    fn from(err: GatewayError) -> Self {
+        match err {
+            GatewayError::IoError(msg, err) => SolveError::Io(msg, err),
+            err @ (GatewayError::ReqwestError(_)
+            | GatewayError::ReqwestMiddlewareError(_)
+            | GatewayError::FetchRepoDataError(_)
+            | GatewayError::SubdirNotFoundError(_)) => SolveError::Network(err),
+            err => SolveError::Gateway(err),
+        }
+    }
+}
+
+/// Returns the short name of a channel, i.e. the last path segment of a
+/// channel url (`https://conda.anaconda.org/conda-forge/` -> `conda-forge`).
+pub(crate) fn short_channel_name(channel: &str) -> &str {
+    channel
+        .rsplit('/')
+        .find(|s| !s.is_empty())
+        .unwrap_or(channel)
+}
+
+/// Builds a table of the packages (name, version, build, channel and size),
+/// sorted by name.
+fn packages_table(packages: &[RepoDataRecord]) -> Table {
     let mut table = Table::new();
     table
         .load_preset(comfy_table::presets::UTF8_FULL_CONDENSED)
@@ -29,30 +131,94 @@ fn print_as_table(packages: &[RepoDataRecord]) {
         .iter()
         .sorted_by_key(|p| p.package_record.name.as_normalized())
     {
-        let channel_short = if package.channel.as_deref().unwrap_or_default().contains('/') {
-            package
-                .channel
-                .as_ref()
-                .and_then(|s| s.rsplit('/').find(|s| !s.is_empty()))
-                .expect("expected channel to be defined and contain '/'")
-                .to_string()
-        } else {
-            package.channel.as_deref().unwrap_or_default().to_string()
-        };
+        let channel_short = short_channel_name(package.channel.as_deref().unwrap_or_default());
 
         table.add_row([
             package.package_record.name.as_normalized().to_string(),
             package.package_record.version.to_string(),
             package.package_record.build.clone(),
-            channel_short,
+            channel_short.to_string(),
             HumanBytes(package.package_record.size.unwrap_or(0)).to_string(),
             // package.package_record.license.clone().unwrap_or_else(|| "".to_string()),
         ]);
     }
 
-    tracing::info!("\n{table}");
+    table
+}
+
+/// Writes the packages as a table to the given writer.
+pub fn write_as_table(
+    packages: &[RepoDataRecord],
+    writer: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    writeln!(writer, "{}", packages_table(packages))
+}
+
+/// Returns the name of the solve strategy and, for strategies that do not
+/// pick the highest versions, a note explaining the effect.
+fn describe_solve_strategy(strategy: SolveStrategy) -> (&'static str, Option<&'static str>) {
+    match strategy {
+        SolveStrategy::Highest => ("Highest", None),
+        SolveStrategy::LowestVersion => (
+            "LowestVersion",
+            Some("the lowest compatible version of every package is selected"),
+        ),
+        SolveStrategy::LowestVersionDirect => (
+            "LowestVersionDirect",
+            Some(
+                "the lowest compatible version of the direct dependencies is selected, \
+                 the highest version of all other packages",
+            ),
+        ),
+    }
+}
+
+fn print_as_table(packages: &[RepoDataRecord]) {
+    tracing::info!("\n{}", packages_table(packages));
+}
+
+/// Removes channels that resolve to the same canonical name as an earlier
+/// channel (e.g. a trailing slash or a different case in the host). The order
+/// of the remaining channels is preserved because it determines the channel
+/// priority.
+pub(crate) fn dedup_channels(
+    channels: &[ChannelUrl],
+    channel_config: &ChannelConfig,
+) -> Vec<ChannelUrl> {
+    let mut seen = HashSet::new();
+    channels
+        .iter()
+        .filter(|channel| {
+            let canonical_name = channel_config.canonical_name(channel.url());
+            let is_new = seen.insert(canonical_name.trim_end_matches('/').to_string());
+            if !is_new {
+                tracing::warn!(
+                    "Ignoring duplicate channel {} ({})",
+                    channel.url(),
+                    canonical_name
+                );
+            }
+            is_new
+        })
+        .cloned()
+        .collect()
 }
 
+/// Solves the environment for the given specs.
+///
+/// With [`ChannelPriority::Strict`] a package is only taken from the first
+/// channel (in the order of `channels`) that provides it, even if a later
+/// channel has a higher version. With [`ChannelPriority::Disabled`] the
+/// candidates of all channels are considered and the highest version wins.
+///
+/// The `channel_preferences` prefer the candidates of a package from the given
+/// channel, unless the environment cannot be solved with them.
+///
+/// The `pins` are added as constraints to the solve. If a pin conflicts with
+/// one of the `specs`, a [`SolveError::PinConflict`] is returned.
+///
+/// The returned records include all transitive dependencies, use
+/// [`direct_matches`] to find the records that satisfied the `specs`.
 #[allow(clippy::too_many_arguments)]
 pub async fn solve_environment(
     name: &str,
@@ -63,7 +229,10 @@ pub async fn solve_environment(
     channel_priority: ChannelPriority,
     solve_strategy: SolveStrategy,
     exclude_newer: Option<chrono::DateTime<chrono::Utc>>,
+    channel_preferences: &HashMap<PackageName, ChannelUrl>,
+    pins: &[MatchSpec],
 ) -> anyhow::Result<Vec<RepoDataRecord>> {
+    let channels = &dedup_channels(channels, &tool_configuration.channel_config);
     let vp_string = format!("[{}]", target_platform.virtual_packages.iter().format(", "));
 
     tracing::info!("\nResolving {name} environment:\n");
@@ -85,6 +254,17 @@ pub async fn solve_environment(
     for spec in specs {
         tracing::info!("   - {}", spec);
     }
+    if !pins.is_empty() {
+        tracing::info!("  Pins:");
+        for pin in pins {
+            tracing::info!("   - {}", pin);
+        }
+    }
+    let (strategy, note) = describe_solve_strategy(solve_strategy);
+    tracing::info!("  Strategy: {strategy}");
+    if let Some(note) = note {
+        tracing::info!("    {}", style(note).dim());
+    }
 
     let repo_data = load_repodatas(
         channels,
@@ -93,33 +273,120 @@ pub async fn solve_environment(
         tool_configuration,
     )
     .await?;
+    let candidates = apply_channel_preferences(&repo_data, &HashMap::new());
+    check_pins(&candidates, specs, pins)?;
 
-    // Now that we parsed and downloaded all information, construct the packaging
-    // problem that we need to solve. We do this by constructing a
-    // `SolverProblem`. This encapsulates all the information required to be
-    // able to solve the problem.
-    let solver_task = SolverTask {
-        virtual_packages: target_platform.virtual_packages.clone(),
-        specs: specs.to_vec(),
-        channel_priority,
-        strategy: solve_strategy,
-        exclude_newer,
-        ..SolverTask::from_iter(&repo_data)
-    };
+    // Keep the candidates of the packages to explain, including the ones that
+    // are filtered by the channel preferences.
+    let explain_candidates = candidates
+        .iter()
+        .flatten()
+        .filter(|record| {
+            tool_configuration
+                .explain_packages
+                .contains(&record.package_record.name)
+        })
+        .map(|record| (*record).clone())
+        .collect::<Vec<_>>();
+
+    let solver_result =
+        solve_with_channel_preferences(&repo_data, channel_preferences, |repo_data| {
+            // Now that we parsed and downloaded all information, construct the
+            // packaging problem that we need to solve. We do this by constructing a
+            // `SolverProblem`. This encapsulates all the information required to be
+            // able to solve the problem.
+            let solver_task = SolverTask {
+                virtual_packages: target_platform.virtual_packages.clone(),
+                specs: specs.to_vec(),
+                constraints: pins.to_vec(),
+                channel_priority,
+                strategy: solve_strategy,
+                exclude_newer,
+                ..SolverTask::from_iter(repo_data)
+            };
 
-    // Next, use a solver to solve this specific problem. This provides us with all
-    // the operations we need to apply to our environment to bring it up to
-    // date.
-    let solver_result = tool_configuration
-        .fancy_log_handler
-        .wrap_in_progress("solving", move || Solver.solve(solver_task))?;
+            // Next, use a solver to solve this specific problem. This provides us
+            // with all the operations we need to apply to our environment to bring
+            // it up to date.
+            tool_configuration
+                .fancy_log_handler
+                .wrap_in_progress("solving", move || Solver.solve(solver_task))
+        })
+        .map_err(|err| SolveError::from_solver(err, specs))?;
 
     // Print the result as a table
     print_as_table(&solver_result.records);
 
+    for package in &tool_configuration.explain_packages {
+        tracing::info!(
+            "\n{}",
+            explain_package(
+                package,
+                specs,
+                pins,
+                &solver_result.records,
+                &explain_candidates
+            )
+        );
+    }
+
+    if !tool_configuration.expected_channels.is_empty() {
+        let unexpected = unexpected_channels(
+            &solver_result.records,
+            &tool_configuration.expected_channels,
+            &tool_configuration.channel_config,
+        );
+        if !unexpected.is_empty() {
+            if tool_configuration.strict_expected_channels {
+                return Err(SolveError::UnexpectedChannels(unexpected).into());
+            }
+            tracing::warn!("Packages of the {name} environment come from unexpected channels:");
+            for package in &unexpected {
+                tracing::warn!("   - {package}");
+            }
+        }
+    }
+
+    tracing::debug!(
+        "Hash of the {name} environment: {:x}",
+        environment_hash(&solver_result.records)
+    );
+
     Ok(solver_result.records)
 }
 
+/// Runs `solve` for every item with at most `max_concurrent_solves` solves in
+/// flight. The results are returned in the order of the items. If any of the
+/// solves fails, all errors are returned instead.
+pub async fn solve_concurrently<I, T, E, F, Fut>(
+    items: I,
+    max_concurrent_solves: usize,
+    solve: F,
+) -> Result<Vec<T>, Vec<E>>
+where
+    I: IntoIterator,
+    F: FnMut(I::Item) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let (solved, errors): (Vec<_>, Vec<_>) = futures::stream::iter(items)
+        .map(solve)
+        .buffered(max_concurrent_solves.max(1))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .partition_result();
+
+    if errors.is_empty() {
+        Ok(solved)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Solves the environment and installs it into `target_prefix`. The
+/// `io_concurrency_limit` overrides the limit of the tool configuration for
+/// this installation (see
+/// [`tool_configuration::Configuration::io_concurrency_limit_or_default`]).
 #[allow(clippy::too_many_arguments)]
 pub async fn create_environment(
     name: &str,
@@ -131,6 +398,7 @@ pub async fn create_environment(
     channel_priority: ChannelPriority,
     solve_strategy: SolveStrategy,
     exclude_newer: Option<chrono::DateTime<chrono::Utc>>,
+    io_concurrency_limit: Option<usize>,
 ) -> anyhow::Result<Vec<RepoDataRecord>> {
     let required_packages = solve_environment(
         name,
@@ -141,6 +409,8 @@ pub async fn create_environment(
         channel_priority,
         solve_strategy,
         exclude_newer,
+        &tool_configuration.channel_preferences,
+        &tool_configuration.solve_pins,
     )
     .await?;
 
@@ -149,26 +419,524 @@ pub async fn create_environment(
         &required_packages,
         target_platform.platform,
         target_prefix,
+        false,
         tool_configuration,
+        io_concurrency_limit,
     )
     .await?;
 
     Ok(required_packages)
 }
 
+/// Explains which requirements bounded the version of `package` in a solved
+/// environment, as a tree of the requested specs, pins and the dependencies and
+/// constraints of the other solved packages that mention the package. If a
+/// newer version is available, the requirements that exclude it are listed.
+fn explain_package(
+    package: &PackageName,
+    specs: &[MatchSpec],
+    pins: &[MatchSpec],
+    records: &[RepoDataRecord],
+    candidates: &[RepoDataRecord],
+) -> String {
+    let Some(selected) = records
+        .iter()
+        .find(|record| &record.package_record.name == package)
+    else {
+        return format!("{} is not part of the environment", package.as_normalized());
+    };
+
+    let is_package = |spec: &MatchSpec| {
+        spec.name
+            .as_ref()
+            .and_then(|name| name.as_exact())
+            .is_some_and(|name| name == package)
+    };
+    let parse = |spec: &String| MatchSpec::from_str(spec, ParseStrictness::Lenient).ok();
+
+    // The requirements on the package and where they come from
+    let mut bounds = Vec::new();
+    for spec in specs.iter().filter(|spec| is_package(spec)) {
+        bounds.push(("requested".to_string(), spec.clone()));
+    }
+    for pin in pins.iter().filter(|pin| is_package(pin)) {
+        bounds.push(("pinned".to_string(), pin.clone()));
+    }
+    for record in records {
+        let record_name = format!(
+            "{} {}",
+            record.package_record.name.as_normalized(),
+            record.package_record.version
+        );
+        for spec in record.package_record.depends.iter().filter_map(parse) {
+            if is_package(&spec) {
+                bounds.push((format!("required by {record_name}"), spec));
+            }
+        }
+        for spec in record.package_record.constrains.iter().filter_map(parse) {
+            if is_package(&spec) {
+                bounds.push((format!("constrained by {record_name}"), spec));
+            }
+        }
+    }
+
+    let mut lines = bounds
+        .iter()
+        .map(|(source, spec)| format!("{source}: `{spec}`"))
+        .collect::<Vec<_>>();
+
+    let newest = candidates
+        .iter()
+        .filter(|candidate| &candidate.package_record.name == package)
+        .max_by(|a, b| a.package_record.version.cmp(&b.package_record.version));
+    if let Some(newest) = newest
+        && newest.package_record.version > selected.package_record.version
+    {
+        let excluded_by = bounds
+            .iter()
+            .filter(|(_, spec)| !spec.matches(&newest.package_record))
+            .map(|(source, spec)| format!("{source} (`{spec}`)"))
+            .collect::<Vec<_>>();
+        lines.push(if excluded_by.is_empty() {
+            format!(
+                "newest version {} is allowed by these requirements, it was excluded by \
+                 requirements of its own dependencies",
+                newest.package_record.version
+            )
+        } else {
+            format!(
+                "newest version {} is excluded by: {}",
+                newest.package_record.version,
+                excluded_by.join(", ")
+            )
+        });
+    }
+
+    let mut tree = format!(
+        "Why {} {} {} was selected:",
+        package.as_normalized(),
+        selected.package_record.version,
+        selected.package_record.build
+    );
+    for (index, line) in lines.iter().enumerate() {
+        let branch = if index + 1 == lines.len() {
+            "└─"
+        } else {
+            "├─"
+        };
+        tree.push_str(&format!("\n{branch} {line}"));
+    }
+    tree
+}
+
+/// Returns the record that satisfied each of the requested `specs` of a solve,
+/// in the order of the specs, e.g. to report that `python` was resolved to
+/// `python 3.11.7`. All other `records` are transitive dependencies. A spec
+/// maps to `None` if no record matches it, which does not happen for the
+/// records returned by [`solve_environment`].
+pub fn direct_matches<'a>(
+    specs: &'a [MatchSpec],
+    records: &'a [RepoDataRecord],
+) -> Vec<(&'a MatchSpec, Option<&'a RepoDataRecord>)> {
+    specs
+        .iter()
+        .map(|spec| {
+            let record = records
+                .iter()
+                .find(|record| spec.matches(&record.package_record));
+            (spec, record)
+        })
+        .collect()
+}
+
+/// Returns a description (`name version from channel`) of every record that
+/// does not come from one of the `expected` channels. Channels are compared by
+/// their canonical name.
+fn unexpected_channels(
+    records: &[RepoDataRecord],
+    expected: &[ChannelUrl],
+    channel_config: &ChannelConfig,
+) -> Vec<String> {
+    let canonical_name = |url: &Url| {
+        channel_config
+            .canonical_name(url)
+            .trim_end_matches('/')
+            .to_string()
+    };
+    let expected = expected
+        .iter()
+        .map(|channel| canonical_name(channel.url()))
+        .collect::<HashSet<_>>();
+
+    records
+        .iter()
+        .filter_map(|record| {
+            let channel = record
+                .channel
+                .as_deref()
+                .and_then(|channel| Url::parse(channel).ok())
+                .map(|url| canonical_name(&url));
+            if channel
+                .as_ref()
+                .is_some_and(|channel| expected.contains(channel))
+            {
+                return None;
+            }
+            Some(format!(
+                "{} {} from {}",
+                record.package_record.name.as_normalized(),
+                record.package_record.version,
+                channel.as_deref().unwrap_or("an unknown channel")
+            ))
+        })
+        .collect()
+}
+
+/// Computes a stable fingerprint of a solved environment, e.g. to skip a
+/// rebuild when the resolved dependencies did not change.
+///
+/// The records are sorted by package name, version and build string. For
+/// every record the SHA256 hash is updated with the normalized package name,
+/// the version, the build string and the lowercase hex encoded `sha256` of
+/// the package (empty if the record has no hash), each followed by a `0`
+/// byte. The order of `records`, the channel and the URL do not influence the
+/// hash.
+pub fn environment_hash(records: &[RepoDataRecord]) -> Sha256Hash {
+    let mut hasher = Sha256::default();
+    for record in records.iter().sorted_by(|a, b| {
+        let a = &a.package_record;
+        let b = &b.package_record;
+        (a.name.as_normalized(), &a.version, &a.build).cmp(&(
+            b.name.as_normalized(),
+            &b.version,
+            &b.build,
+        ))
+    }) {
+        let package = &record.package_record;
+        let sha256 = package
+            .sha256
+            .map(|sha256| format!("{sha256:x}"))
+            .unwrap_or_default();
+        for field in [
+            package.name.as_normalized(),
+            &package.version.to_string(),
+            &package.build,
+            &sha256,
+        ] {
+            hasher.update(field.as_bytes());
+            hasher.update([0]);
+        }
+    }
+    hasher.finalize()
+}
+
+/// Makes sure that every spec can be satisfied together with the pins of the
+/// same package, i.e. that there is a record that matches both.
+fn check_pins(
+    repo_data: &[Vec<&RepoDataRecord>],
+    specs: &[MatchSpec],
+    pins: &[MatchSpec],
+) -> Result<(), SolveError> {
+    for pin in pins {
+        let Some(pin_name) = pin.name.as_ref().and_then(|name| name.as_exact()) else {
+            continue;
+        };
+        for spec in specs
+            .iter()
+            .filter(|spec| spec.name.as_ref().and_then(|name| name.as_exact()) == Some(pin_name))
+        {
+            let satisfiable = repo_data.iter().flatten().any(|record| {
+                pin.matches(&record.package_record) && spec.matches(&record.package_record)
+            });
+            if !satisfiable {
+                return Err(SolveError::PinConflict {
+                    pin: Box::new(pin.clone()),
+                    spec: Box::new(spec.clone()),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the record was loaded from the given channel.
+fn is_from_channel(record: &RepoDataRecord, channel: &ChannelUrl) -> bool {
+    record.channel.as_deref().map(|c| c.trim_end_matches('/'))
+        == Some(channel.as_str().trim_end_matches('/'))
+}
+
+/// Removes the candidates of a package that do not come from its preferred
+/// channel, if the preferred channel provides the package. Packages whose
+/// preferred channel does not provide them are left untouched.
+fn apply_channel_preferences<'a, R>(
+    repo_data: &'a [R],
+    channel_preferences: &HashMap<PackageName, ChannelUrl>,
+) -> Vec<Vec<&'a RepoDataRecord>>
+where
+    &'a R: IntoIterator<Item = &'a RepoDataRecord>,
+{
+    let mut repo_data = repo_data
+        .iter()
+        .map(|records| records.into_iter().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    for (name, channel) in channel_preferences {
+        let is_preferred = |record: &RepoDataRecord| {
+            &record.package_record.name == name && is_from_channel(record, channel)
+        };
+        if !repo_data
+            .iter()
+            .flatten()
+            .any(|record| is_preferred(record))
+        {
+            tracing::debug!(
+                "Preferred channel {} does not provide {}",
+                channel,
+                name.as_normalized()
+            );
+            continue;
+        }
+
+        let mut removed = 0;
+        for records in repo_data.iter_mut() {
+            let before = records.len();
+            records.retain(|record| &record.package_record.name != name || is_preferred(record));
+            removed += before - records.len();
+        }
+
+        if removed > 0 {
+            tracing::info!(
+                "Preferring channel {} for {} (ignoring {} candidates from other channels)",
+                channel,
+                name.as_normalized(),
+                removed
+            );
+        }
+    }
+
+    repo_data
+}
+
+/// Solves with the candidates filtered by the channel preferences (see
+/// [`apply_channel_preferences`]). The preferences only bias the solve: if the
+/// environment cannot be solved with them, e.g. because the preferred channel
+/// has no version that satisfies a spec, it is solved again with the
+/// candidates of all channels.
+fn solve_with_channel_preferences<'a, R, T>(
+    repo_data: &'a [R],
+    channel_preferences: &HashMap<PackageName, ChannelUrl>,
+    solve: impl Fn(Vec<Vec<&'a RepoDataRecord>>) -> Result<T, rattler_solve::SolveError>,
+) -> Result<T, rattler_solve::SolveError>
+where
+    &'a R: IntoIterator<Item = &'a RepoDataRecord>,
+{
+    match solve(apply_channel_preferences(repo_data, channel_preferences)) {
+        Err(rattler_solve::SolveError::Unsolvable(reasons)) if !channel_preferences.is_empty() => {
+            tracing::warn!(
+                "Cannot solve the environment with the channel preferences ({}), ignoring them",
+                reasons.join(", ")
+            );
+            solve(apply_channel_preferences(repo_data, &HashMap::new()))
+        }
+        result => result,
+    }
+}
+
 /// Load repodata from channels. Only includes necessary records for platform &
 /// specs.
+///
+/// If a fallback mirror is configured for a channel (see
+/// [`tool_configuration::Configuration::channel_fallbacks`]) and loading the
+/// repodata fails, or a requested package cannot be found, the query is
+/// retried with the fallback mirrors.
 pub async fn load_repodatas(
     channels: &[ChannelUrl],
     target_platform: Platform,
     specs: &[MatchSpec],
     tool_configuration: &tool_configuration::Configuration,
-) -> anyhow::Result<Vec<rattler_repodata_gateway::RepoData>> {
+) -> Result<Vec<rattler_repodata_gateway::RepoData>, SolveError> {
+    let fallback_channels = channels
+        .iter()
+        .map(|url| {
+            tool_configuration
+                .channel_fallbacks
+                .get(url)
+                .unwrap_or(url)
+                .clone()
+        })
+        .collect::<Vec<_>>();
+    let has_fallbacks = fallback_channels.as_slice() != channels;
+
+    let result = query_repodatas(channels, target_platform, specs, tool_configuration).await;
+
+    let missing = match &result {
+        Ok(repodata) if has_fallbacks => missing_packages(repodata, specs),
+        _ => Vec::new(),
+    };
+
+    let result = match result {
+        Err(err) if has_fallbacks => {
+            tracing::warn!("Failed to load repodata ({err}), trying fallback mirrors");
+            query_repodatas(
+                &fallback_channels,
+                target_platform,
+                specs,
+                tool_configuration,
+            )
+            .await
+            .map(|repodata| (repodata, fallback_channels.as_slice()))
+        }
+        Ok(repodata) if has_fallbacks && !missing.is_empty() => {
+            tracing::warn!(
+                "Could not find {} in the channels, trying fallback mirrors",
+                missing.iter().format(", ")
+            );
+            match query_repodatas(
+                &fallback_channels,
+                target_platform,
+                specs,
+                tool_configuration,
+            )
+            .await
+            {
+                Ok(fallback_repodata)
+                    if missing_packages(&fallback_repodata, specs).len() < missing.len() =>
+                {
+                    Ok((fallback_repodata, fallback_channels.as_slice()))
+                }
+                Ok(_) => Ok((repodata, channels)),
+                Err(err) => {
+                    tracing::warn!("Failed to load repodata from fallback mirrors: {err}");
+                    Ok((repodata, channels))
+                }
+            }
+        }
+        result => result.map(|repodata| (repodata, channels)),
+    };
+
+    let (repodata, served_by) = result?;
+    if has_fallbacks {
+        for (channel, mirror) in channels.iter().zip(served_by.iter()) {
+            tracing::info!(
+                "Repodata for {} served by {}",
+                tool_configuration
+                    .channel_config
+                    .canonical_name(channel.url()),
+                mirror
+            );
+        }
+    }
+
+    Ok(repodata)
+}
+
+/// Returns the names of the specs that have no matching record in the
+/// repodata. Virtual packages (e.g. `__glibc`) never have records and are
+/// not reported.
+fn missing_packages<'a, R>(repodata: &'a [R], specs: &[MatchSpec]) -> Vec<String>
+where
+    &'a R: IntoIterator<Item = &'a RepoDataRecord>,
+{
+    specs
+        .iter()
+        .filter_map(|spec| spec.name.as_ref()?.as_exact())
+        .filter(|name| !name.as_normalized().starts_with("__"))
+        .filter(|name| {
+            !repodata
+                .iter()
+                .flatten()
+                .any(|record| &record.package_record.name == *name)
+        })
+        .map(|name| name.as_normalized().to_string())
+        .collect()
+}
+
+/// Returns the names of the virtual packages (e.g. `__cuda` or `__glibc`)
+/// that the given records depend on.
+pub fn referenced_virtual_packages<'a>(
+    records: impl IntoIterator<Item = &'a RepoDataRecord>,
+) -> BTreeSet<String> {
+    records
+        .into_iter()
+        .flat_map(|record| record.package_record.depends.iter())
+        .filter_map(|dependency| {
+            dependency
+                .split(|c: char| c.is_whitespace() || "<>=!~[".contains(c))
+                .next()
+        })
+        .filter(|name| name.starts_with("__"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Queries the repodata for the specs and returns the virtual packages that
+/// may be required to install them. This considers the dependencies of all
+/// candidate records, so not every returned virtual package is necessarily
+/// part of the final solution. Useful to check upfront whether the target
+/// platform declares all virtual packages (e.g. for cross-compilation).
+pub async fn required_virtual_packages(
+    channels: &[ChannelUrl],
+    target_platform: Platform,
+    specs: &[MatchSpec],
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<BTreeSet<String>, SolveError> {
+    let repodata = load_repodatas(channels, target_platform, specs, tool_configuration).await?;
+
+    let mut virtual_packages = referenced_virtual_packages(repodata.iter().flat_map(|r| r.iter()));
+    virtual_packages.extend(
+        specs
+            .iter()
+            .filter_map(|spec| spec.name.as_ref()?.as_exact())
+            .map(|name| name.as_normalized())
+            .filter(|name| name.starts_with("__"))
+            .map(str::to_string),
+    );
+
+    Ok(virtual_packages)
+}
+
+async fn query_repodatas(
+    channels: &[ChannelUrl],
+    target_platform: Platform,
+    specs: &[MatchSpec],
+    tool_configuration: &tool_configuration::Configuration,
+) -> Result<Vec<rattler_repodata_gateway::RepoData>, SolveError> {
     let channels = channels
         .iter()
         .map(|url| Channel::from_url(url.clone()))
         .collect::<Vec<_>>();
 
+    let mut reporter = GatewayReporter::builder()
+        .with_multi_progress(
+            tool_configuration
+                .fancy_log_handler
+                .multi_progress()
+                .clone(),
+        )
+        .with_progress_template(tool_configuration.fancy_log_handler.default_bytes_style())
+        .with_finish_template(
+            tool_configuration
+                .fancy_log_handler
+                .finished_progress_style(),
+        )
+        .with_download_summary(tool_configuration.repodata_download_summary)
+        .with_overall_progress(tool_configuration.repodata_overall_progress)
+        .with_steady_tick(Duration::from_millis(100))
+        .with_stall_timeout(Duration::from_secs(10))
+        .with_progress_output(tool_configuration.progress_output)
+        // Only remove our own bars once the query is done, other solves or
+        // installations may share the multi progress.
+        .with_clear_on_drop(true);
+
+    // With a per-host limit downloads can be queued, show them as waiting
+    if tool_configuration.repodata_host_concurrency.is_some() {
+        reporter = reporter.with_waiting_template(
+            tool_configuration
+                .fancy_log_handler
+                .deserializing_progress_style(),
+        );
+    }
+
     let result = tool_configuration
         .repodata_gateway
         .query(
@@ -176,86 +944,326 @@ pub async fn load_repodatas(
             [target_platform, Platform::NoArch],
             specs.to_vec(),
         )
-        .with_reporter(
-            GatewayReporter::builder()
-                .with_multi_progress(
-                    tool_configuration
-                        .fancy_log_handler
-                        .multi_progress()
-                        .clone(),
-                )
-                .with_progress_template(tool_configuration.fancy_log_handler.default_bytes_style())
-                .with_finish_template(
-                    tool_configuration
-                        .fancy_log_handler
-                        .finished_progress_style(),
-                )
-                .finish(),
-        )
+        .with_reporter(reporter.finish())
         .recursive(true)
         .into_future()
         .boxed()
         .await?;
 
-    tool_configuration
-        .fancy_log_handler
-        .multi_progress()
-        .clear()
-        .unwrap();
-
     Ok(result)
 }
 
+/// Compares the hash of a package archive with the sha256 of its repodata
+/// record, or with the md5 if the record has no sha256.
+fn check_record_hash(record: &RepoDataRecord, archive: &Path) -> anyhow::Result<()> {
+    let package_record = &record.package_record;
+    let (expected, actual) = if let Some(sha256) = &package_record.sha256 {
+        let actual = rattler_digest::compute_file_digest::<Sha256>(archive)?;
+        (format!("sha256 {sha256:x}"), format!("sha256 {actual:x}"))
+    } else if let Some(md5) = &package_record.md5 {
+        let actual = rattler_digest::compute_file_digest::<rattler_digest::Md5>(archive)?;
+        (format!("md5 {md5:x}"), format!("md5 {actual:x}"))
+    } else {
+        tracing::warn!(
+            "The repodata record of {} has no sha256 or md5, skipping the hash check",
+            package_record.name.as_normalized()
+        );
+        return Ok(());
+    };
+
+    if expected != actual {
+        anyhow::bail!(
+            "the archive of {} does not match its repodata record (expected {expected}, got {actual})",
+            package_record.name.as_normalized()
+        );
+    }
+    Ok(())
+}
+
+/// Makes sure the package in the package cache belongs to the repodata record
+/// and is intact before any of its files are linked.
+///
+/// The package cache checks downloaded archives against the sha256 of the
+/// record and fetches entries that were extracted from an archive with a
+/// different sha256 again. Local archives are checked against the sha256 (or
+/// md5) of the record here. Finally the hashes of all cached files are checked
+/// against `paths.json`. A corrupted entry is invalidated and fetched again by
+/// the package cache.
+async fn verify_cached_package(
+    record: &RepoDataRecord,
+    tool_configuration: &tool_configuration::Configuration,
+) -> anyhow::Result<()> {
+    let name = record.package_record.name.as_normalized();
+    if let Ok(archive) = record.url.to_file_path() {
+        check_record_hash(record, &archive)?;
+    }
+
+    let fetch = || async {
+        tool_configuration
+            .package_cache
+            .get_or_fetch_from_url(
+                &record.package_record,
+                record.url.clone(),
+                tool_configuration.client.get_client().clone().into(),
+                None,
+            )
+            .await
+            .with_context(|| format!("failed to fetch package {name}"))
+    };
+
+    let cache_entry = fetch().await?;
+    let path = cache_entry.path().to_path_buf();
+    if validate_cache_entry(&path).await.is_ok() {
+        return Ok(());
+    }
+
+    tracing::warn!(
+        "Package {name} in the package cache is corrupted, fetching it again ({})",
+        path.display()
+    );
+    // release the lock on the entry before invalidating it, the package cache
+    // fetches missing entries again
+    drop(cache_entry);
+    if path.exists() {
+        fs_err::remove_dir_all(&path)?;
+    }
+
+    let cache_entry = fetch().await?;
+    validate_cache_entry(cache_entry.path())
+        .await
+        .with_context(|| {
+            format!(
+                "package {name} in the package cache is corrupted ({})",
+                cache_entry.path().display()
+            )
+        })
+}
+
+/// Checks the hashes of all files of an extracted package against its
+/// `paths.json`.
+async fn validate_cache_entry(path: &Path) -> anyhow::Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || validate_package_directory(&path, ValidationMode::Full))
+        .await??;
+    Ok(())
+}
+
+/// Configures the installer to use the given link method.
+fn with_link_method(installer: Installer, link_method: LinkMethod) -> Installer {
+    // rattler does not export the type of the link options, so we let the
+    // compiler infer it from `Installer::with_link_options`.
+    fn configure<O: Default>(
+        installer: Installer,
+        with_link_options: fn(Installer, O) -> Installer,
+        configure: impl FnOnce(&mut O),
+    ) -> Installer {
+        let mut options = O::default();
+        configure(&mut options);
+        with_link_options(installer, options)
+    }
+
+    configure(installer, Installer::with_link_options, |options| {
+        options.allow_hard_links = link_method.allow_hard_links();
+        options.allow_ref_links = link_method.allow_ref_links();
+    })
+}
+
+/// The installer silently falls back to copying files if the requested link
+/// method fails. If a link method is explicitly requested, check that it is
+/// supported between the package cache and the target prefix and fail early
+/// otherwise.
+async fn check_link_method(
+    link_method: LinkMethod,
+    record: &RepoDataRecord,
+    target_prefix: &Path,
+    tool_configuration: &tool_configuration::Configuration,
+) -> anyhow::Result<()> {
+    if !matches!(link_method, LinkMethod::Hardlink | LinkMethod::Reflink) {
+        return Ok(());
+    }
+
+    let cache_entry = tool_configuration
+        .package_cache
+        .get_or_fetch_from_url(
+            &record.package_record,
+            record.url.clone(),
+            tool_configuration.client.get_client().clone().into(),
+            None,
+        )
+        .await
+        .with_context(|| {
+            format!(
+                "failed to fetch package {}",
+                record.package_record.name.as_normalized()
+            )
+        })?;
+
+    let source = cache_entry.path().join("info/index.json");
+    let probe_dir = tempfile::Builder::new()
+        .prefix(".link-check")
+        .tempdir_in(target_prefix)?;
+    let destination = probe_dir.path().join("index.json");
+    let result = match link_method {
+        LinkMethod::Hardlink => fs_err::hard_link(&source, &destination),
+        _ => reflink_copy::reflink(&source, &destination),
+    };
+
+    result.with_context(|| {
+        format!(
+            "the `{link_method}` link method is not supported between the package cache ({}) and {}",
+            cache_entry.path().display(),
+            target_prefix.display()
+        )
+    })
+}
+
+/// Removes the files in the prefix that do not belong to any installed
+/// package, e.g. files that were created by a previous build.
+fn clean_host_prefix(target_prefix: &Path) -> anyhow::Result<()> {
+    let extra_files = Files::from_prefix(target_prefix, &Default::default(), &Default::default())?;
+
+    tracing::info!(
+        "Cleaning up {} files in the prefix from a previous build.",
+        extra_files.new_files.len()
+    );
+
+    for f in extra_files.new_files {
+        if !f.is_dir() {
+            fs_err::remove_file(target_prefix.join(f))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets the reporter of the installer, wrapped to call the link callback if
+/// one is configured.
+fn with_reporter<R: rattler::install::Reporter + 'static>(
+    installer: Installer,
+    reporter: R,
+    link_callback: Option<LinkCallback>,
+) -> Installer {
+    match link_callback {
+        Some(callback) => installer.with_reporter(LinkCallbackReporter::new(reporter, callback)),
+        None => installer.with_reporter(reporter),
+    }
+}
+
+/// Makes sure that files can be created in `path` (creating the directory if
+/// needed), so that permission problems are reported before any package is
+/// downloaded.
+fn ensure_writable(path: &Path, description: &str) -> anyhow::Result<()> {
+    fs_err::create_dir_all(path)
+        .and_then(|_| tempfile::tempfile_in(path).map(drop))
+        .with_context(|| format!("the {description} at {} is not writable", path.display()))
+}
+
+/// Returns true if the package is already extracted in the package cache, so
+/// that installing it does not write to the cache.
+fn is_in_package_cache(record: &RepoDataRecord, package_cache_dir: &Path) -> bool {
+    package_cache_dir
+        .join(CacheKey::from(&record.package_record).to_string())
+        .join("info/index.json")
+        .is_file()
+}
+
+/// Installs the packages into `target_prefix`. The `io_concurrency_limit`
+/// overrides the limit of the tool configuration for this installation.
+///
+/// For the host environment (`is_host`), files that are left over in the
+/// prefix from a previous build are removed, unless
+/// [`tool_configuration::Configuration::skip_host_prefix_cleanup`] is set.
 pub async fn install_packages(
     name: &str,
     required_packages: &[RepoDataRecord],
     target_platform: Platform,
     target_prefix: &Path,
+    is_host: bool,
     tool_configuration: &tool_configuration::Configuration,
+    io_concurrency_limit: Option<usize>,
 ) -> anyhow::Result<()> {
+    // Fail early with a clear error instead of halfway through the
+    // installation if the cache or prefix are read-only. The cache is only
+    // written to if a package has to be fetched, so a read-only cache that
+    // already holds every package is fine.
+    if required_packages
+        .iter()
+        .any(|record| !is_in_package_cache(record, &tool_configuration.package_cache_dir))
+    {
+        ensure_writable(&tool_configuration.package_cache_dir, "package cache")?;
+    }
+    ensure_writable(target_prefix, "target prefix")?;
+
+    let history_file = target_prefix.join("conda-meta/history");
+    let had_history_file = history_file.exists();
+
     // Make sure the target prefix exists, regardless of whether we'll actually
-    // install anything in there.
-    let prefix = rattler_conda_types::prefix::Prefix::create(target_prefix).with_context(|| {
+    // install anything in there. `Prefix::create` also creates an empty
+    // `conda-meta/history` file.
+    if tool_configuration.create_history_file {
+        rattler_conda_types::prefix::Prefix::create(target_prefix).map(drop)
+    } else {
+        fs_err::create_dir_all(target_prefix.join("conda-meta"))
+    }
+    .with_context(|| {
         format!(
             "failed to create target prefix: {}",
             target_prefix.display()
         )
     })?;
 
-    if !prefix.path().join("conda-meta/history").exists() {
-        // Create an empty history file if it doesn't exist
-        fs_err::create_dir_all(prefix.path().join("conda-meta"))?;
-        fs_err::File::create(prefix.path().join("conda-meta/history"))?;
-    }
-
     let installed_packages = PrefixRecord::collect_from_prefix(target_prefix)?;
 
-    if !installed_packages.is_empty() && name.starts_with("host") {
-        // we have to clean up extra files in the prefix
-        let extra_files =
-            Files::from_prefix(target_prefix, &Default::default(), &Default::default())?;
+    if is_host && !installed_packages.is_empty() {
+        if tool_configuration.skip_host_prefix_cleanup {
+            tracing::info!("Keeping files in the prefix from a previous build.");
+        } else {
+            clean_host_prefix(target_prefix)?;
+        }
+    }
 
-        tracing::info!(
-            "Cleaning up {} files in the prefix from a previous build.",
-            extra_files.new_files.len()
-        );
+    if tool_configuration.verify_package_hashes {
+        // every package may be fetched again, so the verification is bounded
+        // like the installation itself
+        futures::stream::iter(required_packages)
+            .map(|record| verify_cached_package(record, tool_configuration))
+            .buffer_unordered(
+                tool_configuration.io_concurrency_limit_or_default(io_concurrency_limit),
+            )
+            .try_collect::<Vec<_>>()
+            .await?;
+    }
 
-        for f in extra_files.new_files {
-            if !f.is_dir() {
-                fs_err::remove_file(target_prefix.join(f))?;
-            }
-        }
+    if let Some(record) = required_packages.first() {
+        check_link_method(
+            tool_configuration.link_method,
+            record,
+            target_prefix,
+            tool_configuration,
+        )
+        .await?;
     }
 
     tracing::info!("\nInstalling {name} environment\n");
-    Installer::new()
+    if !tool_configuration.execute_link_scripts {
+        tracing::warn!(
+            "Post-link scripts are disabled, some packages in the {name} environment may not function correctly"
+        );
+    }
+    let installer = with_link_method(Installer::new(), tool_configuration.link_method);
+    let installer = installer
         .with_download_client(tool_configuration.client.get_client().clone())
         .with_target_platform(target_platform)
-        .with_execute_link_scripts(true)
+        .with_execute_link_scripts(tool_configuration.execute_link_scripts)
         .with_package_cache(tool_configuration.package_cache.clone())
         .with_installed_packages(installed_packages)
-        .with_io_concurrency_limit(tool_configuration.io_concurrency_limit.unwrap_or_default())
-        .with_reporter(
+        .with_io_concurrency_limit(
+            tool_configuration.io_concurrency_limit_or_default(io_concurrency_limit),
+        );
+    let link_callback = tool_configuration.link_callback.clone();
+    let installer = match tool_configuration.progress_output {
+        ProgressOutput::Bars => with_reporter(
+            installer,
             IndicatifReporter::builder()
                 .with_multi_progress(
                     tool_configuration
@@ -268,10 +1276,21 @@ pub async fn install_packages(
                         .with_prefix(tool_configuration.fancy_log_handler.with_indent_levels("")),
                 )
                 .finish(),
-        )
+            link_callback,
+        ),
+        ProgressOutput::Json => {
+            with_reporter(installer, JsonInstallReporter::default(), link_callback)
+        }
+    };
+    installer
         .install(&target_prefix, required_packages.to_owned())
         .await?;
 
+    // The installer always creates an empty history file, remove it again
+    if !tool_configuration.create_history_file && !had_history_file && history_file.exists() {
+        fs_err::remove_file(&history_file)?;
+    }
+
     tracing::info!(
         "{} Successfully updated the {name} environment",
         console::style(console::Emoji("✔", "")).green(),
@@ -279,3 +1298,759 @@ pub async fn install_packages(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rattler_conda_types::PackageRecord;
+
+    use super::*;
+
+    fn record(name: &str, depends: &[&str]) -> RepoDataRecord {
+        let mut package_record = PackageRecord::new(
+            PackageName::from_str(name).unwrap(),
+            "1.0".parse::<rattler_conda_types::Version>().unwrap(),
+            "0".to_string(),
+        );
+        package_record.depends = depends.iter().map(|d| d.to_string()).collect();
+        RepoDataRecord {
+            package_record,
+            file_name: format!("{name}-1.0-0.conda"),
+            url: Url::parse(&format!("https://example.com/linux-64/{name}-1.0-0.conda")).unwrap(),
+            channel: Some("example".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_referenced_virtual_packages() {
+        let records = [
+            record("cuda-runtime", &["__cuda >=12", "__glibc>=2.17,<3.0.a0"]),
+            record("numpy", &["python >=3.10", "libblas"]),
+            record("python", &[]),
+        ];
+
+        let virtual_packages = referenced_virtual_packages(&records);
+        assert_eq!(
+            virtual_packages.into_iter().collect::<Vec<_>>(),
+            vec!["__cuda".to_string(), "__glibc".to_string()]
+        );
+
+        assert!(referenced_virtual_packages(&records[1..]).is_empty());
+    }
+
+    #[test]
+    fn test_missing_packages() {
+        let repo_data = vec![vec![record("numpy", &[])], vec![record("python", &[])]];
+        let specs = ["numpy", "torch", "__glibc >=2.17", "__cuda"]
+            .map(|spec| MatchSpec::from_str(spec, ParseStrictness::Strict).unwrap());
+
+        // virtual packages never have records and are not missing
+        assert_eq!(missing_packages(&repo_data, &specs), vec!["torch"]);
+    }
+
+    #[test]
+    fn test_check_record_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("numpy-1.0-0.conda");
+        fs_err::write(&archive, "archive").unwrap();
+
+        let mut numpy = record("numpy", &[]);
+        // nothing to compare against
+        check_record_hash(&numpy, &archive).unwrap();
+
+        numpy.package_record.md5 = Some(
+            rattler_digest::compute_bytes_digest::<rattler_digest::Md5>("archive"),
+        );
+        check_record_hash(&numpy, &archive).unwrap();
+
+        // the sha256 takes precedence over the md5
+        numpy.package_record.sha256 = Some(Sha256::digest("other"));
+        let err = check_record_hash(&numpy, &archive).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("the archive of numpy does not match its repodata record")
+        );
+
+        numpy.package_record.sha256 = Some(Sha256::digest("archive"));
+        check_record_hash(&numpy, &archive).unwrap();
+    }
+
+    #[test]
+    fn test_write_as_table() {
+        let mut numpy = record("numpy", &["python >=3.10"]);
+        numpy.package_record.size = Some(2048);
+        numpy.channel = Some("https://conda.anaconda.org/conda-forge/".to_string());
+        let packages = [record("python", &[]), numpy];
+
+        let mut buffer = Vec::new();
+        write_as_table(&packages, &mut buffer).unwrap();
+        let table = String::from_utf8(buffer).unwrap();
+
+        let lines = table.lines().collect::<Vec<_>>();
+        assert!(lines[1].contains("Package") && lines[1].contains("Size"));
+        // packages are sorted by name and the channel is shortened
+        let numpy_line = lines.iter().position(|l| l.contains("numpy")).unwrap();
+        let python_line = lines.iter().position(|l| l.contains("python")).unwrap();
+        assert!(numpy_line < python_line);
+        assert!(lines[numpy_line].contains("conda-forge"));
+        assert!(lines[numpy_line].contains("2.00 KiB"));
+        assert!(lines[python_line].contains("example"));
+    }
+
+    fn channel_record(name: &str, version: &str, channel: &str) -> RepoDataRecord {
+        let package_record = PackageRecord::new(
+            PackageName::from_str(name).unwrap(),
+            version.parse::<rattler_conda_types::Version>().unwrap(),
+            "0".to_string(),
+        );
+        RepoDataRecord {
+            package_record,
+            file_name: format!("{name}-{version}-0.conda"),
+            url: Url::parse(&format!("{channel}linux-64/{name}-{version}-0.conda")).unwrap(),
+            channel: Some(channel.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_apply_channel_preferences() {
+        let conda_forge = "https://conda.anaconda.org/conda-forge/";
+        let custom = "https://example.com/custom/";
+        let repo_data = vec![
+            vec![
+                channel_record("numpy", "2.0", conda_forge),
+                channel_record("torch", "2.5", conda_forge),
+            ],
+            vec![
+                channel_record("numpy", "1.0", custom),
+                channel_record("torch", "2.4", custom),
+            ],
+        ];
+
+        let solve_specs =
+            |specs: &[&str], channel_preferences: &HashMap<PackageName, ChannelUrl>| {
+                solve_with_channel_preferences(&repo_data, channel_preferences, |repo_data| {
+                    let solver_task = SolverTask {
+                        specs: specs
+                            .iter()
+                            .map(|spec| MatchSpec::from_str(spec, ParseStrictness::Strict).unwrap())
+                            .collect(),
+                        channel_priority: ChannelPriority::Disabled,
+                        ..SolverTask::from_iter(repo_data)
+                    };
+                    Solver.solve(solver_task)
+                })
+                .unwrap()
+                .records
+                .into_iter()
+                .map(|record| {
+                    (
+                        record.package_record.name.as_normalized().to_string(),
+                        record.channel.unwrap(),
+                    )
+                })
+                .collect::<HashMap<_, _>>()
+            };
+        let solve = |channel_preferences: &HashMap<PackageName, ChannelUrl>| {
+            solve_specs(&["numpy", "torch"], channel_preferences)
+        };
+
+        // without preferences the highest versions win
+        let solved = solve(&HashMap::new());
+        assert_eq!(solved["numpy"], conda_forge);
+        assert_eq!(solved["torch"], conda_forge);
+
+        // the preferred channel wins for torch, even though it has a lower version
+        let channel_preferences = HashMap::from([(
+            PackageName::from_str("torch").unwrap(),
+            ChannelUrl::from(Url::parse(custom).unwrap()),
+        )]);
+        let solved = solve(&channel_preferences);
+        assert_eq!(solved["numpy"], conda_forge);
+        assert_eq!(solved["torch"], custom);
+
+        // if the preferred channel has no satisfying version, the other
+        // channels are used instead of failing the solve
+        let solved = solve_specs(&["numpy", "torch >=2.5"], &channel_preferences);
+        assert_eq!(solved["torch"], conda_forge);
+
+        // a preferred channel that does not provide the package is ignored
+        let channel_preferences = HashMap::from([(
+            PackageName::from_str("numpy").unwrap(),
+            ChannelUrl::from(Url::parse("https://example.com/other/").unwrap()),
+        )]);
+        assert_eq!(solve(&channel_preferences)["numpy"], conda_forge);
+    }
+
+    #[test]
+    fn test_describe_solve_strategy() {
+        assert_eq!(
+            describe_solve_strategy(SolveStrategy::Highest),
+            ("Highest", None)
+        );
+        let (name, note) = describe_solve_strategy(SolveStrategy::LowestVersion);
+        assert_eq!(name, "LowestVersion");
+        assert!(
+            note.unwrap()
+                .contains("lowest compatible version of every package")
+        );
+        let (name, note) = describe_solve_strategy(SolveStrategy::LowestVersionDirect);
+        assert_eq!(name, "LowestVersionDirect");
+        assert!(note.unwrap().contains("direct dependencies"));
+    }
+
+    #[test]
+    fn test_solver_channel_priority() {
+        let first = "https://example.com/first/";
+        let second = "https://example.com/second/";
+        let repo_data = [
+            vec![channel_record("numpy", "1.0", first)],
+            vec![
+                channel_record("numpy", "2.0", second),
+                channel_record("torch", "2.5", second),
+            ],
+        ];
+
+        let solve = |spec: &str, channel_priority: ChannelPriority| {
+            let solver_task = SolverTask {
+                specs: vec![MatchSpec::from_str(spec, ParseStrictness::Strict).unwrap()],
+                channel_priority,
+                ..SolverTask::from_iter(repo_data.iter())
+            };
+            Solver.solve(solver_task).map(|result| {
+                let record = &result.records[0];
+                (
+                    record.package_record.version.to_string(),
+                    record.channel.clone().unwrap(),
+                )
+            })
+        };
+
+        // strict: only the first channel that provides a package is considered
+        assert_eq!(
+            solve("numpy", ChannelPriority::Strict).unwrap(),
+            ("1.0".to_string(), first.to_string())
+        );
+        assert!(solve("numpy >=2", ChannelPriority::Strict).is_err());
+        assert_eq!(
+            solve("torch", ChannelPriority::Strict).unwrap(),
+            ("2.5".to_string(), second.to_string())
+        );
+
+        // disabled: the highest version wins, regardless of the channel
+        assert_eq!(
+            solve("numpy", ChannelPriority::Disabled).unwrap(),
+            ("2.0".to_string(), second.to_string())
+        );
+        assert_eq!(
+            solve("numpy <2", ChannelPriority::Disabled).unwrap(),
+            ("1.0".to_string(), first.to_string())
+        );
+        assert_eq!(
+            solve("torch", ChannelPriority::Disabled).unwrap(),
+            ("2.5".to_string(), second.to_string())
+        );
+    }
+
+    #[test]
+    fn test_check_pins() {
+        let channel = "https://example.com/channel/";
+        let records = [
+            channel_record("numpy", "1.0", channel),
+            channel_record("numpy", "2.0", channel),
+            channel_record("python", "3.12", channel),
+        ];
+        let repo_data = [records.iter().collect::<Vec<_>>()];
+        let spec = |s: &str| MatchSpec::from_str(s, ParseStrictness::Strict).unwrap();
+
+        let pins = [spec("numpy ==1.0 0")];
+        check_pins(&repo_data, &[spec("numpy"), spec("python")], &pins).unwrap();
+        check_pins(&repo_data, &[spec("numpy <2")], &pins).unwrap();
+
+        let err = check_pins(&repo_data, &[spec("numpy >=2")], &pins).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the pinned package `numpy ==1.0 0` conflicts with the requirement `numpy >=2`"
+        );
+
+        // a pin without any matching package cannot satisfy the requirement
+        let pins = [spec("numpy ==3.0 0")];
+        assert!(check_pins(&repo_data, &[spec("numpy")], &pins).is_err());
+
+        // pins for packages that are not requested directly are left to the solver
+        check_pins(&repo_data, &[spec("python")], &pins).unwrap();
+
+        // the pins are applied as constraints
+        let solver_task = SolverTask {
+            specs: vec![spec("numpy"), spec("python")],
+            constraints: vec![spec("numpy ==1.0 0")],
+            ..SolverTask::from_iter([records.iter()])
+        };
+        let solved = Solver.solve(solver_task).unwrap().records;
+        let numpy = solved
+            .iter()
+            .find(|r| r.package_record.name.as_normalized() == "numpy")
+            .unwrap();
+        assert_eq!(numpy.package_record.version.to_string(), "1.0");
+    }
+
+    #[test]
+    fn test_direct_matches() {
+        let channel = "https://example.com/channel/";
+        let records = [
+            channel_record("numpy", "2.0", channel),
+            channel_record("python", "3.11.7", channel),
+        ];
+        let specs = ["python >=3.10", "numpy", "torch"]
+            .map(|s| MatchSpec::from_str(s, ParseStrictness::Strict).unwrap());
+
+        let matches = direct_matches(&specs, &records)
+            .into_iter()
+            .map(|(spec, record)| {
+                (
+                    spec.to_string(),
+                    record.map(|r| r.package_record.version.to_string()),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            matches,
+            vec![
+                ("python >=3.10".to_string(), Some("3.11.7".to_string())),
+                ("numpy".to_string(), Some("2.0".to_string())),
+                ("torch".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unexpected_channels() {
+        let channel_config = ChannelConfig::default_with_root_dir(std::env::temp_dir());
+        let conda_forge = "https://conda.anaconda.org/conda-forge/";
+        let custom = "https://example.com/custom/";
+        let mut local = channel_record("zlib", "1.3", custom);
+        local.channel = None;
+        let records = [
+            channel_record("numpy", "2.0", conda_forge),
+            channel_record("python", "3.11.7", custom),
+            local,
+        ];
+        let expected = [ChannelUrl::from(
+            Url::parse("https://conda.anaconda.org/conda-forge").unwrap(),
+        )];
+
+        assert_eq!(
+            unexpected_channels(&records, &expected, &channel_config),
+            [
+                "python 3.11.7 from https://example.com/custom",
+                "zlib 1.3 from an unknown channel"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_environment_hash() {
+        let channel = "https://example.com/channel/";
+        let other_channel = "https://example.com/other/";
+        let mut numpy = channel_record("numpy", "2.0", channel);
+        numpy.package_record.sha256 = Some(
+            rattler_digest::parse_digest_from_hex::<Sha256>(
+                "7a7e3dd7c4e1e5e1b8d8f4b7a0a1a2f3e4d5c6b7a8f9e0d1c2b3a4f5e6d7c8b9",
+            )
+            .unwrap(),
+        );
+        let records = [numpy.clone(), channel_record("python", "3.11.7", channel)];
+        let hash = environment_hash(&records);
+
+        // The order and the channel of the records do not matter
+        let reordered = [channel_record("python", "3.11.7", other_channel), numpy];
+        assert_eq!(hash, environment_hash(&reordered));
+        assert_eq!(
+            format!("{hash:x}"),
+            "d77eee797e7cb9ef9b2d74f9fb504e92ba32e9953829a482aeabb33be3f8aebc"
+        );
+
+        // Any change in the solution changes the hash
+        let updated = [
+            channel_record("numpy", "2.0", channel),
+            channel_record("python", "3.11.7", channel),
+        ];
+        assert_ne!(hash, environment_hash(&updated));
+    }
+
+    #[test]
+    fn test_explain_package() {
+        let channel = "https://example.com/channel/";
+        let mut scipy = channel_record("scipy", "1.11", channel);
+        scipy.package_record.depends = vec!["numpy >=1.21,<2".to_string()];
+        let mut pandas = channel_record("pandas", "2.1", channel);
+        pandas.package_record.constrains = vec!["numpy <1.27".to_string()];
+        let records = [channel_record("numpy", "1.26", channel), scipy, pandas];
+        let candidates = [
+            channel_record("numpy", "1.26", channel),
+            channel_record("numpy", "2.0", channel),
+        ];
+        let spec = |s: &str| MatchSpec::from_str(s, ParseStrictness::Strict).unwrap();
+        let numpy = PackageName::from_str("numpy").unwrap();
+
+        insta::assert_snapshot!(explain_package(
+            &numpy,
+            &[spec("numpy >=1.20"), spec("scipy"), spec("pandas")],
+            &[],
+            &records,
+            &candidates,
+        ));
+
+        let torch = PackageName::from_str("torch").unwrap();
+        assert_eq!(
+            explain_package(&torch, &[], &[], &records, &candidates),
+            "torch is not part of the environment"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_solve_concurrently() {
+        let channel = "https://example.com/channel/";
+        let repo_data = std::sync::Arc::new(vec![
+            channel_record("numpy", "2.0", channel),
+            channel_record("python", "3.12", channel),
+            channel_record("torch", "2.5", channel),
+        ]);
+
+        let solve = |specs: &[&str]| {
+            let repo_data = repo_data.clone();
+            let specs = specs
+                .iter()
+                .map(|spec| MatchSpec::from_str(spec, ParseStrictness::Strict).unwrap())
+                .collect::<Vec<_>>();
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    let solver_task = SolverTask {
+                        specs,
+                        ..SolverTask::from_iter([repo_data.iter()])
+                    };
+                    Solver.solve(solver_task).map(|result| {
+                        result
+                            .records
+                            .into_iter()
+                            .map(|record| record.package_record.name.as_normalized().to_string())
+                            .sorted()
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .await
+                .unwrap()
+            }
+        };
+
+        let solved = solve_concurrently([vec!["numpy", "python"], vec!["torch"]], 2, |specs| {
+            solve(&specs)
+        })
+        .await
+        .unwrap();
+        assert_eq!(solved, [vec!["numpy", "python"], vec!["torch"]]);
+
+        // all errors are collected
+        let errors = solve_concurrently(
+            [vec!["numpy"], vec!["missing"], vec!["scipy"]],
+            2,
+            |specs| solve(&specs),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_solve_environments_concurrently() {
+        // a local channel with a few noarch packages
+        let channel_dir = tempfile::tempdir().unwrap();
+        let package = |name: &str, depends: &[&str]| {
+            (
+                format!("{name}-1.0-0.tar.bz2"),
+                serde_json::json!({
+                    "name": name,
+                    "version": "1.0",
+                    "build": "0",
+                    "build_number": 0,
+                    "depends": depends,
+                    "subdir": "noarch",
+                }),
+            )
+        };
+        let packages = serde_json::Map::from_iter([
+            package("numpy", &["python"]),
+            package("python", &[]),
+            package("torch", &["numpy"]),
+        ]);
+        for (subdir, packages) in [
+            (Platform::NoArch, packages),
+            (Platform::current(), serde_json::Map::new()),
+        ] {
+            let subdir_dir = channel_dir.path().join(subdir.as_str());
+            fs_err::create_dir_all(&subdir_dir).unwrap();
+            fs_err::write(
+                subdir_dir.join("repodata.json"),
+                serde_json::json!({
+                    "info": {"subdir": subdir.as_str()},
+                    "packages": packages,
+                })
+                .to_string(),
+            )
+            .unwrap();
+        }
+        let channels = [ChannelUrl::from(
+            Url::from_directory_path(channel_dir.path()).unwrap(),
+        )];
+
+        let cache = tempfile::tempdir().unwrap();
+        let tool_configuration = tool_configuration::Configuration::builder()
+            .with_cache_dir(cache.path().to_path_buf())
+            .finish();
+        let target_platform = PlatformWithVirtualPackages {
+            platform: Platform::current(),
+            virtual_packages: vec![],
+        };
+
+        let solve = |specs: Vec<&str>| {
+            let specs = specs
+                .into_iter()
+                .map(|spec| MatchSpec::from_str(spec, ParseStrictness::Strict).unwrap())
+                .collect::<Vec<_>>();
+            let (channels, target_platform, tool_configuration) =
+                (&channels, &target_platform, &tool_configuration);
+            async move {
+                solve_environment(
+                    "test",
+                    &specs,
+                    target_platform,
+                    channels,
+                    tool_configuration,
+                    ChannelPriority::Strict,
+                    SolveStrategy::Highest,
+                    None,
+                    &HashMap::new(),
+                    &[],
+                )
+                .await
+                .map(|records| {
+                    records
+                        .into_iter()
+                        .map(|record| record.package_record.name.as_normalized().to_string())
+                        .sorted()
+                        .collect::<Vec<_>>()
+                })
+            }
+        };
+
+        let solved = solve_concurrently([vec!["numpy"], vec!["torch"]], 2, solve)
+            .await
+            .unwrap();
+        assert_eq!(
+            solved,
+            [vec!["numpy", "python"], vec!["numpy", "python", "torch"]]
+        );
+
+        // the repodata was loaded once into the shared gateway, so the solves
+        // still work without the channel on disk
+        fs_err::remove_dir_all(channel_dir.path().join(Platform::NoArch.as_str())).unwrap();
+        let solved = solve_concurrently([vec!["python"], vec!["torch"]], 2, solve)
+            .await
+            .unwrap();
+        assert_eq!(solved, [vec!["python"], vec!["numpy", "python", "torch"]]);
+    }
+
+    #[test]
+    fn test_clean_host_prefix() {
+        let prefix = tempfile::tempdir().unwrap();
+        fs_err::create_dir_all(prefix.path().join("lib")).unwrap();
+        fs_err::write(
+            prefix.path().join("lib/leftover.txt"),
+            "from a previous build",
+        )
+        .unwrap();
+
+        clean_host_prefix(prefix.path()).unwrap();
+        assert!(!prefix.path().join("lib/leftover.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ensure_writable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        ensure_writable(&dir.path().join("new/prefix"), "target prefix").unwrap();
+        assert!(dir.path().join("new/prefix").is_dir());
+
+        let readonly = dir.path().join("readonly");
+        fs_err::create_dir(&readonly).unwrap();
+        fs_err::set_permissions(&readonly, std::fs::Permissions::from_mode(0o555)).unwrap();
+        // root can write to read-only directories
+        if tempfile::tempfile_in(&readonly).is_err() {
+            let err = ensure_writable(&readonly, "package cache").unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "the package cache at {} is not writable",
+                    readonly.display()
+                )
+            );
+            assert!(format!("{err:#}").contains("ermission denied"));
+        }
+    }
+
+    #[test]
+    fn test_is_in_package_cache() {
+        let cache = tempfile::tempdir().unwrap();
+        let numpy = record("numpy", &[]);
+        assert!(!is_in_package_cache(&numpy, cache.path()));
+
+        let package_dir = cache
+            .path()
+            .join(CacheKey::from(&numpy.package_record).to_string());
+        fs_err::create_dir_all(package_dir.join("info")).unwrap();
+        fs_err::write(package_dir.join("info/index.json"), "{}").unwrap();
+        assert!(is_in_package_cache(&numpy, cache.path()));
+        assert!(!is_in_package_cache(&record("python", &[]), cache.path()));
+    }
+
+    #[tokio::test]
+    async fn test_install_packages_history_file() {
+        for create_history_file in [true, false] {
+            let prefix = tempfile::tempdir().unwrap();
+            let cache = tempfile::tempdir().unwrap();
+            let tool_configuration = tool_configuration::Configuration::builder()
+                .with_cache_dir(cache.path().to_path_buf())
+                .with_create_history_file(create_history_file)
+                .finish();
+
+            install_packages(
+                "test",
+                &[],
+                Platform::current(),
+                prefix.path(),
+                false,
+                &tool_configuration,
+                None,
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(
+                prefix.path().join("conda-meta/history").exists(),
+                create_history_file
+            );
+        }
+    }
+
+    /// Writes a package with a single file `share/test.txt` and returns its
+    /// repodata record.
+    fn write_test_package(dir: &Path) -> RepoDataRecord {
+        use rattler_conda_types::compression_level::CompressionLevel;
+
+        let source = dir.join("source");
+        fs_err::create_dir_all(source.join("info")).unwrap();
+        fs_err::create_dir_all(source.join("share")).unwrap();
+        fs_err::write(source.join("share/test.txt"), "test").unwrap();
+        fs_err::write(
+            source.join("info/index.json"),
+            r#"{"name": "test-pkg", "version": "1.0", "build": "0", "build_number": 0, "subdir": "noarch"}"#,
+        )
+        .unwrap();
+        fs_err::write(
+            source.join("info/paths.json"),
+            format!(
+                r#"{{"paths": [{{"_path": "share/test.txt", "path_type": "hardlink", "sha256": "{:x}", "size_in_bytes": 4}}], "paths_version": 1}}"#,
+                Sha256::digest("test")
+            ),
+        )
+        .unwrap();
+
+        let archive = dir.join("test-pkg-1.0-0.conda");
+        let paths =
+            ["info/index.json", "info/paths.json", "share/test.txt"].map(|path| source.join(path));
+        rattler_package_streaming::write::write_conda_package(
+            fs_err::File::create(&archive).unwrap(),
+            &source,
+            &paths,
+            CompressionLevel::Default,
+            None,
+            "test-pkg-1.0-0",
+            None,
+            None,
+        )
+        .unwrap();
+
+        let mut package_record = PackageRecord::new(
+            PackageName::from_str("test-pkg").unwrap(),
+            "1.0".parse::<rattler_conda_types::Version>().unwrap(),
+            "0".to_string(),
+        );
+        package_record.subdir = "noarch".to_string();
+        package_record.sha256 =
+            Some(rattler_digest::compute_file_digest::<Sha256>(&archive).unwrap());
+        RepoDataRecord {
+            package_record,
+            file_name: "test-pkg-1.0-0.conda".to_string(),
+            url: Url::from_file_path(&archive).unwrap(),
+            channel: None,
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_install_packages_link_method() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let record = write_test_package(dir.path());
+
+        for (link_method, links) in [(LinkMethod::Hardlink, 2), (LinkMethod::Copy, 1)] {
+            let prefix = dir.path().join(format!("prefix-{link_method}"));
+            let tool_configuration = tool_configuration::Configuration::builder()
+                .with_cache_dir(dir.path().join("cache"))
+                .with_link_method(link_method)
+                .finish();
+
+            install_packages(
+                "test",
+                std::slice::from_ref(&record),
+                Platform::current(),
+                &prefix,
+                false,
+                &tool_configuration,
+                None,
+            )
+            .await
+            .unwrap();
+
+            // a hard linked file shares its inode with the file in the cache
+            let installed = fs_err::metadata(prefix.join("share/test.txt")).unwrap();
+            assert_eq!(installed.nlink(), links, "{link_method}");
+        }
+    }
+
+    #[test]
+    fn test_dedup_channels() {
+        let channel_config = ChannelConfig::default_with_root_dir(std::env::temp_dir());
+        let channel = |url: &str| ChannelUrl::from(Url::parse(url).unwrap());
+        let channels = [
+            channel("https://conda.anaconda.org/conda-forge"),
+            channel("https://example.com/channel"),
+            channel("https://conda.anaconda.org/conda-forge/"),
+            channel("https://EXAMPLE.com/channel/"),
+            channel("https://conda.anaconda.org/bioconda"),
+        ];
+
+        let deduped = dedup_channels(&channels, &channel_config);
+        let names = deduped
+            .iter()
+            .map(|channel| channel_config.canonical_name(channel.url()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["conda-forge", "https://example.com/channel/", "bioconda"]
+        );
+    }
+}
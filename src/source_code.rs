@@ -1,10 +1,18 @@
 //! Provides a trait for source code that can be used for error reporting. See
 //! [`SourceCode`].
 use miette::{MietteError, MietteSpanContents, SourceSpan, SpanContents};
+use std::io::{IsTerminal, Read};
 use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
 use std::{path::Path, sync::Arc};
 
+use indexmap::IndexMap;
+use rattler_digest::{Sha256, digest::Digest};
 use std::fmt::Debug;
+use url::Url;
+
+use crate::tool_configuration::BaseClient;
 
 /// A helper trait that provides source code for rattler-build.
 ///
@@ -13,6 +21,9 @@ use std::fmt::Debug;
 pub trait SourceCode: Debug + Clone + AsRef<str> + miette::SourceCode {}
 impl<T: Debug + Clone + AsRef<str> + miette::SourceCode> SourceCode for T {}
 
+/// The name of a source that is read from stdin.
+const STDIN_NAME: &str = "<stdin>";
+
 /// The contents of a specific source file together with the name of the source
 /// file.
 ///
@@ -24,8 +35,13 @@ pub struct Source {
     pub name: String,
     /// The source code.
     pub code: Arc<str>,
-    /// The actual path to the source file.
+    /// The actual path to the source file. This is empty for sources that do
+    /// not live on disk (see [`Source::from_string`] and [`Source::from_stdin`])
+    /// and must not be used to resolve relative paths in that case.
     pub path: PathBuf,
+    /// Whether `\r\n` line endings were normalized to `\n` when the source was
+    /// constructed. All spans refer to the normalized code.
+    pub normalized_line_endings: bool,
 }
 
 impl Source {
@@ -50,13 +66,163 @@ impl Source {
             .unwrap_or_default()
             .into_owned();
 
-        let contents = fs_err::read_to_string(&path)?;
+        let (code, normalized_line_endings) = read_cached(&path)?;
         Ok(Self {
             name,
-            code: Arc::from(contents.as_str()),
+            code,
             path,
+            normalized_line_endings,
         })
     }
+
+    /// Constructs a new instance from source code that does not live on disk,
+    /// e.g. a recipe that was generated in memory.
+    ///
+    /// The name is used to identify the source in error messages. As there is
+    /// no file backing the source, `path` is left empty.
+    pub fn from_string(name: impl Into<String>, code: impl AsRef<str>) -> Self {
+        let (code, normalized_line_endings) = normalize_line_endings(code.as_ref());
+        Self {
+            name: name.into(),
+            code,
+            path: PathBuf::new(),
+            normalized_line_endings,
+        }
+    }
+
+    /// Constructs a new instance by reading all of stdin (e.g. `cat recipe.yaml
+    /// | rattler-build ...`). The source is named `<stdin>` and, like
+    /// [`Source::from_string`], has an empty `path`.
+    ///
+    /// Fails if stdin is a terminal (nothing was piped) or if it is empty.
+    pub fn from_stdin() -> std::io::Result<Self> {
+        let stdin = std::io::stdin();
+        if stdin.is_terminal() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "stdin is a terminal, pipe the recipe into rattler-build to read it from stdin",
+            ));
+        }
+        Self::from_reader(STDIN_NAME, stdin.lock())
+    }
+
+    /// Constructs a new instance by downloading the source code from the given
+    /// URL with the (authenticated) download client of rattler-build.
+    ///
+    /// The URL is used as the name of the source. The downloaded text is stored
+    /// in `cache_dir`, and that file becomes the `path` of the source. Network
+    /// errors are returned as [`std::io::Error`]s.
+    pub async fn from_url(
+        url: &Url,
+        client: &BaseClient,
+        cache_dir: &Path,
+    ) -> std::io::Result<Self> {
+        let text = client
+            .for_host(url)
+            .get(url.clone())
+            .send()
+            .await
+            .map_err(std::io::Error::other)?
+            .error_for_status()
+            .map_err(std::io::Error::other)?
+            .text()
+            .await
+            .map_err(std::io::Error::other)?;
+
+        // the URL hash keeps snippets with the same file name apart and is
+        // stable across runs, so that a download replaces its previous copy
+        let url_hash = format!("{:x}", Sha256::digest(url.as_str()));
+        let file_name = url
+            .path_segments()
+            .and_then(|segments| segments.filter(|s| !s.is_empty()).next_back())
+            .unwrap_or("recipe.yaml");
+        let path = cache_dir.join(format!("{url_hash}_{file_name}"));
+        // leave an unchanged copy (and its modification time) alone
+        if fs_err::read(&path).ok().as_deref() != Some(text.as_bytes()) {
+            fs_err::create_dir_all(cache_dir)?;
+            fs_err::write(&path, &text)?;
+        }
+
+        let (code, normalized_line_endings) = normalize_line_endings(&text);
+        Ok(Self {
+            name: url.to_string(),
+            code,
+            path,
+            normalized_line_endings,
+        })
+    }
+
+    /// Returns the 1-based line and column of the start of the span (or byte
+    /// offset). The column counts characters, not bytes, so multi-byte UTF-8
+    /// characters before the offset are counted once.
+    ///
+    /// Returns `None` if the offset is outside of the source or not on a
+    /// character boundary.
+    pub fn line_column(&self, span: impl Into<SourceSpan>) -> Option<(usize, usize)> {
+        let before = self.code.get(..span.into().offset())?;
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        let line = before.matches('\n').count() + 1;
+        let column = before[line_start..].chars().count() + 1;
+        Some((line, column))
+    }
+
+    /// Reads the source code from the reader until EOF.
+    fn from_reader(name: &str, mut reader: impl Read) -> std::io::Result<Self> {
+        let mut code = String::new();
+        reader.read_to_string(&mut code)?;
+        if code.trim().is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("no input was read from {name}"),
+            ));
+        }
+        Ok(Self::from_string(name, code))
+    }
+}
+
+/// The maximum number of files whose contents are kept in [`SOURCE_CACHE`].
+/// When it is full, the file that was read first is evicted.
+const SOURCE_CACHE_CAPACITY: usize = 64;
+
+/// The contents of the files that were read with [`Source::from_rooted_path`],
+/// keyed by their canonical path. Every entry remembers the modification time
+/// of the file, so that a file that changed since is read again.
+type SourceCache = IndexMap<PathBuf, (SystemTime, Arc<str>, bool)>;
+
+static SOURCE_CACHE: LazyLock<Mutex<SourceCache>> = LazyLock::new(Default::default);
+
+/// Reads (and normalizes) the contents of the file, or returns the shared
+/// contents of a previous read if the file did not change since.
+fn read_cached(path: &Path) -> std::io::Result<(Arc<str>, bool)> {
+    let canonical_path = dunce::canonicalize(path)?;
+    let modified = fs_err::metadata(&canonical_path)?.modified()?;
+
+    if let Some((cached_modified, code, normalized)) =
+        SOURCE_CACHE.lock().unwrap().get(&canonical_path)
+        && *cached_modified == modified
+    {
+        return Ok((code.clone(), *normalized));
+    }
+
+    let contents = fs_err::read_to_string(path)?;
+    let (code, normalized) = normalize_line_endings(&contents);
+    let mut cache = SOURCE_CACHE.lock().unwrap();
+    cache.shift_remove(&canonical_path);
+    if cache.len() >= SOURCE_CACHE_CAPACITY {
+        cache.shift_remove_index(0);
+    }
+    cache.insert(canonical_path, (modified, code.clone(), normalized));
+    Ok((code, normalized))
+}
+
+/// Converts `\r\n` line endings to `\n`, so that the YAML parser and miette
+/// agree on the byte offsets of spans. Returns whether anything was changed.
+fn normalize_line_endings(code: &str) -> (Arc<str>, bool) {
+    if code.contains("\r\n") {
+        (Arc::from(code.replace("\r\n", "\n")), true)
+    } else {
+        (Arc::from(code), false)
+    }
 }
 
 impl AsRef<str> for Source {
@@ -86,3 +252,163 @@ impl miette::SourceCode for Source {
         Ok(Box::new(contents))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use miette::SourceSpan;
+
+    use rattler_digest::{Sha256, digest::Digest};
+
+    use super::{SOURCE_CACHE, SOURCE_CACHE_CAPACITY, STDIN_NAME, Source};
+    use crate::tool_configuration::BaseClient;
+    use crate::{assert_miette_snapshot, recipe::Recipe, selectors::SelectorConfig};
+
+    #[test]
+    fn test_from_string() {
+        let source = Source::from_string(
+            "generated/recipe.yaml",
+            "package:\n  name: foo\n  version: 0.1.0\nbuild:\n  number: abc\n",
+        );
+        assert_eq!(source.name, "generated/recipe.yaml");
+        assert!(source.path.as_os_str().is_empty());
+        assert!(!source.normalized_line_endings);
+
+        let err = Recipe::from_yaml(source, SelectorConfig::default())
+            .unwrap_err()
+            .remove(0);
+        assert_miette_snapshot!(err);
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let source = Source::from_reader(STDIN_NAME, "package:\n  name: foo\n".as_bytes()).unwrap();
+        assert_eq!(source.name, "<stdin>");
+        assert_eq!(source.as_ref(), "package:\n  name: foo\n");
+        assert!(source.path.as_os_str().is_empty());
+
+        let err = Source::from_reader(STDIN_NAME, " \n".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert_eq!(err.to_string(), "no input was read from <stdin>");
+    }
+
+    #[test]
+    fn test_crlf() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("test-data/recipes/crlf/recipe.yaml");
+        let source = Source::from_rooted_path(path.parent().unwrap(), path.clone()).unwrap();
+        assert!(source.normalized_line_endings);
+        assert!(!source.as_ref().contains('\r'));
+
+        let err = Recipe::from_yaml(source, SelectorConfig::default())
+            .unwrap_err()
+            .remove(0);
+        assert_miette_snapshot!(err);
+    }
+
+    /// Serves a single HTTP response on a local port and returns its URL.
+    fn serve_once(status: &'static str, body: &'static str) -> url::Url {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+        });
+        format!("http://127.0.0.1:{port}/snippets/recipe.yaml")
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_from_url() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let url = serve_once("200 OK", "package:\n  name: foo\n  version: 0.1.0\n");
+
+        let source = Source::from_url(&url, &BaseClient::default(), cache_dir.path())
+            .await
+            .unwrap();
+        assert_eq!(source.name, url.to_string());
+        assert!(source.path.starts_with(cache_dir.path()));
+        let url_hash = format!("{:x}", Sha256::digest(url.as_str()));
+        assert_eq!(
+            source.path.file_name().unwrap().to_string_lossy(),
+            format!("{url_hash}_recipe.yaml")
+        );
+        assert_eq!(
+            fs_err::read_to_string(&source.path).unwrap(),
+            source.as_ref()
+        );
+
+        let outputs = crate::recipe::parser::find_outputs_from_src(source).unwrap();
+        assert_eq!(outputs.len(), 1);
+
+        let url = serve_once("404 Not Found", "");
+        let err = Source::from_url(&url, &BaseClient::default(), cache_dir.path())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("404"));
+    }
+
+    #[test]
+    fn test_from_path_cache() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("recipe.yaml");
+        fs_err::write(&path, "package:\n  name: foo\n").unwrap();
+
+        let first = Source::from_rooted_path(tmp_dir.path(), path.clone()).unwrap();
+        let second = Source::from_rooted_path(tmp_dir.path(), path.clone()).unwrap();
+        assert!(Arc::ptr_eq(&first.code, &second.code));
+
+        // a modified file is read again
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        fs_err::write(&path, "package:\n  name: bar\n").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        let third = Source::from_rooted_path(tmp_dir.path(), path.clone()).unwrap();
+        assert!(!Arc::ptr_eq(&first.code, &third.code));
+        assert_eq!(third.as_ref(), "package:\n  name: bar\n");
+
+        // reading more files than fit into the cache evicts the oldest ones
+        for idx in 0..SOURCE_CACHE_CAPACITY {
+            let other = tmp_dir.path().join(format!("other-{idx}.yaml"));
+            fs_err::write(&other, "package:\n  name: other\n").unwrap();
+            Source::from_rooted_path(tmp_dir.path(), other).unwrap();
+        }
+        assert!(SOURCE_CACHE.lock().unwrap().len() <= SOURCE_CACHE_CAPACITY);
+        let fourth = Source::from_rooted_path(tmp_dir.path(), path).unwrap();
+        assert!(!Arc::ptr_eq(&third.code, &fourth.code));
+    }
+
+    #[test]
+    fn test_source_from_path_with_unicode() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("recipe.yaml");
+        fs_err::write(&path, "about:\n  summary: \"Grüße 🦀\"\n  license: MIT\n").unwrap();
+        let source = Source::from_rooted_path(tmp_dir.path(), path).unwrap();
+
+        assert_eq!(source.line_column(0), Some((1, 1)));
+        let offset = source.as_ref().find("MIT").unwrap();
+        assert_eq!(source.line_column(offset), Some((3, 12)));
+        let offset = source.as_ref().find('🦀').unwrap();
+        assert_eq!(
+            source.line_column(SourceSpan::new(offset.into(), 4)),
+            Some((2, 19))
+        );
+        assert_eq!(source.line_column(source.as_ref().len()), Some((4, 1)));
+
+        // inside of a multi-byte character or past the end
+        assert_eq!(source.line_column(offset + 1), None);
+        assert_eq!(source.line_column(source.as_ref().len() + 1), None);
+    }
+}
@@ -37,6 +37,8 @@ pub enum Tool {
     InstallNameTool,
     /// The git tool
     Git,
+    /// The strip tool (for removing debug symbols from ELF / MachO binaries)
+    Strip,
 }
 
 impl std::fmt::Display for Tool {
@@ -51,6 +53,7 @@ impl std::fmt::Display for Tool {
                 Tool::Patchelf => "patchelf".to_string(),
                 Tool::InstallNameTool => "install_name_tool".to_string(),
                 Tool::Git => "git".to_string(),
+                Tool::Strip => "strip".to_string(),
             }
         )
     }
@@ -116,6 +119,12 @@ impl SystemTools {
 
     /// Find the tool in the system and return the path to the tool
     pub fn find_tool(&self, tool: Tool) -> Result<PathBuf, which::Error> {
+        self.find_tool_with_version(tool).map(|(path, _)| path)
+    }
+
+    /// Find the tool in the system and return the path to the tool together with
+    /// its version (empty if the tool does not report a version)
+    pub fn find_tool_with_version(&self, tool: Tool) -> Result<(PathBuf, String), which::Error> {
         let which = |tool: &str| -> Result<PathBuf, which::Error> {
             if let Some(build_prefix) = &self.build_prefix {
                 let build_prefix_activator =
@@ -146,12 +155,24 @@ impl SystemTools {
             }
             Tool::InstallNameTool => {
                 let path = which("install_name_tool")?;
-                (path, "".to_string())
+                // not every install_name_tool supports `--version`
+                let found_version = std::process::Command::new(&path)
+                    .arg("--version")
+                    .output()
+                    .ok()
+                    .filter(|output| output.status.success())
+                    .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+                    .unwrap_or_default();
+                (path, found_version)
             }
             Tool::Codesign => {
                 let path = which("codesign")?;
                 (path, "".to_string())
             }
+            Tool::Strip => {
+                let path = which("strip")?;
+                (path, "".to_string())
+            }
             Tool::Git => {
                 let path = which("git")?;
                 let output = std::process::Command::new(&path)
@@ -178,11 +199,20 @@ impl SystemTools {
         };
 
         let found_version = found_version.trim().to_string();
+        tracing::debug!(
+            "Found {tool} at {} (version: {})",
+            tool_path.display(),
+            if found_version.is_empty() {
+                "unknown"
+            } else {
+                &found_version
+            }
+        );
 
         if let Some(build_prefix) = &self.build_prefix {
             // Do not cache tools found in the (temporary) build prefix
             if tool_path.starts_with(build_prefix) {
-                return Ok(tool_path);
+                return Ok((tool_path, found_version));
             }
         }
 
@@ -201,10 +231,13 @@ impl SystemTools {
                 );
             }
         } else {
-            self.used_tools.lock().unwrap().insert(tool, found_version);
+            self.used_tools
+                .lock()
+                .unwrap()
+                .insert(tool, found_version.clone());
         }
 
-        Ok(tool_path)
+        Ok((tool_path, found_version))
     }
 
     /// Create a new `std::process::Command` for the given tool. The command is created with the
@@ -266,6 +299,23 @@ mod tests {
         assert!(used_tools.get(&Tool::Patchelf).unwrap() == &version);
     }
 
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_find_tool_with_version() {
+        if which::which("git").is_err() {
+            return;
+        }
+
+        let system_tool = SystemTools::new();
+        let (path, version) = system_tool.find_tool_with_version(Tool::Git).unwrap();
+        assert_eq!(path, system_tool.find_tool(Tool::Git).unwrap());
+        assert!(version.starts_with("git version"));
+        assert!(logs_contain(&format!(
+            "Found git at {} (version: {version})",
+            path.display()
+        )));
+    }
+
     #[test]
     fn test_serialize() {
         // fix versions in used tools to test deserialization
@@ -5,7 +5,9 @@ use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
 use clap::ValueEnum;
 use rattler::package_cache::PackageCache;
-use rattler_conda_types::{ChannelConfig, Platform};
+use rattler_conda_types::{
+    ChannelConfig, ChannelUrl, MatchSpec, PackageName, Platform, RepoDataRecord,
+};
 #[cfg(feature = "s3")]
 use rattler_networking::s3_middleware;
 use rattler_networking::{
@@ -21,7 +23,10 @@ use reqwest_retry::{RetryTransientMiddleware, policies::ExponentialBackoff};
 use thiserror::Error;
 use url::Url;
 
-use crate::console_utils::LoggingOutputHandler;
+use crate::{
+    console_utils::LoggingOutputHandler,
+    render::reporters::{LinkCallback, LinkStage},
+};
 
 /// The user agent to use for the reqwest client
 pub const APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
@@ -37,6 +42,63 @@ pub enum SkipExisting {
     All,
 }
 
+/// The method used to link files from the package cache into an environment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LinkMethod {
+    /// Use the fastest method supported by the filesystem (reflink, hard link or
+    /// copy)
+    #[default]
+    Auto,
+    /// Always hard link files
+    Hardlink,
+    /// Always reflink (copy-on-write) files
+    Reflink,
+    /// Always copy files
+    Copy,
+}
+
+impl LinkMethod {
+    /// Whether hard links are allowed (`None` means: if supported)
+    pub fn allow_hard_links(self) -> Option<bool> {
+        match self {
+            LinkMethod::Auto => None,
+            LinkMethod::Hardlink => Some(true),
+            LinkMethod::Reflink | LinkMethod::Copy => Some(false),
+        }
+    }
+
+    /// Whether reflinks are allowed (`None` means: if supported)
+    pub fn allow_ref_links(self) -> Option<bool> {
+        match self {
+            LinkMethod::Auto => None,
+            LinkMethod::Reflink => Some(true),
+            LinkMethod::Hardlink | LinkMethod::Copy => Some(false),
+        }
+    }
+}
+
+impl std::fmt::Display for LinkMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkMethod::Auto => write!(f, "auto"),
+            LinkMethod::Hardlink => write!(f, "hardlink"),
+            LinkMethod::Reflink => write!(f, "reflink"),
+            LinkMethod::Copy => write!(f, "copy"),
+        }
+    }
+}
+
+/// How progress of downloads and installations is reported
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProgressOutput {
+    /// Draw progress bars
+    #[default]
+    Bars,
+    /// Write newline-delimited JSON events to stderr instead of drawing
+    /// progress bars, for machine consumers
+    Json,
+}
+
 /// Container for the CLI test strategy
 #[derive(Debug, Clone, Copy, ValueEnum, Default)]
 pub enum TestStrategy {
@@ -221,9 +283,14 @@ pub struct Configuration {
     /// threads does not matter for the final result.
     pub compression_threads: Option<u32>,
 
-    /// Concurrency limit for I/O operations
+    /// Concurrency limit for I/O operations when installing packages. Defaults
+    /// to 8 times the number of CPUs, see
+    /// [`Configuration::io_concurrency_limit_or_default`].
     pub io_concurrency_limit: Option<usize>,
 
+    /// The directory of the writable package cache layer.
+    pub package_cache_dir: PathBuf,
+
     /// The package cache to use to store packages in.
     pub package_cache: PackageCache,
 
@@ -252,6 +319,157 @@ pub struct Configuration {
     /// This is only useful for other libraries that build their own environments and only use rattler-build
     /// to execute scripts / bundle up files.
     pub environments_externally_managed: bool,
+
+    /// Whether to log a summary line for every downloaded repodata file instead of
+    /// only showing (and then clearing) the progress bars. Useful for CI logs.
+    pub repodata_download_summary: bool,
+
+    /// Whether to show a single progress bar for all repodata downloads instead
+    /// of one bar per download
+    pub repodata_overall_progress: bool,
+
+    /// Fallback mirrors for channels. If loading the repodata from a channel fails,
+    /// or it does not contain a requested package, the fallback is tried instead.
+    pub channel_fallbacks: HashMap<ChannelUrl, ChannelUrl>,
+
+    /// The maximum number of concurrent repodata requests per host. `None` means
+    /// no limit.
+    pub repodata_host_concurrency: Option<usize>,
+
+    /// Whether to verify the cached packages against the sha256 (or md5) of their
+    /// repodata records and the hashes of all their files before they are installed
+    /// into an environment. This detects a stale or corrupted package cache at the
+    /// cost of reading every cached file.
+    pub verify_package_hashes: bool,
+
+    /// The method used to link files from the package cache into the build and host
+    /// environments.
+    pub link_method: LinkMethod,
+
+    /// Preferred channels for individual packages. If a package is available in the
+    /// preferred channel, the candidates from the other channels are ignored.
+    pub channel_preferences: HashMap<PackageName, ChannelUrl>,
+
+    /// The maximum number of outputs whose environments are solved concurrently
+    /// (e.g. when rendering with `--with-solve`). The outputs share the
+    /// repodata gateway, so the repodata is only loaded once.
+    pub max_concurrent_solves: usize,
+
+    /// Whether to only log the rpath changes that relinking would make, without
+    /// modifying any binaries (useful for debugging relocatability issues).
+    pub relink_dry_run: bool,
+
+    /// The signing identity that macOS binaries are re-signed with after relinking.
+    /// If this is `None`, binaries are signed ad-hoc.
+    pub codesign_identity: Option<String>,
+
+    /// How progress of downloads and installations is reported
+    pub progress_output: ProgressOutput,
+
+    /// Whether to run the post-link scripts of packages when installing the
+    /// build, host and test environments (defaults to `true`)
+    pub execute_link_scripts: bool,
+
+    /// Callback that is invoked when a package starts and finishes linking
+    /// into an environment
+    pub link_callback: Option<LinkCallback>,
+
+    /// Whether to keep files in the host prefix that are left over from a previous
+    /// build instead of removing them before installing the host environment
+    pub skip_host_prefix_cleanup: bool,
+
+    /// Whether to create an empty `conda-meta/history` file in prefixes that do not
+    /// have one yet (defaults to `true`)
+    pub create_history_file: bool,
+
+    /// Exact pins (`name==version=build`) that are added as constraints to every
+    /// solve, e.g. a curated set of known good packages
+    pub solve_pins: Vec<MatchSpec>,
+
+    /// Packages for which to explain after every solve which requirements bounded
+    /// the selected version
+    pub explain_packages: Vec<PackageName>,
+
+    /// The channels that packages are expected to come from. After every solve a
+    /// warning is logged for packages from other channels. Empty to disable the check
+    pub expected_channels: Vec<ChannelUrl>,
+
+    /// Whether a package from a channel outside of `expected_channels` fails the
+    /// solve instead of logging a warning
+    pub strict_expected_channels: bool,
+}
+
+/// Middleware that limits the number of concurrent requests per host. Requests
+/// over the limit are queued until the body of a previous request to the same
+/// host has been read to the end (or the response has been dropped).
+struct HostConcurrencyMiddleware {
+    limit: usize,
+    semaphores: std::sync::Mutex<HashMap<String, Arc<tokio::sync::Semaphore>>>,
+}
+
+impl HostConcurrencyMiddleware {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            semaphores: Default::default(),
+        }
+    }
+
+    fn semaphore(&self, url: &Url) -> Arc<tokio::sync::Semaphore> {
+        let host = format!(
+            "{}:{}",
+            url.host_str().unwrap_or_default(),
+            url.port_or_known_default().unwrap_or_default()
+        );
+        self.semaphores
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| Arc::new(tokio::sync::Semaphore::new(self.limit)))
+            .clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl reqwest_middleware::Middleware for HostConcurrencyMiddleware {
+    async fn handle(
+        &self,
+        req: reqwest::Request,
+        extensions: &mut http::Extensions,
+        next: reqwest_middleware::Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        use futures::StreamExt;
+        use reqwest::ResponseBuilderExt;
+
+        let semaphore = self.semaphore(req.url());
+        let permit = semaphore
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let mut response = next.run(req, extensions).await?;
+
+        // hand the permit to the body, so that it is only released once the
+        // body has been downloaded
+        let url = response.url().clone();
+        let mut builder = http::Response::builder()
+            .status(response.status())
+            .version(response.version());
+        if let Some(headers) = builder.headers_mut() {
+            *headers = std::mem::take(response.headers_mut());
+        }
+        if let Some(extensions) = builder.extensions_mut() {
+            *extensions = std::mem::take(response.extensions_mut());
+        }
+        let builder = builder.url(url);
+        let body = response.bytes_stream().map(move |chunk| {
+            let _permit = &permit;
+            chunk
+        });
+        let response = builder
+            .body(reqwest::Body::wrap_stream(body))
+            .expect("the parts are taken from a valid response");
+        Ok(response.into())
+    }
 }
 
 /// Get the authentication storage from the given file
@@ -313,6 +531,25 @@ pub struct ConfigurationBuilder {
     allow_symlinks_on_windows: bool,
     allow_absolute_license_paths: bool,
     environments_externally_managed: bool,
+    repodata_download_summary: bool,
+    repodata_overall_progress: bool,
+    channel_fallbacks: HashMap<ChannelUrl, ChannelUrl>,
+    repodata_host_concurrency: Option<usize>,
+    verify_package_hashes: bool,
+    link_method: LinkMethod,
+    channel_preferences: HashMap<PackageName, ChannelUrl>,
+    max_concurrent_solves: usize,
+    relink_dry_run: bool,
+    codesign_identity: Option<String>,
+    progress_output: ProgressOutput,
+    execute_link_scripts: bool,
+    skip_host_prefix_cleanup: bool,
+    link_callback: Option<LinkCallback>,
+    create_history_file: bool,
+    solve_pins: Vec<MatchSpec>,
+    explain_packages: Vec<PackageName>,
+    expected_channels: Vec<ChannelUrl>,
+    strict_expected_channels: bool,
 }
 
 impl Configuration {
@@ -321,6 +558,17 @@ impl Configuration {
     pub fn builder() -> ConfigurationBuilder {
         ConfigurationBuilder::new()
     }
+
+    /// Returns the I/O concurrency limit to use when installing packages.
+    /// `limit` overrides the configured limit, if neither is set the limit is
+    /// 8 times the number of CPUs. A limit of 0 is treated as 1 instead of
+    /// unlimited.
+    pub fn io_concurrency_limit_or_default(&self, limit: Option<usize>) -> usize {
+        limit
+            .or(self.io_concurrency_limit)
+            .unwrap_or_else(|| num_cpus::get() * 8)
+            .max(1)
+    }
 }
 
 impl ConfigurationBuilder {
@@ -348,6 +596,25 @@ impl ConfigurationBuilder {
             allow_symlinks_on_windows: false,
             allow_absolute_license_paths: false,
             environments_externally_managed: false,
+            repodata_download_summary: false,
+            repodata_overall_progress: false,
+            channel_fallbacks: HashMap::new(),
+            repodata_host_concurrency: None,
+            verify_package_hashes: false,
+            link_method: LinkMethod::Auto,
+            channel_preferences: HashMap::new(),
+            max_concurrent_solves: 1,
+            relink_dry_run: false,
+            codesign_identity: None,
+            progress_output: ProgressOutput::default(),
+            execute_link_scripts: true,
+            skip_host_prefix_cleanup: false,
+            link_callback: None,
+            create_history_file: true,
+            solve_pins: Vec::new(),
+            explain_packages: Vec::new(),
+            expected_channels: Vec::new(),
+            strict_expected_channels: false,
         }
     }
 
@@ -542,13 +809,191 @@ impl ConfigurationBuilder {
         }
     }
 
+    /// Whether to log a summary line for every downloaded repodata file. This keeps
+    /// a record of what was downloaded after the progress bars are cleared.
+    pub fn with_repodata_download_summary(self, repodata_download_summary: bool) -> Self {
+        Self {
+            repodata_download_summary,
+            ..self
+        }
+    }
+
+    /// Whether to show a single progress bar with the total bytes and the number
+    /// of remaining downloads instead of one bar per repodata download.
+    pub fn with_repodata_overall_progress(self, repodata_overall_progress: bool) -> Self {
+        Self {
+            repodata_overall_progress,
+            ..self
+        }
+    }
+
+    /// Set fallback mirrors for channels. The key is the primary channel url and
+    /// the value the url of the mirror to fall back to.
+    pub fn with_channel_fallbacks(
+        self,
+        channel_fallbacks: HashMap<ChannelUrl, ChannelUrl>,
+    ) -> Self {
+        Self {
+            channel_fallbacks,
+            ..self
+        }
+    }
+
+    /// Set the maximum number of concurrent repodata requests per host, or
+    /// `None` to not limit the number of requests.
+    pub fn with_repodata_host_concurrency(self, repodata_host_concurrency: Option<usize>) -> Self {
+        Self {
+            repodata_host_concurrency,
+            ..self
+        }
+    }
+
+    /// Set whether the hashes of cached packages should be verified before they are
+    /// installed.
+    pub fn with_verify_package_hashes(self, verify_package_hashes: bool) -> Self {
+        Self {
+            verify_package_hashes,
+            ..self
+        }
+    }
+
+    /// Set the method used to link files from the package cache into the build and
+    /// host environments.
+    pub fn with_link_method(self, link_method: LinkMethod) -> Self {
+        Self {
+            link_method,
+            ..self
+        }
+    }
+
+    /// Set the preferred channel for individual packages.
+    pub fn with_channel_preferences(
+        self,
+        channel_preferences: HashMap<PackageName, ChannelUrl>,
+    ) -> Self {
+        Self {
+            channel_preferences,
+            ..self
+        }
+    }
+
+    /// Set the maximum number of outputs that are solved concurrently.
+    pub fn with_max_concurrent_solves(self, max_concurrent_solves: usize) -> Self {
+        Self {
+            max_concurrent_solves,
+            ..self
+        }
+    }
+
+    /// Set whether relinking only logs the planned rpath changes instead of
+    /// applying them.
+    pub fn with_relink_dry_run(self, relink_dry_run: bool) -> Self {
+        Self {
+            relink_dry_run,
+            ..self
+        }
+    }
+
+    /// Set the signing identity for re-signing macOS binaries after relinking.
+    pub fn with_codesign_identity(self, codesign_identity: Option<String>) -> Self {
+        Self {
+            codesign_identity,
+            ..self
+        }
+    }
+
+    /// Set how progress of downloads and installations is reported
+    pub fn with_progress_output(self, progress_output: ProgressOutput) -> Self {
+        Self {
+            progress_output,
+            ..self
+        }
+    }
+
+    /// Set whether to run the post-link scripts of packages when installing
+    /// environments. Disabling this is useful for sandboxed builds, but some
+    /// packages may not function without their post-link scripts.
+    pub fn with_execute_link_scripts(self, execute_link_scripts: bool) -> Self {
+        Self {
+            execute_link_scripts,
+            ..self
+        }
+    }
+
+    /// Set whether to keep files in the host prefix that are left over from a
+    /// previous build. This is useful when iterating on a build locally.
+    pub fn with_skip_host_prefix_cleanup(self, skip_host_prefix_cleanup: bool) -> Self {
+        Self {
+            skip_host_prefix_cleanup,
+            ..self
+        }
+    }
+
+    /// Set a callback that is invoked when a package starts and finishes
+    /// linking into an environment. This allows library users to collect
+    /// metrics, e.g. install timings per package.
+    pub fn with_link_callback(
+        self,
+        callback: impl Fn(&RepoDataRecord, LinkStage) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            link_callback: Some(Arc::new(callback)),
+            ..self
+        }
+    }
+
+    /// Set whether to create an empty `conda-meta/history` file when installing
+    /// into a prefix that does not have one. Skipping it is useful for tools that
+    /// manage their own history, but may confuse tools that expect conda-style
+    /// metadata in the prefix.
+    pub fn with_create_history_file(self, create_history_file: bool) -> Self {
+        Self {
+            create_history_file,
+            ..self
+        }
+    }
+
+    /// Set exact pins (`name==version=build`) that are added as constraints to
+    /// every solve. A pin that conflicts with a requirement of the recipe fails the
+    /// solve.
+    pub fn with_solve_pins(self, solve_pins: Vec<MatchSpec>) -> Self {
+        Self { solve_pins, ..self }
+    }
+
+    /// Set the packages for which to explain after every solve which requirements
+    /// bounded the selected version (e.g. why `numpy 1.26` was selected instead of
+    /// `numpy 2.0`).
+    pub fn with_explain_packages(self, explain_packages: Vec<PackageName>) -> Self {
+        Self {
+            explain_packages,
+            ..self
+        }
+    }
+
+    /// Set the channels that solved packages are expected to come from
+    pub fn with_expected_channels(self, expected_channels: Vec<ChannelUrl>) -> Self {
+        Self {
+            expected_channels,
+            ..self
+        }
+    }
+
+    /// Fail the solve instead of warning when a package comes from an unexpected channel
+    pub fn with_strict_expected_channels(self, strict_expected_channels: bool) -> Self {
+        Self {
+            strict_expected_channels,
+            ..self
+        }
+    }
+
     /// Construct a [`Configuration`] from the builder.
     pub fn finish(self) -> Configuration {
         let cache_dir = self.cache_dir.unwrap_or_else(|| {
             rattler_cache::default_cache_dir().expect("failed to determine default cache directory")
         });
         let client = self.client.unwrap_or_default();
-        let package_cache = PackageCache::new(cache_dir.join(rattler_cache::PACKAGE_CACHE_DIR));
+        let package_cache_dir = cache_dir.join(rattler_cache::PACKAGE_CACHE_DIR);
+        let package_cache = PackageCache::new(&package_cache_dir);
         let channel_config = self.channel_config.unwrap_or_else(|| {
             ChannelConfig::default_with_root_dir(
                 std::env::current_dir().unwrap_or_else(|_err| PathBuf::from("/")),
@@ -557,7 +1002,14 @@ impl ConfigurationBuilder {
         let repodata_gateway = Gateway::builder()
             .with_cache_dir(cache_dir.join(rattler_cache::REPODATA_CACHE_DIR))
             .with_package_cache(package_cache.clone())
-            .with_client(client.client.clone())
+            .with_client(match self.repodata_host_concurrency {
+                Some(limit) => {
+                    reqwest_middleware::ClientBuilder::from_client(client.client.clone())
+                        .with(HostConcurrencyMiddleware::new(limit))
+                        .build()
+                }
+                None => client.client.clone(),
+            })
             .with_channel_config(rattler_repodata_gateway::ChannelConfig {
                 default: rattler_repodata_gateway::SourceConfig {
                     jlap_enabled: self.use_jlap,
@@ -589,6 +1041,7 @@ impl ConfigurationBuilder {
             channel_config,
             compression_threads: self.compression_threads,
             io_concurrency_limit: self.io_concurrency_limit,
+            package_cache_dir,
             package_cache,
             repodata_gateway,
             channel_priority: self.channel_priority,
@@ -598,6 +1051,25 @@ impl ConfigurationBuilder {
             allow_symlinks_on_windows: self.allow_symlinks_on_windows,
             allow_absolute_license_paths: self.allow_absolute_license_paths,
             environments_externally_managed: self.environments_externally_managed,
+            repodata_download_summary: self.repodata_download_summary,
+            repodata_overall_progress: self.repodata_overall_progress,
+            channel_fallbacks: self.channel_fallbacks,
+            repodata_host_concurrency: self.repodata_host_concurrency,
+            verify_package_hashes: self.verify_package_hashes,
+            link_method: self.link_method,
+            channel_preferences: self.channel_preferences,
+            max_concurrent_solves: self.max_concurrent_solves,
+            relink_dry_run: self.relink_dry_run,
+            codesign_identity: self.codesign_identity,
+            progress_output: self.progress_output,
+            execute_link_scripts: self.execute_link_scripts,
+            skip_host_prefix_cleanup: self.skip_host_prefix_cleanup,
+            link_callback: self.link_callback,
+            create_history_file: self.create_history_file,
+            solve_pins: self.solve_pins,
+            explain_packages: self.explain_packages,
+            expected_channels: self.expected_channels,
+            strict_expected_channels: self.strict_expected_channels,
         }
     }
 }
@@ -683,3 +1155,117 @@ pub async fn resolve_s3_credentials(
     let resolved = rattler_s3::ResolvedS3Credentials::from_sdk().await?;
     Ok(resolved)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_execute_link_scripts() {
+        assert!(Configuration::builder().finish().execute_link_scripts);
+        assert!(
+            !Configuration::builder()
+                .with_execute_link_scripts(false)
+                .finish()
+                .execute_link_scripts
+        );
+    }
+
+    #[tokio::test]
+    async fn test_host_concurrency_middleware() {
+        use std::io::{BufRead, Write};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // a server that sends the headers right away and the body later, and
+        // records how many requests it is answering at the same time
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        {
+            let (active, max_active) = (active.clone(), max_active.clone());
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let (active, max_active) = (active.clone(), max_active.clone());
+                    std::thread::spawn(move || {
+                        let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                        let mut line = String::new();
+                        while reader.read_line(&mut line).unwrap() > 2 {
+                            line.clear();
+                        }
+                        let current = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_active.fetch_max(current, Ordering::SeqCst);
+                        write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\n"
+                        )
+                        .unwrap();
+                        stream.flush().unwrap();
+                        std::thread::sleep(std::time::Duration::from_millis(100));
+                        active.fetch_sub(1, Ordering::SeqCst);
+                        stream.write_all(b"body").unwrap();
+                    });
+                }
+            });
+        }
+
+        let client = reqwest_middleware::ClientBuilder::new(
+            reqwest::Client::builder().no_proxy().build().unwrap(),
+        )
+        .with(HostConcurrencyMiddleware::new(1))
+        .build();
+        let bodies = futures::future::join_all((0..3).map(|_| async {
+            let response = client.get(url.clone()).send().await.unwrap();
+            assert_eq!(response.url(), &url);
+            response.bytes().await.unwrap()
+        }))
+        .await;
+
+        assert!(bodies.iter().all(|body| body.as_ref() == b"body"));
+        assert_eq!(max_active.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_io_concurrency_limit() {
+        let configuration = Configuration::builder().finish();
+        assert_eq!(
+            configuration.io_concurrency_limit_or_default(None),
+            num_cpus::get() * 8
+        );
+        assert_eq!(configuration.io_concurrency_limit_or_default(Some(3)), 3);
+        assert_eq!(configuration.io_concurrency_limit_or_default(Some(0)), 1);
+
+        let configuration = Configuration::builder()
+            .with_io_concurrency_limit(Some(16))
+            .finish();
+        assert_eq!(configuration.io_concurrency_limit_or_default(None), 16);
+        assert_eq!(configuration.io_concurrency_limit_or_default(Some(4)), 4);
+
+        let configuration = Configuration::builder()
+            .with_io_concurrency_limit(Some(0))
+            .finish();
+        assert_eq!(configuration.io_concurrency_limit_or_default(None), 1);
+    }
+
+    #[test]
+    fn test_link_method_options() {
+        let configuration = Configuration::builder()
+            .with_link_method(LinkMethod::Copy)
+            .finish();
+        assert_eq!(configuration.link_method, LinkMethod::Copy);
+        assert_eq!(configuration.link_method.allow_hard_links(), Some(false));
+        assert_eq!(configuration.link_method.allow_ref_links(), Some(false));
+
+        assert_eq!(LinkMethod::Hardlink.allow_hard_links(), Some(true));
+        assert_eq!(LinkMethod::Hardlink.allow_ref_links(), Some(false));
+        assert_eq!(LinkMethod::Reflink.allow_hard_links(), Some(false));
+        assert_eq!(LinkMethod::Reflink.allow_ref_links(), Some(true));
+
+        // by default the installer decides based on the filesystem
+        let configuration = Configuration::builder().finish();
+        assert_eq!(configuration.link_method, LinkMethod::Auto);
+        assert_eq!(configuration.link_method.allow_hard_links(), None);
+        assert_eq!(configuration.link_method.allow_ref_links(), None);
+    }
+}
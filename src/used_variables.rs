@@ -234,14 +234,23 @@ fn find_jinja<S: SourceCode>(
     Ok(())
 }
 
+/// Returns the variables used in a jinja expression, e.g. the condition of an
+/// if-selector.
+pub(crate) fn variables_from_expression(expr: &str) -> Result<HashSet<String>, minijinja::Error> {
+    let selector_tmpl = format!("${{{{ {} }}}}", expr);
+    let ast = parse(&selector_tmpl, "selector.yaml")?;
+    let mut variables = HashSet::new();
+    extract_variables(&ast, &mut variables);
+    Ok(variables)
+}
+
 #[allow(clippy::result_large_err)]
 fn variables_from_raw_expr<S: SourceCode>(
     expr: &str,
     src: S,
     span: &Span,
 ) -> Result<HashSet<String>, ParsingError<S>> {
-    let selector_tmpl = format!("${{{{ {} }}}}", expr);
-    let ast = parse(&selector_tmpl, "selector.yaml").map_err(|e| {
+    variables_from_expression(expr).map_err(|e| {
         ParsingError::from_partial(
             src,
             crate::_partialerror!(
@@ -250,10 +259,7 @@ fn variables_from_raw_expr<S: SourceCode>(
                 label = "failed to parse as jinja expression"
             ),
         )
-    })?;
-    let mut variables = HashSet::new();
-    extract_variables(&ast, &mut variables);
-    Ok(variables)
+    })
 }
 
 fn variables_from_skip<S: SourceCode>(
@@ -8,6 +8,7 @@ use std::{
 };
 
 use indexmap::IndexSet;
+use itertools::Itertools;
 use miette::Diagnostic;
 use rattler_conda_types::{NoArchType, Platform};
 use serde::{Deserialize, Serialize};
@@ -21,7 +22,9 @@ use crate::{
     normalized_key::NormalizedKey,
     recipe::{
         Jinja, Recipe, Render,
-        custom_yaml::{HasSpan, Node, RenderedMappingNode, RenderedNode, TryConvertNode},
+        custom_yaml::{
+            HasSpan, Node, RenderedMappingNode, RenderedNode, RenderedScalarNode, TryConvertNode,
+        },
         error::{ErrorKind, ParsingError, PartialParsingError},
         variable::Variable,
     },
@@ -72,6 +75,32 @@ impl std::hash::Hash for DiscoveredOutput {
     }
 }
 
+/// Make sure that the names of the discovered outputs are unique after
+/// normalization. Names that only differ in their separators (e.g. `my-tool`
+/// and `my_tool`) map to the same variant key and are rejected. Multiple
+/// variants of the same output are allowed.
+pub fn validate_unique_output_names<'a>(
+    outputs: impl IntoIterator<Item = &'a DiscoveredOutput>,
+) -> Result<(), VariantExpandError> {
+    let mut seen = HashMap::<String, &str>::new();
+    for output in outputs {
+        let normalized = NormalizedKey::from(output.name.as_str()).normalize();
+        match seen.get(&normalized) {
+            Some(other) if *other != output.name => {
+                return Err(VariantExpandError::CollidingOutputNames(
+                    other.to_string(),
+                    output.name.clone(),
+                ));
+            }
+            Some(_) => {}
+            None => {
+                seen.insert(normalized, &output.name);
+            }
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 /// Represents a pin configuration for a package.
 pub struct Pin {
@@ -228,6 +257,9 @@ pub enum VariantExpandError {
     #[error("Duplicate outputs: {0}")]
     DuplicateOutputs(String),
 
+    #[error("Output names `{0}` and `{1}` are the same after normalization")]
+    CollidingOutputNames(String, String),
+
     #[error("Missing output: {0} (used in pin_subpackage)")]
     MissingOutput(String),
 
@@ -398,7 +430,7 @@ impl VariantConfig {
                 for key in zip {
                     let value = match self.variants.get(key) {
                         None => {
-                            return Err(VariantExpandError::InvalidZipKeyLength(key.normalize()));
+                            return Err(VariantExpandError::InvalidZipKeyLength(key.to_string()));
                         }
                         Some(value) => value,
                     };
@@ -406,7 +438,7 @@ impl VariantConfig {
                     if let Some(l) = prev_len
                         && l != value.len()
                     {
-                        return Err(VariantExpandError::InvalidZipKeyLength(key.normalize()));
+                        return Err(VariantExpandError::InvalidZipKeyLength(key.to_string()));
                     }
                     prev_len = Some(value.len());
                 }
@@ -564,6 +596,7 @@ impl VariantConfig {
             }
         }
 
+        validate_unique_output_names(&recipes)?;
         Ok(recipes)
     }
 }
@@ -580,6 +613,32 @@ impl TryConvertNode<VariantConfig> for RenderedMappingNode {
     fn try_convert(&self, _name: &str) -> Result<VariantConfig, Vec<PartialParsingError>> {
         let mut config = VariantConfig::default();
 
+        // `my-key`, `my_key` and `my.key` are the same variant key, so one of
+        // them would silently replace the other
+        let mut spellings = BTreeMap::<NormalizedKey, Vec<&RenderedScalarNode>>::new();
+        for (key, _) in self.iter() {
+            spellings.entry(key.as_str().into()).or_default().push(key);
+        }
+        let conflicts = spellings
+            .values()
+            .filter(|keys| keys.len() > 1)
+            .map(|keys| {
+                let spellings = keys.iter().map(|key| format!("`{}`", key.as_str()));
+                _partialerror!(
+                    *keys[1].span(),
+                    ErrorKind::DuplicateKey(keys[1].as_str().to_string()),
+                    label = "conflicting spelling of a variant key",
+                    help = format!(
+                        "{} are the same variant key after normalization, use only one of them",
+                        spellings.format(", ")
+                    )
+                )
+            })
+            .collect::<Vec<_>>();
+        if !conflicts.is_empty() {
+            return Err(conflicts);
+        }
+
         for (key, value) in self.iter() {
             let key_str = key.as_str();
             match key_str {
@@ -967,4 +1026,175 @@ compiler:
 
         insta::assert_yaml_snapshot!(used_variables_all);
     }
+
+    #[test]
+    fn test_conflicting_variant_key_spellings() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("variants.yaml");
+        fs::write(
+            &path,
+            "my-key:\n  - 1\nmy_key:\n  - 2\nmy.key:\n  - 3\npython:\n  - 3.12\n",
+        )
+        .unwrap();
+
+        let err =
+            VariantConfig::from_files(std::slice::from_ref(&path), &SelectorConfig::default())
+                .unwrap_err();
+        let VariantConfigError::RecipeParseErrors(errors) = err else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(errors.errs.len(), 1);
+        let help = errors.errs[0].help.as_deref().unwrap();
+        assert_eq!(
+            help,
+            "`my-key`, `my_key`, `my.key` are the same variant key after normalization, use only one of them"
+        );
+
+        // a single spelling is fine
+        fs::write(&path, "my-key:\n  - 1\npython:\n  - 3.12\n").unwrap();
+        assert!(VariantConfig::from_files(&[path], &SelectorConfig::default()).is_ok());
+    }
+
+    /// Finds the variants of the outputs of a recipe with the default variant
+    /// and selector config.
+    fn find_variants(
+        recipe_text: &str,
+    ) -> Result<IndexSet<DiscoveredOutput>, VariantError<String>> {
+        let outputs = crate::recipe::parser::find_outputs_from_src(recipe_text).unwrap();
+        VariantConfig::default().find_variants(
+            &outputs,
+            recipe_text.to_string(),
+            &SelectorConfig::default(),
+        )
+    }
+
+    #[test]
+    fn test_colliding_output_names() {
+        let recipe_text = r#"
+recipe:
+  name: my-tool
+  version: 0.1.0
+outputs:
+  - package:
+      name: my-tool
+  - package:
+      name: my_tool
+  - package:
+      name: my-tool-extra
+"#;
+        let Err(VariantError::ExpandError(VariantExpandError::CollidingOutputNames(a, b))) =
+            find_variants(recipe_text)
+        else {
+            panic!("expected a colliding output names error");
+        };
+        let mut names = [a, b];
+        names.sort();
+        assert_eq!(names, ["my-tool", "my_tool"]);
+
+        // only the colliding outputs are rejected
+        let recipe_text = recipe_text.replace("name: my_tool", "name: my_tool_docs");
+        assert_eq!(find_variants(&recipe_text).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_split_output_architectures_build_string() {
+        let recipe_text = r#"
+recipe:
+  name: some-lib
+  version: 0.1.0
+build:
+  number: 3
+outputs:
+  - package:
+      name: some-lib
+    architectures: [arm64, x86_64]
+  - package:
+      name: other
+    requirements:
+      run:
+        - ${{ pin_subpackage('some-lib') }}
+"#;
+        let discovered = find_variants(recipe_text).unwrap();
+
+        let mut build_strings = discovered
+            .iter()
+            .map(|output| (output.name.as_str(), output.build_string.as_str()))
+            .collect::<Vec<_>>();
+        build_strings.sort();
+        let lib_hash = discovered
+            .iter()
+            .find(|output| output.name == "some-lib")
+            .unwrap()
+            .hash
+            .to_string();
+        let other_hash = discovered
+            .iter()
+            .find(|output| output.name == "other")
+            .unwrap()
+            .hash
+            .to_string();
+        assert_eq!(
+            build_strings,
+            [
+                ("other", format!("{other_hash}_3").as_str()),
+                ("some-lib", format!("{lib_hash}_3_arm64").as_str()),
+                ("some-lib", format!("{lib_hash}_3_x86_64").as_str()),
+            ]
+        );
+
+        // the split outputs are variants of one package
+        assert!(validate_unique_output_names(&discovered).is_ok());
+
+        // but an exact pin cannot choose between the architectures
+        let recipe_text = recipe_text.replace("'some-lib'", "'some-lib', exact=True");
+        assert!(matches!(
+            find_variants(&recipe_text),
+            Err(VariantError::ExpandError(
+                VariantExpandError::DuplicateOutputs(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_outputs_sorted_by_pin_subpackage() {
+        let recipe_text = r#"
+recipe:
+  name: test
+  version: 0.1.0
+outputs:
+  - package:
+      name: output-c
+    requirements:
+      host:
+        - ${{ pin_subpackage('output-b') }}
+  - package:
+      name: output-b
+    requirements:
+      run:
+        - ${{ pin_subpackage('output-a', exact=True) }}
+      run_exports:
+        - ${{ pin_subpackage('output-b') }}
+  - package:
+      name: output-a
+"#;
+        // the outputs are returned in build order
+        let names = find_variants(recipe_text)
+            .unwrap()
+            .into_iter()
+            .map(|output| output.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["output-a", "output-b", "output-c"]);
+
+        // a genuine cycle is rejected
+        let recipe_text = recipe_text.replace(
+            "name: output-a",
+            "name: output-a\n    requirements:\n      run:\n        - ${{ pin_subpackage('output-c') }}",
+        );
+        assert!(matches!(
+            find_variants(&recipe_text),
+            Err(VariantError::ExpandError(
+                VariantExpandError::CycleInRecipeOutputs(_)
+            ))
+        ));
+    }
 }
@@ -165,13 +165,14 @@ impl<S: SourceCode> Stage1Render<S> {
         let recipe = &self.inner[idx].recipe;
 
         // Filter out any ignore keys
-        let ignore_keys: HashSet<NormalizedKey> = recipe
-            .build()
-            .variant()
-            .ignore_keys
-            .iter()
-            .map(|k| k.as_str().into())
-            .collect();
+        let (ignore_keys, _) = NormalizedKey::set_from(
+            recipe
+                .build()
+                .variant()
+                .ignore_keys
+                .iter()
+                .map(String::as_str),
+        );
 
         all_vars.retain(|var| !ignore_keys.contains(var));
 
@@ -202,6 +203,20 @@ impl<S: SourceCode> Stage1Render<S> {
                     pin.as_source().to_string(),
                 ));
             };
+            // the per-architecture outputs of an output with an `architectures`
+            // list share their name, so an exact pin is ambiguous
+            if self
+                .inner
+                .iter()
+                .filter(|x| x.recipe.package().name() == pin)
+                .count()
+                > 1
+            {
+                return Err(VariantExpandError::DuplicateOutputs(format!(
+                    "{} is built for multiple architectures and cannot be pinned exactly",
+                    pin.as_source()
+                )));
+            }
             // find the referenced output
             let build_string = self.build_string_for_output(other_idx)?;
             let version = self.inner[other_idx].recipe.package().version();
@@ -229,11 +244,13 @@ impl<S: SourceCode> Stage1Render<S> {
         selector_config.hash = Some(hash.clone());
         let jinja = Jinja::new(selector_config.clone()).with_context(&recipe.context);
 
-        Ok(recipe
-            .build()
-            .string()
-            .resolve(&hash, recipe.build().number, &jinja)
-            .into_owned())
+        let string = recipe.build().string();
+        let resolved = string.resolve(&hash, recipe.build().number, &jinja);
+        // an already resolved build string contains the suffix
+        Ok(match recipe.build().string_suffix() {
+            Some(suffix) if string.as_resolved().is_none() => format!("{resolved}_{suffix}"),
+            _ => resolved.into_owned(),
+        })
     }
 
     /// sort the outputs topologically
@@ -395,14 +412,8 @@ pub(crate) fn stage_1_render<S: SourceCode>(
             additional_variables.extend(env_vars.keys().cloned().map(Into::into));
 
             // filter out any ignore keys
-            let extra_ignore_keys: HashSet<NormalizedKey> = output
-                .build()
-                .variant()
-                .ignore_keys
-                .clone()
-                .into_iter()
-                .map(Into::into)
-                .collect();
+            let (extra_ignore_keys, _) =
+                NormalizedKey::set_from(output.build().variant().ignore_keys.clone());
 
             additional_variables.retain(|x| !extra_ignore_keys.contains(x));
             extra_vars_per_output.push(additional_variables);
@@ -2,17 +2,17 @@
 use std::{
     collections::{HashMap, HashSet},
     path::{Path, PathBuf},
+    sync::LazyLock,
 };
 
 use fs_err::File;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use goblin::pe::PE;
+use itertools::Itertools;
 use rattler_conda_types::Platform;
 use rattler_shell::activation::prefix_path_entries;
 
-use crate::{
-    post_process::relink::{RelinkError, Relinker},
-    recipe::parser::GlobVec,
-};
+use crate::post_process::relink::{AppliedRelink, RelinkError, RelinkOptions, Relinker};
 
 #[derive(Debug)]
 pub struct Dll {
@@ -22,7 +22,8 @@ pub struct Dll {
     libraries: HashSet<PathBuf>,
 }
 
-/// List of System DLLs that are allowed to be linked against.
+/// List of System DLLs that are allowed to be linked against. The entries are
+/// glob patterns that are matched case-insensitively against the file name.
 pub const WIN_ALLOWLIST: &[&str] = &[
     "ADVAPI32.dll",
     "bcrypt.dll",
@@ -45,6 +46,11 @@ pub const WIN_ALLOWLIST: &[&str] = &[
     "WS2_32.dll",
     "ntdll.dll",
     "msvcrt.dll",
+    // the universal C runtime and its API sets
+    "api-ms-win-crt-*.dll",
+    "api-ms-win-core-*.dll",
+    "ucrtbase.dll",
+    "vcruntime*.dll",
 ];
 
 #[derive(Debug, thiserror::Error)]
@@ -95,6 +101,74 @@ impl Dll {
     }
 }
 
+impl Dll {
+    /// The directories that are searched for the DLLs imported by this DLL, in
+    /// order of precedence: the directory of the DLL itself, the `PATH` entries
+    /// of the environment (e.g. `Library/bin`) and the `PATH` of this process.
+    ///
+    /// The directories are searched both in the files of the package (`prefix`)
+    /// and in the environment it is built against (`encoded_prefix`).
+    fn search_dirs(
+        &self,
+        prefix: &Path,
+        encoded_prefix: &Path,
+    ) -> Result<Vec<PathBuf>, RelinkError> {
+        let path_in_prefix = self.path.strip_prefix(prefix)?;
+
+        let mut search_dirs = Vec::new();
+        if let Some(parent) = path_in_prefix.parent() {
+            search_dirs.push(prefix.join(parent));
+            search_dirs.push(encoded_prefix.join(parent));
+        }
+        search_dirs.extend(prefix_path_entries(prefix, &Platform::Win64));
+        search_dirs.extend(prefix_path_entries(encoded_prefix, &Platform::Win64));
+
+        let path = std::env::var("PATH").unwrap_or_default();
+        search_dirs.extend(std::env::split_paths(&path));
+
+        Ok(search_dirs.into_iter().unique().collect())
+    }
+
+    /// Looks up the imported (non-system) DLLs in the given search directories.
+    fn resolve_in(&self, search_dirs: &[PathBuf]) -> HashMap<PathBuf, Option<PathBuf>> {
+        let mut result = HashMap::new();
+        for lib in &self.libraries {
+            if is_system_dll(lib) {
+                continue;
+            }
+
+            let dll_name = lib.file_name().unwrap_or_default();
+            let resolved = search_dirs
+                .iter()
+                .map(|search_dir| search_dir.join(dll_name))
+                .find(|potential_path| potential_path.exists());
+
+            // If not found anywhere, keep the original name but mark as None
+            result.insert(lib.clone(), resolved);
+        }
+        result
+    }
+}
+
+/// Returns true if the DLL is a system DLL that is always available.
+fn is_system_dll(lib: &Path) -> bool {
+    static SYSTEM_DLLS: LazyLock<GlobSet> = LazyLock::new(|| {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in WIN_ALLOWLIST {
+            builder.add(
+                GlobBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .expect("the allowlist contains valid globs"),
+            );
+        }
+        builder.build().expect("the allowlist contains valid globs")
+    });
+
+    lib.file_name()
+        .is_some_and(|name| SYSTEM_DLLS.is_match(Path::new(name)))
+}
+
 impl Relinker for Dll {
     fn test_file(path: &Path) -> Result<bool, RelinkError> {
         match Self::try_new(path)? {
@@ -121,79 +195,67 @@ impl Relinker for Dll {
         prefix: &Path,
         encoded_prefix: &Path,
     ) -> HashMap<PathBuf, Option<PathBuf>> {
-        let mut result = HashMap::new();
-        for lib in &self.libraries {
-            if WIN_ALLOWLIST.iter().any(|&sys_dll| {
-                lib.file_name()
-                    .and_then(|n| n.to_str())
-                    .map(|n| n.eq_ignore_ascii_case(sys_dll))
-                    .unwrap_or(false)
-            }) {
-                continue;
-            }
-
-            let dll_name = lib.file_name().unwrap_or_default();
-
-            // 1. Check in the same directory as the original DLL
-            let path_in_prefix = self
-                .path
-                .strip_prefix(prefix)
-                .expect("DLL path should be in prefix");
-            let path = encoded_prefix.join(path_in_prefix);
-            let same_dir = path.parent().map(|p| p.join(dll_name));
-
-            if let Some(path) = same_dir
-                && path.exists()
-            {
-                result.insert(lib.clone(), Some(path));
-                continue;
-            }
-
-            // 2. Check all directories in the search path
-            let mut found = false;
-            let path_entries = prefix_path_entries(encoded_prefix, &Platform::Win64);
-            let path = std::env::var("PATH").unwrap_or_default();
-            let search_dirs = path_entries
-                .into_iter()
-                .chain(std::env::split_paths(&path))
-                .collect::<Vec<_>>();
-
-            for search_dir in search_dirs {
-                let potential_path = search_dir.join(dll_name);
-                if potential_path.exists() {
-                    result.insert(lib.clone(), Some(potential_path));
-                    found = true;
-                    break;
-                }
-            }
-
-            if !found {
-                // If not found anywhere, keep the original name but mark as None
-                result.insert(lib.clone(), None);
+        match self.search_dirs(prefix, encoded_prefix) {
+            Ok(search_dirs) => self.resolve_in(&search_dirs),
+            Err(e) => {
+                tracing::warn!("Cannot resolve the imports of {}: {e}", self.path.display());
+                self.resolve_in(&[])
             }
         }
-        result
     }
 
     fn resolve_rpath(&self, _rpath: &Path, _prefix: &Path, _encoded_prefix: &Path) -> PathBuf {
         unimplemented!("This function does not make sense on Windows")
     }
 
-    fn relink(
+    fn rpaths(&self) -> Vec<PathBuf> {
+        // DLLs don't have rpaths
+        Vec::new()
+    }
+
+    fn planned_rpaths(
         &self,
         _prefix: &Path,
         _encoded_prefix: &Path,
-        _custom_rpaths: &[String],
-        _rpath_allowlist: &GlobVec,
+        _options: &RelinkOptions,
+    ) -> Result<Vec<PathBuf>, crate::post_process::relink::RelinkError> {
+        Ok(Vec::new())
+    }
+
+    /// DLLs have no rpaths, so nothing is rewritten. Instead, the import table is
+    /// validated: every imported (non-system) DLL has to be found in the DLL
+    /// search path of the environment, otherwise a warning is emitted.
+    fn relink(
+        &self,
+        prefix: &Path,
+        encoded_prefix: &Path,
+        _options: &RelinkOptions,
         _system_tools: &crate::system_tools::SystemTools,
-    ) -> Result<(), crate::post_process::relink::RelinkError> {
-        // On Windows, we don't need to relink anything
-        Ok(())
+    ) -> Result<Option<AppliedRelink>, crate::post_process::relink::RelinkError> {
+        let path_in_prefix = self.path.strip_prefix(prefix)?;
+
+        let mut missing = self
+            .resolve_in(&self.search_dirs(prefix, encoded_prefix)?)
+            .into_iter()
+            .filter(|(_, resolved)| resolved.is_none())
+            .map(|(lib, _)| lib)
+            .collect::<Vec<_>>();
+        missing.sort();
+
+        for lib in missing {
+            tracing::warn!(
+                "{} imports {} which is not found next to it or on the PATH of the environment",
+                path_in_prefix.display(),
+                lib.display()
+            );
+        }
+
+        // DLLs are not modified
+        Ok(None)
     }
 }
 
 #[cfg(test)]
-#[cfg(target_os = "windows")]
 mod tests {
     use super::*;
     use fs_err as fs;
@@ -253,6 +315,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(target_os = "windows")]
     fn test_system_dll_filtering() {
         let test_dlls = vec![
             "KERNEL32.dll",
@@ -288,4 +351,73 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn test_dll_search_order() -> Result<(), RelinkError> {
+        // a package that ships the DLL in a non-standard subfolder
+        let prefix = tempfile::tempdir()?;
+        let encoded_prefix = tempfile::tempdir()?;
+        let package_dll = prefix.path().join("Library/lib/zstd/zstd.dll");
+        fs::create_dir_all(package_dll.parent().unwrap())?;
+
+        let dll = Dll {
+            path: package_dll.clone(),
+            libraries: [
+                "KERNEL32.dll",
+                "VCRUNTIME140.dll",
+                "api-ms-win-crt-heap-l1-1-0.dll",
+                "libhelper.dll",
+                "libmissing.dll",
+            ]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+        };
+
+        // the helper is provided by the environment
+        let helper = encoded_prefix.path().join("Library/bin/libhelper.dll");
+        fs::create_dir_all(helper.parent().unwrap())?;
+        fs::write(&helper, "")?;
+
+        let resolved = dll.resolve_libraries(prefix.path(), encoded_prefix.path());
+        assert_eq!(
+            resolved.get(Path::new("libhelper.dll")),
+            Some(&Some(helper.clone()))
+        );
+        assert_eq!(resolved.get(Path::new("libmissing.dll")), Some(&None));
+        // the C runtime is part of the system
+        assert_eq!(resolved.len(), 2);
+
+        // next to the DLL takes precedence over the environment
+        let local_helper = package_dll.with_file_name("libhelper.dll");
+        fs::write(&local_helper, "")?;
+        let resolved = dll.resolve_libraries(prefix.path(), encoded_prefix.path());
+        assert_eq!(
+            resolved.get(Path::new("libhelper.dll")),
+            Some(&Some(local_helper))
+        );
+
+        let relink = |prefix: &Path| {
+            dll.relink(
+                prefix,
+                encoded_prefix.path(),
+                &RelinkOptions::default(),
+                &crate::system_tools::SystemTools::default(),
+            )
+        };
+        relink(prefix.path())?;
+        assert!(logs_contain("imports libmissing.dll which is not found"));
+        assert!(!logs_contain("imports libhelper.dll"));
+        assert!(!logs_contain("imports api-ms-win-crt-heap-l1-1-0.dll"));
+        assert!(!logs_contain("imports VCRUNTIME140.dll"));
+
+        // a DLL outside of the prefix is an error instead of a panic
+        assert!(matches!(
+            relink(encoded_prefix.path()),
+            Err(RelinkError::StripPrefixError(_))
+        ));
+
+        Ok(())
+    }
 }
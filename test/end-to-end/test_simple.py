@@ -1465,7 +1465,7 @@ def test_missing_pin_subpackage(
             stderr=STDOUT,
         )
     stdout = e.value.output
-    assert "Missing output: test1 (used in pin_subpackage)" in stdout
+    assert "invalid value for `pin_subpackage`: `test1`" in stdout
 
 
 def test_cycle_detection(rattler_build: RattlerBuild, recipes: Path, tmp_path: Path):